@@ -149,6 +149,9 @@ impl Capturer {
                     is_hardware,
                     is_discrete,
                     is_integrated,
+                    dedicated_video_memory: adapter_desc1.DedicatedVideoMemory as u64,
+                    dedicated_system_memory: adapter_desc1.DedicatedSystemMemory as u64,
+                    shared_system_memory: adapter_desc1.SharedSystemMemory as u64,
                 };
 
                 adapter_desc
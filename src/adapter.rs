@@ -0,0 +1,398 @@
+//! Choosing an adapter from the `AdapterDesc`s DXGI reports, and classifying adapters
+//! by their PCI vendor and device ids.
+
+use regex::Regex;
+
+use crate::{AdapterDesc, Luid, OutputDesc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AdapterKind {
+    Discrete,
+    Integrated,
+}
+
+impl AdapterKind {
+    /// Classifies a hardware adapter by its PCI ids. `None` for vendors without a table,
+    /// e.g. the Microsoft Basic Render Driver or virtual machine adapters.
+    ///
+    /// ```
+    /// use dxgi::AdapterKind;
+    ///
+    /// // UHD Graphics 630 and Arc A770
+    /// assert_eq!(AdapterKind::from_ids(0x8086, 0x3e92), Some(AdapterKind::Integrated));
+    /// assert_eq!(AdapterKind::from_ids(0x8086, 0x56a0), Some(AdapterKind::Discrete));
+    /// // Radeon RX 6600 and the Radeon graphics of a Ryzen 7 5800U
+    /// assert_eq!(AdapterKind::from_ids(0x1002, 0x73ff), Some(AdapterKind::Discrete));
+    /// assert_eq!(AdapterKind::from_ids(0x1002, 0x1638), Some(AdapterKind::Integrated));
+    /// // GeForce RTX 3060 and the GeForce 9400M chipset graphics
+    /// assert_eq!(AdapterKind::from_ids(0x10de, 0x2503), Some(AdapterKind::Discrete));
+    /// assert_eq!(AdapterKind::from_ids(0x10de, 0x0863), Some(AdapterKind::Integrated));
+    /// assert_eq!(AdapterKind::from_ids(0x4d4f4351, 0x36334330), Some(AdapterKind::Integrated));
+    /// assert_eq!(AdapterKind::from_ids(0x1414, 0x8c), None);
+    /// assert_eq!(AdapterKind::from_ids(0x15ad, 0x0405), None);
+    /// ```
+    pub fn from_ids(vendor_id: u32, device_id: u32) -> Option<Self> {
+        let listed = |table: &[(u32, u32)]| {
+            table
+                .iter()
+                .any(|&(first, last)| (first..=last).contains(&device_id))
+        };
+        match Vendor::from_id(vendor_id) {
+            Vendor::Nvidia if listed(NVIDIA_INTEGRATED) => Some(AdapterKind::Integrated),
+            Vendor::Nvidia => Some(AdapterKind::Discrete),
+            Vendor::Amd if listed(AMD_INTEGRATED) => Some(AdapterKind::Integrated),
+            Vendor::Amd => Some(AdapterKind::Discrete),
+            Vendor::Intel if listed(INTEL_DISCRETE) => Some(AdapterKind::Discrete),
+            Vendor::Intel => Some(AdapterKind::Integrated),
+            Vendor::Qualcomm => Some(AdapterKind::Integrated),
+            Vendor::Microsoft | Vendor::Other(_) => None,
+        }
+    }
+
+    fn matches(&self, adapter: &AdapterDesc) -> bool {
+        match self {
+            AdapterKind::Discrete => adapter.is_discrete,
+            AdapterKind::Integrated => adapter.is_integrated,
+        }
+    }
+}
+
+// NVIDIA graphics are discrete except for the chipset graphics of nForce boards and ION
+const NVIDIA_INTEGRATED: &[(u32, u32)] = &[
+    (0x0240, 0x0247), // C51, GeForce 6100 and 6150
+    (0x03d0, 0x03d6), // C61, GeForce 6100 and 6150 nForce 4xx
+    (0x053a, 0x053e), // C68, GeForce 7025 and 7050 nForce 6xx
+    (0x07e0, 0x07e5), // C73, GeForce 7050 to 7150 nForce 6xxi
+    (0x0840, 0x084f), // MCP77 and MCP78, GeForce 8100 to 8300
+    (0x0860, 0x087f), // MCP79 and MCP7A, GeForce 9300 and 9400, ION
+];
+
+// Intel graphics are integrated except for these device id ranges
+const INTEL_DISCRETE: &[(u32, u32)] = &[
+    (0x4905, 0x4909), // DG1, Iris Xe MAX
+    (0x5690, 0x56c2), // Alchemist, Arc A-series and Flex
+    (0xe202, 0xe212), // Battlemage, Arc B-series
+];
+
+// AMD graphics are discrete except for the APUs
+const AMD_INTEGRATED: &[(u32, u32)] = &[
+    (0x1304, 0x131d), // Kaveri
+    (0x13c0, 0x13c0), // Granite Ridge
+    (0x1506, 0x1506), // Mendocino
+    (0x150e, 0x150e), // Strix Point
+    (0x1586, 0x1586), // Strix Halo
+    (0x15bf, 0x15bf), // Phoenix
+    (0x15c8, 0x15c8), // Phoenix 2
+    (0x15d8, 0x15d8), // Picasso
+    (0x15dd, 0x15dd), // Raven Ridge
+    (0x15e7, 0x15e7), // Barcelo
+    (0x1636, 0x1636), // Renoir
+    (0x1638, 0x1638), // Cezanne
+    (0x163f, 0x163f), // Van Gogh
+    (0x164c, 0x164c), // Lucienne
+    (0x164e, 0x164e), // Raphael
+    (0x1681, 0x1681), // Rembrandt
+    (0x9640, 0x964f), // Llano
+    (0x9802, 0x980a), // Brazos
+    (0x9830, 0x983f), // Kabini
+    (0x9850, 0x985f), // Mullins
+    (0x9874, 0x9877), // Carrizo
+    (0x98e4, 0x98e4), // Stoney Ridge
+    (0x9900, 0x99af), // Trinity, Richland
+];
+
+/// GPU vendor, from the PCI vendor id.
+///
+/// ```
+/// use dxgi::Vendor;
+///
+/// assert_eq!(Vendor::from_id(0x10de), Vendor::Nvidia);
+/// assert_eq!(Vendor::from_id(0x1022), Vendor::Amd);
+/// assert_eq!(Vendor::from_id(0x5143), Vendor::Qualcomm);
+/// assert_eq!(Vendor::from_id(0x15ad), Vendor::Other(0x15ad));
+/// assert_eq!(Vendor::Intel.to_string(), "Intel");
+/// assert_eq!(Vendor::Other(0x15ad).to_string(), "15ad");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Vendor {
+    Nvidia,
+    Amd,
+    Intel,
+    // WARP and the basic display adapters
+    Microsoft,
+    // Adreno, on Windows on ARM
+    Qualcomm,
+    Other(u32),
+}
+
+impl Vendor {
+    pub fn from_id(vendor_id: u32) -> Self {
+        match vendor_id {
+            0x10de => Vendor::Nvidia,
+            // ATI and AMD
+            0x1002 | 0x1022 => Vendor::Amd,
+            0x8086 => Vendor::Intel,
+            0x1414 => Vendor::Microsoft,
+            // PCI id and the ACPI id "QCOM"
+            0x5143 | 0x4d4f_4351 => Vendor::Qualcomm,
+            other => Vendor::Other(other),
+        }
+    }
+}
+
+impl std::fmt::Display for Vendor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Vendor::Nvidia => f.write_str("NVIDIA"),
+            Vendor::Amd => f.write_str("AMD"),
+            Vendor::Intel => f.write_str("Intel"),
+            Vendor::Microsoft => f.write_str("Microsoft"),
+            Vendor::Qualcomm => f.write_str("Qualcomm"),
+            Vendor::Other(id) => write!(f, "{:04x}", id),
+        }
+    }
+}
+
+// DXGI_ADAPTER_FLAG_SOFTWARE
+const ADAPTER_FLAG_SOFTWARE: u32 = 2;
+
+/// The fields of `DXGI_ADAPTER_DESC1`, as plain Rust types.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RawAdapterDesc {
+    pub description: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub dedicated_video_memory: u64,
+    pub dedicated_system_memory: u64,
+    pub shared_system_memory: u64,
+    pub luid: Luid,
+    // DXGI_ADAPTER_FLAG bits
+    pub flags: u32,
+}
+
+impl RawAdapterDesc {
+    pub fn is_software(&self) -> bool {
+        self.flags & ADAPTER_FLAG_SOFTWARE != 0
+            // Microsoft Basic Render Driver, WARP
+            || (self.vendor_id == 0x1414 && self.device_id == 0x8c)
+    }
+}
+
+impl AdapterDesc {
+    /// `index` and `is_default` depend on the enumeration order, DXGI lists the adapter
+    /// of the primary output first.
+    ///
+    /// ```
+    /// use dxgi::{AdapterDesc, RawAdapterDesc};
+    ///
+    /// // vendor, device, flags => software, discrete, integrated
+    /// let cases = [
+    ///     (0x10de, 0x2503, 0, false, true, false),
+    ///     (0x1002, 0x73ff, 0, false, true, false),
+    ///     (0x1002, 0x15bf, 0, false, false, true),
+    ///     (0x8086, 0x3e92, 0, false, false, true),
+    ///     (0x8086, 0xe20b, 0, false, true, false),
+    ///     (0x5143, 0x0636, 0, false, false, true),
+    ///     // Basic Render Driver, with and without the software flag
+    ///     (0x1414, 0x008c, 2, true, false, false),
+    ///     (0x1414, 0x008c, 0, true, false, false),
+    ///     // a software flag wins over the device tables
+    ///     (0x10de, 0x2503, 2, true, false, false),
+    ///     // Hyper-V video and VMware SVGA are hardware of unknown kind
+    ///     (0x1414, 0x5353, 0, false, false, false),
+    ///     (0x15ad, 0x0405, 0, false, false, false),
+    /// ];
+    /// for (vendor_id, device_id, flags, software, discrete, integrated) in cases {
+    ///     let raw = RawAdapterDesc {
+    ///         vendor_id,
+    ///         device_id,
+    ///         flags,
+    ///         dedicated_video_memory: 8 << 30,
+    ///         ..Default::default()
+    ///     };
+    ///     let adapter = AdapterDesc::from_raw(&raw, 1, false);
+    ///     let case = format!("{:04x}:{:04x} flags {}", vendor_id, device_id, flags);
+    ///     assert_eq!(adapter.is_software, software, "{}", case);
+    ///     assert_eq!(adapter.is_hardware, !software, "{}", case);
+    ///     assert_eq!(adapter.is_discrete, discrete, "{}", case);
+    ///     assert_eq!(adapter.is_integrated, integrated, "{}", case);
+    ///     assert_eq!(adapter.dedicated_video_memory, 8 << 30);
+    /// }
+    /// ```
+    pub fn from_raw(raw: &RawAdapterDesc, index: u32, is_default: bool) -> Self {
+        let is_software = raw.is_software();
+        let kind = if is_software {
+            None
+        } else {
+            AdapterKind::from_ids(raw.vendor_id, raw.device_id)
+        };
+        AdapterDesc {
+            index,
+            description: raw.description.clone(),
+            luid: raw.luid,
+            device_id: raw.device_id,
+            vendor_id: raw.vendor_id,
+            is_default,
+            is_software,
+            is_hardware: !is_software,
+            is_discrete: kind == Some(AdapterKind::Discrete),
+            is_integrated: kind == Some(AdapterKind::Integrated),
+            dedicated_video_memory: raw.dedicated_video_memory,
+            dedicated_system_memory: raw.dedicated_system_memory,
+            shared_system_memory: raw.shared_system_memory,
+        }
+    }
+
+    pub fn vendor(&self) -> Vendor {
+        Vendor::from_id(self.vendor_id)
+    }
+}
+
+/// Adapter selection policy.
+///
+/// `vendor`, `description` and `allow_software` decide which adapters are
+/// candidates at all. Among those the adapter with `preferred_luid` comes first, then
+/// adapters of `preferred_kind`, then hardware before software, then enumeration order.
+/// The default picks the first hardware adapter.
+///
+/// ```
+/// use dxgi::{AdapterDesc, AdapterKind, AdapterSelector, Luid, Vendor};
+///
+/// let adapter = |index: u32, description: &str, vendor_id, discrete: bool, software: bool| AdapterDesc {
+///     index,
+///     description: description.to_string(),
+///     luid: Luid(index as i64 + 1),
+///     vendor_id,
+///     is_discrete: discrete,
+///     is_integrated: !discrete && !software,
+///     is_software: software,
+///     is_hardware: !software,
+///     ..Default::default()
+/// };
+/// let adapters = [
+///     adapter(0, "Intel(R) UHD Graphics 630", 0x8086, false, false),
+///     adapter(1, "NVIDIA GeForce RTX 3060 Laptop GPU", 0x10de, true, false),
+///     adapter(2, "Microsoft Basic Render Driver", 0x1414, false, true),
+/// ];
+/// let pick = |selector: &AdapterSelector| selector.select(&adapters).map(|a| a.index);
+///
+/// assert_eq!(pick(&AdapterSelector::default()), Some(0));
+/// let discrete = AdapterSelector {
+///     preferred_kind: Some(AdapterKind::Discrete),
+///     ..Default::default()
+/// };
+/// assert_eq!(pick(&discrete), Some(1));
+/// // owning the output beats the kind preference
+/// let owner = AdapterSelector {
+///     preferred_luid: Some(Luid(1)),
+///     ..discrete.clone()
+/// };
+/// assert_eq!(pick(&owner), Some(0));
+/// let nvidia = AdapterSelector {
+///     vendor: Some(Vendor::Nvidia),
+///     ..Default::default()
+/// };
+/// assert_eq!(pick(&nvidia), Some(1));
+/// let rtx = AdapterSelector::default().with_description("(?i)rtx").unwrap();
+/// assert_eq!(pick(&rtx), Some(1));
+///
+/// // WARP is only a candidate when allowed
+/// let warp = AdapterSelector::default().with_description("Basic Render").unwrap();
+/// assert_eq!(pick(&warp), None);
+/// let warp = AdapterSelector {
+///     allow_software: true,
+///     ..warp
+/// };
+/// assert_eq!(pick(&warp), Some(2));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AdapterSelector {
+    // e.g. the adapter_luid of the output to capture
+    pub preferred_luid: Option<Luid>,
+    pub preferred_kind: Option<AdapterKind>,
+    pub vendor: Option<Vendor>,
+    // matched against the adapter description
+    pub description: Option<Regex>,
+    // also consider software adapters such as WARP
+    pub allow_software: bool,
+}
+
+impl AdapterSelector {
+    /// Prefers the adapter `output` is attached to.
+    pub fn for_output(output: &OutputDesc) -> Self {
+        Self {
+            preferred_luid: Some(output.adapter_luid),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_description(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.description = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    pub fn accepts(&self, adapter: &AdapterDesc) -> bool {
+        (self.allow_software || !adapter.is_software)
+            && self.vendor.is_none_or(|v| adapter.vendor() == v)
+            && self
+                .description
+                .as_ref()
+                .is_none_or(|re| re.is_match(&adapter.description))
+    }
+
+    /// The accepted adapters, best first.
+    pub fn rank<'a>(&self, adapters: &'a [AdapterDesc]) -> Vec<&'a AdapterDesc> {
+        let mut candidates: Vec<_> = adapters.iter().filter(|a| self.accepts(a)).collect();
+        // stable, so ties keep the enumeration order
+        candidates.sort_by_key(|a| {
+            (
+                self.preferred_luid != Some(a.luid),
+                self.preferred_kind.is_some_and(|k| !k.matches(a)),
+                a.is_software,
+            )
+        });
+        candidates
+    }
+
+    pub fn select<'a>(&self, adapters: &'a [AdapterDesc]) -> Option<&'a AdapterDesc> {
+        self.rank(adapters).into_iter().next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(index: u32, description: &str) -> AdapterDesc {
+        AdapterDesc {
+            index,
+            description: description.to_string(),
+            is_hardware: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn description_pattern() {
+        let adapters = [
+            adapter(0, "Intel(R) UHD Graphics 630"),
+            adapter(1, "NVIDIA GeForce GTX 1650"),
+            adapter(2, "NVIDIA GeForce RTX 3060 Laptop GPU"),
+            adapter(3, "nvidia rtx a2000"),
+            adapter(4, "Quadro RTX 4000 by NVIDIA"),
+        ];
+        let selector = AdapterSelector::default()
+            .with_description("(?i)^nvidia.*rtx")
+            .unwrap();
+        let ranked: Vec<_> = selector.rank(&adapters).iter().map(|a| a.index).collect();
+        assert_eq!(ranked, [2, 3]);
+        // case-sensitive without the flag
+        let selector = AdapterSelector::default()
+            .with_description("^nvidia")
+            .unwrap();
+        assert_eq!(selector.select(&adapters).map(|a| a.index), Some(3));
+        assert!(AdapterSelector::default().with_description("(rtx").is_err());
+    }
+}
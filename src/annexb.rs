@@ -0,0 +1,667 @@
+use std::io;
+
+use crate::codec::Codec;
+
+pub const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalType {
+    Vps,
+    Sps,
+    Pps,
+    // H.264 IDR, or any H.265 IRAP picture (IDR/CRA/BLA)
+    Idr,
+    Slice,
+    Sei,
+    Aud,
+    Other(u8),
+}
+
+impl NalType {
+    pub fn parse(codec: Codec, nal: &[u8]) -> Option<NalType> {
+        let header = *nal.first()?;
+        let nal_type = match codec {
+            Codec::H264 => match header & 0x1f {
+                1..=4 => NalType::Slice,
+                5 => NalType::Idr,
+                6 => NalType::Sei,
+                7 => NalType::Sps,
+                8 => NalType::Pps,
+                9 => NalType::Aud,
+                t => NalType::Other(t),
+            },
+            Codec::H265 => {
+                if nal.len() < 2 {
+                    return None;
+                }
+                match (header >> 1) & 0x3f {
+                    0..=9 => NalType::Slice,
+                    16..=21 => NalType::Idr,
+                    32 => NalType::Vps,
+                    33 => NalType::Sps,
+                    34 => NalType::Pps,
+                    35 => NalType::Aud,
+                    39 | 40 => NalType::Sei,
+                    t => NalType::Other(t),
+                }
+            }
+            _ => return None,
+        };
+        Some(nal_type)
+    }
+
+    pub fn is_parameter_set(&self) -> bool {
+        matches!(self, NalType::Vps | NalType::Sps | NalType::Pps)
+    }
+
+    pub fn is_vcl(&self) -> bool {
+        matches!(self, NalType::Idr | NalType::Slice)
+    }
+}
+
+/// Iterator over the NAL units of an Annex-B byte stream, without start codes.
+pub struct NalIter<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for NalIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (start, _) = find_start_code(self.data, self.pos)?;
+            let end = match find_start_code(self.data, start) {
+                Some((_, next)) => next,
+                None => self.data.len(),
+            };
+            self.pos = end;
+            let nal = trim_trailing_zeros(&self.data[start..end]);
+            if !nal.is_empty() {
+                return Some(nal);
+            }
+        }
+    }
+}
+
+/// Splits an Annex-B stream into NAL units. Bytes before the first start code are ignored.
+pub fn split(data: &[u8]) -> NalIter<'_> {
+    NalIter { data, pos: 0 }
+}
+
+// Returns (payload start, start code position) of the first start code at or after `from`.
+fn find_start_code(data: &[u8], from: usize) -> Option<(usize, usize)> {
+    let mut i = from;
+    while i + 3 <= data.len() {
+        if data[i + 2] > 1 {
+            i += 3;
+        } else if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            let begin = if i > from && data[i - 1] == 0 {
+                i - 1
+            } else {
+                i
+            };
+            return Some((i + 3, begin));
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let end = nal.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1);
+    &nal[..end]
+}
+
+pub fn is_keyframe(codec: Codec, data: &[u8]) -> bool {
+    split(data).any(|nal| NalType::parse(codec, nal) == Some(NalType::Idr))
+}
+
+/// Converts an Annex-B stream into length-prefixed (AVCC/HVCC) form.
+/// `length_size` is the size of the big-endian length field, 1 to 4 bytes.
+pub fn annexb_to_length_prefixed(data: &[u8], length_size: usize) -> io::Result<Vec<u8>> {
+    check_length_size(length_size)?;
+    let mut out = Vec::with_capacity(data.len() + 16);
+    for nal in split(data) {
+        if length_size < 4 && nal.len() >> (length_size * 8) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "nal of {} bytes does not fit {} byte length",
+                    nal.len(),
+                    length_size
+                ),
+            ));
+        }
+        let len = (nal.len() as u32).to_be_bytes();
+        out.extend_from_slice(&len[4 - length_size..]);
+        out.extend_from_slice(nal);
+    }
+    Ok(out)
+}
+
+/// Converts a length-prefixed (AVCC/HVCC) buffer back into an Annex-B stream.
+pub fn length_prefixed_to_annexb(data: &[u8], length_size: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() + 16);
+    for nal in LengthPrefixedIter::new(data, length_size)? {
+        out.extend_from_slice(&START_CODE);
+        out.extend_from_slice(nal?);
+    }
+    Ok(out)
+}
+
+/// Iterator over the NAL units of a length-prefixed buffer.
+pub struct LengthPrefixedIter<'a> {
+    data: &'a [u8],
+    length_size: usize,
+}
+
+impl<'a> LengthPrefixedIter<'a> {
+    pub fn new(data: &'a [u8], length_size: usize) -> io::Result<Self> {
+        check_length_size(length_size)?;
+        Ok(Self { data, length_size })
+    }
+}
+
+impl<'a> Iterator for LengthPrefixedIter<'a> {
+    type Item = io::Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        if self.data.len() < self.length_size {
+            self.data = &[];
+            return Some(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let (len, rest) = self.data.split_at(self.length_size);
+        let len = len.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        if rest.len() < len {
+            self.data = &[];
+            return Some(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+        let (nal, rest) = rest.split_at(len);
+        self.data = rest;
+        Some(Ok(nal))
+    }
+}
+
+fn check_length_size(length_size: usize) -> io::Result<()> {
+    if !(1..=4).contains(&length_size) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid nal length size {}", length_size),
+        ));
+    }
+    Ok(())
+}
+
+/// Removes emulation prevention bytes (00 00 03 -> 00 00), giving the raw RBSP.
+pub fn to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// Keeps the most recent VPS/SPS/PPS seen in a stream so late-joining
+/// clients can be primed before the next keyframe.
+#[derive(Debug, Clone)]
+pub struct ParameterSetCache {
+    codec: Codec,
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl ParameterSetCache {
+    pub fn new(codec: Codec) -> Self {
+        Self {
+            codec,
+            vps: None,
+            sps: None,
+            pps: None,
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Records a single NAL unit, returns true if it was a parameter set.
+    pub fn insert(&mut self, nal: &[u8]) -> bool {
+        let slot = match NalType::parse(self.codec, nal) {
+            Some(NalType::Vps) => &mut self.vps,
+            Some(NalType::Sps) => &mut self.sps,
+            Some(NalType::Pps) => &mut self.pps,
+            _ => return false,
+        };
+        if slot.as_deref() != Some(nal) {
+            log::debug!("{} parameter set updated: {} bytes", self.codec, nal.len());
+            *slot = Some(nal.to_vec());
+        }
+        true
+    }
+
+    /// Records every parameter set found in an Annex-B buffer.
+    pub fn update(&mut self, data: &[u8]) {
+        for nal in split(data) {
+            self.insert(nal);
+        }
+    }
+
+    pub fn vps(&self) -> Option<&[u8]> {
+        self.vps.as_deref()
+    }
+
+    pub fn sps(&self) -> Option<&[u8]> {
+        self.sps.as_deref()
+    }
+
+    pub fn pps(&self) -> Option<&[u8]> {
+        self.pps.as_deref()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        let vps = self.codec != Codec::H265 || self.vps.is_some();
+        vps && self.sps.is_some() && self.pps.is_some()
+    }
+
+    /// The cached parameter sets as an Annex-B stream, in VPS, SPS, PPS order.
+    pub fn to_annexb(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for nal in [&self.vps, &self.sps, &self.pps].into_iter().flatten() {
+            out.extend_from_slice(&START_CODE);
+            out.extend_from_slice(nal);
+        }
+        out
+    }
+
+    /// The `AVCDecoderConfigurationRecord` / `HEVCDecoderConfigurationRecord` for the
+    /// cached parameter sets, with 4 byte NAL lengths. Used by the MP4 and Matroska muxers.
+    pub fn decoder_config(&self) -> Option<Vec<u8>> {
+        match self.codec {
+            Codec::H264 => self.avc_decoder_config(),
+            Codec::H265 => self.hevc_decoder_config(),
+            _ => None,
+        }
+    }
+
+    fn avc_decoder_config(&self) -> Option<Vec<u8>> {
+        let sps = self.sps()?;
+        let pps = self.pps()?;
+        if sps.len() < 4 {
+            return None;
+        }
+        let mut out = vec![1];
+        out.extend_from_slice(&sps[1..4]);
+        out.push(0xff); // 4 byte lengths
+        out.push(0xe1);
+        out.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        out.extend_from_slice(sps);
+        out.push(1);
+        out.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        out.extend_from_slice(pps);
+        Some(out)
+    }
+
+    fn hevc_decoder_config(&self) -> Option<Vec<u8>> {
+        let vps = self.vps()?;
+        let sps = self.sps()?;
+        let pps = self.pps()?;
+        let info = HevcSps::parse(sps)?;
+        let mut out = vec![1];
+        out.extend_from_slice(&info.profile_tier_level);
+        out.extend_from_slice(&0xf000u16.to_be_bytes()); // min_spatial_segmentation_idc
+        out.push(0xfc); // parallelismType
+        out.push(0xfc | info.chroma_format_idc as u8);
+        out.push(0xf8 | (info.bit_depth_luma - 8) as u8);
+        out.push(0xf8 | (info.bit_depth_chroma - 8) as u8);
+        out.extend_from_slice(&[0, 0]); // avgFrameRate
+        out.push((info.max_sub_layers << 3) | ((info.temporal_id_nested as u8) << 2) | 3);
+        out.push(3);
+        for (nal_type, nal) in [(32u8, vps), (33, sps), (34, pps)] {
+            out.push(0x80 | nal_type);
+            out.extend_from_slice(&1u16.to_be_bytes());
+            out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            out.extend_from_slice(nal);
+        }
+        Some(out)
+    }
+
+    /// The RFC 6381 codec parameter for the cached SPS, e.g. `avc1.64001f`, as needed by
+    /// browsers' Media Source Extensions.
+    pub fn codec_string(&self) -> Option<String> {
+        let sps = self.sps()?;
+        match self.codec {
+            Codec::H264 => {
+                let profile = sps.get(1..4)?;
+                Some(format!(
+                    "avc1.{:02x}{:02x}{:02x}",
+                    profile[0], profile[1], profile[2]
+                ))
+            }
+            Codec::H265 => {
+                let ptl = HevcSps::parse(sps)?.profile_tier_level;
+                let space = ["", "A", "B", "C"][(ptl[0] >> 6) as usize];
+                let tier = if ptl[0] & 0x20 != 0 { 'H' } else { 'L' };
+                // compatibility flags are written in reverse bit order
+                let compatibility =
+                    u32::from_be_bytes(ptl[1..5].try_into().unwrap()).reverse_bits();
+                let mut s = format!(
+                    "hvc1.{}{}.{:x}.{}{}",
+                    space,
+                    ptl[0] & 0x1f,
+                    compatibility,
+                    tier,
+                    ptl[11]
+                );
+                // constraint flags, trailing zero bytes omitted
+                let constraints = &ptl[5..11];
+                let len = constraints
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .map_or(0, |p| p + 1);
+                for b in &constraints[..len] {
+                    s.push_str(&format!(".{:x}", b));
+                }
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+
+    /// Prefixes a keyframe with the cached parameter sets when it does not carry its own.
+    pub fn prepare_keyframe(&self, data: &[u8]) -> Vec<u8> {
+        let has_sps = split(data).any(|nal| NalType::parse(self.codec, nal) == Some(NalType::Sps));
+        if has_sps || !is_keyframe(self.codec, data) {
+            return data.to_vec();
+        }
+        let mut out = self.to_annexb();
+        out.extend_from_slice(data);
+        out
+    }
+}
+
+// The H.265 SPS fields needed for an hvcC record.
+struct HevcSps {
+    profile_tier_level: [u8; 12],
+    max_sub_layers: u8,
+    temporal_id_nested: bool,
+    chroma_format_idc: u32,
+    bit_depth_luma: u32,
+    bit_depth_chroma: u32,
+}
+
+impl HevcSps {
+    fn parse(sps: &[u8]) -> Option<HevcSps> {
+        let rbsp = to_rbsp(sps.get(2..)?);
+        let mut profile_tier_level = [0; 12];
+        profile_tier_level.copy_from_slice(rbsp.get(1..13)?);
+
+        let mut r = BitReader::new(&rbsp);
+        r.skip(4)?; // sps_video_parameter_set_id
+        let max_sub_layers_minus1 = r.read_bits(3)? as usize;
+        let temporal_id_nested = r.read_bit()? == 1;
+        r.skip(96)?; // general profile_tier_level
+        let mut sub_layer_flags = Vec::with_capacity(max_sub_layers_minus1);
+        for _ in 0..max_sub_layers_minus1 {
+            sub_layer_flags.push((r.read_bit()?, r.read_bit()?));
+        }
+        if max_sub_layers_minus1 > 0 {
+            r.skip((8 - max_sub_layers_minus1) * 2)?;
+        }
+        for (profile_present, level_present) in sub_layer_flags {
+            if profile_present == 1 {
+                r.skip(88)?;
+            }
+            if level_present == 1 {
+                r.skip(8)?;
+            }
+        }
+        r.read_ue()?; // sps_seq_parameter_set_id
+        let chroma_format_idc = r.read_ue()?;
+        if chroma_format_idc > 3 {
+            return None;
+        }
+        if chroma_format_idc == 3 {
+            r.skip(1)?;
+        }
+        r.read_ue()?; // pic_width_in_luma_samples
+        r.read_ue()?; // pic_height_in_luma_samples
+        if r.read_bit()? == 1 {
+            for _ in 0..4 {
+                r.read_ue()?; // conformance window offsets
+            }
+        }
+        // hvcC has 3 bits for each bit depth minus 8
+        let bit_depth_luma = r.read_ue()?.checked_add(8).filter(|d| *d < 16)?;
+        let bit_depth_chroma = r.read_ue()?.checked_add(8).filter(|d| *d < 16)?;
+        Some(HevcSps {
+            profile_tier_level,
+            max_sub_layers: max_sub_layers_minus1 as u8 + 1,
+            temporal_id_nested,
+            chroma_format_idc,
+            bit_depth_luma,
+            bit_depth_chroma,
+        })
+    }
+}
+
+/// MSB-first bit reader over an RBSP, with Exp-Golomb support.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn read_bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    pub(crate) fn read_bits(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Some(value)
+    }
+
+    pub(crate) fn skip(&mut self, n: usize) -> Option<()> {
+        if self.pos + n > self.data.len() * 8 {
+            return None;
+        }
+        self.pos += n;
+        Some(())
+    }
+
+    pub(crate) fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.read_bits(zeros)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MSB-first bit writer for building parameter sets
+    #[derive(Default)]
+    struct Bits {
+        bytes: Vec<u8>,
+        len: usize,
+    }
+
+    impl Bits {
+        fn put(&mut self, value: u64, n: usize) -> &mut Self {
+            for i in (0..n).rev() {
+                if self.len / 8 == self.bytes.len() {
+                    self.bytes.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                *self.bytes.last_mut().unwrap() |= bit << (7 - self.len % 8);
+                self.len += 1;
+            }
+            self
+        }
+
+        fn ue(&mut self, value: u64) -> &mut Self {
+            let bits = 64 - (value + 1).leading_zeros() as usize;
+            self.put(0, bits - 1).put(value + 1, bits)
+        }
+
+        // rbsp trailing bits and emulation prevention, behind an H.265 SPS header
+        fn sps(&mut self) -> Vec<u8> {
+            self.put(1, 1);
+            let mut nal = vec![0x42, 0x01];
+            let mut zeros = 0;
+            for &b in &self.bytes {
+                if zeros >= 2 && b <= 3 {
+                    nal.push(3);
+                    zeros = 0;
+                }
+                zeros = if b == 0 { zeros + 1 } else { 0 };
+                nal.push(b);
+            }
+            nal
+        }
+    }
+
+    // Main profile, level 3.1, 4:2:0
+    fn hevc_sps(bit_depth_luma_minus8: u64) -> Vec<u8> {
+        let mut bits = Bits::default();
+        bits.put(0, 4).put(0, 3).put(1, 1); // vps id, max_sub_layers_minus1, nested
+        bits.put(0, 2).put(0, 1).put(1, 5); // profile space, tier, profile
+        bits.put(0x6000_0000, 32).put(0x90 << 40, 48).put(93, 8);
+        bits.ue(0).ue(1).ue(1280).ue(720).put(0, 1);
+        bits.ue(bit_depth_luma_minus8).ue(0);
+        bits.sps()
+    }
+
+    #[test]
+    fn split_start_codes() {
+        let data = [
+            0xff, 0xee, // before the first start code
+            0, 0, 0, 1, 0x67, 1, 2, //
+            0, 0, 1, 0x68, 3, //
+            0, 0, 0, 1, 0, 0, 1, // empty
+            0x65, 0, 0, 3, 0, 4, 0, 0, // trailing zeros
+        ];
+        let nals: Vec<_> = split(&data).collect();
+        assert_eq!(
+            nals,
+            [&[0x67, 1, 2][..], &[0x68, 3], &[0x65, 0, 0, 3, 0, 4]]
+        );
+        assert_eq!(split(&[1, 2, 3]).count(), 0);
+        assert_eq!(split(&[0, 0, 1]).count(), 0);
+        assert!(is_keyframe(Codec::H264, &data));
+        assert!(!is_keyframe(Codec::H264, &data[..14]));
+    }
+
+    #[test]
+    fn length_prefixed_round_trip() {
+        let annexb = [0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4];
+        let avcc = annexb_to_length_prefixed(&annexb, 4).unwrap();
+        assert_eq!(
+            avcc,
+            [0, 0, 0, 3, 0x67, 1, 2, 0, 0, 0, 2, 0x68, 3, 0, 0, 0, 2, 0x65, 4]
+        );
+        assert_eq!(length_prefixed_to_annexb(&avcc, 4).unwrap(), {
+            let mut four_byte = annexb.to_vec();
+            four_byte.insert(7, 0);
+            four_byte
+        });
+        let short = annexb_to_length_prefixed(&annexb, 2).unwrap();
+        assert_eq!(&short[..5], [0, 3, 0x67, 1, 2]);
+        assert_eq!(
+            length_prefixed_to_annexb(&short, 2).unwrap(),
+            length_prefixed_to_annexb(&avcc, 4).unwrap()
+        );
+
+        let mut big = START_CODE.to_vec();
+        big.resize(4 + 256, 0x41);
+        assert!(annexb_to_length_prefixed(&big, 1).is_err());
+        assert_eq!(annexb_to_length_prefixed(&big, 2).unwrap()[..2], [1, 0]);
+        assert!(annexb_to_length_prefixed(&annexb, 0).is_err());
+        assert!(annexb_to_length_prefixed(&annexb, 5).is_err());
+
+        // a length running past the end
+        let nals: Vec<_> = LengthPrefixedIter::new(&avcc[..avcc.len() - 1], 4)
+            .unwrap()
+            .collect();
+        assert_eq!(nals.len(), 3);
+        assert!(nals[2].is_err());
+        assert!(length_prefixed_to_annexb(&[0, 0, 1], 4).is_err());
+    }
+
+    #[test]
+    fn avc_decoder_config() {
+        let mut cache = ParameterSetCache::new(Codec::H264);
+        cache.update(&[0, 0, 0, 1, 0x67, 0x64, 0, 0x1f, 0xac, 0, 0, 0, 1, 0x68, 0xee, 0x3c]);
+        assert!(cache.is_complete());
+        assert_eq!(
+            cache.decoder_config().unwrap(),
+            [
+                1, 0x64, 0, 0x1f, 0xff, 0xe1, 0, 5, 0x67, 0x64, 0, 0x1f, 0xac, 1, 0, 3, 0x68,
+                0xee, 0x3c
+            ]
+        );
+        assert_eq!(cache.codec_string().unwrap(), "avc1.64001f");
+    }
+
+    #[test]
+    fn hevc_decoder_config() {
+        let sps = hevc_sps(2);
+        let mut cache = ParameterSetCache::new(Codec::H265);
+        for nal in [&[0x40, 0x01, 0x0c][..], &sps, &[0x44, 0x01, 0xc1]] {
+            assert!(cache.insert(nal));
+        }
+        let hvcc = cache.decoder_config().unwrap();
+        assert_eq!(hvcc[0], 1);
+        assert_eq!(hvcc[1..13], [1, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93]);
+        assert_eq!(hvcc[16], 0xfc | 1); // 4:2:0
+        assert_eq!(hvcc[17], 0xf8 | 2); // 10 bit luma
+        assert_eq!(hvcc[18], 0xf8);
+        assert_eq!(hvcc[21], 1 << 3 | 1 << 2 | 3); // one layer, nested, 4 byte lengths
+        assert_eq!(hvcc[22], 3);
+        // the VPS array
+        assert_eq!(hvcc[23..31], [0x80 | 32, 0, 1, 0, 3, 0x40, 0x01, 0x0c]);
+        assert_eq!(cache.codec_string().unwrap(), "hvc1.1.6.L93.90");
+    }
+
+    #[test]
+    fn malformed_hevc_sps() {
+        let mut cache = ParameterSetCache::new(Codec::H265);
+        cache.insert(&[0x40, 0x01, 0x0c]);
+        cache.insert(&[0x44, 0x01, 0xc1]);
+        // bit depth of 2^32 - 2, overflows when adding 8
+        cache.insert(&hevc_sps(u32::MAX as u64 - 1));
+        assert!(cache.decoder_config().is_none());
+        cache.insert(&hevc_sps(9));
+        assert!(cache.decoder_config().is_none());
+        cache.insert(&hevc_sps(0)[..12]);
+        assert!(cache.decoder_config().is_none());
+        assert!(cache.codec_string().is_none());
+    }
+}
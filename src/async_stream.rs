@@ -0,0 +1,277 @@
+//! Captured frames as a `futures_core::Stream`, for async services.
+//!
+//! Capturing blocks, so it stays on a `CaptureWorker` thread. The stream only pops the
+//! worker's queue, and the worker wakes the task when it queues a frame. Nothing here
+//! depends on a particular runtime.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+
+use crate::backend::CaptureBackend;
+use crate::frame::Frame;
+use crate::worker::{CaptureWorker, WorkerConfig, WorkerStats};
+
+/// A stream of the frames a `CaptureWorker` captures.
+///
+/// The stream ends when the worker does, after a capture error for example, which
+/// `stats` then reports. Dropping the stream stops the worker without blocking, its
+/// thread ends after the capture in progress.
+///
+/// ```
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+/// use futures_core::Stream;
+/// use dxgi::async_stream::AsyncFrames;
+/// use dxgi::frame::Frame;
+/// use dxgi::synthetic::SyntheticBackend;
+/// use dxgi::worker::WorkerConfig;
+///
+/// async fn take(frames: &mut AsyncFrames, n: usize) -> Vec<Frame> {
+///     let mut taken = Vec::new();
+///     while taken.len() < n {
+///         match poll_fn(|cx| Pin::new(&mut *frames).poll_next(cx)).await {
+///             Some(frame) => taken.push(frame),
+///             None => break,
+///         }
+///     }
+///     taken
+/// }
+/// let start = || {
+///     AsyncFrames::start(WorkerConfig::default(), || {
+///         Ok(SyntheticBackend::new(64, 48, 100))
+///     })
+///     .unwrap()
+/// };
+///
+/// let runtime = tokio::runtime::Builder::new_current_thread()
+///     .build()
+///     .unwrap();
+/// let frames = runtime.block_on(async { take(&mut start(), 3).await });
+/// assert_eq!(frames.len(), 3);
+/// assert!(frames.iter().all(|f| (f.width, f.height) == (64, 48)));
+///
+/// let frames = smol::block_on(async { take(&mut start(), 3).await });
+/// assert_eq!(frames.len(), 3);
+///
+/// // the stream ends with the frames still queued once the worker is gone
+/// let mut frames = start();
+/// frames.worker().pause();
+/// let stats = smol::block_on(async {
+///     let stats = frames.stop_worker();
+///     let queued = stats.captured - stats.dropped;
+///     assert_eq!(take(&mut frames, 100).await.len() as u64, queued);
+///     assert!(take(&mut frames, 1).await.is_empty());
+///     stats
+/// });
+/// assert!(stats.error.is_none());
+/// ```
+pub struct AsyncFrames {
+    worker: CaptureWorker,
+}
+
+impl AsyncFrames {
+    /// Starts a `CaptureWorker` on the backend `open` creates and streams its frames.
+    pub fn start<B, F>(config: WorkerConfig, open: F) -> std::io::Result<Self>
+    where
+        B: CaptureBackend,
+        F: FnOnce() -> anyhow::Result<B> + Send + 'static,
+    {
+        CaptureWorker::start(config, open).map(Self::new)
+    }
+
+    pub fn new(worker: CaptureWorker) -> Self {
+        Self { worker }
+    }
+
+    /// For `pause`, `resume` and the stats.
+    pub fn worker(&self) -> &CaptureWorker {
+        &self.worker
+    }
+
+    pub fn stats(&self) -> WorkerStats {
+        self.worker.stats()
+    }
+
+    /// Stops capturing. Frames already queued are still streamed, then the stream ends.
+    ///
+    /// Waits for the worker thread so the stats are final, which blocks for up to
+    /// `WorkerConfig::timeout`. Dropping the stream does not wait.
+    pub fn stop_worker(&mut self) -> WorkerStats {
+        self.worker.shutdown();
+        self.worker.stats()
+    }
+}
+
+impl From<CaptureWorker> for AsyncFrames {
+    fn from(worker: CaptureWorker) -> Self {
+        Self::new(worker)
+    }
+}
+
+impl Drop for AsyncFrames {
+    fn drop(&mut self) {
+        // an executor thread must not wait for a capture
+        self.worker.detach();
+    }
+}
+
+impl Stream for AsyncFrames {
+    type Item = Frame;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame>> {
+        self.worker.queue().poll_pop(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::synthetic::SyntheticBackend;
+
+    // the synthetic desktop, paused while `paused` is set
+    struct Switched {
+        inner: SyntheticBackend,
+        paused: Arc<AtomicBool>,
+        // set once the worker thread drops the backend
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl CaptureBackend for Switched {
+        fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+            self.inner.set_paused(self.paused.load(Ordering::SeqCst));
+            self.inner.capture(timeout, skip)
+        }
+
+        fn width(&self) -> u32 {
+            self.inner.width()
+        }
+
+        fn height(&self) -> u32 {
+            self.inner.height()
+        }
+    }
+
+    impl Drop for Switched {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn switched(
+        config: WorkerConfig,
+        paused: bool,
+    ) -> (AsyncFrames, Arc<AtomicBool>, Arc<AtomicBool>) {
+        let paused = Arc::new(AtomicBool::new(paused));
+        let dropped = Arc::new(AtomicBool::new(false));
+        let backend = Switched {
+            inner: SyntheticBackend::new(64, 48, 100),
+            paused: paused.clone(),
+            dropped: dropped.clone(),
+        };
+        let frames = AsyncFrames::start(config, move || Ok(backend)).unwrap();
+        (frames, paused, dropped)
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(start.elapsed() < Duration::from_secs(5), "{}", what);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn wakes_once_per_registration() {
+        let (mut frames, paused, _) = switched(WorkerConfig::default(), true);
+        let wakes = Arc::new(CountingWaker::default());
+        let waker = wakes.clone().into();
+        let mut cx = Context::from_waker(&waker);
+        let woken = || wakes.0.load(Ordering::SeqCst);
+
+        assert!(Pin::new(&mut frames).poll_next(&mut cx).is_pending());
+        // paused, nothing is queued and nobody wakes the task
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(woken(), 0);
+        assert!(Pin::new(&mut frames).poll_next(&mut cx).is_pending());
+        assert_eq!(frames.stats().captured, 0);
+
+        paused.store(false, Ordering::SeqCst);
+        wait_until("not woken by the first frame", || woken() == 1);
+        let (mut pending, mut received) = (1, 0);
+        while received < 5 {
+            match Pin::new(&mut frames).poll_next(&mut cx) {
+                Poll::Ready(Some(frame)) => {
+                    assert_eq!((frame.width, frame.height), (64, 48));
+                    received += 1;
+                }
+                Poll::Ready(None) => panic!("stream ended"),
+                Poll::Pending => {
+                    pending += 1;
+                    wait_until("not woken by the next frame", || woken() == pending);
+                    // the frames queued after the first do not wake again
+                    std::thread::sleep(Duration::from_millis(30));
+                    assert_eq!(woken(), pending);
+                }
+            }
+        }
+        assert!(pending > 1);
+    }
+
+    #[test]
+    fn drop_does_not_wait_for_the_capture() {
+        let config = WorkerConfig {
+            timeout: 1000,
+            ..Default::default()
+        };
+        let (frames, _, dropped) = switched(config, true);
+        // the worker is now in a capture that times out after a second
+        std::thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        drop(frames);
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!dropped.load(Ordering::SeqCst));
+        // the worker ends after that capture, and with it the backend
+        wait_until("worker still running", || dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn multi_thread_runtime() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        let (mut frames, _, dropped) = switched(WorkerConfig::default(), false);
+        let task = runtime.spawn(async move {
+            let mut received = Vec::new();
+            while received.len() < 5 {
+                match poll_fn(|cx| Pin::new(&mut frames).poll_next(cx)).await {
+                    Some(frame) => received.push(frame.present_time),
+                    None => break,
+                }
+            }
+            received
+        });
+        let received = runtime.block_on(task).unwrap();
+        assert_eq!(received.len(), 5);
+        assert!(received.windows(2).all(|w| w[0] < w[1]));
+        // the stream was dropped with the task
+        wait_until("worker still running", || dropped.load(Ordering::SeqCst));
+    }
+}
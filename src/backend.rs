@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::frame::Frame;
+#[cfg(windows)]
+use crate::frame::PixelFormat;
+
+/// `capture` timeout that waits until a frame is presented, `INFINITE` to DXGI.
+pub const INFINITE: u32 = u32::MAX;
+
+// longest a `Wait::Until` capture keeps waiting after the stop token is set, in ms
+const STOP_CHECK_INTERVAL: u32 = 10;
+
+/// Cancels `Wait::Until` captures. Clones share the state.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken(Arc<AtomicBool>);
+
+impl StopToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// How long `CaptureBackend::capture_wait` waits for a new frame.
+#[derive(Debug, Clone, Default)]
+pub enum Wait {
+    // never block, only take a frame that was already presented
+    Poll,
+    // up to this many milliseconds
+    Timeout(u32),
+    // until a frame is presented, however long that takes
+    #[default]
+    Blocking,
+    // like `Blocking`, but give up once the token is stopped
+    Until(StopToken),
+}
+
+/// A source of captured frames.
+///
+/// `capture` follows `CaptureDXGI::capture`: it waits up to `timeout` milliseconds for
+/// a new frame. When nothing new arrives it returns the previous frame again, or
+/// `Ok(None)` if `skip` is set or no frame has been captured yet. A timeout is never an
+/// error, not even before the first frame. A timeout of 0 never blocks and `INFINITE`
+/// waits until the desktop presents. An implementation must not return early without
+/// a new frame, so a caller can wait with one call rather than spinning.
+/// `capture_wait` builds the other waits on top of that, `Wait::Until` as a series of
+/// short timeouts.
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use dxgi::backend::{CaptureBackend, StopToken, Wait};
+/// use dxgi::synthetic::SyntheticBackend;
+///
+/// let mut backend = SyntheticBackend::new(64, 48, 20);
+/// let timed = |backend: &mut SyntheticBackend, wait: &Wait, skip| {
+///     let started = Instant::now();
+///     let frame = backend.capture_wait(wait, skip).unwrap();
+///     (frame.is_some(), started.elapsed())
+/// };
+/// let ms = Duration::from_millis;
+///
+/// // the first frame is presented right away, the next one 50 ms later
+/// assert!(timed(&mut backend, &Wait::Blocking, true).0);
+/// let (new, elapsed) = timed(&mut backend, &Wait::Poll, true);
+/// assert!(!new && elapsed < ms(20));
+/// // not skipping returns the previous frame instead of nothing
+/// assert!(timed(&mut backend, &Wait::Poll, false).0);
+/// let (new, elapsed) = timed(&mut backend, &Wait::Blocking, true);
+/// assert!(new && elapsed >= ms(20));
+///
+/// // a paused desktop times out after the full timeout
+/// backend.set_paused(true);
+/// let (new, elapsed) = timed(&mut backend, &Wait::Timeout(30), true);
+/// assert!(!new && elapsed >= ms(30));
+///
+/// // and only a stop token ends an unbounded wait
+/// let stop = StopToken::new();
+/// let stopper = stop.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(ms(40));
+///     stopper.stop();
+/// });
+/// let (new, elapsed) = timed(&mut backend, &Wait::Until(stop), true);
+/// assert!(!new && elapsed >= ms(40));
+/// ```
+pub trait CaptureBackend {
+    fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>>;
+
+    fn width(&self) -> u32;
+
+    fn height(&self) -> u32;
+
+    /// `capture` with an explicit wait. Once a `Wait::Until` token is stopped it returns
+    /// what `capture` returns on a timeout.
+    fn capture_wait(&mut self, wait: &Wait, skip: bool) -> anyhow::Result<Option<Frame>> {
+        match wait {
+            Wait::Poll => self.capture(0, skip),
+            Wait::Timeout(timeout) => self.capture(*timeout, skip),
+            Wait::Blocking => self.capture(INFINITE, skip),
+            Wait::Until(stop) => {
+                while !stop.is_stopped() {
+                    if let Some(frame) = self.capture(STOP_CHECK_INTERVAL, true)? {
+                        return Ok(Some(frame));
+                    }
+                }
+                self.capture(0, skip)
+            }
+        }
+    }
+}
+
+impl<B: CaptureBackend + ?Sized> CaptureBackend for Box<B> {
+    fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+        (**self).capture(timeout, skip)
+    }
+
+    fn capture_wait(&mut self, wait: &Wait, skip: bool) -> anyhow::Result<Option<Frame>> {
+        (**self).capture_wait(wait, skip)
+    }
+
+    fn width(&self) -> u32 {
+        (**self).width()
+    }
+
+    fn height(&self) -> u32 {
+        (**self).height()
+    }
+}
+
+#[cfg(windows)]
+impl CaptureBackend for crate::CaptureDXGI {
+    fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+        use windows::Win32::Graphics::Direct3D11::D3D11_TEXTURE2D_DESC;
+        use windows::Win32::Graphics::Dxgi::DXGI_ERROR_WAIT_TIMEOUT;
+
+        let mut frame = {
+            let other = match crate::CaptureDXGI::capture(self, timeout, skip) {
+                Ok(Some(other)) => other,
+                Ok(None) => return Ok(None),
+                // nothing presented yet, so there is no previous frame either
+                Err(e) if e.code() == DXGI_ERROR_WAIT_TIMEOUT => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let mut desc = D3D11_TEXTURE2D_DESC::default();
+            unsafe {
+                other
+                    .texture
+                    .mapping_buffer
+                    .as_ref()
+                    .unwrap()
+                    .GetDesc(&mut desc)
+            };
+            let mut frame = Frame::new(desc.Width, desc.Height, PixelFormat::Bgra8);
+            let row_len = frame.stride;
+            for y in 0..desc.Height {
+                let src = unsafe {
+                    std::slice::from_raw_parts(
+                        (other.ptr.pData as *const u8)
+                            .add(y as usize * other.ptr.RowPitch as usize),
+                        row_len,
+                    )
+                };
+                frame.row_mut(y).copy_from_slice(src);
+            }
+            frame
+        };
+        frame.present_time = self.present_time();
+        frame.dirty_rects = self.dirty_rects().to_vec();
+        frame.move_rects = self.move_rects().to_vec();
+        Ok(Some(frame))
+    }
+
+    fn width(&self) -> u32 {
+        crate::CaptureDXGI::width(self) as u32
+    }
+
+    fn height(&self) -> u32 {
+        crate::CaptureDXGI::height(self) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::synthetic::SyntheticBackend;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn stop_after(delay: Duration) -> StopToken {
+        let stop = StopToken::new();
+        let stopper = stop.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(delay);
+            stopper.stop();
+        });
+        stop
+    }
+
+    #[test]
+    fn timeouts_before_the_first_frame() {
+        let mut backend = SyntheticBackend::new(64, 48, 30);
+        backend.set_paused(true);
+        assert!(backend.capture_wait(&Wait::Poll, false).unwrap().is_none());
+        assert!(backend
+            .capture_wait(&Wait::Timeout(20), false)
+            .unwrap()
+            .is_none());
+        // outlasts many timed out slices, not only the first
+        let started = Instant::now();
+        let frame = backend
+            .capture_wait(&Wait::Until(stop_after(ms(60))), false)
+            .unwrap();
+        assert!(frame.is_none());
+        assert!(started.elapsed() >= ms(60));
+        assert_eq!(backend.frame_index(), 0);
+    }
+
+    #[test]
+    fn until_waits_for_the_next_present() {
+        let mut backend = SyntheticBackend::new(64, 48, 20);
+        assert!(backend.capture_wait(&Wait::Poll, true).unwrap().is_some());
+        let started = Instant::now();
+        let frame = backend
+            .capture_wait(&Wait::Until(StopToken::new()), true)
+            .unwrap()
+            .unwrap();
+        assert!(started.elapsed() >= ms(30));
+        assert_eq!(frame.present_time, backend.interval());
+
+        // a token stopped up front still takes what is there
+        let stop = StopToken::new();
+        stop.stop();
+        let frame = backend.capture_wait(&Wait::Until(stop.clone()), false);
+        assert_eq!(frame.unwrap().unwrap().present_time, backend.interval());
+        assert!(backend
+            .capture_wait(&Wait::Until(stop), true)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn boxed_backends_forward_waits() {
+        let mut backend: Box<dyn CaptureBackend> = Box::new(SyntheticBackend::new(64, 48, 10));
+        assert_eq!((backend.width(), backend.height()), (64, 48));
+        assert!(backend
+            .capture_wait(&Wait::Blocking, true)
+            .unwrap()
+            .is_some());
+        let started = Instant::now();
+        let frame = backend
+            .capture_wait(&Wait::Until(stop_after(ms(20))), true)
+            .unwrap();
+        assert!(frame.is_none());
+        assert!(started.elapsed() < ms(80));
+    }
+}
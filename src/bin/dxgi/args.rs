@@ -0,0 +1,185 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::Failure;
+
+/// The arguments after the subcommand. Options are taken out as they are looked up,
+/// whatever is left over when the command is done is reported as a usage error.
+pub struct Args {
+    args: Vec<String>,
+}
+
+impl Args {
+    pub fn new(args: Vec<String>) -> Self {
+        Self { args }
+    }
+
+    /// Removes every occurrence of the flag.
+    pub fn flag(&mut self, names: &[&str]) -> bool {
+        let before = self.args.len();
+        self.args.retain(|a| !names.contains(&a.as_str()));
+        self.args.len() != before
+    }
+
+    /// `--name value` or `--name=value`, the last occurrence wins.
+    pub fn value<T>(&mut self, names: &[&str]) -> Result<Option<T>, Failure>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let mut found = None;
+        let mut i = 0;
+        while i < self.args.len() {
+            let arg = &self.args[i];
+            if names.contains(&arg.as_str()) {
+                if i + 1 == self.args.len() {
+                    return Err(Failure::Usage(format!("{} needs a value", arg)));
+                }
+                let value = self.args.remove(i + 1);
+                let name = self.args.remove(i);
+                found = Some((name, value));
+                continue;
+            }
+            if let Some((name, value)) = arg.split_once('=') {
+                if names.contains(&name) {
+                    found = Some((name.to_string(), value.to_string()));
+                    self.args.remove(i);
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        match found {
+            Some((name, value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| Failure::Usage(format!("bad value for {}: {}", name, e))),
+            None => Ok(None),
+        }
+    }
+
+    /// The first argument that is not an option.
+    pub fn positional(&mut self) -> Option<String> {
+        let i = self.args.iter().position(|a| !a.starts_with('-'))?;
+        Some(self.args.remove(i))
+    }
+
+    pub fn finish(self) -> Result<(), Failure> {
+        match self.args.first() {
+            Some(arg) => Err(Failure::Usage(format!("unexpected argument {}", arg))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `WIDTHxHEIGHT`
+#[derive(Debug, Clone, Copy)]
+pub struct Size(pub u32, pub u32);
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (w, h) = s
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {}", s))?;
+        let parse = |v: &str| match v.parse::<u32>() {
+            Ok(v) if v > 0 => Ok(v),
+            _ => Err(format!("bad dimension {}", v)),
+        };
+        Ok(Size(parse(w)?, parse(h)?))
+    }
+}
+
+/// `1.5`, `500ms`, `10s`, `2m` or `1h`, plain numbers are seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Interval(pub Duration);
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: f64 = number.parse().map_err(|_| format!("bad duration {}", s))?;
+        let seconds = match unit {
+            "ms" => number / 1000.0,
+            "" | "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return Err(format!("bad duration unit in {}", s)),
+        };
+        Duration::try_from_secs_f64(seconds)
+            .map(Interval)
+            .map_err(|_| format!("bad duration {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Args {
+        Args::new(line.split_whitespace().map(str::to_string).collect())
+    }
+
+    fn usage<T>(result: Result<T, Failure>) -> String {
+        match result {
+            Err(Failure::Usage(msg)) => msg,
+            Err(failure) => panic!("not a usage error: {}", failure),
+            Ok(_) => panic!("accepted"),
+        }
+    }
+
+    fn ok<T>(result: Result<T, Failure>) -> T {
+        result.unwrap_or_else(|failure| panic!("{}", failure))
+    }
+
+    #[test]
+    fn flags_and_values() {
+        let mut a = args("-v --fps 20 in.ivf --size=64x48 --verbose --fps=25 -o out.mkv");
+        assert!(a.flag(&["-v", "--verbose"]));
+        assert!(!a.flag(&["-v", "--verbose"]));
+        // the last occurrence wins, in either form
+        assert_eq!(ok(a.value::<u32>(&["--fps"])), Some(25));
+        let size = ok(a.value::<Size>(&["--size"])).unwrap();
+        assert_eq!((size.0, size.1), (64, 48));
+        assert_eq!(
+            ok(a.value::<String>(&["-o", "--output"])).as_deref(),
+            Some("out.mkv")
+        );
+        assert_eq!(ok(a.value::<u32>(&["--frames"])), None);
+        assert_eq!(a.positional().as_deref(), Some("in.ivf"));
+        assert!(a.positional().is_none());
+        ok(a.finish());
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(
+            usage(args("--fps").value::<u32>(&["--fps"])),
+            "--fps needs a value"
+        );
+        let msg = usage(args("--fps fast").value::<u32>(&["--fps"]));
+        assert!(msg.starts_with("bad value for --fps"), "{}", msg);
+        let msg = usage(args("--size 0x10").value::<Size>(&["--size"]));
+        assert!(msg.contains("bad dimension 0"), "{}", msg);
+        assert_eq!(usage(args("--nope").finish()), "unexpected argument --nope");
+    }
+
+    #[test]
+    fn intervals() {
+        let parse = |s: &str| s.parse::<Interval>().map(|i| i.0);
+        assert_eq!(parse("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse("5d").is_err());
+        assert!(parse("ms").is_err());
+        assert!(parse("-1").is_err());
+    }
+}
@@ -0,0 +1,613 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use dxgi::backend::CaptureBackend;
+use dxgi::codec::Codec;
+use dxgi::frame::{Frame, Rect};
+use dxgi::ivf::IvfReader;
+use dxgi::net::{Endpoint, FrameServer, Metadata, ServerConfig};
+use dxgi::replay::ReplayBuffer;
+use dxgi::rfb::{RfbConfig, RfbServer};
+use dxgi::segment::ContainerFormat;
+use dxgi::timelapse::{
+    MjpegEncoder, Timelapse, TimelapseConfig, TimelapseRecorder, TimelapseTrigger,
+};
+use dxgi::OutputDesc;
+
+use crate::args::{Args, Interval};
+use crate::report::Report;
+use crate::{Failure, Source};
+
+pub fn help(command: &str) -> Option<&'static str> {
+    Some(match command {
+        "adapters" => "usage: dxgi adapters [--json]",
+        "outputs" => "usage: dxgi outputs [--json] [--synthetic --size WxH]",
+        "snapshot" => concat!(
+            "usage: dxgi snapshot [-o FILE] [options]\n",
+            "  -o, --output FILE         .jpg/.jpeg or .ppm (default snapshot.jpg)\n",
+            "  --quality N               JPEG quality 1-100 (default 90)\n",
+            "  --timeout DURATION        give up without a frame after this (default 5s)",
+        ),
+        "record" => concat!(
+            "usage: dxgi record -o FILE [options]\n",
+            "  -o, --output FILE         motion JPEG video, .mkv or .ivf\n",
+            "  --interval DURATION       take a frame this often (default 1s)\n",
+            "  --change FRACTION         take a frame once this much of the screen changed\n",
+            "  --min-interval DURATION   with --change, at most one frame per (default 1s)\n",
+            "  --duration DURATION       stop after this long\n",
+            "  --frames N                stop after this many frames\n",
+            "  --playback-fps N          frame rate of the video (default 30)\n",
+            "  --overlay                 draw the capture time into the frames\n",
+            "  --quality N               JPEG quality 1-100 (default 85)",
+        ),
+        "stream" => concat!(
+            "usage: dxgi stream [--protocol net|rfb|preview] [options]\n",
+            "  --listen ENDPOINT         tcp://host:port or unix:///path (default\n",
+            "                            127.0.0.1:9000, :5900 for rfb, :8080 for preview)\n",
+            "  --duration DURATION       stop after this long, otherwise run until killed\n",
+            "  --quality N               preview JPEG quality 1-100 (default 80)",
+        ),
+        "bench" => concat!(
+            "usage: dxgi bench [options]\n",
+            "  --frames N                stop after this many new frames (default 300)\n",
+            "  --duration DURATION       stop after this long (default 10s)\n",
+            "  --timeout MS              capture timeout in milliseconds (default 100)",
+        ),
+        "replay" => concat!(
+            "usage: dxgi replay INPUT.ivf [-o OUTPUT] [options]\n",
+            "  -o, --output FILE         save to .mkv, .mp4 or .ivf\n",
+            "  --last DURATION           only keep the last DURATION, cut at a keyframe\n",
+            "  --max-bytes N             memory limit of the replay buffer",
+        ),
+        _ => return None,
+    })
+}
+
+pub fn adapters(args: Args) -> Result<Report, Failure> {
+    args.finish()?;
+    list_adapters()
+}
+
+#[cfg(windows)]
+fn list_adapters() -> Result<Report, Failure> {
+    dxgi::utils::init();
+    let adapters = dxgi::utils::get_adapters_desc()
+        .ok_or_else(|| Failure::Error(anyhow::anyhow!("failed to enumerate adapters")))?;
+    let adapters: Vec<Report> = adapters
+        .iter()
+        .map(|a| {
+            Report::object()
+                .with("index", a.index)
+                .with("description", a.description.as_str())
+                .with("luid", a.luid.to_string())
+                .with("vendor", a.vendor().to_string())
+                .with("vendor_id", format!("{:04x}", a.vendor_id))
+                .with("device_id", format!("{:04x}", a.device_id))
+                .with("is_default", a.is_default)
+                .with("is_software", a.is_software)
+                .with("is_hardware", a.is_hardware)
+                .with("is_discrete", a.is_discrete)
+                .with("is_integrated", a.is_integrated)
+                .with("dedicated_video_memory", a.dedicated_video_memory)
+                .with("dedicated_system_memory", a.dedicated_system_memory)
+                .with("shared_system_memory", a.shared_system_memory)
+        })
+        .collect();
+    Ok(Report::object().with("adapters", adapters))
+}
+
+#[cfg(not(windows))]
+fn list_adapters() -> Result<Report, Failure> {
+    Err(Failure::Unsupported(
+        "DXGI adapters only exist on Windows".to_string(),
+    ))
+}
+
+pub fn outputs(mut args: Args) -> Result<Report, Failure> {
+    let source = Source::from_args(&mut args)?;
+    args.finish()?;
+    let outputs = if source.is_synthetic() {
+        let backend = source.open()?;
+        vec![OutputDesc {
+            device_name: "synthetic".to_string(),
+            desktop_coordinates: Rect::new(0, 0, backend.width() as i32, backend.height() as i32),
+            attached_to_desktop: true,
+            bits_per_color: 8,
+            ..Default::default()
+        }]
+    } else {
+        list_outputs()?
+    };
+    let outputs: Vec<Report> = outputs
+        .iter()
+        .map(|o| {
+            let rect = o.desktop_coordinates;
+            Report::object()
+                .with("adapter", o.adapter_luid.to_string())
+                .with("index", o.index)
+                .with("name", o.device_name.as_str())
+                .with("attached", o.attached_to_desktop)
+                .with("left", rect.left)
+                .with("top", rect.top)
+                .with("width", o.width())
+                .with("height", o.height())
+                .with("rotation", o.rotation.degrees())
+                .with("bits_per_color", o.bits_per_color)
+                .with("color_space", o.color_space.to_raw())
+                .with("hdr", o.is_hdr())
+                .with("min_luminance", o.min_luminance as f64)
+                .with("max_luminance", o.max_luminance as f64)
+                .with(
+                    "max_full_frame_luminance",
+                    o.max_full_frame_luminance as f64,
+                )
+        })
+        .collect();
+    Ok(Report::object().with("outputs", outputs))
+}
+
+#[cfg(windows)]
+fn list_outputs() -> Result<Vec<OutputDesc>, Failure> {
+    dxgi::utils::init();
+    dxgi::utils::get_outputs_desc()
+        .ok_or_else(|| Failure::Error(anyhow::anyhow!("failed to enumerate outputs")))
+}
+
+#[cfg(not(windows))]
+fn list_outputs() -> Result<Vec<OutputDesc>, Failure> {
+    Err(Failure::Unsupported(
+        "DXGI outputs only exist on Windows, use --synthetic".to_string(),
+    ))
+}
+
+pub fn snapshot(mut args: Args) -> Result<Report, Failure> {
+    let source = Source::from_args(&mut args)?;
+    let path: PathBuf = args
+        .value(&["-o", "--output"])?
+        .unwrap_or_else(|| PathBuf::from("snapshot.jpg"));
+    let quality = quality(&mut args, 90)?;
+    let timeout = args
+        .value::<Interval>(&["--timeout"])?
+        .map_or(Duration::from_secs(5), |i| i.0);
+    args.finish()?;
+    let image = ImageFormat::from_path(&path)?;
+
+    let mut backend = source.open()?;
+    let deadline = Instant::now() + timeout;
+    let frame = loop {
+        if let Some(frame) = backend.capture(100, false)? {
+            break frame;
+        }
+        if Instant::now() >= deadline {
+            return Err(Failure::NoCapture(format!(
+                "no frame from {} within {:?}",
+                source.name(),
+                timeout
+            )));
+        }
+    };
+    let bytes = image.write(&path, &frame, quality)?;
+    Ok(Report::object()
+        .with("source", source.name())
+        .with("path", path.display().to_string())
+        .with("format", image.name())
+        .with("width", frame.width)
+        .with("height", frame.height)
+        .with("bytes", bytes))
+}
+
+pub fn record(mut args: Args, quiet: bool) -> Result<Report, Failure> {
+    let source = Source::from_args(&mut args)?;
+    let path: PathBuf = args
+        .value(&["-o", "--output"])?
+        .ok_or_else(|| Failure::Usage("record needs -o FILE".to_string()))?;
+    let interval = args.value::<Interval>(&["--interval"])?;
+    let change = args.value::<f32>(&["--change"])?;
+    let min_interval = args
+        .value::<Interval>(&["--min-interval"])?
+        .map_or(Duration::from_secs(1), |i| i.0);
+    let duration = args.value::<Interval>(&["--duration"])?.map(|i| i.0);
+    let max_frames = args.value::<u64>(&["--frames"])?;
+    let playback_fps = args.value::<u32>(&["--playback-fps"])?;
+    let overlay = args.flag(&["--overlay"]);
+    let quality = quality(&mut args, 85)?;
+    args.finish()?;
+    let trigger = match (interval, change) {
+        (Some(_), Some(_)) => {
+            return Err(Failure::Usage(
+                "--interval and --change exclude each other".to_string(),
+            ))
+        }
+        (_, Some(threshold)) if !(0.0..=1.0).contains(&threshold) => {
+            return Err(Failure::Usage(
+                "--change takes a fraction between 0 and 1".to_string(),
+            ))
+        }
+        (_, Some(threshold)) => TimelapseTrigger::Change {
+            threshold,
+            min_interval,
+        },
+        (interval, None) => {
+            TimelapseTrigger::Interval(interval.map_or(Duration::from_secs(1), |i| i.0))
+        }
+    };
+    if playback_fps == Some(0) {
+        return Err(Failure::Usage("--playback-fps must be above 0".to_string()));
+    }
+    let format = container_format(&path)?;
+    if format == ContainerFormat::Mp4 {
+        return Err(Failure::Usage(
+            "MP4 cannot hold motion JPEG, record to .mkv or .ivf".to_string(),
+        ));
+    }
+
+    let mut config = TimelapseConfig::new(trigger);
+    config.timestamp_overlay = overlay;
+    if let Some(fps) = playback_fps {
+        config.playback_fps = fps;
+    }
+    let fps = config.playback_fps;
+    let timelapse = Timelapse::new(source.open()?, config);
+    let encoder = MjpegEncoder { quality };
+    let mut recorder = TimelapseRecorder::create(&path, format, timelapse, encoder)
+        .with_context(|| path.display().to_string())?;
+    let start = Instant::now();
+    while duration.is_none_or(|d| start.elapsed() < d)
+        && max_frames.is_none_or(|n| recorder.timelapse().taken() < n)
+    {
+        if let Some(taken) = recorder.poll()? {
+            if !quiet {
+                eprintln!("frame {} at {:?}", recorder.timelapse().taken(), taken.pts);
+            }
+        }
+    }
+    let frames = recorder.finish()?;
+    Ok(Report::object()
+        .with("source", source.name())
+        .with("path", path.display().to_string())
+        .with("format", format.extension())
+        .with("codec", Codec::MJPEG.name())
+        .with("frames", frames)
+        .with("playback_fps", fps)
+        .with("bytes", std::fs::metadata(&path)?.len())
+        .with("elapsed_s", start.elapsed().as_secs_f64()))
+}
+
+pub fn stream(mut args: Args, quiet: bool) -> Result<Report, Failure> {
+    let source = Source::from_args(&mut args)?;
+    let protocol = args
+        .value::<String>(&["--protocol"])?
+        .unwrap_or_else(|| "net".to_string());
+    let listen = args.value::<Endpoint>(&["--listen"])?;
+    let duration = args.value::<Interval>(&["--duration"])?.map(|i| i.0);
+    let quality = quality(&mut args, 80)?;
+    args.finish()?;
+    let default_port = match protocol.as_str() {
+        "net" => 9000,
+        "rfb" => 5900,
+        "preview" => 8080,
+        _ => return Err(Failure::Usage(format!("unknown protocol {}", protocol))),
+    };
+    let endpoint = match listen {
+        Some(endpoint) => endpoint,
+        None => format!("127.0.0.1:{}", default_port).parse()?,
+    };
+
+    let mut backend = Counting {
+        inner: source.open()?,
+        frames: 0,
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some(duration) = duration {
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            stop.store(true, Ordering::SeqCst);
+        });
+    }
+    let announce = |endpoint: &Endpoint| {
+        if !quiet {
+            eprintln!("serving {} on {}", protocol, endpoint);
+        }
+    };
+    let start = Instant::now();
+    let endpoint = match protocol.as_str() {
+        "net" => {
+            let mut server = FrameServer::bind(&endpoint, ServerConfig::default())?;
+            announce(server.endpoint());
+            server.set_metadata(Metadata {
+                width: backend.width(),
+                height: backend.height(),
+                format: dxgi::frame::PixelFormat::Bgra8,
+                fps: 0,
+                name: source.name(),
+            });
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(frame) = backend.capture(100, true)? {
+                    server.send_frame(frame);
+                }
+            }
+            server.endpoint().clone()
+        }
+        "rfb" => {
+            let config = RfbConfig {
+                name: source.name(),
+                ..Default::default()
+            };
+            let server = RfbServer::bind(&endpoint, config)?;
+            announce(server.endpoint());
+            server.run(&mut backend, &stop)?;
+            server.endpoint().clone()
+        }
+        _ => serve_preview(&endpoint, quality, &mut backend, &stop, announce)?,
+    };
+    Ok(Report::object()
+        .with("source", source.name())
+        .with("protocol", protocol.as_str())
+        .with("endpoint", endpoint.to_string())
+        .with("frames", backend.frames)
+        .with("elapsed_s", start.elapsed().as_secs_f64()))
+}
+
+#[cfg(feature = "preview")]
+fn serve_preview(
+    endpoint: &Endpoint,
+    quality: u8,
+    backend: &mut dyn CaptureBackend,
+    stop: &AtomicBool,
+    announce: impl Fn(&Endpoint),
+) -> Result<Endpoint, Failure> {
+    use dxgi::preview::{PreviewConfig, PreviewServer};
+
+    let config = PreviewConfig {
+        jpeg_quality: quality,
+        ..Default::default()
+    };
+    let server = PreviewServer::bind(endpoint, config)?;
+    announce(server.endpoint());
+    server.run(backend, stop)?;
+    Ok(server.endpoint().clone())
+}
+
+#[cfg(not(feature = "preview"))]
+fn serve_preview(
+    _: &Endpoint,
+    _: u8,
+    _: &mut dyn CaptureBackend,
+    _: &AtomicBool,
+    _: impl Fn(&Endpoint),
+) -> Result<Endpoint, Failure> {
+    Err(Failure::Unsupported(
+        "built without the `preview` feature".to_string(),
+    ))
+}
+
+pub fn bench(mut args: Args) -> Result<Report, Failure> {
+    let source = Source::from_args(&mut args)?;
+    let max_frames = args.value::<u64>(&["--frames"])?.unwrap_or(300);
+    let duration = args
+        .value::<Interval>(&["--duration"])?
+        .map_or(Duration::from_secs(10), |i| i.0);
+    let timeout = args.value::<u32>(&["--timeout"])?.unwrap_or(100);
+    args.finish()?;
+
+    let mut backend = source.open()?;
+    let (width, height) = (backend.width(), backend.height());
+    let mut latencies = Vec::new();
+    let mut intervals = Vec::new();
+    let mut timeouts = 0u64;
+    let mut last_frame = None;
+    let start = Instant::now();
+    while (latencies.len() as u64) < max_frames && start.elapsed() < duration {
+        let begin = Instant::now();
+        match backend.capture(timeout, true)? {
+            Some(_) => {
+                let now = Instant::now();
+                latencies.push(now - begin);
+                if let Some(last) = last_frame.replace(now) {
+                    intervals.push(now - last);
+                }
+            }
+            None => timeouts += 1,
+        }
+    }
+    let elapsed = start.elapsed();
+    if latencies.is_empty() {
+        return Err(Failure::NoCapture(format!(
+            "no frame from {} within {:?}",
+            source.name(),
+            elapsed
+        )));
+    }
+    // the first frame has no interval, measure the rate from there
+    let fps = match intervals.iter().sum::<Duration>() {
+        total if total.is_zero() => 0.0,
+        total => intervals.len() as f64 / total.as_secs_f64(),
+    };
+    Ok(Report::object()
+        .with("source", source.name())
+        .with("width", width)
+        .with("height", height)
+        .with("frames", latencies.len())
+        .with("timeouts", timeouts)
+        .with("elapsed_s", elapsed.as_secs_f64())
+        .with("fps", fps)
+        .with("capture_ms", stats(&mut latencies))
+        .with("interval_ms", stats(&mut intervals)))
+}
+
+pub fn replay(mut args: Args) -> Result<Report, Failure> {
+    let output: Option<PathBuf> = args.value(&["-o", "--output"])?;
+    let last = args.value::<Interval>(&["--last"])?.map(|i| i.0);
+    let max_bytes = args.value::<usize>(&["--max-bytes"])?;
+    let input = args
+        .positional()
+        .ok_or_else(|| Failure::Usage("replay needs an input file".to_string()))?;
+    args.finish()?;
+    let format = output.as_deref().map(container_format).transpose()?;
+
+    let file = File::open(&input).with_context(|| input.clone())?;
+    let mut reader = IvfReader::new(BufReader::new(file)).with_context(|| input.clone())?;
+    let header = *reader.header();
+    let mut buffer = ReplayBuffer::new(
+        header.codec,
+        last.unwrap_or(Duration::MAX),
+        max_bytes.unwrap_or(usize::MAX),
+    );
+    let (mut packets, mut keyframes, mut bytes) = (0u64, 0u64, 0u64);
+    let mut first_pts = None;
+    let mut last_pts = Duration::ZERO;
+    while let Some(packet) = reader.read_packet()? {
+        packets += 1;
+        keyframes += packet.keyframe as u64;
+        bytes += packet.data.len() as u64;
+        first_pts.get_or_insert(packet.pts);
+        last_pts = packet.pts;
+        buffer.push(packet);
+    }
+    let duration = last_pts.saturating_sub(first_pts.unwrap_or_default());
+
+    let mut report = Report::object()
+        .with("input", input.as_str())
+        .with("codec", header.codec.name())
+        .with("width", header.width)
+        .with("height", header.height)
+        .with("packets", packets)
+        .with("keyframes", keyframes)
+        .with("bytes", bytes)
+        .with("duration_s", duration.as_secs_f64());
+    if let (Some(path), Some(format)) = (output, format) {
+        if buffer.is_empty() {
+            return Err(Failure::Error(anyhow::anyhow!(
+                "{} has no keyframe to start from",
+                input
+            )));
+        }
+        let saved =
+            buffer.save_to_file(&path, format, header.width as u32, header.height as u32)?;
+        report = report.with(
+            "saved",
+            Report::object()
+                .with("path", path.display().to_string())
+                .with("packets", saved)
+                .with("duration_s", buffer.duration().as_secs_f64()),
+        );
+    }
+    Ok(report)
+}
+
+fn quality(args: &mut Args, default: u8) -> Result<u8, Failure> {
+    match args.value::<u8>(&["--quality"])? {
+        Some(q) if !(1..=100).contains(&q) => {
+            Err(Failure::Usage("--quality takes 1 to 100".to_string()))
+        }
+        q => Ok(q.unwrap_or(default)),
+    }
+}
+
+// mean, median, p95 and max in milliseconds
+fn stats(samples: &mut [Duration]) -> Report {
+    if samples.is_empty() {
+        return Report::Null;
+    }
+    samples.sort();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+    let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+    Report::object()
+        .with("mean", ms(mean))
+        .with("p50", ms(percentile(50)))
+        .with("p95", ms(percentile(95)))
+        .with("max", ms(samples[samples.len() - 1]))
+}
+
+fn container_format(path: &Path) -> Result<ContainerFormat, Failure> {
+    match extension(path).as_str() {
+        "mkv" => Ok(ContainerFormat::Mkv),
+        "mp4" => Ok(ContainerFormat::Mp4),
+        "ivf" => Ok(ContainerFormat::Ivf),
+        _ => Err(Failure::Usage(format!(
+            "cannot tell the container of {} (.mkv, .mp4 or .ivf)",
+            path.display()
+        ))),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ImageFormat {
+    Jpeg,
+    Ppm,
+}
+
+impl ImageFormat {
+    fn from_path(path: &Path) -> Result<Self, Failure> {
+        match extension(path).as_str() {
+            "jpg" | "jpeg" => Ok(ImageFormat::Jpeg),
+            "ppm" => Ok(ImageFormat::Ppm),
+            _ => Err(Failure::Usage(format!(
+                "cannot tell the image format of {} (.jpg or .ppm)",
+                path.display()
+            ))),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+
+    // returns the file size
+    fn write(&self, path: &Path, frame: &Frame, quality: u8) -> std::io::Result<u64> {
+        let mut file = BufWriter::new(File::create(path)?);
+        match self {
+            ImageFormat::Jpeg => file.write_all(&dxgi::jpeg::encode(frame, quality))?,
+            ImageFormat::Ppm => {
+                write!(file, "P6\n{} {}\n255\n", frame.width, frame.height)?;
+                let bpp = frame.format.bytes_per_pixel();
+                for y in 0..frame.height {
+                    let row = &frame.row(y)[..frame.width as usize * bpp];
+                    for px in row.chunks_exact(bpp) {
+                        file.write_all(&[px[2], px[1], px[0]])?;
+                    }
+                }
+            }
+        }
+        file.flush()?;
+        Ok(std::fs::metadata(path)?.len())
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+// counts the frames a server pulls through `run`
+struct Counting {
+    inner: Box<dyn CaptureBackend>,
+    frames: u64,
+}
+
+impl CaptureBackend for Counting {
+    fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+        let frame = self.inner.capture(timeout, skip)?;
+        self.frames += frame.is_some() as u64;
+        Ok(frame)
+    }
+
+    fn width(&self) -> u32 {
+        self.inner.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.inner.height()
+    }
+}
@@ -0,0 +1,226 @@
+//! `dxgi`: command-line front end for the capture library.
+//!
+//! Every subcommand prints a report when it is done, as `key: value` lines or with
+//! `--json` as a single JSON object on stdout. Progress and errors go to stderr.
+
+use std::fmt;
+use std::process::ExitCode;
+
+use dxgi::backend::CaptureBackend;
+use dxgi::synthetic::SyntheticBackend;
+use dxgi::MonitorSelector;
+
+mod args;
+mod commands;
+mod report;
+
+use args::{Args, Size};
+
+const EXIT_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_UNSUPPORTED: u8 = 3;
+const EXIT_NO_CAPTURE: u8 = 4;
+
+const USAGE: &str = "\
+usage: dxgi <command> [options]
+
+commands:
+  adapters                  list the hardware adapters
+  outputs                   list the outputs (monitors) of every adapter
+  snapshot [-o FILE]        capture one frame to a .jpg or .ppm file
+  record -o FILE            record a timelapse video, every --interval or on --change
+  stream                    serve frames over --protocol net, rfb or preview
+  bench                     measure capture rate and latency
+  replay INPUT.ivf          inspect a recording, cut the tail with -o and --last
+
+common options:
+  --json                    print the report as JSON
+  -v, --verbose             log to stderr
+  --monitor SELECTOR        index, primary, \\\\.\\DISPLAYn, @X,Y or LUID:INDEX (default 0)
+  --synthetic               use the synthetic test pattern instead of the desktop,
+                            always the case on platforms without DXGI
+  --size WxH, --fps N       synthetic desktop size and rate (default 1280x720, 30)
+
+exit codes: 0 ok, 1 error, 2 usage, 3 unsupported here, 4 nothing to capture
+Run `dxgi <command> --help` for the options of a command.";
+
+/// Why a command failed, decides the exit code.
+pub enum Failure {
+    Usage(String),
+    // the platform or this build cannot do it
+    Unsupported(String),
+    // the capture source could not be opened or produced no frame
+    NoCapture(String),
+    Error(anyhow::Error),
+}
+
+impl Failure {
+    fn code(&self) -> u8 {
+        match self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Unsupported(_) => EXIT_UNSUPPORTED,
+            Failure::NoCapture(_) => EXIT_NO_CAPTURE,
+            Failure::Error(_) => EXIT_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(msg) | Failure::Unsupported(msg) | Failure::NoCapture(msg) => {
+                f.write_str(msg)
+            }
+            Failure::Error(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Failure {
+    fn from(e: anyhow::Error) -> Self {
+        Failure::Error(e)
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Self {
+        Failure::Error(e.into())
+    }
+}
+
+/// Where frames come from: a monitor through DXGI or the synthetic pattern.
+pub struct Source {
+    synthetic: bool,
+    size: Size,
+    fps: u32,
+    monitor: MonitorSelector,
+}
+
+impl Source {
+    pub fn from_args(args: &mut Args) -> Result<Self, Failure> {
+        Ok(Self {
+            synthetic: args.flag(&["--synthetic"]) || cfg!(not(windows)),
+            size: args.value(&["--size"])?.unwrap_or(Size(1280, 720)),
+            fps: args.value(&["--fps"])?.unwrap_or(30),
+            monitor: args.value(&["--monitor"])?.unwrap_or_default(),
+        })
+    }
+
+    pub fn is_synthetic(&self) -> bool {
+        self.synthetic
+    }
+
+    pub fn name(&self) -> String {
+        if self.synthetic {
+            "synthetic".to_string()
+        } else {
+            format!("monitor {}", self.monitor)
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn CaptureBackend>, Failure> {
+        if self.synthetic {
+            return Ok(Box::new(SyntheticBackend::new(
+                self.size.0,
+                self.size.1,
+                self.fps,
+            )));
+        }
+        self.open_dxgi()
+    }
+
+    #[cfg(windows)]
+    fn open_dxgi(&self) -> Result<Box<dyn CaptureBackend>, Failure> {
+        dxgi::utils::init();
+        match dxgi::CaptureDXGI::new_by_selector(&self.monitor) {
+            Some(capture) => Ok(Box::new(capture)),
+            None => Err(Failure::NoCapture(format!(
+                "cannot duplicate monitor {}",
+                self.monitor
+            ))),
+        }
+    }
+
+    #[cfg(not(windows))]
+    fn open_dxgi(&self) -> Result<Box<dyn CaptureBackend>, Failure> {
+        Err(Failure::Unsupported(
+            "desktop duplication needs Windows, use --synthetic".to_string(),
+        ))
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("[{}] {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() -> ExitCode {
+    let mut argv: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = (!argv.is_empty()).then(|| argv.remove(0)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(EXIT_USAGE);
+    };
+    let mut args = Args::new(argv);
+    let json = args.flag(&["--json"]);
+    #[cfg(not(feature = "serde"))]
+    if json {
+        eprintln!("dxgi {}: built without the `serde` feature", command);
+        return ExitCode::from(EXIT_UNSUPPORTED);
+    }
+    if args.flag(&["-v", "--verbose"]) {
+        log::set_logger(&StderrLogger).ok();
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+    if args.flag(&["-h", "--help"]) || command == "help" {
+        match commands::help(&command) {
+            Some(help) => println!("{}", help),
+            None => println!("{}", USAGE),
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let result = match command.as_str() {
+        "adapters" => commands::adapters(args),
+        "outputs" => commands::outputs(args),
+        "snapshot" => commands::snapshot(args),
+        "record" => commands::record(args, json),
+        "stream" => commands::stream(args, json),
+        "bench" => commands::bench(args),
+        "replay" => commands::replay(args),
+        _ => Err(Failure::Usage(format!("unknown command {}", command))),
+    };
+    match result {
+        Ok(report) => {
+            #[cfg(feature = "serde")]
+            if json {
+                println!("{}", report.to_json());
+                return ExitCode::SUCCESS;
+            }
+            print!("{}", report.to_text());
+            ExitCode::SUCCESS
+        }
+        Err(failure) => {
+            eprintln!("dxgi {}: {}", command, failure);
+            if let Failure::Usage(_) = failure {
+                eprintln!("{}", commands::help(&command).unwrap_or(USAGE));
+            }
+            #[cfg(feature = "serde")]
+            if json {
+                let error = report::Report::object()
+                    .with("error", failure.to_string())
+                    .with("code", failure.code() as u32);
+                println!("{}", error.to_json());
+            }
+            ExitCode::from(failure.code())
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt::Write;
+
+/// What a command reports, printed as text or, with the `serde` feature, as JSON.
+/// Objects keep their insertion order.
+#[derive(Debug, Clone)]
+pub enum Report {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Report>),
+    Object(Vec<(&'static str, Report)>),
+}
+
+impl Report {
+    pub fn object() -> Self {
+        Report::Object(Vec::new())
+    }
+
+    pub fn with(mut self, key: &'static str, value: impl Into<Report>) -> Self {
+        if let Report::Object(fields) = &mut self {
+            fields.push((key, value.into()));
+        }
+        self
+    }
+
+    /// The report as one line of JSON.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        // keys are strings and values plain data, nothing here can fail to serialize
+        serde_json::to_string(self).expect("report serializes")
+    }
+
+    /// Indented `key: value` lines for people.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        self.write_text(&mut out, 0);
+        out
+    }
+
+    fn write_text(&self, out: &mut String, indent: usize) {
+        match self {
+            Report::Object(fields) => {
+                for (key, value) in fields {
+                    let _ = write!(out, "{:indent$}{}:", "", key, indent = indent);
+                    if value.is_scalar() {
+                        let _ = writeln!(out, " {}", value.scalar_text());
+                    } else {
+                        out.push('\n');
+                        value.write_text(out, indent + 2);
+                    }
+                }
+            }
+            Report::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if item.is_scalar() {
+                        let _ = writeln!(
+                            out,
+                            "{:indent$}- {}",
+                            "",
+                            item.scalar_text(),
+                            indent = indent
+                        );
+                    } else {
+                        let _ = writeln!(out, "{:indent$}[{}]", "", i, indent = indent);
+                        item.write_text(out, indent + 2);
+                    }
+                }
+            }
+            scalar => {
+                let _ = writeln!(
+                    out,
+                    "{:indent$}{}",
+                    "",
+                    scalar.scalar_text(),
+                    indent = indent
+                );
+            }
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        match self {
+            Report::Array(items) => items.is_empty(),
+            Report::Object(fields) => fields.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn scalar_text(&self) -> String {
+        match self {
+            Report::Null => "-".to_string(),
+            Report::Str(s) => s.clone(),
+            Report::Float(v) => format!("{:.3}", v),
+            Report::Array(_) => "(none)".to_string(),
+            Report::Bool(v) => v.to_string(),
+            Report::Int(v) => v.to_string(),
+            Report::Object(_) => "{}".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Report {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Report::Null => serializer.serialize_none(),
+            Report::Bool(v) => serializer.serialize_bool(*v),
+            Report::Int(v) => serializer.serialize_i64(*v),
+            // serde_json writes NaN and infinity as null
+            Report::Float(v) => serializer.serialize_f64(*v),
+            Report::Str(s) => serializer.serialize_str(s),
+            Report::Array(items) => serializer.collect_seq(items),
+            Report::Object(fields) => serializer.collect_map(fields.iter().map(|(k, v)| (k, v))),
+        }
+    }
+}
+
+impl From<bool> for Report {
+    fn from(v: bool) -> Self {
+        Report::Bool(v)
+    }
+}
+
+macro_rules! from_int {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Report {
+            fn from(v: $t) -> Self {
+                Report::Int(v as i64)
+            }
+        })*
+    };
+}
+
+from_int!(i32, i64, u16, u32, u64, usize);
+
+impl From<f64> for Report {
+    fn from(v: f64) -> Self {
+        Report::Float(v)
+    }
+}
+
+impl From<&str> for Report {
+    fn from(v: &str) -> Self {
+        Report::Str(v.to_string())
+    }
+}
+
+impl From<String> for Report {
+    fn from(v: String) -> Self {
+        Report::Str(v)
+    }
+}
+
+impl<T: Into<Report>> From<Option<T>> for Report {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Report::Null, Into::into)
+    }
+}
+
+impl<T: Into<Report>> From<Vec<T>> for Report {
+    fn from(v: Vec<T>) -> Self {
+        Report::Array(v.into_iter().map(Into::into).collect())
+    }
+}
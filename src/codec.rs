@@ -0,0 +1,133 @@
+/// Video codecs produced by the encoders fed from the capture layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    H264,
+    H265,
+    VP8,
+    VP9,
+    AV1,
+    // motion JPEG, every frame a baseline JPEG image
+    MJPEG,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::H264 => "h264",
+            Codec::H265 => "h265",
+            Codec::VP8 => "vp8",
+            Codec::VP9 => "vp9",
+            Codec::AV1 => "av1",
+            Codec::MJPEG => "mjpeg",
+        }
+    }
+
+    // whether packets are Annex-B NAL streams
+    pub fn is_annexb(&self) -> bool {
+        matches!(self, Codec::H264 | Codec::H265)
+    }
+}
+
+pub fn is_keyframe(codec: Codec, data: &[u8]) -> bool {
+    match codec {
+        Codec::H264 | Codec::H265 => crate::annexb::is_keyframe(codec, data),
+        // frame tag bit 0 is the inverse key frame flag
+        Codec::VP8 => data.first().is_some_and(|b| b & 1 == 0),
+        Codec::VP9 => vp9_is_keyframe(data),
+        // temporal units starting a coded video sequence carry a sequence header
+        Codec::AV1 => av1_obus(data).any(|(obu_type, _)| obu_type == AV1_OBU_SEQUENCE_HEADER),
+        Codec::MJPEG => data.starts_with(&[0xff, 0xd8]),
+    }
+}
+
+fn vp9_is_keyframe(data: &[u8]) -> bool {
+    let Some(&b) = data.first() else {
+        return false;
+    };
+    if b >> 6 != 2 {
+        return false;
+    }
+    let profile = ((b >> 5) & 1) | (((b >> 4) & 1) << 1);
+    // bits left after frame_marker and profile, profile 3 has a reserved zero bit
+    let mut shift = if profile == 3 { 2 } else { 3 };
+    let show_existing_frame = (b >> shift) & 1;
+    if show_existing_frame == 1 {
+        return false;
+    }
+    shift -= 1;
+    (b >> shift) & 1 == 0
+}
+
+pub(crate) const AV1_OBU_SEQUENCE_HEADER: u8 = 1;
+
+// (obu_type, whole obu) for a low overhead bitstream format temporal unit
+pub(crate) fn av1_obus(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let header = *rest.first()?;
+        let obu_type = (header >> 3) & 0xf;
+        let has_extension = header & 0x04 != 0;
+        let has_size = header & 0x02 != 0;
+        let mut pos = 1 + has_extension as usize;
+        let size = if has_size {
+            let mut size = 0usize;
+            for i in 0..8 {
+                let b = *rest.get(pos)?;
+                pos += 1;
+                size |= ((b & 0x7f) as usize) << (i * 7);
+                if b & 0x80 == 0 {
+                    break;
+                }
+            }
+            size
+        } else {
+            rest.len().checked_sub(pos)?
+        };
+        let end = pos.checked_add(size).filter(|end| *end <= rest.len())?;
+        let obu = &rest[..end];
+        rest = &rest[end..];
+        Some((obu_type, obu))
+    })
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One encoded access unit. H.264/H.265 data is kept in Annex-B form, AV1 as a
+/// low overhead bitstream format temporal unit.
+#[derive(Debug, Clone)]
+pub struct Packet {
+    pub codec: Codec,
+    pub data: Vec<u8>,
+    // present time of the captured frame, see `CaptureDXGI::present_time`
+    pub pts: std::time::Duration,
+    pub keyframe: bool,
+}
+
+impl Packet {
+    pub fn new(codec: Codec, data: Vec<u8>, pts: std::time::Duration) -> Self {
+        let keyframe = is_keyframe(codec, &data);
+        Self {
+            codec,
+            data,
+            pts,
+            keyframe,
+        }
+    }
+}
+
+/// Common interface of the container writers, so recorders can be built around any of them.
+pub trait PacketWriter {
+    fn write_packet(&mut self, packet: &Packet) -> std::io::Result<()>;
+
+    /// Finalises the container. Later writes fail.
+    fn close(&mut self) -> std::io::Result<()>;
+
+    /// Bytes accepted but not yet written out, e.g. the current MP4 fragment.
+    fn buffered_bytes(&self) -> u64 {
+        0
+    }
+}
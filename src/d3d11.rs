@@ -1,13 +1,16 @@
 use windows;
 
-use windows::Win32::Foundation::{LUID, S_FALSE};
+use std::mem::{size_of, size_of_val};
+
+use windows::Win32::Foundation::{LUID, RECT, S_FALSE};
 use windows::{core::Result, core::*, Win32::Graphics::Direct3D11::*, Win32::Graphics::Dxgi::*};
 
+use crate::frame::{MoveRect, Rect};
 use crate::staging_texture::StagingTexture;
-use crate::{OtherFrame, OutputDuplication};
+use crate::{AdapterSelector, MonitorSelector, OtherFrame, OutputDesc, OutputDuplication};
 
 use crate::utils::{
-    acquire_duplication, get_hardware_adapter_desc, init_adaptor, init_adaptor_by_luid,
+    acquire_duplication, get_adapters_desc, get_outputs_desc, init_adaptor_by_luid, qpc_to_duration,
 };
 
 pub struct CaptureDXGI {
@@ -15,10 +18,17 @@ pub struct CaptureDXGI {
     device_context: ID3D11DeviceContext,
     duplicator: Option<OutputDuplication>,
     staging_texture: Option<StagingTexture>,
+    // what to duplicate again after the duplication is lost
+    selector: MonitorSelector,
+    // the output duplicated so far, a reacquired duplication must be of the same one
+    device_name: String,
     capture_monitor_index: u32,
     width: u32,
     height: u32,
     luid: i64,
+    last_present_time: i64,
+    dirty_rects: Vec<Rect>,
+    move_rects: Vec<MoveRect>,
 }
 
 impl Drop for CaptureDXGI {
@@ -26,118 +36,155 @@ impl Drop for CaptureDXGI {
 }
 
 impl CaptureDXGI {
+    /// Captures the `capture_monitor_index`-th output attached to the desktop, counting
+    /// across adapters like `MonitorSelector::Index`.
     pub fn new(capture_monitor_index: u32) -> Option<CaptureDXGI> {
-        match init_adaptor() {
-            Some((a, d, ctx)) => {
-                match acquire_duplication(&a, &d, capture_monitor_index) {
-                    Some(duplication) => {
-                        let luid = *duplication.adapter_desc.luid;
-                        let width = duplication.output_dimensions.0;
-                        let height = duplication.output_dimensions.1;
-                        return Some(Self {
-                            duplicator: Some(duplication),
-                            device: d,
-                            device_context: ctx,
-                            staging_texture: None,
-                            // resources,
-                            capture_monitor_index,
-                            width,
-                            height,
-                            luid,
-                        });
-                    }
-                    None => {
-                        log::debug!("acquire_duplication is None.");
-                        None
-                    }
-                }
-            }
-            None => {
-                log::debug!("Should have an adaptor and d3d11 device now.");
-                None
-            }
-        }
+        Self::new_by_selector(&MonitorSelector::Index(capture_monitor_index))
     }
 
+    /// Captures output `capture_monitor_index` of the adapter with `luid`, as passed to
+    /// EnumOutputs.
     pub fn new_by_luid(luid: LUID, capture_monitor_index: u32) -> Option<CaptureDXGI> {
-        match init_adaptor_by_luid(luid) {
-            Some((a, d, ctx)) => match acquire_duplication(&a, &d, capture_monitor_index) {
-                Some(duplication) => {
-                    let luid = *duplication.adapter_desc.luid;
-                    let width = duplication.output_dimensions.0;
-                    let height = duplication.output_dimensions.1;
-                    return Some(Self {
-                        duplicator: Some(duplication),
-                        device: d,
-                        device_context: ctx,
-                        staging_texture: None,
-                        capture_monitor_index,
-                        width,
-                        height,
-                        luid,
-                    });
-                }
-                None => {
-                    log::debug!("acquire_duplication is None.");
-                    None
-                }
-            },
-            None => {
-                log::debug!("Should have an adaptor and d3d11 device now.");
-                None
-            }
+        let selector = MonitorSelector::AdapterOutput(luid.into(), capture_monitor_index);
+        Self::duplicate(selector, luid, capture_monitor_index)
+    }
+
+    /// Captures output `capture_monitor_index` of the best adapter `selector` accepts,
+    /// falling back to the next one when that fails.
+    pub fn new_by_adapter(
+        selector: &AdapterSelector,
+        capture_monitor_index: u32,
+    ) -> Option<CaptureDXGI> {
+        let adapters = get_adapters_desc()?;
+        selector.rank(&adapters).into_iter().find_map(|adapter| {
+            log::debug!("trying adapter {}", adapter);
+            Self::new_by_luid(adapter.luid.into(), capture_monitor_index)
+        })
+    }
+
+    /// Captures the output picked by `selector` among all adapters' outputs.
+    pub fn new_by_selector(selector: &MonitorSelector) -> Option<CaptureDXGI> {
+        let outputs = get_outputs_desc()?;
+        let Some(output) = selector.resolve(&outputs) else {
+            log::debug!("no output matches {}", selector);
+            return None;
+        };
+        Self::duplicate(selector.clone(), output.adapter_luid.into(), output.index)
+    }
+
+    fn duplicate(
+        selector: MonitorSelector,
+        luid: LUID,
+        capture_monitor_index: u32,
+    ) -> Option<CaptureDXGI> {
+        let Some((a, adapter_desc, d, ctx)) = init_adaptor_by_luid(luid) else {
+            log::debug!("Should have an adaptor and d3d11 device now.");
+            return None;
+        };
+        let Some(duplication) = acquire_duplication(&a, adapter_desc, &d, capture_monitor_index)
+        else {
+            log::debug!("acquire_duplication is None.");
+            return None;
+        };
+        let luid = *duplication.adapter_desc.luid;
+        let (width, height) = duplication.output_dimensions;
+        Some(Self {
+            device_name: duplication.output_desc.device_name.clone(),
+            duplicator: Some(duplication),
+            device: d,
+            device_context: ctx,
+            staging_texture: None,
+            selector,
+            capture_monitor_index,
+            width,
+            height,
+            luid,
+            last_present_time: 0,
+            dirty_rects: Vec::new(),
+            move_rects: Vec::new(),
+        })
+    }
+
+    // duplicates the output again after a mode change, hot-plug or device loss
+    fn reacquire(&mut self) -> Result<()> {
+        let outputs = get_outputs_desc().unwrap_or_default();
+        let Some(output) = self.selector.resolve(&outputs) else {
+            return Err(Error::new(
+                DXGI_ERROR_NOT_FOUND,
+                format!("no output matches {} any more", self.selector),
+            ));
+        };
+        // after a hot-plug an index or point can land on another monitor
+        if !output.device_name.eq_ignore_ascii_case(&self.device_name) {
+            return Err(Error::new(
+                DXGI_ERROR_NOT_FOUND,
+                format!(
+                    "{} now selects {} instead of {}",
+                    self.selector, output.device_name, self.device_name
+                ),
+            ));
         }
+        let Some((a, adapter_desc, d, ctx)) = init_adaptor_by_luid(output.adapter_luid.into())
+        else {
+            return Err(Error::new(S_FALSE, "init_adaptor_by_luid is None."));
+        };
+        let Some(duplication) = acquire_duplication(&a, adapter_desc, &d, output.index) else {
+            return Err(Error::new(S_FALSE, "acquire_duplication is None."));
+        };
+        self.luid = *duplication.adapter_desc.luid;
+        self.capture_monitor_index = output.index;
+        self.duplicator = Some(duplication);
+        self.device = d;
+        self.device_context = ctx;
+        self.staging_texture = None;
+        Ok(())
     }
 
-    fn capture_to_texture(&self, _timeout: u32, skip: bool) -> Result<ID3D11Texture2D> {
+    fn capture_to_texture(&mut self, timeout: u32, skip: bool) -> Result<ID3D11Texture2D> {
         let duplication = &self.duplicator.as_ref().unwrap().duplication;
-        let mut pp_desktop_resource = None;
+        let started = std::time::Instant::now();
+        self.dirty_rects.clear();
+        self.move_rects.clear();
         unsafe {
-            let timeout_in_ms: u32 = 3;
-            let mut frame_info: DXGI_OUTDUPL_FRAME_INFO = Default::default();
-            duplication.AcquireNextFrame(
-                timeout_in_ms,
-                &mut frame_info,
-                &mut pp_desktop_resource,
-            )?;
-
-            if skip && frame_info.LastPresentTime == 0 {
-                let res = duplication.ReleaseFrame();
-                if let Err(e) = res {
-                    // log::error!("Could not release frame: {:?}", e);
-                    return Err(e);
+            loop {
+                // u32::MAX is INFINITE, 0 only returns a frame that is already there
+                let remaining = match timeout {
+                    u32::MAX => u32::MAX,
+                    _ => timeout.saturating_sub(started.elapsed().as_millis() as u32),
+                };
+                let mut pp_desktop_resource = None;
+                let mut frame_info: DXGI_OUTDUPL_FRAME_INFO = Default::default();
+                duplication.AcquireNextFrame(
+                    remaining,
+                    &mut frame_info,
+                    &mut pp_desktop_resource,
+                )?;
+                if frame_info.LastPresentTime != 0 {
+                    self.last_present_time = frame_info.LastPresentTime;
+                }
+                if frame_info.TotalMetadataBufferSize > 0 {
+                    (self.move_rects, self.dirty_rects) =
+                        frame_rects(duplication, frame_info.TotalMetadataBufferSize);
+                }
+
+                if !skip || frame_info.LastPresentTime != 0 {
+                    return Ok(pp_desktop_resource.unwrap().cast().unwrap());
+                }
+                // only the pointer changed, keep waiting for the rest of the timeout
+                duplication.ReleaseFrame()?;
+                if remaining == 0 {
+                    return Err(Error::new(
+                        DXGI_ERROR_WAIT_TIMEOUT,
+                        "no frame presented within the timeout",
+                    ));
                 }
-                return Err(Error::new(
-                    DXGI_ERROR_WAIT_TIMEOUT,
-                    "acquire_duplication is None.",
-                ));
             }
-            Ok(pp_desktop_resource.unwrap().cast().unwrap())
         }
     }
 
     pub fn capture_next(&mut self, timeout: u32, skip: bool) -> Result<bool> {
         if self.duplicator.is_none() {
-            if let Some((dupl, d3d11_device, ctx)) = match init_adaptor() {
-                Some((a, d, ctx)) => {
-                    match acquire_duplication(&a, &d, self.capture_monitor_index) {
-                        Some(duplication) => Some((Some(duplication), d, ctx)),
-                        None => {
-                            // log::debug!("acquire_duplication is None.");
-                            return Err(Error::new(S_FALSE, "acquire_duplication is None."));
-                        }
-                    }
-                }
-                None => {
-                    // log::debug!("init_adaptor failed.");
-                    return self.capture_next(timeout, skip);
-                }
-            } {
-                self.duplicator = dupl;
-                self.device = d3d11_device;
-                self.device_context = ctx;
-                self.staging_texture = None;
-            };
+            self.reacquire()?;
         }
 
         // #[cfg(debug_assertions)]
@@ -275,6 +322,29 @@ impl CaptureDXGI {
         self.luid
     }
 
+    // the duplicated output, None while duplication is being reacquired
+    pub fn output_desc(&self) -> Option<&OutputDesc> {
+        self.duplicator.as_ref().map(|d| &d.output_desc)
+    }
+
+    // QPC ticks of the last desktop present, 0 until a frame has been presented
+    pub fn last_present_time(&self) -> i64 {
+        self.last_present_time
+    }
+
+    pub fn present_time(&self) -> std::time::Duration {
+        qpc_to_duration(self.last_present_time)
+    }
+
+    // regions redrawn in the last captured frame, empty when unknown or unchanged
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty_rects
+    }
+
+    pub fn move_rects(&self) -> &[MoveRect] {
+        &self.move_rects
+    }
+
     pub fn width(&self) -> i32 {
         self.width as _
     }
@@ -282,3 +352,53 @@ impl CaptureDXGI {
         self.height as _
     }
 }
+
+// move and dirty rects of the acquired frame, must be read before ReleaseFrame
+unsafe fn frame_rects(
+    duplication: &IDXGIOutputDuplication,
+    metadata_size: u32,
+) -> (Vec<MoveRect>, Vec<Rect>) {
+    let move_len = size_of::<DXGI_OUTDUPL_MOVE_RECT>();
+    let mut moves = vec![DXGI_OUTDUPL_MOVE_RECT::default(); metadata_size as usize / move_len];
+    let mut required = 0;
+    let moves = match duplication.GetFrameMoveRects(
+        size_of_val(moves.as_slice()) as u32,
+        moves.as_mut_ptr(),
+        &mut required,
+    ) {
+        Ok(()) => moves[..required as usize / move_len]
+            .iter()
+            .map(|m| MoveRect {
+                source_x: m.SourcePoint.x,
+                source_y: m.SourcePoint.y,
+                destination: Rect::new(
+                    m.DestinationRect.left,
+                    m.DestinationRect.top,
+                    m.DestinationRect.right,
+                    m.DestinationRect.bottom,
+                ),
+            })
+            .collect(),
+        Err(e) => {
+            log::debug!("GetFrameMoveRects failed: {:?}", e);
+            Vec::new()
+        }
+    };
+    let mut dirty = vec![RECT::default(); metadata_size as usize / size_of::<RECT>()];
+    let dirty = match duplication.GetFrameDirtyRects(
+        size_of_val(dirty.as_slice()) as u32,
+        dirty.as_mut_ptr(),
+        &mut required,
+    ) {
+        Ok(()) => dirty[..required as usize / size_of::<RECT>()]
+            .iter()
+            .map(|r| Rect::new(r.left, r.top, r.right, r.bottom))
+            .collect(),
+        Err(e) => {
+            log::debug!("GetFrameDirtyRects failed: {:?}", e);
+            // the moves alone would look like the whole change
+            return (Vec::new(), Vec::new());
+        }
+    };
+    (moves, dirty)
+}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    Bgra8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Bgra8 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl Rect {
+    pub fn new(left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        (self.right - self.left).max(0)
+    }
+
+    pub fn height(&self) -> i32 {
+        (self.bottom - self.top).max(0)
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width() as u64 * self.height() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.area() == 0
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.left && x < self.right && y >= self.top && y < self.bottom
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        Rect::new(
+            self.left.max(other.left),
+            self.top.max(other.top),
+            self.right.min(other.right),
+            self.bottom.min(other.bottom),
+        )
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.intersect(other).is_empty()
+    }
+
+    /// Smallest rect covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        Rect::new(
+            self.left.min(other.left),
+            self.top.min(other.top),
+            self.right.max(other.right),
+            self.bottom.max(other.bottom),
+        )
+    }
+}
+
+/// A region copied from elsewhere in the previous frame, e.g. a dragged window or a
+/// scroll. Applied before the dirty rects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MoveRect {
+    // top-left corner of the source region
+    pub source_x: i32,
+    pub source_y: i32,
+    pub destination: Rect,
+}
+
+impl MoveRect {
+    pub fn source(&self) -> Rect {
+        Rect::new(
+            self.source_x,
+            self.source_y,
+            self.source_x + self.destination.width(),
+            self.source_y + self.destination.height(),
+        )
+    }
+}
+
+/// A captured frame copied out of the GPU into CPU memory.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    // bytes per row, at least width * bytes_per_pixel
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub data: Vec<u8>,
+    pub present_time: Duration,
+    // regions redrawn since the previous frame, not including move destinations
+    pub dirty_rects: Vec<Rect>,
+    pub move_rects: Vec<MoveRect>,
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        let stride = width as usize * format.bytes_per_pixel();
+        Self {
+            width,
+            height,
+            stride,
+            format,
+            data: vec![0; stride * height as usize],
+            present_time: Duration::ZERO,
+            dirty_rects: Vec::new(),
+            move_rects: Vec::new(),
+        }
+    }
+
+    /// Everything that differs from the previous frame: dirty rects and move
+    /// destinations. Empty when unknown.
+    pub fn changed_rects(&self) -> Vec<Rect> {
+        let moved = self.move_rects.iter().map(|m| m.destination);
+        self.dirty_rects.iter().copied().chain(moved).collect()
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width as i32, self.height as i32)
+    }
+
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.width as usize * self.format.bytes_per_pixel()]
+    }
+
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let start = y as usize * self.stride;
+        let len = self.width as usize * self.format.bytes_per_pixel();
+        &mut self.data[start..start + len]
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, bgra: [u8; 4]) {
+        let rect = rect.intersect(&self.bounds());
+        for y in rect.top..rect.bottom {
+            let row = self.row_mut(y as u32);
+            for x in rect.left..rect.right {
+                let i = x as usize * 4;
+                row[i..i + 4].copy_from_slice(&bgra);
+            }
+        }
+    }
+
+    /// Per `tile` x `tile` block, whether it differs from `other`, row-major. `None`
+    /// when the geometry does not match.
+    pub fn changed_tiles(&self, other: &Frame, tile: u32) -> Option<Vec<bool>> {
+        if self.width != other.width || self.height != other.height || self.format != other.format {
+            return None;
+        }
+        let tile = tile.max(1);
+        let bpp = self.format.bytes_per_pixel();
+        let tiles_x = self.width.div_ceil(tile);
+        let tiles_y = self.height.div_ceil(tile);
+        let mut changed = Vec::with_capacity((tiles_x * tiles_y) as usize);
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                let x0 = (tx * tile) as usize * bpp;
+                let x1 = ((tx + 1) * tile).min(self.width) as usize * bpp;
+                let mut rows = ty * tile..((ty + 1) * tile).min(self.height);
+                changed.push(rows.any(|y| self.row(y)[x0..x1] != other.row(y)[x0..x1]));
+            }
+        }
+        Some(changed)
+    }
+
+    /// Fraction of `tile` x `tile` blocks that differ from `other`, 1.0 when the
+    /// geometry does not match.
+    pub fn changed_fraction(&self, other: &Frame, tile: u32) -> f32 {
+        match self.changed_tiles(other, tile) {
+            Some(tiles) if !tiles.is_empty() => {
+                tiles.iter().filter(|c| **c).count() as f32 / tiles.len() as f32
+            }
+            Some(_) => 0.0,
+            None => 1.0,
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::codec::{Codec, Packet, PacketWriter};
+
+const SIGNATURE: &[u8; 4] = b"DKIF";
+const HEADER_LEN: u16 = 32;
+const FRAME_HEADER_LEN: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IvfHeader {
+    pub codec: Codec,
+    pub width: u16,
+    pub height: u16,
+    // seconds per pts tick, as numerator / denominator
+    pub timebase_num: u32,
+    pub timebase_den: u32,
+    pub frame_count: u32,
+}
+
+impl IvfHeader {
+    pub fn new(codec: Codec, width: u16, height: u16) -> Self {
+        Self {
+            codec,
+            width,
+            height,
+            timebase_num: 1,
+            timebase_den: 1000,
+            frame_count: 0,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_LEN as usize] {
+        let mut out = [0; HEADER_LEN as usize];
+        out[0..4].copy_from_slice(SIGNATURE);
+        out[6..8].copy_from_slice(&HEADER_LEN.to_le_bytes());
+        out[8..12].copy_from_slice(fourcc(self.codec));
+        out[12..14].copy_from_slice(&self.width.to_le_bytes());
+        out[14..16].copy_from_slice(&self.height.to_le_bytes());
+        // the file stores the frame rate, i.e. the inverse of the timebase
+        out[16..20].copy_from_slice(&self.timebase_den.to_le_bytes());
+        out[20..24].copy_from_slice(&self.timebase_num.to_le_bytes());
+        out[24..28].copy_from_slice(&self.frame_count.to_le_bytes());
+        out
+    }
+
+    fn from_bytes(data: &[u8; HEADER_LEN as usize]) -> io::Result<Self> {
+        if &data[0..4] != SIGNATURE {
+            return Err(invalid_data("not an ivf file"));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let codec = codec_from_fourcc(&data[8..12]).ok_or_else(|| {
+            invalid_data(format!(
+                "unknown ivf fourcc {}",
+                String::from_utf8_lossy(&data[8..12])
+            ))
+        })?;
+        let header = Self {
+            codec,
+            width: u16_at(12),
+            height: u16_at(14),
+            timebase_den: u32_at(16),
+            timebase_num: u32_at(20),
+            frame_count: u32_at(24),
+        };
+        if header.timebase_num == 0 || header.timebase_den == 0 {
+            return Err(invalid_data("invalid ivf timebase"));
+        }
+        Ok(header)
+    }
+
+    fn to_pts(self, d: Duration) -> u64 {
+        (d.as_nanos() * self.timebase_den as u128 / (self.timebase_num as u128 * 1_000_000_000))
+            as u64
+    }
+
+    fn to_duration(self, pts: u64) -> Duration {
+        let nanos =
+            pts as u128 * self.timebase_num as u128 * 1_000_000_000 / self.timebase_den as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+fn fourcc(codec: Codec) -> &'static [u8; 4] {
+    match codec {
+        Codec::VP8 => b"VP80",
+        Codec::VP9 => b"VP90",
+        Codec::AV1 => b"AV01",
+        Codec::H264 => b"H264",
+        Codec::H265 => b"HEVC",
+        Codec::MJPEG => b"MJPG",
+    }
+}
+
+fn codec_from_fourcc(fourcc: &[u8]) -> Option<Codec> {
+    match fourcc {
+        b"VP80" => Some(Codec::VP8),
+        b"VP90" => Some(Codec::VP9),
+        b"AV01" => Some(Codec::AV1),
+        b"H264" | b"AVC1" => Some(Codec::H264),
+        b"HEVC" | b"H265" => Some(Codec::H265),
+        b"MJPG" => Some(Codec::MJPEG),
+        _ => None,
+    }
+}
+
+fn invalid_data<E>(msg: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// IVF writer. Packet timestamps are stored relative to the first packet.
+pub struct IvfWriter<W: Write> {
+    writer: W,
+    header: IvfHeader,
+    first_pts: Option<Duration>,
+    last_pts: Option<u64>,
+}
+
+impl<W: Write> IvfWriter<W> {
+    pub fn new(mut writer: W, header: IvfHeader) -> io::Result<Self> {
+        writer.write_all(&header.to_bytes())?;
+        Ok(Self {
+            writer,
+            header,
+            first_pts: None,
+            last_pts: None,
+        })
+    }
+
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> io::Result<()> {
+        if packet.codec != self.header.codec {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "expected {} packet, got {}",
+                    self.header.codec, packet.codec
+                ),
+            ));
+        }
+        let first_pts = *self.first_pts.get_or_insert(packet.pts);
+        let mut pts = self.header.to_pts(packet.pts.saturating_sub(first_pts));
+        // keep pts strictly increasing when the timebase is coarser than the frame interval
+        if let Some(last) = self.last_pts {
+            pts = pts.max(last + 1);
+        }
+        self.last_pts = Some(pts);
+
+        let mut frame_header = [0; FRAME_HEADER_LEN];
+        frame_header[0..4].copy_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        frame_header[4..12].copy_from_slice(&pts.to_le_bytes());
+        self.writer.write_all(&frame_header)?;
+        self.writer.write_all(&packet.data)?;
+        self.header.frame_count += 1;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// Rewrites the header with the number of frames written so far.
+    pub fn update_frame_count(&mut self) -> io::Result<()> {
+        let position = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&self.header.to_bytes())?;
+        self.writer.seek(SeekFrom::Start(position))?;
+        Ok(())
+    }
+
+    /// Writes the final frame count into the header and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.update_frame_count()?;
+        self.into_inner()
+    }
+}
+
+// The header's frame count can only be fixed up on a seekable sink.
+impl<W: Write + Seek> PacketWriter for IvfWriter<W> {
+    fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        self.write(packet)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.update_frame_count()?;
+        self.writer.flush()
+    }
+}
+
+/// IVF reader yielding `Packet`s, for feeding recorded bitstreams back into decoders.
+pub struct IvfReader<R: Read> {
+    reader: R,
+    header: IvfHeader,
+}
+
+impl<R: Read> IvfReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut data = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut data)?;
+        let header = IvfHeader::from_bytes(&data)?;
+        let header_len = u16::from_le_bytes([data[6], data[7]]);
+        if header_len > HEADER_LEN {
+            io::copy(
+                &mut reader.by_ref().take((header_len - HEADER_LEN) as u64),
+                &mut io::sink(),
+            )?;
+        }
+        Ok(Self { reader, header })
+    }
+
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Reads the next frame, `None` at a clean end of file.
+    pub fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut frame_header = [0; FRAME_HEADER_LEN];
+        let mut filled = 0;
+        while filled < FRAME_HEADER_LEN {
+            match self.reader.read(&mut frame_header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let len = u32::from_le_bytes(frame_header[0..4].try_into().unwrap()) as usize;
+        let pts = u64::from_le_bytes(frame_header[4..12].try_into().unwrap());
+        // the size comes from the file, only allocate what is actually there
+        let mut data = Vec::new();
+        self.reader
+            .by_ref()
+            .take(len as u64)
+            .read_to_end(&mut data)?;
+        if data.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(Packet::new(
+            self.header.codec,
+            data,
+            self.header.to_duration(pts),
+        )))
+    }
+}
+
+impl<R: Read> Iterator for IvfReader<R> {
+    type Item = io::Result<Packet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn vp8(keyframe: bool, ms: u64) -> Packet {
+        let tag = if keyframe { 0x10 } else { 0x11 };
+        Packet::new(
+            Codec::VP8,
+            vec![tag, 0, 0, 0x9d, 0x01, 0x2a, ms as u8],
+            Duration::from_millis(ms),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = IvfHeader::new(Codec::VP8, 320, 240);
+        let mut writer = IvfWriter::new(Cursor::new(Vec::new()), header).unwrap();
+        let packets = [vp8(true, 500), vp8(false, 533), vp8(false, 567)];
+        for packet in &packets {
+            writer.write_packet(packet).unwrap();
+        }
+        writer.close().unwrap();
+        let data = writer.into_inner().unwrap().into_inner();
+
+        let mut reader = IvfReader::new(&data[..]).unwrap();
+        assert_eq!(
+            *reader.header(),
+            IvfHeader {
+                frame_count: 3,
+                ..header
+            }
+        );
+        let read: Vec<_> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read.len(), 3);
+        for (read, written) in read.iter().zip(&packets) {
+            assert_eq!(read.data, written.data);
+            assert_eq!(read.keyframe, written.keyframe);
+            assert_eq!(read.pts, written.pts - Duration::from_millis(500));
+        }
+        assert!(reader.read_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn frame_count_without_seek() {
+        let mut writer = IvfWriter::new(Vec::new(), IvfHeader::new(Codec::VP8, 8, 8)).unwrap();
+        writer.write(&vp8(true, 0)).unwrap();
+        let data = writer.into_inner().unwrap();
+        assert_eq!(IvfReader::new(&data[..]).unwrap().header().frame_count, 0);
+
+        let mut writer =
+            IvfWriter::new(Cursor::new(Vec::new()), IvfHeader::new(Codec::VP8, 8, 8)).unwrap();
+        writer.write(&vp8(true, 0)).unwrap();
+        writer.write(&vp8(false, 1)).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(IvfReader::new(&data[..]).unwrap().header().frame_count, 2);
+    }
+
+    #[test]
+    fn bogus_frame_size() {
+        let mut data = IvfHeader::new(Codec::VP8, 8, 8).to_bytes().to_vec();
+        // claims 4 GiB, only 3 bytes follow
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 3]);
+        let mut reader = IvfReader::new(&data[..]).unwrap();
+        let err = reader.read_packet().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // a cut frame header
+        let mut reader = IvfReader::new(&data[..HEADER_LEN as usize + 5]).unwrap();
+        let err = reader.read_packet().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
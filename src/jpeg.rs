@@ -0,0 +1,311 @@
+//! Baseline JPEG encoding of captured frames, for previews and snapshots.
+//!
+//! YCbCr 4:2:0 with the example quantization and Huffman tables of ITU T.81 Annex K.
+//! Not fast, but it has no dependencies.
+
+use std::f32::consts::PI;
+
+use crate::frame::Frame;
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const LUMA_QUANT: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMA_QUANT: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+const DC_LUMA_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const DC_CHROMA_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const AC_LUMA_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const AC_LUMA_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const AC_CHROMA_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const AC_CHROMA_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+// (code, length) per symbol
+struct HuffmanTable {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanTable {
+    fn new(bits: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut k = 0;
+        for (i, &count) in bits.iter().enumerate() {
+            for _ in 0..count {
+                codes[values[k] as usize] = (code, i as u8 + 1);
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        Self { codes }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u16, len: u8) {
+        self.bits = self.bits << len | (value as u32 & ((1 << len) - 1));
+        self.count += len as u32;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.bits >> self.count) as u8;
+            self.out.push(byte);
+            // byte stuffing, 0xff is a marker prefix in entropy coded data
+            if byte == 0xff {
+                self.out.push(0);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            // pad with ones
+            let pad = 8 - self.count as u8;
+            self.put(0xff, pad);
+        }
+        self.out
+    }
+}
+
+fn scaled_table(base: &[u8; 64], quality: u8) -> [u8; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    base.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u8)
+}
+
+fn put_marker(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+// magnitude category and the bits that follow it
+fn category(value: i32) -> (u8, u16) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { value - 1 } else { value };
+    (size as u8, bits as u16)
+}
+
+struct Component<'a> {
+    quant: [f32; 64],
+    dc: &'a HuffmanTable,
+    ac: &'a HuffmanTable,
+    last_dc: i32,
+}
+
+impl Component<'_> {
+    fn encode_block(&mut self, block: &[f32; 64], cos: &[[f32; 8]; 8], w: &mut BitWriter) {
+        // separable DCT-II, rows then columns
+        let mut tmp = [0f32; 64];
+        for y in 0..8 {
+            for u in 0..8 {
+                tmp[y * 8 + u] = (0..8).map(|x| block[y * 8 + x] * cos[u][x]).sum();
+            }
+        }
+        let mut coefficients = [0i32; 64];
+        for (k, &i) in ZIGZAG.iter().enumerate() {
+            let (v, u) = (i / 8, i % 8);
+            let sum: f32 = (0..8).map(|y| tmp[y * 8 + u] * cos[v][y]).sum();
+            coefficients[k] = (sum / 4.0 * self.quant[k]).round() as i32;
+        }
+
+        let diff = coefficients[0] - self.last_dc;
+        self.last_dc = coefficients[0];
+        let (size, bits) = category(diff);
+        let (code, len) = self.dc.codes[size as usize];
+        w.put(code, len);
+        w.put(bits, size);
+
+        let mut run = 0;
+        for &c in &coefficients[1..] {
+            if c == 0 {
+                run += 1;
+                continue;
+            }
+            while run >= 16 {
+                let (code, len) = self.ac.codes[0xf0];
+                w.put(code, len);
+                run -= 16;
+            }
+            let (size, bits) = category(c);
+            let (code, len) = self.ac.codes[(run << 4 | size) as usize];
+            w.put(code, len);
+            w.put(bits, size);
+            run = 0;
+        }
+        if run > 0 {
+            let (code, len) = self.ac.codes[0];
+            w.put(code, len);
+        }
+    }
+}
+
+/// Encodes `frame` at `quality` 1 - 100. Frames larger than 65535 pixels in either
+/// dimension cannot be represented and are cropped.
+pub fn encode(frame: &Frame, quality: u8) -> Vec<u8> {
+    let width = frame.width.min(u16::MAX as u32) as usize;
+    let height = frame.height.min(u16::MAX as u32) as usize;
+    let luma_quant = scaled_table(&LUMA_QUANT, quality);
+    let chroma_quant = scaled_table(&CHROMA_QUANT, quality);
+
+    let mut out = Vec::with_capacity(width * height / 4 + 1024);
+    out.extend_from_slice(&[0xff, 0xd8]);
+    put_marker(&mut out, 0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    for (id, table) in [(0u8, &luma_quant), (1, &chroma_quant)] {
+        let mut body = vec![id];
+        body.extend(ZIGZAG.iter().map(|&i| table[i]));
+        put_marker(&mut out, 0xdb, &body);
+    }
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(height as u16).to_be_bytes());
+    sof.extend_from_slice(&(width as u16).to_be_bytes());
+    sof.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    put_marker(&mut out, 0xc0, &sof);
+    let tables: [(u8, &[u8; 16], &[u8]); 4] = [
+        (0x00, &DC_LUMA_BITS, &DC_VALUES),
+        (0x10, &AC_LUMA_BITS, &AC_LUMA_VALUES),
+        (0x01, &DC_CHROMA_BITS, &DC_VALUES),
+        (0x11, &AC_CHROMA_BITS, &AC_CHROMA_VALUES),
+    ];
+    for (class, bits, values) in tables {
+        let mut body = vec![class];
+        body.extend_from_slice(bits);
+        body.extend_from_slice(values);
+        put_marker(&mut out, 0xc4, &body);
+    }
+    put_marker(&mut out, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let dc_luma = HuffmanTable::new(&DC_LUMA_BITS, &DC_VALUES);
+    let ac_luma = HuffmanTable::new(&AC_LUMA_BITS, &AC_LUMA_VALUES);
+    let dc_chroma = HuffmanTable::new(&DC_CHROMA_BITS, &DC_VALUES);
+    let ac_chroma = HuffmanTable::new(&AC_CHROMA_BITS, &AC_CHROMA_VALUES);
+    // quantizer reciprocals in zigzag order
+    let reciprocal = |table: &[u8; 64]| -> [f32; 64] {
+        let mut r = [0f32; 64];
+        for (k, &i) in ZIGZAG.iter().enumerate() {
+            r[k] = 1.0 / table[i] as f32;
+        }
+        r
+    };
+    let mut components = [
+        Component {
+            quant: reciprocal(&luma_quant),
+            dc: &dc_luma,
+            ac: &ac_luma,
+            last_dc: 0,
+        },
+        Component {
+            quant: reciprocal(&chroma_quant),
+            dc: &dc_chroma,
+            ac: &ac_chroma,
+            last_dc: 0,
+        },
+        Component {
+            quant: reciprocal(&chroma_quant),
+            dc: &dc_chroma,
+            ac: &ac_chroma,
+            last_dc: 0,
+        },
+    ];
+    let mut cos = [[0f32; 8]; 8];
+    for (u, row) in cos.iter_mut().enumerate() {
+        let scale = if u == 0 { 1.0 / 2f32.sqrt() } else { 1.0 };
+        for (x, c) in row.iter_mut().enumerate() {
+            *c = scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+        }
+    }
+
+    let mut writer = BitWriter {
+        out,
+        bits: 0,
+        count: 0,
+    };
+    let bpp = frame.format.bytes_per_pixel();
+    // level shifted Y, Cb, Cr of one 16x16 macroblock, edges replicated
+    let mut ycc = [[0f32; 256]; 3];
+    for my in (0..height).step_by(16) {
+        for mx in (0..width).step_by(16) {
+            for y in 0..16 {
+                let row = frame.row((my + y).min(height - 1) as u32);
+                for x in 0..16 {
+                    let i = (mx + x).min(width - 1) * bpp;
+                    let (b, g, r) = (row[i] as f32, row[i + 1] as f32, row[i + 2] as f32);
+                    ycc[0][y * 16 + x] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+                    ycc[1][y * 16 + x] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    ycc[2][y * 16 + x] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+            }
+            let mut block = [0f32; 64];
+            for (by, bx) in [(0, 0), (0, 8), (8, 0), (8, 8)] {
+                for y in 0..8 {
+                    for x in 0..8 {
+                        block[y * 8 + x] = ycc[0][(by + y) * 16 + bx + x];
+                    }
+                }
+                components[0].encode_block(&block, &cos, &mut writer);
+            }
+            for c in 1..3 {
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let i = y * 32 + x * 2;
+                        let p = &ycc[c];
+                        block[y * 8 + x] = (p[i] + p[i + 1] + p[i + 16] + p[i + 17]) / 4.0;
+                    }
+                }
+                components[c].encode_block(&block, &cos, &mut writer);
+            }
+        }
+    }
+    let mut out = writer.finish();
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
@@ -1,24 +1,82 @@
+#[cfg(windows)]
 use staging_texture::StagingTexture;
+#[cfg(windows)]
 use windows::Win32::{Foundation::LUID, Graphics::{Direct3D11::{ID3D11ShaderResourceView, ID3D11Texture2D, D3D11_MAPPED_SUBRESOURCE}, Dxgi::IDXGIOutputDuplication}};
 
+#[cfg(windows)]
 pub mod d3d11;
+#[cfg(windows)]
 pub mod utils;
+#[cfg(windows)]
 pub mod staging_texture;
 
+pub mod adapter;
+pub mod annexb;
+#[cfg(feature = "async")]
+pub mod async_stream;
+pub mod backend;
+pub mod codec;
+pub mod frame;
+pub mod ivf;
+pub mod jpeg;
+pub mod mkv;
+pub mod mp4;
+pub mod net;
+pub mod output;
+pub mod pacer;
+#[cfg(feature = "preview")]
+pub mod preview;
+pub mod queue;
+pub mod replay;
+pub mod rfb;
+pub mod rtp;
+pub mod segment;
+pub mod shm;
+pub mod stream;
+pub mod synthetic;
+pub mod timelapse;
+pub mod topology;
+pub mod worker;
+
+#[cfg(windows)]
 pub use d3d11::CaptureDXGI;
+pub use adapter::{AdapterKind, AdapterSelector, RawAdapterDesc, Vendor};
+pub use output::{ColorSpace, MonitorSelector, OutputDesc, Rotation};
 
+#[cfg(windows)]
 pub struct OtherFrame<'a> {
     pub texture: &'a StagingTexture,
     pub ptr: D3D11_MAPPED_SUBRESOURCE,
 }
 
+#[cfg(windows)]
 pub struct OutputDuplication {
     duplication: IDXGIOutputDuplication,
     output_dimensions: (u32, u32),
     adapter_desc: AdapterDesc,
+    output_desc: OutputDesc,
 }
 
-#[derive(Debug, Clone, Default)]
+/// With the `serde` feature the descriptions round-trip through any serde format:
+///
+/// ```
+/// # #[cfg(feature = "serde")] {
+/// use dxgi::{AdapterDesc, Luid};
+///
+/// let adapter = AdapterDesc {
+///     description: "NVIDIA GeForce RTX 3060".to_string(),
+///     luid: Luid(0x0000_0000_0001_2f7e),
+///     vendor_id: 0x10de,
+///     is_hardware: true,
+///     ..Default::default()
+/// };
+/// let json = serde_json::to_string(&adapter).unwrap();
+/// assert!(json.contains(r#""luid":"0000000000012f7e""#));
+/// assert_eq!(serde_json::from_str::<AdapterDesc>(&json).unwrap(), adapter);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AdapterDesc {
     // 适配器索引
     pub index: u32,
@@ -40,16 +98,36 @@ pub struct AdapterDesc {
     pub is_discrete: bool,
     // 集成显卡
     pub is_integrated: bool,
+    // 专用显存（字节）
+    pub dedicated_video_memory: u64,
+    // 专用系统内存（字节）
+    pub dedicated_system_memory: u64,
+    // 共享系统内存（字节）
+    pub shared_system_memory: u64,
 }
 
-#[derive(Clone, Debug, Default)]
+/// Adapter LUID. `Display` and `FromStr` use 16 hex digits, which is also the serde form.
+///
+/// ```
+/// use dxgi::Luid;
+///
+/// let luid = Luid(0x0000_0001_0000_d2c4);
+/// assert_eq!(luid.to_string(), "000000010000d2c4");
+/// assert_eq!("000000010000d2c4".parse::<Luid>().unwrap(), luid);
+/// let negative = Luid(-2);
+/// assert_eq!(negative.to_string().parse::<Luid>().unwrap(), negative);
+/// assert!("not a luid".parse::<Luid>().is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Luid(pub i64);
 
+#[cfg(windows)]
 impl From<windows::Win32::Foundation::LUID> for Luid {
     fn from(src: windows::Win32::Foundation::LUID) -> Luid {
         Luid(src.LowPart as i64 | ((src.HighPart as i64) << 32))
     }
 }
+#[cfg(windows)]
 impl From<Luid> for windows::Win32::Foundation::LUID {
     fn from(src: Luid) -> windows::Win32::Foundation::LUID {
         LUID {
@@ -73,6 +151,32 @@ impl std::fmt::Display for Luid {
     }
 }
 
+impl std::str::FromStr for Luid {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(|v| Luid(v as i64))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Luid {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Luid {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::{Error, Unexpected};
+
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|_| D::Error::invalid_value(Unexpected::Str(&s), &"16 hex digits"))
+    }
+}
+
 impl std::fmt::Display for AdapterDesc {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, " Adapter: [{}]", self.description)?;
@@ -0,0 +1,877 @@
+use std::io::{self, Seek, SeekFrom, Write};
+use std::time::Duration;
+
+use crate::annexb::{self, BitReader, NalType, ParameterSetCache};
+use crate::codec::{self, Codec, Packet, PacketWriter};
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const VOID: u32 = 0xEC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+// Segment and clusters are written with an unknown size so a truncated file stays parseable.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+const TRACK: u8 = 1;
+// reserved after Info for the SeekHead, enough for three entries and a Void
+const SEEK_HEAD_SPACE: usize = 96;
+const DEFAULT_CLUSTER_DURATION: Duration = Duration::from_secs(5);
+// SimpleBlock timestamps are signed 16 bit offsets from the cluster timestamp
+const MAX_BLOCK_OFFSET_MS: u64 = i16::MAX as u64;
+
+/// Streaming Matroska/WebM writer.
+///
+/// Every SimpleBlock is flushed as soon as it is written, so a recording cut off by a
+/// process crash plays back up to the last complete block. Flushing only hands the
+/// bytes to the OS, they are not synced to disk. Timestamps are in milliseconds
+/// relative to the first written packet. Cues are appended on `finish`, and a SeekHead
+/// pointing at them is written into space reserved after Info.
+pub struct MkvWriter<W: Write> {
+    writer: Option<W>,
+    codec: Codec,
+    width: u32,
+    height: u32,
+    max_cluster_duration: u64,
+    parameter_sets: ParameterSetCache,
+    started: bool,
+    first_pts: Option<Duration>,
+    // bytes written into the segment payload, cue positions are relative to its start
+    position: u64,
+    // segment positions of the reserved SeekHead space, Tracks and Cues
+    seek_head_position: u64,
+    tracks_position: u64,
+    cues_position: Option<u64>,
+    cluster_timestamp: Option<u64>,
+    cues: Vec<(u64, u64)>,
+}
+
+impl<W: Write> MkvWriter<W> {
+    pub fn new(writer: W, codec: Codec, width: u32, height: u32) -> Self {
+        Self {
+            writer: Some(writer),
+            codec,
+            width,
+            height,
+            max_cluster_duration: DEFAULT_CLUSTER_DURATION.as_millis() as u64,
+            parameter_sets: ParameterSetCache::new(codec),
+            started: false,
+            first_pts: None,
+            position: 0,
+            seek_head_position: 0,
+            tracks_position: 0,
+            cues_position: None,
+            cluster_timestamp: None,
+            cues: Vec::new(),
+        }
+    }
+
+    pub fn set_cluster_duration(&mut self, duration: Duration) {
+        self.max_cluster_duration = (duration.as_millis() as u64).clamp(1, MAX_BLOCK_OFFSET_MS);
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> io::Result<()> {
+        self.writer()?;
+        if packet.codec != self.codec {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected {} packet, got {}", self.codec, packet.codec),
+            ));
+        }
+        if self.codec.is_annexb() {
+            self.parameter_sets.update(&packet.data);
+        }
+
+        if !self.started {
+            let ready = !self.codec.is_annexb() || self.parameter_sets.is_complete();
+            if !packet.keyframe || !ready {
+                log::debug!("mkv: dropping packet before first keyframe");
+                return Ok(());
+            }
+            let (header, segment_start, info_len) = self.build_header(&packet.data)?;
+            self.writer()?.write_all(&header)?;
+            self.position = (header.len() - segment_start) as u64;
+            self.seek_head_position = info_len as u64;
+            self.tracks_position = (info_len + SEEK_HEAD_SPACE) as u64;
+            self.started = true;
+            self.first_pts = Some(packet.pts);
+        }
+
+        let timestamp = packet
+            .pts
+            .saturating_sub(self.first_pts.unwrap_or_default())
+            .as_millis() as u64;
+        let new_cluster = match self.cluster_timestamp {
+            None => true,
+            Some(cluster) => {
+                packet.keyframe
+                    || timestamp < cluster
+                    || timestamp - cluster >= self.max_cluster_duration
+            }
+        };
+
+        let mut out = Vec::new();
+        if new_cluster {
+            if packet.keyframe {
+                self.cues.push((timestamp, self.position));
+            }
+            write_id(&mut out, CLUSTER);
+            out.extend_from_slice(&UNKNOWN_SIZE);
+            write_uint(&mut out, TIMESTAMP, timestamp);
+            self.cluster_timestamp = Some(timestamp);
+        }
+
+        let data = if self.codec.is_annexb() {
+            let mut data = Vec::with_capacity(packet.data.len());
+            for nal in annexb::split(&packet.data) {
+                match NalType::parse(self.codec, nal) {
+                    Some(t) if t.is_parameter_set() => continue,
+                    Some(NalType::Aud) => continue,
+                    _ => {}
+                }
+                data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                data.extend_from_slice(nal);
+            }
+            data
+        } else {
+            packet.data.clone()
+        };
+        let offset = (timestamp - self.cluster_timestamp.unwrap_or(timestamp)) as i16;
+        write_id(&mut out, SIMPLE_BLOCK);
+        write_size(&mut out, 4 + data.len() as u64);
+        out.push(0x80 | TRACK);
+        out.extend_from_slice(&offset.to_be_bytes());
+        out.push(if packet.keyframe { 0x80 } else { 0 });
+        out.extend_from_slice(&data);
+
+        self.position += out.len() as u64;
+        let writer = self.writer()?;
+        writer.write_all(&out)?;
+        writer.flush()
+    }
+
+    fn writer(&mut self) -> io::Result<&mut W> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("mkv writer already finished"))
+    }
+
+    fn write_cues(&mut self) -> io::Result<()> {
+        if !self.started || self.cues.is_empty() {
+            return Ok(());
+        }
+        let mut cues = Vec::new();
+        for (time, position) in &self.cues {
+            let mut positions = Vec::new();
+            write_uint(&mut positions, CUE_TRACK, TRACK as u64);
+            write_uint(&mut positions, CUE_CLUSTER_POSITION, *position);
+            let mut point = Vec::new();
+            write_uint(&mut point, CUE_TIME, *time);
+            write_element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            write_element(&mut cues, CUE_POINT, &point);
+        }
+        let mut out = Vec::new();
+        write_element(&mut out, CUES, &cues);
+        self.cues.clear();
+        self.cues_position = Some(self.position);
+        self.position += out.len() as u64;
+        self.writer()?.write_all(&out)
+    }
+
+    // returns the header, the offset of the segment payload in it and the length of Info
+    fn build_header(&self, keyframe: &[u8]) -> io::Result<(Vec<u8>, usize, usize)> {
+        let (codec_id, codec_private) = match self.codec {
+            Codec::H264 => ("V_MPEG4/ISO/AVC", self.parameter_sets.decoder_config()),
+            Codec::H265 => ("V_MPEGH/ISO/HEVC", self.parameter_sets.decoder_config()),
+            Codec::VP8 => ("V_VP8", None),
+            Codec::VP9 => ("V_VP9", None),
+            Codec::AV1 => ("V_AV1", av1_codec_config(keyframe)),
+            Codec::MJPEG => ("V_MJPEG", None),
+        };
+        if codec_private.is_none() && matches!(self.codec, Codec::H264 | Codec::H265 | Codec::AV1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no {} decoder configuration", self.codec),
+            ));
+        }
+        let doc_type = if matches!(self.codec, Codec::VP8 | Codec::VP9 | Codec::AV1) {
+            "webm"
+        } else {
+            "matroska"
+        };
+
+        let mut ebml = Vec::new();
+        write_uint(&mut ebml, EBML_VERSION, 1);
+        write_uint(&mut ebml, EBML_READ_VERSION, 1);
+        write_uint(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+        write_uint(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+        write_string(&mut ebml, DOC_TYPE, doc_type);
+        write_uint(&mut ebml, DOC_TYPE_VERSION, 4);
+        write_uint(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+
+        let mut info = Vec::new();
+        write_uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        write_string(&mut info, MUXING_APP, "dxgi");
+        write_string(&mut info, WRITING_APP, "dxgi");
+
+        let mut video = Vec::new();
+        write_uint(&mut video, PIXEL_WIDTH, self.width as u64);
+        write_uint(&mut video, PIXEL_HEIGHT, self.height as u64);
+
+        let mut track = Vec::new();
+        write_uint(&mut track, TRACK_NUMBER, TRACK as u64);
+        write_uint(&mut track, TRACK_UID, TRACK as u64);
+        write_uint(&mut track, TRACK_TYPE, 1);
+        write_uint(&mut track, FLAG_LACING, 0);
+        write_string(&mut track, CODEC_ID, codec_id);
+        if let Some(codec_private) = codec_private {
+            write_element(&mut track, CODEC_PRIVATE, &codec_private);
+        }
+        write_element(&mut track, VIDEO, &video);
+        let mut tracks = Vec::new();
+        write_element(&mut tracks, TRACK_ENTRY, &track);
+
+        let mut out = Vec::new();
+        write_element(&mut out, EBML, &ebml);
+        write_id(&mut out, SEGMENT);
+        out.extend_from_slice(&UNKNOWN_SIZE);
+        let segment_start = out.len();
+        write_element(&mut out, INFO, &info);
+        let info_len = out.len() - segment_start;
+        write_void(&mut out, SEEK_HEAD_SPACE);
+        write_element(&mut out, TRACKS, &tracks);
+        Ok((out, segment_start, info_len))
+    }
+}
+
+// The SeekHead can only be filled in on a seekable sink, elsewhere the space stays Void.
+impl<W: Write + Seek> MkvWriter<W> {
+    /// Writes the SeekHead into the space reserved for it.
+    pub fn update_seek_head(&mut self) -> io::Result<()> {
+        if !self.started {
+            return Ok(());
+        }
+        let mut entries = vec![(INFO, 0), (TRACKS, self.tracks_position)];
+        entries.extend(self.cues_position.map(|position| (CUES, position)));
+        let mut seeks = Vec::new();
+        for (id, position) in entries {
+            let mut seek = Vec::new();
+            write_element(&mut seek, SEEK_ID, &id.to_be_bytes());
+            write_uint(&mut seek, SEEK_POSITION, position);
+            write_element(&mut seeks, SEEK, &seek);
+        }
+        let mut out = Vec::new();
+        write_element(&mut out, SEEK_HEAD, &seeks);
+        let padding = SEEK_HEAD_SPACE - out.len();
+        write_void(&mut out, padding);
+
+        let position = self.position;
+        let seek_head_position = self.seek_head_position;
+        let writer = self.writer()?;
+        let end = writer.stream_position()?;
+        writer.seek(SeekFrom::Start(end - position + seek_head_position))?;
+        writer.write_all(&out)?;
+        writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    /// Writes the cues and the SeekHead and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_cues()?;
+        self.update_seek_head()?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write + Seek> PacketWriter for MkvWriter<W> {
+    fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        self.write(packet)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.write_cues()?;
+        self.update_seek_head()?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for MkvWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_none() {
+            return;
+        }
+        if let Err(e) = self.write_cues() {
+            log::error!("mkv: failed to write cues: {:?}", e);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+// AV1CodecConfigurationRecord built from the sequence header OBU of a keyframe, AV1
+// spec 5.5. `None` if the sequence header is missing or cut short.
+fn av1_codec_config(temporal_unit: &[u8]) -> Option<Vec<u8>> {
+    let (_, obu) = codec::av1_obus(temporal_unit)
+        .find(|(obu_type, _)| *obu_type == codec::AV1_OBU_SEQUENCE_HEADER)?;
+    let header_len = if obu[0] & 0x04 != 0 { 2 } else { 1 };
+    let mut payload = obu.get(header_len..)?;
+    if obu[0] & 0x02 != 0 {
+        // skip the leb128 size
+        let len = payload.iter().position(|b| b & 0x80 == 0)? + 1;
+        payload = &payload[len..];
+    }
+
+    let mut r = BitReader::new(payload);
+    let seq_profile = r.read_bits(3)?;
+    r.skip(1)?; // still_picture
+    let reduced_still_picture_header = r.read_bit()? == 1;
+    let (seq_level_idx, seq_tier) = if reduced_still_picture_header {
+        (r.read_bits(5)?, 0)
+    } else {
+        let mut buffer_delay_length = 0;
+        let timing_info_present = r.read_bit()? == 1;
+        let decoder_model_info_present = timing_info_present && {
+            r.skip(64)?; // num_units_in_display_tick, time_scale
+            if r.read_bit()? == 1 {
+                // equal_picture_interval, num_ticks_per_picture_minus_1 as uvlc
+                let mut zeros = 0;
+                while r.read_bit()? == 0 {
+                    zeros += 1;
+                }
+                if zeros < 32 {
+                    r.skip(zeros)?;
+                }
+            }
+            let present = r.read_bit()? == 1;
+            if present {
+                buffer_delay_length = r.read_bits(5)? as usize + 1;
+                r.skip(32 + 5 + 5)?; // decoding tick and time length fields
+            }
+            present
+        };
+        let initial_display_delay_present = r.read_bit()? == 1;
+        let operating_points = r.read_bits(5)? + 1;
+        let mut first = None;
+        for _ in 0..operating_points {
+            r.skip(12)?; // operating_point_idc
+            let level = r.read_bits(5)?;
+            let tier = if level > 7 { r.read_bit()? } else { 0 };
+            if decoder_model_info_present && r.read_bit()? == 1 {
+                // decoder and encoder buffer delay, low_delay_mode_flag
+                r.skip(2 * buffer_delay_length + 1)?;
+            }
+            if initial_display_delay_present && r.read_bit()? == 1 {
+                r.skip(4)?;
+            }
+            first.get_or_insert((level, tier));
+        }
+        first?
+    };
+
+    let frame_width_bits = r.read_bits(4)? as usize + 1;
+    let frame_height_bits = r.read_bits(4)? as usize + 1;
+    r.skip(frame_width_bits + frame_height_bits)?;
+    if !reduced_still_picture_header && r.read_bit()? == 1 {
+        // delta_frame_id_length_minus_2, additional_frame_id_length_minus_1
+        r.skip(7)?;
+    }
+    r.skip(3)?; // use_128x128_superblock, enable_filter_intra, enable_intra_edge_filter
+    if !reduced_still_picture_header {
+        // interintra, masked compound, warped motion, dual filter
+        r.skip(4)?;
+        let enable_order_hint = r.read_bit()? == 1;
+        if enable_order_hint {
+            r.skip(2)?; // enable_jnt_comp, enable_ref_frame_mvs
+        }
+        let force_screen_content_tools = if r.read_bit()? == 1 {
+            2 // SELECT_SCREEN_CONTENT_TOOLS
+        } else {
+            r.read_bit()?
+        };
+        if force_screen_content_tools > 0 && r.read_bit()? == 0 {
+            r.skip(1)?; // seq_force_integer_mv
+        }
+        if enable_order_hint {
+            r.skip(3)?; // order_hint_bits_minus_1
+        }
+    }
+    r.skip(3)?; // enable_superres, enable_cdef, enable_restoration
+
+    // color_config
+    let high_bitdepth = r.read_bit()?;
+    let twelve_bit = if seq_profile == 2 && high_bitdepth == 1 {
+        r.read_bit()?
+    } else {
+        0
+    };
+    let monochrome = if seq_profile == 1 { 0 } else { r.read_bit()? };
+    let (primaries, transfer, matrix) = if r.read_bit()? == 1 {
+        (r.read_bits(8)?, r.read_bits(8)?, r.read_bits(8)?)
+    } else {
+        (2, 2, 2)
+    };
+    let (subsampling_x, subsampling_y, chroma_sample_position) = if monochrome == 1 {
+        (1, 1, 0)
+    } else if (primaries, transfer, matrix) == (1, 13, 0) {
+        // sRGB, always 4:4:4
+        (0, 0, 0)
+    } else {
+        r.skip(1)?; // color_range
+        let (x, y) = match seq_profile {
+            0 => (1, 1),
+            1 => (0, 0),
+            _ if twelve_bit == 1 => {
+                let x = r.read_bit()?;
+                (x, if x == 1 { r.read_bit()? } else { 0 })
+            }
+            _ => (1, 0),
+        };
+        let position = if x == 1 && y == 1 { r.read_bits(2)? } else { 0 };
+        (x, y, position)
+    };
+
+    let mut out = vec![0x81];
+    out.push(((seq_profile as u8) << 5) | seq_level_idx as u8);
+    out.push(
+        ((seq_tier as u8) << 7)
+            | ((high_bitdepth as u8) << 6)
+            | ((twelve_bit as u8) << 5)
+            | ((monochrome as u8) << 4)
+            | ((subsampling_x as u8) << 3)
+            | ((subsampling_y as u8) << 2)
+            | chroma_sample_position as u8,
+    );
+    out.push(0);
+    out.extend_from_slice(obu);
+    Some(out)
+}
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = (id.leading_zeros() / 8) as usize;
+    out.extend_from_slice(&bytes[skip.min(3)..]);
+}
+
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    // the all ones value of each length is reserved for unknown sizes
+    let len = (1..=8).find(|n| size < (1u64 << (7 * n)) - 1).unwrap_or(8);
+    let value = size | (1u64 << (7 * len));
+    out.extend_from_slice(&value.to_be_bytes()[8 - len..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(out, id);
+    write_size(out, payload.len() as u64);
+    out.extend_from_slice(payload);
+}
+
+// a Void element of `len` bytes in all, at least 2
+fn write_void(out: &mut Vec<u8>, len: usize) {
+    write_id(out, VOID);
+    // a one byte size holds up to 126
+    write_size(out, len as u64 - 2);
+    out.resize(out.len() + len - 2, 0);
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = ((value.leading_zeros() / 8) as usize).min(7);
+    write_element(out, id, &bytes[skip..]);
+}
+
+fn write_string(out: &mut Vec<u8>, id: u32, value: &str) {
+    write_element(out, id, value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_id(data: &[u8]) -> Option<(u32, usize)> {
+        let len = data.first()?.leading_zeros() as usize + 1;
+        let id = data.get(..len)?;
+        Some((id.iter().fold(0, |acc, b| (acc << 8) | *b as u32), len))
+    }
+
+    // (value, length), `None` as the value for an unknown size
+    fn read_size(data: &[u8]) -> Option<(Option<u64>, usize)> {
+        let len = data.first()?.leading_zeros() as usize + 1;
+        let bytes = data.get(..len.min(8))?;
+        let value =
+            bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64) & ((1 << (7 * len)) - 1);
+        let unknown = value == (1 << (7 * len)) - 1;
+        Some(((!unknown).then_some(value), len))
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Block {
+        // absolute timestamp in ms and offset of the first byte after the block
+        timestamp: u64,
+        keyframe: bool,
+        data: Vec<u8>,
+        end: usize,
+    }
+
+    // every complete SimpleBlock, stops at the first element cut off
+    fn parse_blocks(data: &[u8]) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+        let mut cluster_timestamp = 0;
+        while let Some((id, id_len)) = read_id(&data[pos..]) {
+            let Some((size, size_len)) = read_size(&data[pos + id_len..]) else {
+                break;
+            };
+            let start = pos + id_len + size_len;
+            // master elements of unknown size are entered, their children follow
+            let Some(size) = size else {
+                assert!(id == SEGMENT || id == CLUSTER, "unknown size for {:x}", id);
+                pos = start;
+                continue;
+            };
+            let end = start + size as usize;
+            let Some(payload) = data.get(start..end) else {
+                break;
+            };
+            match id {
+                TIMESTAMP => {
+                    cluster_timestamp = payload.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+                }
+                SIMPLE_BLOCK => {
+                    assert_eq!(payload[0], 0x80 | TRACK);
+                    let offset = i16::from_be_bytes([payload[1], payload[2]]);
+                    blocks.push(Block {
+                        timestamp: cluster_timestamp.wrapping_add_signed(offset as i64),
+                        keyframe: payload[3] & 0x80 != 0,
+                        data: payload[4..].to_vec(),
+                        end,
+                    });
+                }
+                _ => {}
+            }
+            pos = end;
+        }
+        blocks
+    }
+
+    fn packet(nals: &[&[u8]], ms: u64) -> Packet {
+        let mut data = Vec::new();
+        for nal in nals {
+            data.extend_from_slice(&annexb::START_CODE);
+            data.extend_from_slice(nal);
+        }
+        Packet::new(Codec::H264, data, Duration::from_millis(ms))
+    }
+
+    #[test]
+    fn truncated_stream_parses_up_to_the_cut() {
+        let sps = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+        let mut writer = MkvWriter::new(io::Cursor::new(Vec::new()), Codec::H264, 640, 480);
+        writer.set_cluster_duration(Duration::from_millis(200));
+        let mut expected = Vec::new();
+        for i in 0..30u64 {
+            let ms = 1000 + i * 33;
+            let (packet, nal) = if i % 12 == 0 {
+                let idr = [0x65, i as u8, 1, 2, 3, 4];
+                (packet(&[&sps, &pps, &idr], ms), idr.to_vec())
+            } else {
+                let slice = [0x41, i as u8, 5, 6];
+                (packet(&[&slice], ms), slice.to_vec())
+            };
+            writer.write(&packet).unwrap();
+            let mut data = (nal.len() as u32).to_be_bytes().to_vec();
+            data.extend_from_slice(&nal);
+            expected.push((ms - 1000, i % 12 == 0, data));
+        }
+        let out = writer.finish().unwrap().into_inner();
+
+        let blocks = parse_blocks(&out);
+        let parsed: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.timestamp, b.keyframe, b.data.clone()))
+            .collect();
+        assert_eq!(parsed, expected);
+        // clusters on keyframes and every 200 ms, cues at the end
+        let clusters = out
+            .windows(4)
+            .filter(|w| *w == CLUSTER.to_be_bytes())
+            .count();
+        assert_eq!(clusters, 5);
+        assert!(out[blocks.last().unwrap().end..].starts_with(&CUES.to_be_bytes()));
+
+        // cut anywhere, the blocks that were complete before the cut still parse
+        let header_end = blocks[0].end - blocks[0].data.len() - 4 - 2;
+        for cut in header_end..out.len() {
+            let truncated = parse_blocks(&out[..cut]);
+            let complete = blocks.iter().take_while(|b| b.end <= cut).count();
+            assert_eq!(truncated.len(), complete, "cut at {}", cut);
+            assert_eq!(truncated[..], blocks[..complete]);
+        }
+    }
+
+    // (id, segment position) of the elements at the top level of the segment
+    fn segment_children(data: &[u8]) -> (usize, Vec<(u32, u64)>) {
+        let (_, len) = read_id(data).unwrap();
+        let (size, size_len) = read_size(&data[len..]).unwrap();
+        let mut pos = len + size_len + size.unwrap() as usize;
+        assert_eq!(read_id(&data[pos..]).unwrap().0, SEGMENT);
+        pos += 4 + 8;
+        let segment_start = pos;
+        let mut children = Vec::new();
+        while let Some((id, id_len)) = read_id(&data[pos..]) {
+            children.push((id, (pos - segment_start) as u64));
+            let (size, size_len) = read_size(&data[pos + id_len..]).unwrap();
+            pos += id_len + size_len;
+            // clusters have an unknown size, their blocks are skipped one by one
+            if let Some(size) = size.filter(|_| id != CLUSTER) {
+                pos += size as usize;
+            }
+            if pos >= data.len() {
+                break;
+            }
+        }
+        (segment_start, children)
+    }
+
+    fn seek_entries(payload: &[u8]) -> Vec<(u32, u64)> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos < payload.len() {
+            let (id, id_len) = read_id(&payload[pos..]).unwrap();
+            let (size, size_len) = read_size(&payload[pos + id_len..]).unwrap();
+            let start = pos + id_len + size_len;
+            let seek = &payload[start..start + size.unwrap() as usize];
+            assert_eq!(id, SEEK);
+            // SeekID holding a 4 byte id, then SeekPosition
+            assert_eq!(&seek[..3], &[0x53, 0xAB, 0x84]);
+            let seek_id = u32::from_be_bytes(seek[3..7].try_into().unwrap());
+            assert_eq!(&seek[7..9], &[0x53, 0xAC]);
+            let len = (seek[9] & 0x7f) as usize;
+            let position = seek[10..10 + len]
+                .iter()
+                .fold(0, |acc, b| (acc << 8) | *b as u64);
+            entries.push((seek_id, position));
+            pos = start + seek.len();
+        }
+        entries
+    }
+
+    #[test]
+    fn seek_head_points_at_the_cues() {
+        let sps = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+        let mut writer = MkvWriter::new(io::Cursor::new(Vec::new()), Codec::H264, 64, 48);
+        writer
+            .write(&packet(&[&sps, &pps, &[0x65, 1, 2, 3]], 0))
+            .unwrap();
+        writer.write(&packet(&[&[0x41, 4, 5]], 33)).unwrap();
+
+        // until finished, the space is a Void
+        let (_, children) = segment_children(writer.writer.as_ref().unwrap().get_ref());
+        let ids: Vec<_> = children.iter().map(|(id, _)| *id).collect();
+        assert_eq!(
+            ids,
+            [
+                INFO,
+                VOID,
+                TRACKS,
+                CLUSTER,
+                TIMESTAMP,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK
+            ]
+        );
+
+        let out = writer.finish().unwrap().into_inner();
+        let (segment_start, children) = segment_children(&out);
+        let ids: Vec<_> = children.iter().map(|(id, _)| *id).collect();
+        assert_eq!(
+            ids,
+            [
+                INFO,
+                SEEK_HEAD,
+                VOID,
+                TRACKS,
+                CLUSTER,
+                TIMESTAMP,
+                SIMPLE_BLOCK,
+                SIMPLE_BLOCK,
+                CUES
+            ]
+        );
+        let position = |wanted| children.iter().find(|(id, _)| *id == wanted).unwrap().1;
+        // the SeekHead and its padding fill exactly the reserved space
+        assert_eq!(
+            position(TRACKS) - position(SEEK_HEAD),
+            SEEK_HEAD_SPACE as u64
+        );
+
+        let start = segment_start + position(SEEK_HEAD) as usize + 4;
+        let (size, size_len) = read_size(&out[start..]).unwrap();
+        let payload = &out[start + size_len..][..size.unwrap() as usize];
+        assert_eq!(
+            seek_entries(payload),
+            [
+                (INFO, position(INFO)),
+                (TRACKS, position(TRACKS)),
+                (CUES, position(CUES))
+            ]
+        );
+    }
+
+    // writes bits most significant first
+    #[derive(Default)]
+    struct Bits(Vec<bool>);
+
+    impl Bits {
+        fn put(&mut self, n: usize, value: u64) -> &mut Self {
+            self.0.extend((0..n).rev().map(|i| value >> i & 1 == 1));
+            self
+        }
+
+        // a sequence header OBU with a size field
+        fn obu(&self) -> Vec<u8> {
+            let payload: Vec<u8> = self
+                .0
+                .chunks(8)
+                .map(|byte| {
+                    byte.iter()
+                        .enumerate()
+                        .fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i))
+                })
+                .collect();
+            let mut obu = vec![(codec::AV1_OBU_SEQUENCE_HEADER << 3) | 0x02];
+            obu.push(payload.len() as u8);
+            obu.extend_from_slice(&payload);
+            obu
+        }
+    }
+
+    // the fields following the operating points, up to and including color_config
+    fn rest(bits: &mut Bits, profile: u64, high_bitdepth: bool, color: Option<(u64, u64, u64)>) {
+        bits.put(4, 11).put(4, 10).put(12, 1919).put(11, 1079);
+        bits.put(1, 0); // frame_id_numbers_present_flag
+        bits.put(3, 0).put(4, 0);
+        bits.put(1, 1).put(2, 0); // enable_order_hint, jnt_comp and ref_frame_mvs
+        bits.put(1, 1); // seq_choose_screen_content_tools
+        bits.put(1, 1); // seq_choose_integer_mv
+        bits.put(3, 6); // order_hint_bits_minus_1
+        bits.put(3, 0);
+        bits.put(1, high_bitdepth as u64);
+        if profile != 1 {
+            bits.put(1, 0); // mono_chrome
+        }
+        match color {
+            Some((primaries, transfer, matrix)) => {
+                bits.put(1, 1)
+                    .put(8, primaries)
+                    .put(8, transfer)
+                    .put(8, matrix);
+            }
+            None => {
+                bits.put(1, 0);
+            }
+        }
+        if color != Some((1, 13, 0)) {
+            bits.put(1, 0); // color_range
+            if profile == 0 {
+                bits.put(2, 1); // chroma_sample_position
+            }
+        }
+        bits.put(1, 0).put(1, 0); // separate_uv_delta_q, film_grain_params_present
+    }
+
+    #[test]
+    fn av1_config_from_the_sequence_header() {
+        // main profile 8 bit 4:2:0, level 5.0 (12) in the high tier
+        let mut bits = Bits::default();
+        bits.put(3, 0).put(1, 0).put(1, 0);
+        bits.put(1, 0)
+            .put(1, 0)
+            .put(5, 0)
+            .put(12, 0)
+            .put(5, 12)
+            .put(1, 1);
+        rest(&mut bits, 0, false, None);
+        let obu = bits.obu();
+        let config = av1_codec_config(&obu).unwrap();
+        assert_eq!(&config[..4], &[0x81, 12, 0x80 | 0x0c | 1, 0]);
+        assert_eq!(&config[4..], &obu[..]);
+
+        // 10 bit with timing and decoder model info and two operating points
+        let mut bits = Bits::default();
+        bits.put(3, 0).put(1, 0).put(1, 0);
+        bits.put(1, 1); // timing_info_present_flag
+        bits.put(32, 1).put(32, 60).put(1, 1).put(5, 0b00111); // uvlc 6
+        bits.put(1, 1).put(5, 9).put(32, 1).put(5, 31).put(5, 31);
+        bits.put(1, 1).put(5, 1); // initial_display_delay_present_flag, 2 points
+        for level in [9, 4] {
+            bits.put(12, 0x103).put(5, level);
+            if level > 7 {
+                bits.put(1, 0);
+            }
+            bits.put(1, 1).put(10, 5).put(10, 7).put(1, 0); // operating_parameters_info
+            bits.put(1, 1).put(4, 3);
+        }
+        rest(&mut bits, 0, true, Some((9, 16, 9)));
+        let config = av1_codec_config(&bits.obu()).unwrap();
+        assert_eq!(&config[..4], &[0x81, 9, 0x40 | 0x0c | 1, 0]);
+
+        // high profile, 4:4:4
+        let mut bits = Bits::default();
+        bits.put(3, 1).put(1, 0).put(1, 0);
+        bits.put(1, 0)
+            .put(1, 0)
+            .put(5, 0)
+            .put(12, 0)
+            .put(5, 8)
+            .put(1, 0);
+        rest(&mut bits, 1, false, Some((1, 13, 0)));
+        let config = av1_codec_config(&bits.obu()).unwrap();
+        assert_eq!(&config[..4], &[0x81, 0x20 | 8, 0, 0]);
+
+        // cut short
+        let obu = bits.obu();
+        let mut truncated = obu[..obu.len() - 2].to_vec();
+        truncated[1] -= 2;
+        assert_eq!(av1_codec_config(&truncated), None);
+    }
+}
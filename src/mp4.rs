@@ -0,0 +1,568 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::annexb::{self, NalType, ParameterSetCache};
+use crate::codec::{Codec, Packet, PacketWriter};
+
+pub const TIMESCALE: u32 = 90_000;
+const TRACK_ID: u32 = 1;
+const DEFAULT_FRAGMENT_DURATION: Duration = Duration::from_secs(1);
+// 30fps, used when the last sample of a stream has no successor to measure against
+const FALLBACK_SAMPLE_DURATION: u32 = TIMESCALE / 30;
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x0200_0000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x0101_0000;
+
+struct Sample {
+    data: Vec<u8>,
+    decode_time: u64,
+    keyframe: bool,
+}
+
+/// Fragmented MP4 writer for H.264/H.265 packets.
+///
+/// Nothing is written until the first keyframe with its parameter sets arrives. Each
+/// keyframe starts a new fragment, and long GOPs are cut every `fragment_duration`.
+pub struct Mp4Writer<W: Write> {
+    writer: Option<W>,
+    codec: Codec,
+    width: u32,
+    height: u32,
+    fragment_duration: u64,
+    parameter_sets: ParameterSetCache,
+    init_segment: Option<Vec<u8>>,
+    first_pts: Option<Duration>,
+    pending: Vec<Sample>,
+    last_duration: u32,
+    sequence_number: u32,
+}
+
+impl<W: Write> Mp4Writer<W> {
+    pub fn new(writer: W, codec: Codec, width: u32, height: u32) -> Self {
+        Self {
+            writer: Some(writer),
+            codec,
+            width,
+            height,
+            fragment_duration: to_ticks(DEFAULT_FRAGMENT_DURATION),
+            parameter_sets: ParameterSetCache::new(codec),
+            init_segment: None,
+            first_pts: None,
+            pending: Vec::new(),
+            last_duration: FALLBACK_SAMPLE_DURATION,
+            sequence_number: 0,
+        }
+    }
+
+    pub fn set_fragment_duration(&mut self, duration: Duration) {
+        self.fragment_duration = to_ticks(duration).max(1);
+    }
+
+    /// `ftyp` + `moov`, available once the first keyframe has been written.
+    pub fn init_segment(&self) -> Option<&[u8]> {
+        self.init_segment.as_deref()
+    }
+
+    /// The inner writer, `None` once finished.
+    pub fn get_mut(&mut self) -> Option<&mut W> {
+        self.writer.as_mut()
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> io::Result<()> {
+        self.writer()?;
+        if !self.codec.is_annexb() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is not supported in mp4", self.codec),
+            ));
+        }
+        if packet.codec != self.codec {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("expected {} packet, got {}", self.codec, packet.codec),
+            ));
+        }
+        self.parameter_sets.update(&packet.data);
+
+        if self.init_segment.is_none() {
+            if !packet.keyframe || !self.parameter_sets.is_complete() {
+                log::debug!("mp4: dropping packet before first keyframe");
+                return Ok(());
+            }
+            let init = self.build_init_segment()?;
+            self.writer()?.write_all(&init)?;
+            self.init_segment = Some(init);
+            self.first_pts = Some(packet.pts);
+        }
+
+        let first_pts = self.first_pts.unwrap_or_default();
+        let decode_time = to_ticks(packet.pts.saturating_sub(first_pts));
+        if let Some(first) = self.pending.first() {
+            if packet.keyframe
+                || decode_time.saturating_sub(first.decode_time) >= self.fragment_duration
+            {
+                self.flush_fragment(Some(decode_time))?;
+            }
+        }
+
+        let mut data = Vec::with_capacity(packet.data.len());
+        for nal in annexb::split(&packet.data) {
+            match NalType::parse(self.codec, nal) {
+                Some(t) if t.is_parameter_set() => continue,
+                Some(NalType::Aud) => continue,
+                _ => {}
+            }
+            data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            data.extend_from_slice(nal);
+        }
+        self.pending.push(Sample {
+            data,
+            decode_time,
+            keyframe: packet.keyframe,
+        });
+        Ok(())
+    }
+
+    /// Size of the samples held back for the current fragment.
+    pub fn buffered_bytes(&self) -> u64 {
+        self.pending.iter().map(|s| s.data.len() as u64).sum()
+    }
+
+    /// Writes any pending samples and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_fragment(None)?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn writer(&mut self) -> io::Result<&mut W> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| io::Error::other("mp4 writer already finished"))
+    }
+
+    fn flush_fragment(&mut self, next_decode_time: Option<u64>) -> io::Result<()> {
+        if self.pending.is_empty() || self.writer.is_none() {
+            return Ok(());
+        }
+        let samples = std::mem::take(&mut self.pending);
+        let mut durations = Vec::with_capacity(samples.len());
+        for (i, sample) in samples.iter().enumerate() {
+            let next = match samples.get(i + 1) {
+                Some(next) => Some(next.decode_time),
+                None => next_decode_time,
+            };
+            let duration = match next {
+                Some(next) => (next.saturating_sub(sample.decode_time) as u32).max(1),
+                None => self.last_duration,
+            };
+            self.last_duration = duration;
+            durations.push(duration);
+        }
+
+        self.sequence_number += 1;
+        let fragment = build_fragment(self.sequence_number, &samples, &durations);
+        self.writer()?.write_all(&fragment)
+    }
+
+    fn build_init_segment(&self) -> io::Result<Vec<u8>> {
+        let sample_entry = sample_entry(&self.parameter_sets, self.width, self.height)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid parameter sets"))?;
+
+        let mut out = Vec::new();
+        write_box(&mut out, b"ftyp", |b| {
+            b.extend_from_slice(b"isom");
+            put_u32(b, 0x200);
+            for brand in [b"isom", b"iso6", b"iso2", b"mp41"] {
+                b.extend_from_slice(brand);
+            }
+            b.extend_from_slice(match self.codec {
+                Codec::H265 => b"hvc1",
+                _ => b"avc1",
+            });
+        });
+        write_box(&mut out, b"moov", |b| {
+            write_full_box(b, b"mvhd", 0, 0, |b| {
+                put_u32(b, 0); // creation_time
+                put_u32(b, 0); // modification_time
+                put_u32(b, 1000);
+                put_u32(b, 0); // duration, unknown for fragmented files
+                put_u32(b, 0x0001_0000); // rate
+                put_u16(b, 0x0100); // volume
+                b.extend_from_slice(&[0; 10]);
+                put_matrix(b);
+                b.extend_from_slice(&[0; 24]);
+                put_u32(b, TRACK_ID + 1);
+            });
+            write_box(b, b"trak", |b| {
+                write_full_box(b, b"tkhd", 0, 3, |b| {
+                    put_u32(b, 0);
+                    put_u32(b, 0);
+                    put_u32(b, TRACK_ID);
+                    put_u32(b, 0);
+                    put_u32(b, 0); // duration
+                    b.extend_from_slice(&[0; 8]);
+                    put_u16(b, 0); // layer
+                    put_u16(b, 0); // alternate_group
+                    put_u16(b, 0); // volume
+                    put_u16(b, 0);
+                    put_matrix(b);
+                    put_u32(b, self.width << 16);
+                    put_u32(b, self.height << 16);
+                });
+                write_box(b, b"mdia", |b| {
+                    write_full_box(b, b"mdhd", 0, 0, |b| {
+                        put_u32(b, 0);
+                        put_u32(b, 0);
+                        put_u32(b, TIMESCALE);
+                        put_u32(b, 0);
+                        put_u16(b, 0x55c4); // "und"
+                        put_u16(b, 0);
+                    });
+                    write_full_box(b, b"hdlr", 0, 0, |b| {
+                        put_u32(b, 0);
+                        b.extend_from_slice(b"vide");
+                        b.extend_from_slice(&[0; 12]);
+                        b.extend_from_slice(b"VideoHandler\0");
+                    });
+                    write_box(b, b"minf", |b| {
+                        write_full_box(b, b"vmhd", 0, 1, |b| b.extend_from_slice(&[0; 8]));
+                        write_box(b, b"dinf", |b| {
+                            write_full_box(b, b"dref", 0, 0, |b| {
+                                put_u32(b, 1);
+                                write_full_box(b, b"url ", 0, 1, |_| {});
+                            });
+                        });
+                        write_box(b, b"stbl", |b| {
+                            write_full_box(b, b"stsd", 0, 0, |b| {
+                                put_u32(b, 1);
+                                b.extend_from_slice(&sample_entry);
+                            });
+                            write_full_box(b, b"stts", 0, 0, |b| put_u32(b, 0));
+                            write_full_box(b, b"stsc", 0, 0, |b| put_u32(b, 0));
+                            write_full_box(b, b"stsz", 0, 0, |b| {
+                                put_u32(b, 0);
+                                put_u32(b, 0);
+                            });
+                            write_full_box(b, b"stco", 0, 0, |b| put_u32(b, 0));
+                        });
+                    });
+                });
+            });
+            write_box(b, b"mvex", |b| {
+                write_full_box(b, b"trex", 0, 0, |b| {
+                    put_u32(b, TRACK_ID);
+                    put_u32(b, 1); // default_sample_description_index
+                    put_u32(b, 0);
+                    put_u32(b, 0);
+                    put_u32(b, 0);
+                });
+            });
+        });
+        Ok(out)
+    }
+}
+
+impl<W: Write> PacketWriter for Mp4Writer<W> {
+    fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        self.write(packet)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.flush_fragment(None)?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn buffered_bytes(&self) -> u64 {
+        Mp4Writer::buffered_bytes(self)
+    }
+}
+
+impl<W: Write> Drop for Mp4Writer<W> {
+    fn drop(&mut self) {
+        if self.writer.is_none() {
+            return;
+        }
+        if let Err(e) = self.flush_fragment(None) {
+            log::error!("mp4: failed to write last fragment: {:?}", e);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            let _ = writer.flush();
+        }
+    }
+}
+
+fn build_fragment(sequence_number: u32, samples: &[Sample], durations: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut data_offset_pos = 0;
+    write_box(&mut out, b"moof", |b| {
+        write_full_box(b, b"mfhd", 0, 0, |b| put_u32(b, sequence_number));
+        write_box(b, b"traf", |b| {
+            // default-base-is-moof
+            write_full_box(b, b"tfhd", 0, 0x02_0000, |b| put_u32(b, TRACK_ID));
+            write_full_box(b, b"tfdt", 1, 0, |b| put_u64(b, samples[0].decode_time));
+            // data-offset, sample duration, size and flags present
+            write_full_box(b, b"trun", 0, 0x701, |b| {
+                put_u32(b, samples.len() as u32);
+                data_offset_pos = b.len();
+                put_u32(b, 0);
+                for (sample, duration) in samples.iter().zip(durations) {
+                    put_u32(b, *duration);
+                    put_u32(b, sample.data.len() as u32);
+                    put_u32(
+                        b,
+                        if sample.keyframe {
+                            SAMPLE_FLAGS_SYNC
+                        } else {
+                            SAMPLE_FLAGS_NON_SYNC
+                        },
+                    );
+                }
+            });
+        });
+    });
+    // samples start right after the mdat header
+    let data_offset = out.len() as u32 + 8;
+    out[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+    let mdat_len: usize = samples.iter().map(|s| s.data.len()).sum();
+    put_u32(&mut out, (mdat_len + 8) as u32);
+    out.extend_from_slice(b"mdat");
+    for sample in samples {
+        out.extend_from_slice(&sample.data);
+    }
+    out
+}
+
+fn sample_entry(ps: &ParameterSetCache, width: u32, height: u32) -> Option<Vec<u8>> {
+    let config = ps.decoder_config()?;
+    let (entry, config_box) = match ps.codec() {
+        Codec::H264 => (b"avc1", b"avcC"),
+        Codec::H265 => (b"hvc1", b"hvcC"),
+        _ => return None,
+    };
+    let mut out = Vec::new();
+    write_box(&mut out, entry, |b| {
+        put_visual_sample_entry(b, width, height);
+        write_box(b, config_box, |b| b.extend_from_slice(&config));
+    });
+    Some(out)
+}
+
+fn put_visual_sample_entry(b: &mut Vec<u8>, width: u32, height: u32) {
+    b.extend_from_slice(&[0; 6]);
+    put_u16(b, 1); // data_reference_index
+    b.extend_from_slice(&[0; 16]);
+    put_u16(b, width as u16);
+    put_u16(b, height as u16);
+    put_u32(b, 0x0048_0000); // 72 dpi
+    put_u32(b, 0x0048_0000);
+    put_u32(b, 0);
+    put_u16(b, 1); // frame_count
+    b.extend_from_slice(&[0; 32]); // compressorname
+    put_u16(b, 0x0018);
+    put_u16(b, 0xffff);
+}
+
+fn put_matrix(b: &mut Vec<u8>) {
+    for v in [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000] {
+        put_u32(b, v);
+    }
+}
+
+fn put_u16(b: &mut Vec<u8>, v: u16) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_u32(b: &mut Vec<u8>, v: u32) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_u64(b: &mut Vec<u8>, v: u64) {
+    b.extend_from_slice(&v.to_be_bytes());
+}
+
+pub(crate) fn write_box(out: &mut Vec<u8>, name: &[u8; 4], f: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    put_u32(out, 0);
+    out.extend_from_slice(name);
+    f(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+pub(crate) fn write_full_box(
+    out: &mut Vec<u8>,
+    name: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, name, |b| {
+        put_u32(b, ((version as u32) << 24) | (flags & 0x00ff_ffff));
+        f(b);
+    });
+}
+
+pub fn to_ticks(d: Duration) -> u64 {
+    (d.as_nanos() * TIMESCALE as u128 / 1_000_000_000) as u64
+}
+
+/// A box header read back from an MP4 buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader<'a> {
+    pub name: [u8; 4],
+    pub payload: &'a [u8],
+}
+
+/// Iterates over the sibling boxes in `data`, stopping at the first malformed header.
+pub fn boxes(data: &[u8]) -> impl Iterator<Item = BoxHeader<'_>> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        if size < 8 || size > rest.len() {
+            return None;
+        }
+        let name = rest[4..8].try_into().unwrap();
+        let payload = &rest[8..size];
+        rest = &rest[size..];
+        Some(BoxHeader { name, payload })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: [u8; 7] = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    fn packet(nals: &[&[u8]], ms: u64) -> Packet {
+        let mut data = Vec::new();
+        for nal in nals {
+            data.extend_from_slice(&annexb::START_CODE);
+            data.extend_from_slice(nal);
+        }
+        Packet::new(Codec::H264, data, Duration::from_millis(ms))
+    }
+
+    fn child<'a>(data: &'a [u8], name: &[u8; 4]) -> &'a [u8] {
+        boxes(data)
+            .find(|b| &b.name == name)
+            .unwrap_or_else(|| panic!("no {} box", String::from_utf8_lossy(name)))
+            .payload
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn fragments_parse_back() {
+        let mut writer = Mp4Writer::new(Vec::new(), Codec::H264, 640, 480);
+        // dropped, nothing can be decoded before the first keyframe
+        writer.write(&packet(&[&[0x41, 9]], 0)).unwrap();
+        writer
+            .write(&packet(&[&SPS, &PPS, &[0x65, 1, 2, 3]], 100))
+            .unwrap();
+        writer
+            .write(&packet(&[&[0x09, 0xf0], &[0x41, 4, 5]], 133))
+            .unwrap();
+        writer.write(&packet(&[&[0x41, 6]], 200)).unwrap();
+        writer
+            .write(&packet(&[&SPS, &PPS, &[0x65, 7]], 300))
+            .unwrap();
+        let out = writer.finish().unwrap();
+
+        let top: Vec<_> = boxes(&out).collect();
+        let names: Vec<_> = top.iter().map(|b| &b.name).collect();
+        assert_eq!(
+            names,
+            [b"ftyp", b"moov", b"moof", b"mdat", b"moof", b"mdat"]
+        );
+        assert_eq!(
+            top.iter().map(|b| b.payload.len() + 8).sum::<usize>(),
+            out.len()
+        );
+
+        let stbl = [b"trak", b"mdia", b"minf", b"stbl"]
+            .iter()
+            .fold(top[1].payload, |b, name| child(b, name));
+        let avc1 = boxes(&child(stbl, b"stsd")[8..]).next().unwrap();
+        assert_eq!(&avc1.name, b"avc1");
+        let avcc = boxes(&avc1.payload[78..]).next().unwrap();
+        assert_eq!(&avcc.name, b"avcC");
+        assert_eq!(avcc.payload[1..4], SPS[1..4]);
+
+        // (samples, decode time of the first, durations) per fragment
+        type Fragment<'a> = (&'a [&'a [u8]], u64, &'a [u32]);
+        let expected: [Fragment; 2] = [
+            (
+                &[
+                    &[0, 0, 0, 4, 0x65, 1, 2, 3],
+                    &[0, 0, 0, 3, 0x41, 4, 5],
+                    &[0, 0, 0, 2, 0x41, 6],
+                ],
+                0,
+                &[2970, 6030, 9000],
+            ),
+            (&[&[0, 0, 0, 2, 0x65, 7]], 18000, &[9000]),
+        ];
+        let mut offset = top[0].payload.len() + top[1].payload.len() + 16;
+        for (i, (samples, decode_time, durations)) in expected.iter().enumerate() {
+            let (moof, mdat) = (&top[2 + i * 2], &top[3 + i * 2]);
+            assert_eq!(u32_at(child(moof.payload, b"mfhd"), 4), i as u32 + 1);
+            let traf = child(moof.payload, b"traf");
+            let tfdt = child(traf, b"tfdt");
+            assert_eq!(
+                u64::from_be_bytes(tfdt[4..12].try_into().unwrap()),
+                *decode_time
+            );
+
+            let trun = child(traf, b"trun");
+            assert_eq!(u32_at(trun, 0) & 0xff_ffff, 0x701);
+            assert_eq!(u32_at(trun, 4) as usize, samples.len());
+            // relative to the start of the moof, pointing at the first mdat byte
+            let data_offset = u32_at(trun, 8) as usize;
+            assert_eq!(data_offset, moof.payload.len() + 8 + 8);
+            let end = offset + moof.payload.len() + mdat.payload.len() + 16;
+            let mut data = &out[offset + data_offset..end];
+            assert_eq!(data, mdat.payload);
+            for (j, sample) in samples.iter().enumerate() {
+                let entry = &trun[12 + j * 12..];
+                assert_eq!(u32_at(entry, 0), durations[j]);
+                assert_eq!(u32_at(entry, 4) as usize, sample.len());
+                let flags = if j == 0 {
+                    SAMPLE_FLAGS_SYNC
+                } else {
+                    SAMPLE_FLAGS_NON_SYNC
+                };
+                assert_eq!(u32_at(entry, 8), flags);
+                assert_eq!(&data[..sample.len()], *sample);
+                data = &data[sample.len()..];
+            }
+            assert!(data.is_empty());
+            offset += moof.payload.len() + mdat.payload.len() + 16;
+        }
+    }
+
+    #[test]
+    fn long_gop_is_cut() {
+        let mut writer = Mp4Writer::new(Vec::new(), Codec::H264, 64, 64);
+        writer.set_fragment_duration(Duration::from_millis(100));
+        writer.write(&packet(&[&SPS, &PPS, &[0x65, 1]], 0)).unwrap();
+        for ms in (40..400).step_by(40) {
+            writer.write(&packet(&[&[0x41, 2]], ms)).unwrap();
+        }
+        let out = writer.finish().unwrap();
+        let fragments = boxes(&out).filter(|b| &b.name == b"moof").count();
+        assert_eq!(fragments, 4);
+    }
+}
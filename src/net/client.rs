@@ -0,0 +1,109 @@
+use std::io::{self, BufReader};
+use std::time::Duration;
+
+use super::protocol::{self, CursorUpdate, Message, Metadata};
+use super::{merge_cursor, Endpoint, Stream};
+use crate::frame::Frame;
+
+/// Receiving end of a `FrameServer` stream.
+///
+/// `next_frame` keeps the current frame up to date by applying deltas, and asks the
+/// server for a whole frame when a delta does not apply to it.
+pub struct FrameClient {
+    reader: BufReader<Stream>,
+    writer: Stream,
+    metadata: Option<Metadata>,
+    cursor: Option<CursorUpdate>,
+    frame: Option<(u64, Frame)>,
+}
+
+impl FrameClient {
+    pub fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        let mut writer = Stream::connect(endpoint)?;
+        let mut reader = BufReader::with_capacity(1 << 16, writer.try_clone()?);
+        protocol::read_hello(&mut reader)?;
+        protocol::write_hello(&mut writer)?;
+        Ok(Self {
+            reader,
+            writer,
+            metadata: None,
+            cursor: None,
+            frame: None,
+        })
+    }
+
+    /// Bounds how long `recv` and `next_frame` wait, they then fail with `WouldBlock` or
+    /// `TimedOut` depending on the platform.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)
+    }
+
+    /// The next message as sent, deltas are not applied. `Ok(None)` once the server
+    /// said goodbye or closed the connection.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        match protocol::read_message(&mut self.reader)? {
+            Some(Message::Goodbye) | None => Ok(None),
+            Some(message) => Ok(Some(message)),
+        }
+    }
+
+    /// Waits for the next frame, tracking metadata and cursor updates on the way.
+    pub fn next_frame(&mut self) -> io::Result<Option<&Frame>> {
+        loop {
+            let Some(message) = self.recv()? else {
+                return Ok(None);
+            };
+            match message {
+                Message::Metadata(metadata) => self.metadata = Some(metadata),
+                Message::Cursor(cursor) => merge_cursor(&mut self.cursor, cursor),
+                Message::Frame { seq, frame } => {
+                    self.frame = Some((seq, frame));
+                    break;
+                }
+                Message::Delta(delta) => match &mut self.frame {
+                    Some((seq, frame)) if *seq == delta.base => {
+                        delta.apply(frame)?;
+                        *seq = delta.seq;
+                        break;
+                    }
+                    _ => {
+                        log::debug!("net: delta {} without base {}", delta.seq, delta.base);
+                        self.request_keyframe()?;
+                    }
+                },
+                Message::KeyframeRequest | Message::Goodbye => {}
+            }
+        }
+        Ok(self.frame.as_ref().map(|(_, frame)| frame))
+    }
+
+    /// Asks the server to send the next frame whole.
+    pub fn request_keyframe(&mut self) -> io::Result<()> {
+        protocol::write_keyframe_request(&mut self.writer)
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Last cursor position, with the last shape received.
+    pub fn cursor(&self) -> Option<&CursorUpdate> {
+        self.cursor.as_ref()
+    }
+
+    /// Sequence number of the current frame.
+    pub fn seq(&self) -> Option<u64> {
+        self.frame.as_ref().map(|(seq, _)| *seq)
+    }
+
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frame.as_ref().map(|(_, frame)| frame)
+    }
+}
+
+impl Drop for FrameClient {
+    fn drop(&mut self) {
+        let _ = protocol::write_goodbye(&mut self.writer);
+        self.writer.shutdown();
+    }
+}
@@ -0,0 +1,477 @@
+//! Local frame streaming between processes over TCP or Unix domain sockets.
+//!
+//! A `FrameServer` fans frames out to any number of `FrameClient`s. See `protocol` for
+//! the wire format.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+mod client;
+pub mod protocol;
+mod server;
+
+pub use crate::queue::DropPolicy;
+pub use client::FrameClient;
+pub use protocol::{CursorShape, CursorUpdate, Delta, Message, Metadata};
+pub use server::{ClientStats, FrameServer, ServerConfig};
+
+// an update without a shape keeps the shape already in `slot`
+pub(crate) fn merge_cursor(slot: &mut Option<CursorUpdate>, mut cursor: CursorUpdate) {
+    if cursor.shape.is_none() {
+        cursor.shape = slot.take().and_then(|old| old.shape);
+    }
+    *slot = Some(cursor);
+}
+
+/// Where a server listens or a client connects: `tcp://host:port` or `unix:///path`.
+/// A bare `host:port` is taken as TCP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix://") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        let addr = s.strip_prefix("tcp://").unwrap_or(s);
+        std::net::ToSocketAddrs::to_socket_addrs(addr)?
+            .next()
+            .map(Endpoint::Tcp)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("bad endpoint: {}", s))
+            })
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub(crate) fn bind(endpoint: &Endpoint) -> io::Result<Self> {
+        let listener = match endpoint {
+            Endpoint::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // a socket file left behind by a previous run would make bind fail
+                if std::fs::symlink_metadata(path)
+                    .is_ok_and(|m| std::os::unix::fs::FileTypeExt::is_socket(&m.file_type()))
+                {
+                    std::fs::remove_file(path)?;
+                }
+                Listener::Unix(UnixListener::bind(path)?, path.clone())
+            }
+        };
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    pub(crate) fn local_endpoint(&self) -> io::Result<Endpoint> {
+        Ok(match self {
+            Listener::Tcp(listener) => Endpoint::Tcp(listener.local_addr()?),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Endpoint::Unix(path.clone()),
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    // non-blocking, `Ok(None)` when nobody is waiting
+    pub(crate) fn accept(&self) -> io::Result<Option<(Stream, String)>> {
+        let accepted = match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, addr)| {
+                let _ = stream.set_nodelay(true);
+                (Stream::Tcp(stream), addr.to_string())
+            }),
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => listener
+                .accept()
+                .map(|(stream, _)| (Stream::Unix(stream), path.display().to_string())),
+        };
+        match accepted {
+            Ok((stream, peer)) => {
+                stream.set_nonblocking(false)?;
+                Ok(Some((stream, peer)))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        Ok(match endpoint {
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Stream::Tcp(stream)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Stream::Unix(UnixStream::connect(path)?),
+        })
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Stream::Tcp(stream) => Stream::Tcp(stream.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Stream::Unix(stream.try_clone()?),
+        })
+    }
+
+    pub(crate) fn shutdown(&self) {
+        let _ = match self {
+            Stream::Tcp(stream) => stream.shutdown(std::net::Shutdown::Both),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(std::net::Shutdown::Both),
+        };
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::backend::CaptureBackend;
+    use crate::frame::{Frame, PixelFormat};
+    use crate::synthetic::SyntheticBackend;
+
+    // a TCP and, where there are any, a Unix domain socket endpoint
+    fn endpoints(name: &str) -> Vec<Endpoint> {
+        #[allow(unused_mut)]
+        let mut endpoints = vec![Endpoint::Tcp("127.0.0.1:0".parse().unwrap())];
+        #[cfg(unix)]
+        endpoints.push(Endpoint::Unix(std::env::temp_dir().join(format!(
+            "dxgi-net-{}-{}.sock",
+            name,
+            std::process::id()
+        ))));
+        endpoints
+    }
+
+    fn bind(endpoint: &Endpoint, queue_capacity: usize, drop_policy: DropPolicy) -> FrameServer {
+        let config = ServerConfig {
+            queue_capacity,
+            drop_policy,
+            ..Default::default()
+        };
+        FrameServer::bind(endpoint, config).unwrap()
+    }
+
+    fn connect(server: &FrameServer) -> FrameClient {
+        let client = FrameClient::connect(server.endpoint()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        // frames sent before the server registered the client would not reach it
+        let start = Instant::now();
+        while server.client_count() == 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        client
+    }
+
+    fn capture(backend: &mut SyntheticBackend) -> Frame {
+        backend.capture(1000, true).unwrap().unwrap()
+    }
+
+    fn next_frame(client: &mut FrameClient) -> (u64, Frame) {
+        let frame = client.next_frame().unwrap().unwrap().clone();
+        (client.seq().unwrap(), frame)
+    }
+
+    #[test]
+    fn full_frame_then_delta() {
+        for endpoint in endpoints("delta") {
+            let mut server = bind(&endpoint, 8, DropPolicy::Block);
+            let mut client = connect(&server);
+            let mut backend = SyntheticBackend::new(256, 32, 1000);
+            let first = capture(&mut backend);
+            let second = capture(&mut backend);
+            assert_eq!(server.send_frame(first.clone()), 1);
+            assert_eq!(server.send_frame(second.clone()), 2);
+
+            let Some(Message::Frame { seq: 1, frame }) = client.recv().unwrap() else {
+                panic!("{}: the first frame is not sent whole", endpoint);
+            };
+            assert_eq!(frame.data, first.data);
+            let Some(Message::Delta(delta)) = client.recv().unwrap() else {
+                panic!("{}: the second frame is not a delta", endpoint);
+            };
+            assert_eq!((delta.seq, delta.base), (2, 1));
+            assert!(delta.pixels.len() < second.data.len() / 2);
+            let mut frame = frame;
+            delta.apply(&mut frame).unwrap();
+            assert_eq!(frame.data, second.data);
+
+            let stats = &server.clients()[0];
+            assert_eq!((stats.frames, stats.deltas, stats.dropped), (1, 1, 0));
+            drop(server);
+            assert!(client.recv().unwrap().is_none());
+        }
+    }
+
+    // sends `count` full HD frames to a client that does not read until they are all
+    // queued or dropped, returns the frames dropped and the seqs the client then gets
+    fn slow_reader(endpoint: &Endpoint, policy: DropPolicy, count: u64) -> (u64, Vec<u64>) {
+        let mut server = bind(endpoint, 1, policy);
+        let mut client = connect(&server);
+        let mut backend = SyntheticBackend::new(1920, 1080, 1000);
+        for _ in 0..count {
+            server.send_frame(capture(&mut backend));
+        }
+        let dropped = server.clients()[0].dropped;
+        drop(server);
+        let mut seqs = Vec::new();
+        while client.next_frame().unwrap().is_some() {
+            seqs.push(client.seq().unwrap());
+        }
+        (dropped, seqs)
+    }
+
+    #[test]
+    fn drop_oldest() {
+        for endpoint in endpoints("oldest") {
+            let (dropped, seqs) = slow_reader(&endpoint, DropPolicy::DropOldest, 20);
+            assert!(dropped > 0, "{}", endpoint);
+            assert_eq!(seqs.len() as u64 + dropped, 20);
+            // the newest frame always gets through
+            assert_eq!(seqs.last(), Some(&20));
+            assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn drop_newest() {
+        for endpoint in endpoints("newest") {
+            let (dropped, seqs) = slow_reader(&endpoint, DropPolicy::DropNewest, 20);
+            assert!(dropped > 0, "{}", endpoint);
+            assert_eq!(seqs.len() as u64 + dropped, 20);
+            // what was queued first is kept, so the client stops short of the newest
+            assert!(seqs.last() < Some(&20));
+            assert_eq!(seqs, (1..=seqs.len() as u64).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn block() {
+        const COUNT: u64 = 8;
+        for endpoint in endpoints("block") {
+            let mut server = bind(&endpoint, 1, DropPolicy::Block);
+            let mut client = connect(&server);
+            let sent = Arc::new(AtomicU64::new(0));
+            let sender = {
+                let sent = sent.clone();
+                std::thread::spawn(move || {
+                    let mut backend = SyntheticBackend::new(1920, 1080, 1000);
+                    for _ in 0..COUNT {
+                        server.send_frame(capture(&mut backend));
+                        sent.fetch_add(1, Ordering::SeqCst);
+                    }
+                    server.clients()[0].dropped
+                })
+            };
+            // the socket buffers and the queue hold a couple of frames, then the sender
+            // waits for the reader
+            std::thread::sleep(Duration::from_millis(300));
+            assert!(sent.load(Ordering::SeqCst) < COUNT, "{}", endpoint);
+            let mut seqs = Vec::new();
+            while seqs.len() < COUNT as usize {
+                next_frame(&mut client);
+                seqs.push(client.seq().unwrap());
+            }
+            assert_eq!(sender.join().unwrap(), 0);
+            assert_eq!(seqs, (1..=COUNT).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn keyframe_request() {
+        for endpoint in endpoints("keyframe") {
+            let mut server = bind(&endpoint, 8, DropPolicy::Block);
+            let mut client = connect(&server);
+            let mut backend = SyntheticBackend::new(256, 32, 1000);
+            server.send_frame(capture(&mut backend));
+            server.send_frame(capture(&mut backend));
+            assert!(matches!(
+                client.recv().unwrap(),
+                Some(Message::Frame { .. })
+            ));
+            assert!(matches!(client.recv().unwrap(), Some(Message::Delta(_))));
+
+            client.request_keyframe().unwrap();
+            // the request is read on its own thread
+            std::thread::sleep(Duration::from_millis(100));
+            let third = capture(&mut backend);
+            server.send_frame(third.clone());
+            let Some(Message::Frame { seq: 3, frame }) = client.recv().unwrap() else {
+                panic!(
+                    "{}: a keyframe request is not answered with a whole frame",
+                    endpoint
+                );
+            };
+            assert_eq!(frame.data, third.data);
+            // and deltas resume after it
+            server.send_frame(capture(&mut backend));
+            assert!(matches!(client.recv().unwrap(), Some(Message::Delta(_))));
+        }
+    }
+
+    #[test]
+    fn late_joiner() {
+        for endpoint in endpoints("late") {
+            let mut server = bind(&endpoint, 2, DropPolicy::DropOldest);
+            let metadata = Metadata {
+                width: 64,
+                height: 32,
+                format: PixelFormat::Bgra8,
+                fps: 1000,
+                name: "synthetic".to_string(),
+            };
+            server.set_metadata(metadata.clone());
+            let shape = CursorShape {
+                width: 1,
+                height: 1,
+                hot_x: 0,
+                hot_y: 0,
+                data: vec![1, 2, 3, 4],
+            };
+            server.send_cursor(CursorUpdate {
+                x: 1,
+                y: 2,
+                visible: true,
+                shape: Some(shape.clone()),
+            });
+            server.send_cursor(CursorUpdate {
+                x: 3,
+                y: 4,
+                visible: true,
+                shape: None,
+            });
+            let mut backend = SyntheticBackend::new(64, 32, 1000);
+            server.send_frame(capture(&mut backend));
+            let last = capture(&mut backend);
+            server.send_frame(last.clone());
+
+            // nobody was connected for any of that
+            let mut client = connect(&server);
+            let (seq, frame) = next_frame(&mut client);
+            assert_eq!(seq, 2);
+            assert_eq!(frame.data, last.data);
+            assert_eq!(client.metadata(), Some(&metadata));
+            let cursor = client.cursor().unwrap();
+            assert_eq!((cursor.x, cursor.y), (3, 4));
+            assert_eq!(cursor.shape.as_ref(), Some(&shape));
+
+            // the next frame can be a delta on the one it joined with
+            let next = capture(&mut backend);
+            server.send_frame(next.clone());
+            let (seq, frame) = next_frame(&mut client);
+            assert_eq!((seq, frame.data), (3, next.data));
+        }
+    }
+}
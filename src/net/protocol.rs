@@ -0,0 +1,487 @@
+//! Wire format, all integers little endian.
+//!
+//! Both sides open with a hello: `"DXGF"`, version `u16`, reserved `u16`. The server
+//! sends first, the client answers with the same version or hangs up.
+//!
+//! After that every message is an 8 byte header, type `u8`, flags `u8`, reserved `u16`,
+//! payload length `u32`, followed by the payload. Unknown types are skipped, so later
+//! versions can add messages without breaking older clients.
+//!
+//! | type | message          | payload                                                    |
+//! |------|------------------|------------------------------------------------------------|
+//! | 1    | metadata         | width, height `u32`, format `u8`, 3 reserved, fps `u32`, name length `u16`, UTF-8 name |
+//! | 2    | frame            | seq, present time (us) `u64`, width, height `u32`, format `u8`, 3 reserved, rect count `u32`, rects, pixels |
+//! | 3    | delta            | seq, base seq, present time (us) `u64`, width, height `u32`, format `u8`, 3 reserved, rect count `u32`, rects, pixels of each rect |
+//! | 4    | cursor           | x, y `i32`, visible `u8`, has shape `u8`, 2 reserved, then width, height, hot x, hot y `u32` and BGRA pixels when it has a shape |
+//! | 5    | keyframe request | empty, client to server                                    |
+//! | 6    | goodbye          | empty                                                      |
+//!
+//! A rect is left, top, right, bottom `i32`. Pixels are tightly packed rows. A delta only
+//! applies to the frame numbered `base`.
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::frame::{Frame, PixelFormat, Rect};
+
+pub const MAGIC: [u8; 4] = *b"DXGF";
+pub const VERSION: u16 = 1;
+// rejects garbage lengths before allocating
+pub const MAX_PAYLOAD: u32 = 256 << 20;
+
+const HEADER_LEN: usize = 8;
+const RECT_LEN: usize = 16;
+
+const TYPE_METADATA: u8 = 1;
+const TYPE_FRAME: u8 = 2;
+const TYPE_DELTA: u8 = 3;
+const TYPE_CURSOR: u8 = 4;
+const TYPE_KEYFRAME_REQUEST: u8 = 5;
+const TYPE_GOODBYE: u8 = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    // nominal capture rate, 0 when unknown
+    pub fps: u32,
+    // e.g. the monitor's device name
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorShape {
+    pub width: u32,
+    pub height: u32,
+    pub hot_x: u32,
+    pub hot_y: u32,
+    // BGRA, tightly packed
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorUpdate {
+    pub x: i32,
+    pub y: i32,
+    pub visible: bool,
+    // only sent when the shape changed
+    pub shape: Option<CursorShape>,
+}
+
+/// Changed regions of a frame relative to frame `base`.
+#[derive(Debug, Clone)]
+pub struct Delta {
+    pub seq: u64,
+    pub base: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub present_time: Duration,
+    pub rects: Vec<Rect>,
+    // pixels of each rect in turn
+    pub pixels: Vec<u8>,
+}
+
+impl Delta {
+    /// Copies the changed regions into `frame`, which must be frame `base`.
+    pub fn apply(&self, frame: &mut Frame) -> io::Result<()> {
+        if frame.width != self.width || frame.height != self.height || frame.format != self.format {
+            return Err(invalid("delta geometry does not match its base frame"));
+        }
+        let bpp = self.format.bytes_per_pixel();
+        let mut offset = 0;
+        for rect in &self.rects {
+            let len = rect.width() as usize * bpp;
+            for y in rect.top..rect.bottom {
+                let x = rect.left as usize * bpp;
+                frame.row_mut(y as u32)[x..x + len]
+                    .copy_from_slice(&self.pixels[offset..offset + len]);
+                offset += len;
+            }
+        }
+        frame.present_time = self.present_time;
+        frame.dirty_rects = self.rects.clone();
+        frame.move_rects.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Metadata(Metadata),
+    Frame { seq: u64, frame: Frame },
+    Delta(Delta),
+    Cursor(CursorUpdate),
+    KeyframeRequest,
+    Goodbye,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn format_code(format: PixelFormat) -> u8 {
+    match format {
+        PixelFormat::Bgra8 => 0,
+    }
+}
+
+fn parse_format(code: u8) -> io::Result<PixelFormat> {
+    match code {
+        0 => Ok(PixelFormat::Bgra8),
+        _ => Err(invalid("unknown pixel format")),
+    }
+}
+
+pub fn write_hello<W: Write>(w: &mut W) -> io::Result<()> {
+    let mut hello = [0u8; 8];
+    hello[..4].copy_from_slice(&MAGIC);
+    hello[4..6].copy_from_slice(&VERSION.to_le_bytes());
+    w.write_all(&hello)?;
+    w.flush()
+}
+
+/// Reads the peer's hello and checks it speaks our version.
+pub fn read_hello<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut hello = [0u8; 8];
+    r.read_exact(&mut hello)?;
+    if hello[..4] != MAGIC {
+        return Err(invalid("not a frame stream"));
+    }
+    let version = u16::from_le_bytes([hello[4], hello[5]]);
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unsupported protocol version {}", version),
+        ));
+    }
+    Ok(version)
+}
+
+fn write_header<W: Write>(w: &mut W, kind: u8, len: usize) -> io::Result<()> {
+    if len > MAX_PAYLOAD as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "message too large",
+        ));
+    }
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = kind;
+    header[4..].copy_from_slice(&(len as u32).to_le_bytes());
+    w.write_all(&header)
+}
+
+fn put_rects(buf: &mut Vec<u8>, rects: &[Rect]) {
+    buf.extend_from_slice(&(rects.len() as u32).to_le_bytes());
+    for rect in rects {
+        for v in [rect.left, rect.top, rect.right, rect.bottom] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+}
+
+// changed rects clipped to the frame, empty ones dropped
+fn clipped_rects(frame: &Frame) -> Vec<Rect> {
+    let bounds = frame.bounds();
+    frame
+        .changed_rects()
+        .iter()
+        .map(|r| r.intersect(&bounds))
+        .filter(|r| !r.is_empty())
+        .collect()
+}
+
+/// Size of the pixel data a delta of `frame` would carry, `None` if its changed rects
+/// are unknown.
+pub fn delta_pixel_len(frame: &Frame) -> Option<usize> {
+    if frame.dirty_rects.is_empty() && frame.move_rects.is_empty() {
+        return None;
+    }
+    let bpp = frame.format.bytes_per_pixel();
+    Some(
+        clipped_rects(frame)
+            .iter()
+            .map(|r| r.area() as usize * bpp)
+            .sum(),
+    )
+}
+
+pub fn write_metadata<W: Write>(w: &mut W, metadata: &Metadata) -> io::Result<()> {
+    let name = metadata.name.as_bytes();
+    let name = &name[..name.len().min(u16::MAX as usize)];
+    let mut buf = Vec::with_capacity(18 + name.len());
+    buf.extend_from_slice(&metadata.width.to_le_bytes());
+    buf.extend_from_slice(&metadata.height.to_le_bytes());
+    buf.extend_from_slice(&[format_code(metadata.format), 0, 0, 0]);
+    buf.extend_from_slice(&metadata.fps.to_le_bytes());
+    buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    buf.extend_from_slice(name);
+    write_header(w, TYPE_METADATA, buf.len())?;
+    w.write_all(&buf)
+}
+
+/// Sends the whole frame. Its changed rects go along so the receiver knows what changed.
+pub fn write_frame<W: Write>(w: &mut W, seq: u64, frame: &Frame) -> io::Result<()> {
+    let rects = clipped_rects(frame);
+    let row_len = frame.width as usize * frame.format.bytes_per_pixel();
+    let mut buf = Vec::with_capacity(32 + rects.len() * RECT_LEN);
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&(frame.present_time.as_micros() as u64).to_le_bytes());
+    buf.extend_from_slice(&frame.width.to_le_bytes());
+    buf.extend_from_slice(&frame.height.to_le_bytes());
+    buf.extend_from_slice(&[format_code(frame.format), 0, 0, 0]);
+    put_rects(&mut buf, &rects);
+    write_header(w, TYPE_FRAME, buf.len() + row_len * frame.height as usize)?;
+    w.write_all(&buf)?;
+    for y in 0..frame.height {
+        w.write_all(frame.row(y))?;
+    }
+    Ok(())
+}
+
+/// Sends only the changed rects of `frame`, to be applied on top of frame `base`.
+pub fn write_delta<W: Write>(w: &mut W, seq: u64, base: u64, frame: &Frame) -> io::Result<()> {
+    let rects = clipped_rects(frame);
+    let bpp = frame.format.bytes_per_pixel();
+    let pixels: usize = rects.iter().map(|r| r.area() as usize * bpp).sum();
+    let mut buf = Vec::with_capacity(40 + rects.len() * RECT_LEN);
+    buf.extend_from_slice(&seq.to_le_bytes());
+    buf.extend_from_slice(&base.to_le_bytes());
+    buf.extend_from_slice(&(frame.present_time.as_micros() as u64).to_le_bytes());
+    buf.extend_from_slice(&frame.width.to_le_bytes());
+    buf.extend_from_slice(&frame.height.to_le_bytes());
+    buf.extend_from_slice(&[format_code(frame.format), 0, 0, 0]);
+    put_rects(&mut buf, &rects);
+    write_header(w, TYPE_DELTA, buf.len() + pixels)?;
+    w.write_all(&buf)?;
+    for rect in &rects {
+        let x = rect.left as usize * bpp;
+        let len = rect.width() as usize * bpp;
+        for y in rect.top..rect.bottom {
+            w.write_all(&frame.row(y as u32)[x..x + len])?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_cursor<W: Write>(w: &mut W, cursor: &CursorUpdate) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(12);
+    buf.extend_from_slice(&cursor.x.to_le_bytes());
+    buf.extend_from_slice(&cursor.y.to_le_bytes());
+    buf.extend_from_slice(&[cursor.visible as u8, cursor.shape.is_some() as u8, 0, 0]);
+    if let Some(shape) = &cursor.shape {
+        if shape.data.len() != shape.width as usize * shape.height as usize * 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cursor shape size does not match its data",
+            ));
+        }
+        for v in [shape.width, shape.height, shape.hot_x, shape.hot_y] {
+            buf.extend_from_slice(&v.to_le_bytes());
+        }
+        buf.extend_from_slice(&shape.data);
+    }
+    write_header(w, TYPE_CURSOR, buf.len())?;
+    w.write_all(&buf)
+}
+
+pub fn write_keyframe_request<W: Write>(w: &mut W) -> io::Result<()> {
+    write_header(w, TYPE_KEYFRAME_REQUEST, 0)?;
+    w.flush()
+}
+
+pub fn write_goodbye<W: Write>(w: &mut W) -> io::Result<()> {
+    write_header(w, TYPE_GOODBYE, 0)?;
+    w.flush()
+}
+
+struct Payload<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Payload<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid("truncated message"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn format(&mut self) -> io::Result<PixelFormat> {
+        let format = parse_format(self.u8()?)?;
+        self.take(3)?;
+        Ok(format)
+    }
+
+    // rects must lie inside width x height
+    fn rects(&mut self, width: u32, height: u32) -> io::Result<Vec<Rect>> {
+        let count = self.u32()? as usize;
+        if count > (self.data.len() - self.pos) / RECT_LEN {
+            return Err(invalid("truncated message"));
+        }
+        let bounds = Rect::new(0, 0, width as i32, height as i32);
+        let mut rects = Vec::with_capacity(count);
+        for _ in 0..count {
+            let rect = Rect::new(self.i32()?, self.i32()?, self.i32()?, self.i32()?);
+            if rect.intersect(&bounds) != rect || rect.is_empty() {
+                return Err(invalid("rect outside the frame"));
+            }
+            rects.push(rect);
+        }
+        Ok(rects)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.pos..];
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+/// Reads the next known message, skipping unknown ones. `Ok(None)` on a clean end of
+/// stream between messages.
+pub fn read_message<R: Read>(r: &mut R) -> io::Result<Option<Message>> {
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        match r.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        r.read_exact(&mut header[1..])?;
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+        if len > MAX_PAYLOAD {
+            return Err(invalid("message too large"));
+        }
+        let mut data = vec![0u8; len as usize];
+        r.read_exact(&mut data)?;
+        let mut p = Payload {
+            data: &data,
+            pos: 0,
+        };
+        let message = match header[0] {
+            TYPE_METADATA => {
+                let width = p.u32()?;
+                let height = p.u32()?;
+                let format = p.format()?;
+                let fps = p.u32()?;
+                let len = p.u16()? as usize;
+                let name = String::from_utf8(p.take(len)?.to_vec())
+                    .map_err(|_| invalid("metadata name is not UTF-8"))?;
+                Message::Metadata(Metadata {
+                    width,
+                    height,
+                    format,
+                    fps,
+                    name,
+                })
+            }
+            TYPE_FRAME => {
+                let seq = p.u64()?;
+                let present_time = Duration::from_micros(p.u64()?);
+                let width = p.u32()?;
+                let height = p.u32()?;
+                let format = p.format()?;
+                let rects = p.rects(width, height)?;
+                let pixels = p.rest();
+                let len = width as usize * format.bytes_per_pixel() * height as usize;
+                if pixels.len() != len {
+                    return Err(invalid("frame size does not match its pixels"));
+                }
+                let mut frame = Frame::new(width, height, format);
+                frame.data.copy_from_slice(pixels);
+                frame.present_time = present_time;
+                frame.dirty_rects = rects;
+                Message::Frame { seq, frame }
+            }
+            TYPE_DELTA => {
+                let seq = p.u64()?;
+                let base = p.u64()?;
+                let present_time = Duration::from_micros(p.u64()?);
+                let width = p.u32()?;
+                let height = p.u32()?;
+                let format = p.format()?;
+                let rects = p.rects(width, height)?;
+                let pixels = p.rest();
+                let bpp = format.bytes_per_pixel();
+                let len: usize = rects.iter().map(|r| r.area() as usize * bpp).sum();
+                if pixels.len() != len {
+                    return Err(invalid("delta rects do not match its pixels"));
+                }
+                Message::Delta(Delta {
+                    seq,
+                    base,
+                    width,
+                    height,
+                    format,
+                    present_time,
+                    rects,
+                    pixels: pixels.to_vec(),
+                })
+            }
+            TYPE_CURSOR => {
+                let x = p.i32()?;
+                let y = p.i32()?;
+                let visible = p.u8()? != 0;
+                let has_shape = p.u8()? != 0;
+                p.take(2)?;
+                let shape = if has_shape {
+                    let width = p.u32()?;
+                    let height = p.u32()?;
+                    let hot_x = p.u32()?;
+                    let hot_y = p.u32()?;
+                    let data = p.rest();
+                    if data.len() != width as usize * height as usize * 4 {
+                        return Err(invalid("cursor shape size does not match its data"));
+                    }
+                    Some(CursorShape {
+                        width,
+                        height,
+                        hot_x,
+                        hot_y,
+                        data: data.to_vec(),
+                    })
+                } else {
+                    None
+                };
+                Message::Cursor(CursorUpdate {
+                    x,
+                    y,
+                    visible,
+                    shape,
+                })
+            }
+            TYPE_KEYFRAME_REQUEST => Message::KeyframeRequest,
+            TYPE_GOODBYE => Message::Goodbye,
+            kind => {
+                log::debug!("net: skipping unknown message type {}", kind);
+                continue;
+            }
+        };
+        return Ok(Some(message));
+    }
+}
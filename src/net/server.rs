@@ -0,0 +1,354 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use super::protocol::{self, CursorUpdate, Message, Metadata};
+use super::{merge_cursor, Endpoint, Listener, Stream};
+use crate::frame::{Frame, PixelFormat};
+use crate::queue::DropPolicy;
+
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // frames queued per client before the drop policy applies
+    pub queue_capacity: usize,
+    // with `Block` the slowest client throttles `send_frame`
+    pub drop_policy: DropPolicy,
+    // a client that does not read for this long is disconnected
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 2,
+            drop_policy: DropPolicy::DropOldest,
+            write_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClientStats {
+    pub peer: String,
+    pub frames: u64,
+    pub deltas: u64,
+    // frames discarded by the drop policy
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct Outbox {
+    metadata: Option<Metadata>,
+    cursor: Option<CursorUpdate>,
+    frames: VecDeque<(u64, Arc<Frame>)>,
+    closed: bool,
+}
+
+struct Connection {
+    outbox: Mutex<Outbox>,
+    // signalled when the outbox gains something to send
+    ready: Condvar,
+    // signalled when a frame leaves the queue, for `DropPolicy::Block`
+    space: Condvar,
+    keyframe: AtomicBool,
+    stats: Mutex<ClientStats>,
+}
+
+impl Connection {
+    fn close(&self) {
+        self.outbox.lock().closed = true;
+        self.ready.notify_all();
+        self.space.notify_all();
+    }
+
+    fn push_frame(&self, seq: u64, frame: Arc<Frame>, config: &ServerConfig) {
+        let mut outbox = self.outbox.lock();
+        while outbox.frames.len() >= config.queue_capacity.max(1) && !outbox.closed {
+            match config.drop_policy {
+                DropPolicy::DropOldest => {
+                    outbox.frames.pop_front();
+                    self.stats.lock().dropped += 1;
+                }
+                DropPolicy::DropNewest => {
+                    self.stats.lock().dropped += 1;
+                    return;
+                }
+                DropPolicy::Block => self.space.wait(&mut outbox),
+            }
+        }
+        if outbox.closed {
+            return;
+        }
+        outbox.frames.push_back((seq, frame));
+        drop(outbox);
+        self.ready.notify_one();
+    }
+}
+
+struct Shared {
+    config: ServerConfig,
+    connections: Mutex<Vec<Arc<Connection>>>,
+    metadata: Mutex<Option<Metadata>>,
+    cursor: Mutex<Option<CursorUpdate>>,
+    last_frame: Mutex<Option<(u64, Arc<Frame>)>>,
+    shutdown: AtomicBool,
+}
+
+/// Streams frames to every connected `FrameClient`.
+///
+/// Each client gets its own writer thread and a small frame queue, so a slow client only
+/// falls behind itself unless the policy is `DropPolicy::Block`. Metadata and cursor
+/// updates are never dropped, only coalesced. A frame goes out as a delta when the
+/// client has the frame before it and the caller supplied dirty or move rects, which
+/// must then be relative to the previous frame passed to `send_frame`. Otherwise, and
+/// after any drop or keyframe request, the whole frame is sent.
+pub struct FrameServer {
+    shared: Arc<Shared>,
+    endpoint: Endpoint,
+    seq: u64,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl FrameServer {
+    pub fn bind(endpoint: &Endpoint, config: ServerConfig) -> io::Result<Self> {
+        let listener = Listener::bind(endpoint)?;
+        let endpoint = listener.local_endpoint()?;
+        let shared = Arc::new(Shared {
+            config,
+            connections: Mutex::new(Vec::new()),
+            metadata: Mutex::new(None),
+            cursor: Mutex::new(None),
+            last_frame: Mutex::new(None),
+            shutdown: AtomicBool::new(false),
+        });
+        let accept = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("frame-server-accept".to_string())
+                .spawn(move || accept_loop(listener, shared))?
+        };
+        log::debug!("net: listening on {}", endpoint);
+        Ok(Self {
+            shared,
+            endpoint,
+            seq: 0,
+            accept: Some(accept),
+        })
+    }
+
+    /// The bound endpoint, with the actual port when bound to port 0.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub fn set_metadata(&self, metadata: Metadata) {
+        *self.shared.metadata.lock() = Some(metadata.clone());
+        for conn in self.shared.connections.lock().iter() {
+            conn.outbox.lock().metadata = Some(metadata.clone());
+            conn.ready.notify_one();
+        }
+    }
+
+    /// Queues `frame` for every client and returns its sequence number.
+    pub fn send_frame(&mut self, frame: Frame) -> u64 {
+        self.seq += 1;
+        let seq = self.seq;
+        let frame = Arc::new(frame);
+        *self.shared.last_frame.lock() = Some((seq, frame.clone()));
+        let connections = self.shared.connections.lock().clone();
+        for conn in connections {
+            conn.push_frame(seq, frame.clone(), &self.shared.config);
+        }
+        seq
+    }
+
+    /// A cursor update without a shape keeps the last shape sent.
+    pub fn send_cursor(&self, cursor: CursorUpdate) {
+        merge_cursor(&mut self.shared.cursor.lock(), cursor.clone());
+        for conn in self.shared.connections.lock().iter() {
+            merge_cursor(&mut conn.outbox.lock().cursor, cursor.clone());
+            conn.ready.notify_one();
+        }
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.shared.connections.lock().len()
+    }
+
+    pub fn clients(&self) -> Vec<ClientStats> {
+        self.shared
+            .connections
+            .lock()
+            .iter()
+            .map(|conn| conn.stats.lock().clone())
+            .collect()
+    }
+}
+
+impl Drop for FrameServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+        // writers drain what is queued, say goodbye and exit on their own
+        for conn in self.shared.connections.lock().iter() {
+            conn.close();
+        }
+    }
+}
+
+fn accept_loop(listener: Listener, shared: Arc<Shared>) {
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok(Some((stream, peer))) => {
+                let shared = shared.clone();
+                let spawned = std::thread::Builder::new()
+                    .name("frame-server-client".to_string())
+                    .spawn(move || {
+                        if let Err(e) = serve(stream, &peer, &shared) {
+                            log::debug!("net: client {} disconnected: {}", peer, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    log::error!("net: failed to spawn client thread: {}", e);
+                }
+            }
+            Ok(None) => std::thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                log::error!("net: accept failed: {}", e);
+                std::thread::sleep(ACCEPT_POLL);
+            }
+        }
+    }
+}
+
+fn serve(stream: Stream, peer: &str, shared: &Arc<Shared>) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(shared.config.write_timeout)?;
+    let mut writer = BufWriter::with_capacity(1 << 16, stream.try_clone()?);
+    protocol::write_hello(&mut writer)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    protocol::read_hello(&mut reader)?;
+    stream.set_read_timeout(None)?;
+
+    let conn = Arc::new(Connection {
+        outbox: Mutex::new(Outbox::default()),
+        ready: Condvar::new(),
+        space: Condvar::new(),
+        keyframe: AtomicBool::new(false),
+        stats: Mutex::new(ClientStats {
+            peer: peer.to_string(),
+            ..Default::default()
+        }),
+    });
+    {
+        // registered before reading the current state, so an update racing with the
+        // connect is at worst seen twice
+        shared.connections.lock().push(conn.clone());
+        let mut outbox = conn.outbox.lock();
+        outbox.metadata = shared.metadata.lock().clone();
+        outbox.cursor = shared.cursor.lock().clone();
+        if let Some(last) = shared.last_frame.lock().clone() {
+            outbox.frames.push_back(last);
+        }
+    }
+    if shared.shutdown.load(Ordering::SeqCst) {
+        conn.close();
+    }
+    log::debug!("net: client {} connected", peer);
+
+    let reader_conn = conn.clone();
+    let spawned = std::thread::Builder::new()
+        .name("frame-server-reader".to_string())
+        .spawn(move || {
+            loop {
+                match protocol::read_message(&mut reader) {
+                    Ok(Some(Message::KeyframeRequest)) => {
+                        reader_conn.keyframe.store(true, Ordering::SeqCst)
+                    }
+                    Ok(Some(Message::Goodbye)) | Ok(None) | Err(_) => break,
+                    Ok(Some(_)) => {}
+                }
+            }
+            reader_conn.close();
+        });
+
+    let result = match spawned {
+        Ok(_) => write_loop(&mut writer, &conn),
+        Err(e) => Err(e),
+    };
+    if result.is_ok() {
+        let _ = protocol::write_goodbye(&mut writer);
+    }
+    conn.close();
+    stream.shutdown();
+    shared
+        .connections
+        .lock()
+        .retain(|other| !Arc::ptr_eq(other, &conn));
+    let stats = conn.stats.lock().clone();
+    log::debug!(
+        "net: client {} done, {} frames, {} deltas, {} dropped",
+        peer,
+        stats.frames,
+        stats.deltas,
+        stats.dropped
+    );
+    result
+}
+
+fn write_loop<W: Write>(writer: &mut W, conn: &Connection) -> io::Result<()> {
+    // seq, width, height and format of the last frame the client has
+    let mut last: Option<(u64, u32, u32, PixelFormat)> = None;
+    loop {
+        let (metadata, cursor, frame) = {
+            let mut outbox = conn.outbox.lock();
+            while outbox.metadata.is_none()
+                && outbox.cursor.is_none()
+                && outbox.frames.is_empty()
+                && !outbox.closed
+            {
+                conn.ready.wait(&mut outbox);
+            }
+            if outbox.closed && outbox.frames.is_empty() {
+                return Ok(());
+            }
+            let frame = outbox.frames.pop_front();
+            (outbox.metadata.take(), outbox.cursor.take(), frame)
+        };
+        conn.space.notify_one();
+
+        if let Some(metadata) = metadata {
+            protocol::write_metadata(writer, &metadata)?;
+        }
+        if let Some(cursor) = cursor {
+            protocol::write_cursor(writer, &cursor)?;
+        }
+        // a client that connected mid-send can be queued the same frame twice
+        let frame = frame.filter(|(seq, _)| last.is_none_or(|(last_seq, ..)| *seq > last_seq));
+        if let Some((seq, frame)) = frame {
+            let keyframe = conn.keyframe.swap(false, Ordering::SeqCst);
+            let follows = last == Some((seq - 1, frame.width, frame.height, frame.format));
+            // a delta bigger than half the frame is not worth it
+            let small = protocol::delta_pixel_len(&frame).is_some_and(|n| n < frame.data.len() / 2);
+            if follows && small && !keyframe {
+                protocol::write_delta(writer, seq, seq - 1, &frame)?;
+                conn.stats.lock().deltas += 1;
+            } else {
+                protocol::write_frame(writer, seq, &frame)?;
+                conn.stats.lock().frames += 1;
+            }
+            last = Some((seq, frame.width, frame.height, frame.format));
+        }
+        writer.flush()?;
+    }
+}
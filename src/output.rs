@@ -0,0 +1,273 @@
+//! Portable descriptions of DXGI outputs (monitors).
+//!
+//! On Windows they are filled in by `utils::enumerate_outputs`, everywhere else they can
+//! be built by hand, e.g. to test monitor selection.
+
+use crate::frame::Rect;
+use crate::Luid;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rotation {
+    #[default]
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+impl Rotation {
+    /// From a `DXGI_MODE_ROTATION` value, unspecified counts as identity.
+    ///
+    /// ```
+    /// use dxgi::Rotation;
+    ///
+    /// assert_eq!(Rotation::from_raw(0), Rotation::Identity);
+    /// assert_eq!(Rotation::from_raw(2).degrees(), 90);
+    /// assert_eq!(Rotation::from_raw(4), Rotation::Rotate270);
+    /// ```
+    pub fn from_raw(rotation: i32) -> Self {
+        match rotation {
+            2 => Rotation::Rotate90,
+            3 => Rotation::Rotate180,
+            4 => Rotation::Rotate270,
+            _ => Rotation::Identity,
+        }
+    }
+
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Rotation::Identity => 0,
+            Rotation::Rotate90 => 90,
+            Rotation::Rotate180 => 180,
+            Rotation::Rotate270 => 270,
+        }
+    }
+}
+
+/// The color space an output is driven in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorSpace {
+    // RGB_FULL_G22_NONE_P709, a regular SDR desktop
+    #[default]
+    Srgb,
+    // RGB_FULL_G10_NONE_P709, linear FP16 scRGB
+    ScRgb,
+    // RGB_FULL_G2084_NONE_P2020, the desktop is in HDR mode
+    Hdr10,
+    // any other DXGI_COLOR_SPACE_TYPE
+    Other(i32),
+}
+
+impl ColorSpace {
+    /// From a `DXGI_COLOR_SPACE_TYPE` value.
+    ///
+    /// ```
+    /// use dxgi::ColorSpace;
+    ///
+    /// assert_eq!(ColorSpace::from_raw(0), ColorSpace::Srgb);
+    /// assert!(ColorSpace::from_raw(12).is_hdr());
+    /// assert_eq!(ColorSpace::from_raw(5), ColorSpace::Other(5));
+    /// assert_eq!(ColorSpace::Other(5).to_raw(), 5);
+    /// ```
+    pub fn from_raw(color_space: i32) -> Self {
+        match color_space {
+            0 => ColorSpace::Srgb,
+            1 => ColorSpace::ScRgb,
+            12 => ColorSpace::Hdr10,
+            other => ColorSpace::Other(other),
+        }
+    }
+
+    pub fn to_raw(&self) -> i32 {
+        match self {
+            ColorSpace::Srgb => 0,
+            ColorSpace::ScRgb => 1,
+            ColorSpace::Hdr10 => 12,
+            ColorSpace::Other(other) => *other,
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ColorSpace::Hdr10)
+    }
+}
+
+/// Output (monitor) as described by DXGI.
+///
+/// ```
+/// # #[cfg(feature = "serde")] {
+/// use dxgi::frame::Rect;
+/// use dxgi::{Luid, OutputDesc, Rotation};
+///
+/// let output = OutputDesc {
+///     adapter_luid: Luid(0x0000_0000_0001_2f7e),
+///     index: 1,
+///     device_name: r"\\.\DISPLAY2".to_string(),
+///     desktop_coordinates: Rect::new(1920, 0, 3840, 1080),
+///     attached_to_desktop: true,
+///     rotation: Rotation::Rotate90,
+///     ..Default::default()
+/// };
+/// let json = serde_json::to_string(&output).unwrap();
+/// assert_eq!(serde_json::from_str::<OutputDesc>(&json).unwrap(), output);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputDesc {
+    pub adapter_luid: Luid,
+    // index on its adapter, as passed to EnumOutputs
+    pub index: u32,
+    // e.g. \\.\DISPLAY1
+    pub device_name: String,
+    // position in the virtual desktop
+    pub desktop_coordinates: Rect,
+    pub attached_to_desktop: bool,
+    pub rotation: Rotation,
+    // HMONITOR, only meaningful within the process that enumerated it
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub monitor: isize,
+    pub bits_per_color: u32,
+    pub color_space: ColorSpace,
+    // in nits
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    pub max_full_frame_luminance: f32,
+}
+
+impl OutputDesc {
+    pub fn width(&self) -> u32 {
+        self.desktop_coordinates.width() as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.desktop_coordinates.height() as u32
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        self.color_space.is_hdr()
+    }
+}
+
+/// Which output to capture.
+///
+/// Only outputs attached to the desktop are candidates, they cannot be duplicated
+/// otherwise. `FromStr` accepts `3` (index), `primary`, `\\.\DISPLAY2` (device name),
+/// `@-1920,200` (point) and `000000000000d2c4:1` (adapter LUID and output index).
+///
+/// ```
+/// use dxgi::frame::Rect;
+/// use dxgi::{Luid, MonitorSelector, OutputDesc};
+///
+/// let output = |luid, index, name: &str, rect| OutputDesc {
+///     adapter_luid: Luid(luid),
+///     index,
+///     device_name: name.to_string(),
+///     desktop_coordinates: rect,
+///     attached_to_desktop: true,
+///     ..Default::default()
+/// };
+/// let mut outputs = vec![
+///     output(1, 0, r"\\.\DISPLAY2", Rect::new(-1920, 0, 0, 1080)),
+///     output(1, 1, r"\\.\DISPLAY1", Rect::new(0, 0, 2560, 1440)),
+///     output(2, 0, r"\\.\DISPLAY3", Rect::new(2560, 0, 4480, 1080)),
+/// ];
+/// let select = |outputs: &[OutputDesc], s: &str| {
+///     let selector: MonitorSelector = s.parse().unwrap();
+///     selector.resolve(outputs).map(|o| o.device_name.clone())
+/// };
+/// assert_eq!(select(&outputs, "1").unwrap(), r"\\.\DISPLAY1");
+/// assert_eq!(select(&outputs, "2").unwrap(), r"\\.\DISPLAY3");
+/// assert_eq!(select(&outputs, "3"), None);
+/// assert_eq!(select(&outputs, "primary").unwrap(), r"\\.\DISPLAY1");
+/// assert_eq!(select(&outputs, r"\\.\display2").unwrap(), r"\\.\DISPLAY2");
+/// assert_eq!(select(&outputs, "@-5,100").unwrap(), r"\\.\DISPLAY2");
+/// assert_eq!(select(&outputs, "@5000,0"), None);
+/// assert_eq!(select(&outputs, "0000000000000002:0").unwrap(), r"\\.\DISPLAY3");
+/// assert_eq!(select(&outputs, "0000000000000002:1"), None);
+///
+/// // detached outputs are skipped, also when counting indices
+/// outputs[0].attached_to_desktop = false;
+/// assert_eq!(select(&outputs, "0").unwrap(), r"\\.\DISPLAY1");
+/// assert_eq!(select(&outputs, r"\\.\DISPLAY2"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MonitorSelector {
+    // n-th attached output, counting across adapters in enumeration order, as taken by
+    // `CaptureDXGI::new`
+    Index(u32),
+    // device name, compared case-insensitively
+    DeviceName(String),
+    // the output at the origin of the virtual desktop
+    Primary,
+    // the output containing this point of the virtual desktop
+    Point(i32, i32),
+    // output `index` of the adapter with this LUID, as passed to EnumOutputs
+    AdapterOutput(Luid, u32),
+}
+
+impl Default for MonitorSelector {
+    fn default() -> Self {
+        MonitorSelector::Index(0)
+    }
+}
+
+impl MonitorSelector {
+    pub fn resolve<'a>(&self, outputs: &'a [OutputDesc]) -> Option<&'a OutputDesc> {
+        let mut attached = outputs.iter().filter(|o| o.attached_to_desktop);
+        match self {
+            MonitorSelector::Index(index) => attached.nth(*index as usize),
+            MonitorSelector::DeviceName(name) => {
+                attached.find(|o| o.device_name.eq_ignore_ascii_case(name))
+            }
+            MonitorSelector::Primary => attached.find(|o| o.desktop_coordinates.contains(0, 0)),
+            MonitorSelector::Point(x, y) => {
+                attached.find(|o| o.desktop_coordinates.contains(*x, *y))
+            }
+            MonitorSelector::AdapterOutput(luid, index) => {
+                attached.find(|o| o.adapter_luid == *luid && o.index == *index)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for MonitorSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MonitorSelector::Index(index) => write!(f, "{}", index),
+            MonitorSelector::DeviceName(name) => f.write_str(name),
+            MonitorSelector::Primary => f.write_str("primary"),
+            MonitorSelector::Point(x, y) => write!(f, "@{},{}", x, y),
+            MonitorSelector::AdapterOutput(luid, index) => write!(f, "{}:{}", luid, index),
+        }
+    }
+}
+
+impl std::str::FromStr for MonitorSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let bad = || format!("bad monitor selector {}", s);
+        if s.eq_ignore_ascii_case("primary") {
+            return Ok(MonitorSelector::Primary);
+        }
+        if s.starts_with(r"\\") {
+            return Ok(MonitorSelector::DeviceName(s.to_string()));
+        }
+        if let Some(point) = s.strip_prefix('@') {
+            let (x, y) = point.split_once(',').ok_or_else(bad)?;
+            let x = x.trim().parse().map_err(|_| bad())?;
+            let y = y.trim().parse().map_err(|_| bad())?;
+            return Ok(MonitorSelector::Point(x, y));
+        }
+        if let Some((luid, index)) = s.split_once(':') {
+            let luid = luid.parse().map_err(|_| bad())?;
+            let index = index.parse().map_err(|_| bad())?;
+            return Ok(MonitorSelector::AdapterOutput(luid, index));
+        }
+        s.parse().map(MonitorSelector::Index).map_err(|_| bad())
+    }
+}
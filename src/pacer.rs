@@ -0,0 +1,423 @@
+//! Delivering captured frames at a target rate.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::backend::CaptureBackend;
+use crate::frame::Frame;
+
+/// Time source of a `Pacer`.
+pub trait Clock {
+    /// Time since a fixed origin, never goes backwards.
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+/// `Instant` based, the default.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    origin: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when told to, for tests. Clones share the time and `sleep`
+/// advances it instead of blocking.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingMode {
+    // a frame every 1 / fps, the last one again when the desktop did not change
+    Fixed(u32),
+    // frames as the desktop presents them, at most max_fps per second
+    Variable { max_fps: u32 },
+}
+
+impl PacingMode {
+    fn interval(&self) -> Duration {
+        let fps = match self {
+            PacingMode::Fixed(fps) => *fps,
+            PacingMode::Variable { max_fps } => *max_fps,
+        };
+        Duration::from_secs(1) / fps.max(1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PacedFrame {
+    pub frame: Frame,
+    // when the frame was due, on the pacer's clock
+    pub deadline: Duration,
+    pub delivered_at: Duration,
+    // the previous frame again, nothing new was presented in time
+    pub repeated: bool,
+}
+
+impl PacedFrame {
+    pub fn lateness(&self) -> Duration {
+        self.delivered_at.saturating_sub(self.deadline)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PacerStats {
+    pub delivered: u64,
+    pub repeated: u64,
+    // fixed cadence slots skipped because a frame was a whole interval late
+    pub missed_deadlines: u64,
+    pub max_lateness: Duration,
+    // smoothed variation of the lateness, computed like RTP interarrival jitter
+    pub jitter: Duration,
+}
+
+/// Paces a capture backend.
+///
+/// `Fixed` sleeps until each slot, then takes the newest frame the backend has without
+/// waiting and repeats the previous one if there is none. A frame that is late by a
+/// whole interval or more skips the slots it overran, counted in `missed_deadlines`,
+/// so the cadence never bursts to catch up. `Variable` waits for the desktop to
+/// present, but never delivers two frames closer than 1 / max_fps.
+///
+/// ```
+/// use std::time::Duration;
+/// use dxgi::backend::CaptureBackend;
+/// use dxgi::frame::{Frame, PixelFormat};
+/// use dxgi::pacer::{ManualClock, PacingMode, Pacer};
+///
+/// // presents a frame on every other capture, each capture takes `cost`
+/// struct Desktop {
+///     clock: ManualClock,
+///     captures: u32,
+///     cost: Duration,
+/// }
+/// impl CaptureBackend for Desktop {
+///     fn capture(&mut self, _: u32, _: bool) -> anyhow::Result<Option<Frame>> {
+///         self.clock.advance(self.cost);
+///         self.captures += 1;
+///         Ok((self.captures % 2 == 1).then(|| Frame::new(4, 4, PixelFormat::Bgra8)))
+///     }
+///     fn width(&self) -> u32 { 4 }
+///     fn height(&self) -> u32 { 4 }
+/// }
+///
+/// let ms = Duration::from_millis;
+/// let clock = ManualClock::default();
+/// let desktop = Desktop { clock: clock.clone(), captures: 0, cost: ms(2) };
+/// let mut pacer = Pacer::with_clock(desktop, PacingMode::Fixed(50), clock.clone());
+/// let mut frames = Vec::new();
+/// for _ in 0..4 {
+///     frames.push(pacer.next_frame().unwrap().unwrap());
+/// }
+/// let deadlines: Vec<_> = frames.iter().map(|f| f.deadline).collect();
+/// assert_eq!(deadlines, [ms(0), ms(20), ms(40), ms(60)]);
+/// let repeated: Vec<_> = frames.iter().map(|f| f.repeated).collect();
+/// assert_eq!(repeated, [false, true, false, true]);
+/// assert!(frames.iter().all(|f| f.lateness() == ms(2)));
+/// assert_eq!(pacer.stats().repeated, 2);
+/// assert_eq!(pacer.stats().jitter, Duration::ZERO);
+///
+/// // a capture that takes 45 ms overruns two slots
+/// pacer.backend_mut().cost = ms(45);
+/// let late = pacer.next_frame().unwrap().unwrap();
+/// assert_eq!(late.deadline, ms(80));
+/// assert_eq!(late.lateness(), ms(45));
+/// assert_eq!(pacer.stats().missed_deadlines, 2);
+/// pacer.backend_mut().cost = ms(2);
+/// assert_eq!(pacer.next_frame().unwrap().unwrap().deadline, ms(140));
+/// assert_eq!(pacer.stats().max_lateness, ms(45));
+/// assert!(pacer.stats().jitter > Duration::ZERO);
+/// ```
+pub struct Pacer<B: CaptureBackend, C: Clock = MonotonicClock> {
+    backend: B,
+    mode: PacingMode,
+    clock: C,
+    next_deadline: Option<Duration>,
+    last: Option<Frame>,
+    last_lateness: Option<Duration>,
+    stats: PacerStats,
+}
+
+impl<B: CaptureBackend> Pacer<B> {
+    pub fn new(backend: B, mode: PacingMode) -> Self {
+        Self::with_clock(backend, mode, MonotonicClock::default())
+    }
+}
+
+impl<B: CaptureBackend, C: Clock> Pacer<B, C> {
+    pub fn with_clock(backend: B, mode: PacingMode, clock: C) -> Self {
+        Self {
+            backend,
+            mode,
+            clock,
+            next_deadline: None,
+            last: None,
+            last_lateness: None,
+            stats: PacerStats::default(),
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+
+    pub fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    pub fn stats(&self) -> PacerStats {
+        self.stats
+    }
+
+    /// Blocks until the next frame is due. `None` when there is no frame to deliver
+    /// yet: nothing has been captured at all, or the desktop did not present within
+    /// one interval in `Variable` mode.
+    pub fn next_frame(&mut self) -> anyhow::Result<Option<PacedFrame>> {
+        let interval = self.mode.interval();
+        let now = self.clock.now();
+        let deadline = *self.next_deadline.get_or_insert(now);
+        if deadline > now {
+            self.clock.sleep(deadline - now);
+        }
+
+        let (frame, repeated) = match self.mode {
+            PacingMode::Fixed(_) => match (self.backend.capture(0, true)?, &self.last) {
+                (Some(frame), _) => (frame, false),
+                (None, Some(last)) => {
+                    let mut frame = last.clone();
+                    frame.dirty_rects.clear();
+                    frame.move_rects.clear();
+                    (frame, true)
+                }
+                (None, None) => {
+                    self.next_deadline = Some(deadline + interval);
+                    return Ok(None);
+                }
+            },
+            PacingMode::Variable { .. } => {
+                let timeout = interval.as_millis().max(1) as u32;
+                match self.backend.capture(timeout, true)? {
+                    Some(frame) => (frame, false),
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        let delivered_at = self.clock.now();
+        let lateness = delivered_at.saturating_sub(deadline);
+        self.next_deadline = Some(match self.mode {
+            PacingMode::Fixed(_) => {
+                let overrun = (lateness.as_nanos() / interval.as_nanos().max(1)) as u32;
+                self.stats.missed_deadlines += overrun as u64;
+                deadline + interval * (overrun + 1)
+            }
+            PacingMode::Variable { .. } => delivered_at + interval,
+        });
+
+        self.stats.delivered += 1;
+        if repeated {
+            self.stats.repeated += 1;
+        }
+        self.stats.max_lateness = self.stats.max_lateness.max(lateness);
+        if let Some(previous) = self.last_lateness.replace(lateness) {
+            // J += (|D| - J) / 16, RFC 3550
+            let d = lateness.abs_diff(previous).as_secs_f64();
+            let jitter = self.stats.jitter.as_secs_f64();
+            self.stats.jitter = Duration::from_secs_f64(jitter + (d - jitter) / 16.0);
+        }
+        self.last = Some(frame.clone());
+        Ok(Some(PacedFrame {
+            frame,
+            deadline,
+            delivered_at,
+            repeated,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // presents a frame at each of `presents`, on the manual clock
+    struct Desktop {
+        clock: ManualClock,
+        presents: Vec<Duration>,
+        timeouts: Vec<u32>,
+    }
+
+    impl Desktop {
+        fn new(clock: &ManualClock, presents: impl IntoIterator<Item = Duration>) -> Self {
+            Self {
+                clock: clock.clone(),
+                presents: presents.into_iter().collect(),
+                timeouts: Vec::new(),
+            }
+        }
+    }
+
+    impl CaptureBackend for Desktop {
+        fn capture(&mut self, timeout: u32, _skip: bool) -> anyhow::Result<Option<Frame>> {
+            self.timeouts.push(timeout);
+            let now = self.clock.now();
+            // frames presented in the meantime collapse into the newest one
+            let due = self.presents.iter().filter(|&&at| at <= now).count();
+            if due > 0 {
+                let at = self.presents.drain(..due).next_back().unwrap();
+                let mut frame = Frame::new(4, 4, PixelFormat::Bgra8);
+                frame.present_time = at;
+                return Ok(Some(frame));
+            }
+            match self.presents.first() {
+                Some(&at) if at - now <= ms(timeout as u64) => {
+                    self.clock.advance(at - now);
+                    self.presents.remove(0);
+                    let mut frame = Frame::new(4, 4, PixelFormat::Bgra8);
+                    frame.present_time = at;
+                    Ok(Some(frame))
+                }
+                _ => {
+                    self.clock.advance(ms(timeout as u64));
+                    Ok(None)
+                }
+            }
+        }
+
+        fn width(&self) -> u32 {
+            4
+        }
+
+        fn height(&self) -> u32 {
+            4
+        }
+    }
+
+    #[test]
+    fn fixed_waits_for_a_first_frame() {
+        let clock = ManualClock::default();
+        let desktop = Desktop::new(&clock, [ms(30), ms(35)]);
+        let mut pacer = Pacer::with_clock(desktop, PacingMode::Fixed(50), clock.clone());
+        // nothing to repeat yet, the slots still pass
+        assert!(pacer.next_frame().unwrap().is_none());
+        assert!(pacer.next_frame().unwrap().is_none());
+        assert_eq!(clock.now(), ms(20));
+        let frame = pacer.next_frame().unwrap().unwrap();
+        assert_eq!((frame.deadline, frame.repeated), (ms(40), false));
+        // the newest of the two presents
+        assert_eq!(frame.frame.present_time, ms(35));
+        let frame = pacer.next_frame().unwrap().unwrap();
+        assert_eq!((frame.deadline, frame.repeated), (ms(60), true));
+        assert!(frame.frame.dirty_rects.is_empty());
+        // fixed mode never waits on the backend
+        assert!(pacer.backend().timeouts.iter().all(|&t| t == 0));
+        let stats = pacer.stats();
+        assert_eq!((stats.delivered, stats.repeated), (2, 1));
+    }
+
+    #[test]
+    fn variable_is_capped_at_max_fps() {
+        let clock = ManualClock::default();
+        // a present every 5 ms for 100 ms, then a single one at 300 ms
+        let presents = (0..20).map(|i| ms(i * 5)).chain([ms(300)]);
+        let desktop = Desktop::new(&clock, presents);
+        let mode = PacingMode::Variable { max_fps: 50 };
+        let mut pacer = Pacer::with_clock(desktop, mode, clock.clone());
+        let mut delivered = Vec::new();
+        while let Some(frame) = pacer.next_frame().unwrap() {
+            assert!(!frame.repeated);
+            delivered.push(frame.delivered_at);
+        }
+        // the presents since 80 ms collapse into the one at 95 ms
+        assert_eq!(delivered, [ms(0), ms(20), ms(40), ms(60), ms(80), ms(100)]);
+        // then a quiet desktop gives up after one interval
+        assert_eq!(clock.now(), ms(140));
+        assert_eq!(pacer.backend().timeouts.last(), Some(&20));
+
+        // a slow desktop is followed as it presents
+        clock.advance(ms(150));
+        let frame = pacer.next_frame().unwrap().unwrap();
+        assert_eq!(
+            (frame.delivered_at, frame.frame.present_time),
+            (ms(300), ms(300))
+        );
+        assert_eq!(pacer.stats().delivered, 7);
+        assert_eq!(pacer.stats().missed_deadlines, 0);
+    }
+
+    #[test]
+    fn jitter_follows_rfc3550() {
+        let clock = ManualClock::default();
+        // every slot has a frame, late by 0, 4, 0 and 4 ms
+        let presents = [ms(0), ms(24), ms(40), ms(64)];
+        let desktop = Desktop::new(&clock, presents);
+        let mut pacer = Pacer::with_clock(desktop, PacingMode::Fixed(50), clock.clone());
+        let mut lateness = Vec::new();
+        for _ in 0..4 {
+            let frame = pacer.next_frame().unwrap().unwrap();
+            lateness.push(frame.lateness());
+            // whatever runs between frames takes until the next present
+            if let Some(&at) = pacer.backend().presents.first() {
+                clock.advance(at.saturating_sub(clock.now()));
+            }
+        }
+        assert_eq!(lateness, [ms(0), ms(4), ms(0), ms(4)]);
+        let mut jitter = 0.0;
+        for _ in 0..3 {
+            jitter += (0.004 - jitter) / 16.0;
+        }
+        let stats = pacer.stats();
+        assert!((stats.jitter.as_secs_f64() - jitter).abs() < 1e-9);
+        assert_eq!(stats.max_lateness, ms(4));
+        assert_eq!(stats.missed_deadlines, 0);
+    }
+}
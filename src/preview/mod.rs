@@ -0,0 +1,744 @@
+//! Browser preview over plain HTTP, for debugging without a dedicated client.
+//!
+//! Routes:
+//! - `/` a viewer page that shows either of the streams below
+//! - `/mjpeg` the frames as a `multipart/x-mixed-replace` JPEG stream
+//! - `/snapshot.jpg` the latest frame
+//! - `/ws` a WebSocket carrying the encoded packets as fragmented MP4, for Media Source
+//!   Extensions: the codec string as a text message, then the init segment and one
+//!   fragment per packet as binary messages
+//!
+//! There is no authentication, bind to localhost.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::annexb::ParameterSetCache;
+use crate::backend::CaptureBackend;
+use crate::codec::{Codec, Packet};
+use crate::frame::Frame;
+use crate::jpeg;
+use crate::mp4::Mp4Writer;
+use crate::net::{Endpoint, Listener, Stream};
+
+mod websocket;
+
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_HEAD: usize = 8192;
+// packets queued per WebSocket client before it has to wait for the next keyframe
+const MAX_QUEUED_PACKETS: usize = 64;
+// how often idle stream threads check for shutdown
+const IDLE_POLL: Duration = Duration::from_millis(500);
+
+const VIEWER: &str = include_str!("viewer.html");
+
+#[derive(Debug, Clone)]
+pub struct PreviewConfig {
+    // 1 - 100
+    pub jpeg_quality: u8,
+    // a client that does not read for this long is disconnected
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            jpeg_quality: 80,
+            write_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Mp4Queue {
+    packets: VecDeque<Arc<Packet>>,
+    // set after an overflow, packets are skipped up to the next keyframe
+    waiting_keyframe: bool,
+    closed: bool,
+}
+
+struct Mp4Client {
+    queue: Mutex<Mp4Queue>,
+    ready: Condvar,
+}
+
+impl Mp4Client {
+    fn close(&self) {
+        self.queue.lock().closed = true;
+        self.ready.notify_one();
+    }
+}
+
+struct Shared {
+    config: PreviewConfig,
+    last_frame: Mutex<Option<Arc<Frame>>>,
+    // latest JPEG with a sequence number, only encoded while MJPEG clients are connected
+    jpeg: Mutex<(u64, Option<Arc<Vec<u8>>>)>,
+    jpeg_ready: Condvar,
+    mjpeg_clients: AtomicUsize,
+    video: Mutex<Option<(Codec, u32, u32)>>,
+    mp4_clients: Mutex<Vec<Arc<Mp4Client>>>,
+    shutdown: AtomicBool,
+}
+
+/// Embedded HTTP server for watching the capture in a browser.
+///
+/// MJPEG is encoded here from the frames passed to `send_frame`. The fMP4 stream needs
+/// H.264 or H.265 packets from an encoder, passed to `send_packet` after `set_video`,
+/// and a client starts at the next keyframe.
+pub struct PreviewServer {
+    shared: Arc<Shared>,
+    endpoint: Endpoint,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl PreviewServer {
+    pub fn bind(endpoint: &Endpoint, config: PreviewConfig) -> io::Result<Self> {
+        let listener = Listener::bind(endpoint)?;
+        let endpoint = listener.local_endpoint()?;
+        let shared = Arc::new(Shared {
+            config,
+            last_frame: Mutex::new(None),
+            jpeg: Mutex::new((0, None)),
+            jpeg_ready: Condvar::new(),
+            mjpeg_clients: AtomicUsize::new(0),
+            video: Mutex::new(None),
+            mp4_clients: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let accept = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("preview-accept".to_string())
+                .spawn(move || accept_loop(listener, shared))?
+        };
+        log::debug!("preview: listening on http://{}", endpoint);
+        Ok(Self {
+            shared,
+            endpoint,
+            accept: Some(accept),
+        })
+    }
+
+    /// The bound endpoint, with the actual port when bound to port 0.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub fn send_frame(&self, frame: Frame) {
+        let frame = Arc::new(frame);
+        *self.shared.last_frame.lock() = Some(frame.clone());
+        if self.shared.mjpeg_clients.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let data = Arc::new(jpeg::encode(&frame, self.shared.config.jpeg_quality));
+        let mut jpeg = self.shared.jpeg.lock();
+        *jpeg = (jpeg.0 + 1, Some(data));
+        drop(jpeg);
+        self.shared.jpeg_ready.notify_all();
+    }
+
+    /// Codec and size of the packets passed to `send_packet`. A change disconnects the
+    /// fMP4 clients, the viewer reconnects on its own.
+    pub fn set_video(&self, codec: Codec, width: u32, height: u32) {
+        let mut video = self.shared.video.lock();
+        if *video == Some((codec, width, height)) {
+            return;
+        }
+        *video = Some((codec, width, height));
+        for client in self.shared.mp4_clients.lock().drain(..) {
+            client.close();
+        }
+    }
+
+    pub fn send_packet(&self, packet: Packet) {
+        let packet = Arc::new(packet);
+        for client in self.shared.mp4_clients.lock().iter() {
+            let mut queue = client.queue.lock();
+            if queue.packets.len() >= MAX_QUEUED_PACKETS {
+                queue.packets.clear();
+                queue.waiting_keyframe = true;
+            }
+            if queue.waiting_keyframe && !packet.keyframe {
+                continue;
+            }
+            queue.waiting_keyframe = false;
+            queue.packets.push_back(packet.clone());
+            drop(queue);
+            client.ready.notify_one();
+        }
+    }
+
+    /// Connected MJPEG and fMP4 streams.
+    pub fn client_count(&self) -> usize {
+        self.shared.mjpeg_clients.load(Ordering::SeqCst) + self.shared.mp4_clients.lock().len()
+    }
+
+    /// Captures from `backend` and serves every new frame until `stop` is set.
+    pub fn run<B: CaptureBackend + ?Sized>(
+        &self,
+        backend: &mut B,
+        stop: &AtomicBool,
+    ) -> anyhow::Result<()> {
+        while !stop.load(Ordering::SeqCst) {
+            if let Some(frame) = backend.capture(100, true)? {
+                self.send_frame(frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PreviewServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+        self.shared.jpeg_ready.notify_all();
+        for client in self.shared.mp4_clients.lock().drain(..) {
+            client.close();
+        }
+    }
+}
+
+fn accept_loop(listener: Listener, shared: Arc<Shared>) {
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok(Some((stream, peer))) => {
+                let shared = shared.clone();
+                let spawned = std::thread::Builder::new()
+                    .name("preview-client".to_string())
+                    .spawn(move || {
+                        if let Err(e) = serve(stream, &shared) {
+                            log::debug!("preview: client {}: {}", peer, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    log::error!("preview: failed to spawn client thread: {}", e);
+                }
+            }
+            Ok(None) => std::thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                log::error!("preview: accept failed: {}", e);
+                std::thread::sleep(ACCEPT_POLL);
+            }
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    // lowercase names
+    headers: HashMap<String, String>,
+}
+
+impl Request {
+    fn read<R: BufRead>(r: &mut R) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            len += line.len();
+            if len > MAX_REQUEST_HEAD {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request head too large",
+                ));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line.to_string());
+        }
+        let mut request_line = lines.first().map(|l| l.split(' ')).into_iter().flatten();
+        let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed request line",
+            ));
+        };
+        let path = target.split('?').next().unwrap_or_default().to_string();
+        let headers = lines[1..]
+            .iter()
+            .filter_map(|l| l.split_once(':'))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+            .collect();
+        Ok(Self {
+            method: method.to_string(),
+            path,
+            headers,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.as_str())
+    }
+}
+
+fn respond<W: Write>(w: &mut W, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        w,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+fn serve(stream: Stream, shared: &Arc<Shared>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(shared.config.write_timeout)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::with_capacity(1 << 16, stream.try_clone()?);
+    let request = Request::read(&mut reader)?;
+    // the streams stay open for as long as the client wants
+    stream.set_read_timeout(None)?;
+    log::debug!("preview: {} {}", request.method, request.path);
+    if request.method != "GET" {
+        return respond(&mut writer, "405 Method Not Allowed", "text/plain", b"");
+    }
+    let result = match request.path.as_str() {
+        "/" | "/index.html" => respond(
+            &mut writer,
+            "200 OK",
+            "text/html; charset=utf-8",
+            VIEWER.as_bytes(),
+        ),
+        "/snapshot.jpg" => match shared.last_frame.lock().clone() {
+            Some(frame) => {
+                let data = jpeg::encode(&frame, shared.config.jpeg_quality);
+                respond(&mut writer, "200 OK", "image/jpeg", &data)
+            }
+            None => respond(
+                &mut writer,
+                "503 Service Unavailable",
+                "text/plain",
+                b"no frame yet",
+            ),
+        },
+        "/mjpeg" => {
+            shared.mjpeg_clients.fetch_add(1, Ordering::SeqCst);
+            let result = serve_mjpeg(&mut writer, shared);
+            shared.mjpeg_clients.fetch_sub(1, Ordering::SeqCst);
+            result
+        }
+        "/ws" => serve_websocket(&request, reader, &mut writer, shared),
+        _ => respond(&mut writer, "404 Not Found", "text/plain", b"not found"),
+    };
+    stream.shutdown();
+    result
+}
+
+fn serve_mjpeg<W: Write>(w: &mut W, shared: &Shared) -> io::Result<()> {
+    w.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    )?;
+    w.flush()?;
+    // start with the latest frame rather than waiting for the next one
+    let first = shared.last_frame.lock().clone();
+    let mut data = first.map(|frame| Arc::new(jpeg::encode(&frame, shared.config.jpeg_quality)));
+    let mut seen = shared.jpeg.lock().0;
+    loop {
+        if let Some(data) = data.take() {
+            write!(
+                w,
+                "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                data.len()
+            )?;
+            w.write_all(&data)?;
+            w.write_all(b"\r\n")?;
+            w.flush()?;
+        }
+        let mut jpeg = shared.jpeg.lock();
+        while jpeg.0 == seen && !shared.shutdown.load(Ordering::SeqCst) {
+            shared.jpeg_ready.wait_for(&mut jpeg, IDLE_POLL);
+        }
+        if shared.shutdown.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        seen = jpeg.0;
+        data = jpeg.1.clone();
+    }
+}
+
+fn serve_websocket<R, W>(
+    request: &Request,
+    mut reader: R,
+    w: &mut W,
+    shared: &Shared,
+) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    let (true, Some(key)) = (upgrade, request.header("sec-websocket-key")) else {
+        return respond(w, "400 Bad Request", "text/plain", b"websocket expected");
+    };
+    let Some((codec, width, height)) = *shared.video.lock() else {
+        return respond(
+            w,
+            "503 Service Unavailable",
+            "text/plain",
+            b"no video stream",
+        );
+    };
+    write!(
+        w,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key)
+    )?;
+    w.flush()?;
+
+    let client = Arc::new(Mp4Client {
+        queue: Mutex::new(Mp4Queue {
+            waiting_keyframe: true,
+            ..Default::default()
+        }),
+        ready: Condvar::new(),
+    });
+    shared.mp4_clients.lock().push(client.clone());
+    if shared.shutdown.load(Ordering::SeqCst) {
+        client.close();
+    }
+    let reader_client = client.clone();
+    let spawned = std::thread::Builder::new()
+        .name("preview-ws-reader".to_string())
+        .spawn(move || {
+            // nothing is expected from the browser but a close
+            loop {
+                match websocket::read_frame(&mut reader) {
+                    Ok((websocket::OPCODE_CLOSE, _)) | Err(_) => break,
+                    Ok(_) => {}
+                }
+            }
+            reader_client.close();
+        });
+    let result = match spawned {
+        Ok(_) => write_mp4(w, &client, codec, width, height),
+        Err(e) => Err(e),
+    };
+    client.close();
+    shared
+        .mp4_clients
+        .lock()
+        .retain(|other| !Arc::ptr_eq(other, &client));
+    if result.is_ok() {
+        let _ = websocket::write_frame(w, websocket::OPCODE_CLOSE, &1000u16.to_be_bytes());
+    }
+    result
+}
+
+fn write_mp4<W: Write>(
+    w: &mut W,
+    client: &Mp4Client,
+    codec: Codec,
+    width: u32,
+    height: u32,
+) -> io::Result<()> {
+    let mut mp4 = Mp4Writer::new(Vec::new(), codec, width, height);
+    // every packet is its own fragment, written once the next one gives its duration
+    mp4.set_fragment_duration(Duration::ZERO);
+    let mut parameter_sets = ParameterSetCache::new(codec);
+    let mut announced = false;
+    loop {
+        let packet = {
+            let mut queue = client.queue.lock();
+            while queue.packets.is_empty() && !queue.closed {
+                client.ready.wait(&mut queue);
+            }
+            if queue.closed {
+                return Ok(());
+            }
+            queue.packets.pop_front().unwrap()
+        };
+        parameter_sets.update(&packet.data);
+        mp4.write(&packet)?;
+        let out = mp4.get_mut().unwrap();
+        if out.is_empty() {
+            continue;
+        }
+        if !announced {
+            let codec_string = parameter_sets.codec_string().unwrap_or_default();
+            websocket::write_frame(w, websocket::OPCODE_TEXT, codec_string.as_bytes())?;
+            announced = true;
+        }
+        websocket::write_frame(w, websocket::OPCODE_BINARY, out)?;
+        out.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpStream;
+
+    use super::*;
+    use crate::synthetic::SyntheticBackend;
+
+    fn server() -> PreviewServer {
+        PreviewServer::bind(
+            &"tcp://127.0.0.1:0".parse().unwrap(),
+            PreviewConfig::default(),
+        )
+        .unwrap()
+    }
+
+    fn read_head<R: BufRead>(r: &mut R) -> String {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                return head;
+            }
+            head.push_str(&line);
+        }
+    }
+
+    // sends a request and reads the response head
+    fn request(server: &PreviewServer, method: &str, path: &str) -> (BufReader<TcpStream>, String) {
+        let Endpoint::Tcp(addr) = server.endpoint() else {
+            unreachable!()
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            method, path
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let head = read_head(&mut reader);
+        (reader, head)
+    }
+
+    fn body(reader: &mut BufReader<TcpStream>, head: &str) -> Vec<u8> {
+        let len = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        body
+    }
+
+    // an unmasked frame from the server
+    fn server_frame<R: Read>(r: &mut R) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        r.read_exact(&mut head).unwrap();
+        assert_eq!(head[0] & 0xf0, 0x80, "fragmented or reserved bits");
+        let len = match head[1] {
+            126 => {
+                let mut b = [0; 2];
+                r.read_exact(&mut b).unwrap();
+                u16::from_be_bytes(b) as usize
+            }
+            127 => {
+                let mut b = [0; 8];
+                r.read_exact(&mut b).unwrap();
+                u64::from_be_bytes(b) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        r.read_exact(&mut payload).unwrap();
+        (head[0] & 0x0f, payload)
+    }
+
+    fn h264(nals: &[&[u8]], ms: u64) -> Packet {
+        let mut data = Vec::new();
+        for nal in nals {
+            data.extend_from_slice(&[0, 0, 0, 1]);
+            data.extend_from_slice(nal);
+        }
+        Packet::new(Codec::H264, data, Duration::from_millis(ms))
+    }
+
+    fn wait_for_clients(server: &PreviewServer, count: usize) {
+        let start = std::time::Instant::now();
+        while server.client_count() != count {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "clients never went to {}",
+                count
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    // width and height from the baseline SOF, the segment right after the tables
+    fn jpeg_size(data: &[u8]) -> (u16, u16) {
+        assert!(data.starts_with(&[0xff, 0xd8]) && data.ends_with(&[0xff, 0xd9]));
+        let mut pos = 2;
+        loop {
+            let marker = data[pos + 1];
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            if marker == 0xc0 {
+                let sof = &data[pos + 4..];
+                return (
+                    u16::from_be_bytes([sof[3], sof[4]]),
+                    u16::from_be_bytes([sof[1], sof[2]]),
+                );
+            }
+            pos += 2 + len;
+        }
+    }
+
+    #[test]
+    fn snapshot() {
+        let server = server();
+        let (_, head) = request(&server, "GET", "/snapshot.jpg");
+        assert!(head.starts_with("HTTP/1.1 503"), "{}", head);
+
+        let mut backend = SyntheticBackend::new(160, 90, 100);
+        let frame = backend.capture(100, true).unwrap().unwrap();
+        server.send_frame(frame.clone());
+        let (mut reader, head) = request(&server, "GET", "/snapshot.jpg?t=1");
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(head.contains("Content-Type: image/jpeg\r\n"), "{}", head);
+        // the encoder is deterministic
+        let data = body(&mut reader, &head);
+        assert_eq!(
+            data,
+            jpeg::encode(&frame, PreviewConfig::default().jpeg_quality)
+        );
+        assert_eq!(jpeg_size(&data), (160, 90));
+    }
+
+    #[test]
+    fn routes() {
+        let server = server();
+        let (mut reader, head) = request(&server, "GET", "/");
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(body(&mut reader, &head), VIEWER.as_bytes());
+        let (_, head) = request(&server, "GET", "/nope");
+        assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+        let (_, head) = request(&server, "POST", "/snapshot.jpg");
+        assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+        // a plain GET rather than a WebSocket upgrade
+        let (_, head) = request(&server, "GET", "/ws");
+        assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+    }
+
+    #[test]
+    fn websocket_fragmented_mp4() {
+        const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+        const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+        let server = server();
+        server.set_video(Codec::H264, 64, 48);
+        let Endpoint::Tcp(addr) = server.endpoint() else {
+            unreachable!()
+        };
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        assert!(
+            head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+            "{}",
+            head
+        );
+        wait_for_clients(&server, 1);
+
+        // the client starts at the first keyframe
+        server.send_packet(h264(&[&[0x41, 1]], 0));
+        server.send_packet(h264(&[SPS, PPS, &[0x65, 1, 2, 3]], 40));
+        server.send_packet(h264(&[&[0x41, 4]], 80));
+        server.send_packet(h264(&[&[0x41, 5]], 120));
+        assert_eq!(
+            server_frame(&mut reader),
+            (websocket::OPCODE_TEXT, b"avc1.42c01e".to_vec())
+        );
+        let names =
+            |data: &[u8]| -> Vec<[u8; 4]> { crate::mp4::boxes(data).map(|b| b.name).collect() };
+        let (opcode, init) = server_frame(&mut reader);
+        assert_eq!(opcode, websocket::OPCODE_BINARY);
+        assert_eq!(names(&init), [*b"ftyp", *b"moov"]);
+        // a fragment is sent once the next packet gives its duration
+        for _ in 0..2 {
+            let (opcode, fragment) = server_frame(&mut reader);
+            assert_eq!(opcode, websocket::OPCODE_BINARY);
+            assert_eq!(names(&fragment), [*b"moof", *b"mdat"]);
+        }
+
+        // a masked close from the browser, answered with a normal closure
+        stream
+            .write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8])
+            .unwrap();
+        assert_eq!(
+            server_frame(&mut reader),
+            (websocket::OPCODE_CLOSE, 1000u16.to_be_bytes().to_vec())
+        );
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+        wait_for_clients(&server, 0);
+    }
+
+    #[test]
+    fn mjpeg_from_the_backend() {
+        let server = server();
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut backend = SyntheticBackend::new(64, 48, 100);
+                server.run(&mut backend, &stop).unwrap();
+            });
+            let (mut reader, head) = request(&server, "GET", "/mjpeg");
+            assert!(
+                head.contains("Content-Type: multipart/x-mixed-replace; boundary=frame\r\n"),
+                "{}",
+                head
+            );
+            let mut parts = Vec::new();
+            for _ in 0..5 {
+                let mut boundary = String::new();
+                reader.read_line(&mut boundary).unwrap();
+                assert_eq!(boundary, "--frame\r\n");
+                let part = read_head(&mut reader);
+                assert!(part.contains("Content-Type: image/jpeg\r\n"), "{}", part);
+                let data = body(&mut reader, &part);
+                assert_eq!(jpeg_size(&data), (64, 48));
+                let mut crlf = [0; 2];
+                reader.read_exact(&mut crlf).unwrap();
+                assert_eq!(&crlf, b"\r\n");
+                parts.push(data);
+            }
+            assert_eq!(server.client_count(), 1);
+            // the bar moves, so the stream is not one frame repeated
+            assert_ne!(parts.first(), parts.last());
+            stop.store(true, Ordering::SeqCst);
+        });
+    }
+}
@@ -0,0 +1,333 @@
+//! Bounded hand-off between threads, e.g. from a `CaptureWorker` to its consumer.
+
+use std::collections::VecDeque;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// What a full queue does with a new item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    // discard the oldest queued item to make room
+    #[default]
+    DropOldest,
+    // discard the new item
+    DropNewest,
+    // wait until the consumer makes room
+    Block,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    dropped: u64,
+    // an async consumer waiting in `poll_pop`
+    waker: Option<Waker>,
+}
+
+/// Bounded multi-producer, multi-consumer queue applying a `DropPolicy` when full.
+pub struct BoundedQueue<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<T> BoundedQueue<T> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                items: VecDeque::with_capacity(capacity.max(1)),
+                closed: false,
+                dropped: 0,
+                waker: None,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn policy(&self) -> DropPolicy {
+        self.policy
+    }
+
+    /// Queues `item`, returns it back if the queue is closed.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        let mut state = self.state.lock();
+        loop {
+            if state.closed {
+                return Err(item);
+            }
+            if state.items.len() < self.capacity {
+                break;
+            }
+            match self.policy {
+                DropPolicy::DropOldest => {
+                    state.items.pop_front();
+                    state.dropped += 1;
+                    break;
+                }
+                DropPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return Ok(());
+                }
+                DropPolicy::Block => self.not_full.wait(&mut state),
+            }
+        }
+        state.items.push_back(item);
+        let waker = state.waker.take();
+        drop(state);
+        self.not_empty.notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Waits for an item. `None` once the queue is closed and drained, or on timeout.
+    pub fn pop(&self, timeout: Option<Duration>) -> Option<T> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.state.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                drop(state);
+                self.not_full.notify_one();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            match deadline {
+                Some(deadline) => {
+                    if self.not_empty.wait_until(&mut state, deadline).timed_out() {
+                        return None;
+                    }
+                }
+                None => self.not_empty.wait(&mut state),
+            }
+        }
+    }
+
+    /// `pop` for async consumers. When the queue is empty the waker of `cx` is woken by
+    /// the next push or by `close`. Only the waker of the last call is kept, so there
+    /// should be a single async consumer.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use std::task::{Context, Poll, Wake, Waker};
+    /// use dxgi::queue::{BoundedQueue, DropPolicy};
+    ///
+    /// #[derive(Default)]
+    /// struct Count(AtomicUsize);
+    /// impl Wake for Count {
+    ///     fn wake(self: Arc<Self>) {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// let count = Arc::new(Count::default());
+    /// let waker = Waker::from(count.clone());
+    /// let mut cx = Context::from_waker(&waker);
+    /// let queue = BoundedQueue::new(2, DropPolicy::DropOldest);
+    ///
+    /// assert_eq!(queue.poll_pop(&mut cx), Poll::Pending);
+    /// queue.push(1).unwrap();
+    /// assert_eq!(count.0.load(Ordering::SeqCst), 1);
+    /// assert_eq!(queue.poll_pop(&mut cx), Poll::Ready(Some(1)));
+    /// // no waker is registered while items are available
+    /// queue.push(2).unwrap();
+    /// assert_eq!(count.0.load(Ordering::SeqCst), 1);
+    /// assert_eq!(queue.poll_pop(&mut cx), Poll::Ready(Some(2)));
+    ///
+    /// assert_eq!(queue.poll_pop(&mut cx), Poll::Pending);
+    /// queue.close();
+    /// assert_eq!(count.0.load(Ordering::SeqCst), 2);
+    /// assert_eq!(queue.poll_pop(&mut cx), Poll::Ready(None));
+    /// ```
+    pub fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.lock();
+        if let Some(item) = state.items.pop_front() {
+            drop(state);
+            self.not_full.notify_one();
+            return Poll::Ready(Some(item));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        // registered under the lock, so a push cannot slip in unnoticed
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    pub fn try_pop(&self) -> Option<T> {
+        let item = self.state.lock().items.pop_front();
+        if item.is_some() {
+            self.not_full.notify_one();
+        }
+        item
+    }
+
+    /// Wakes all waiters. Queued items can still be popped, new pushes fail.
+    pub fn close(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            state.closed = true;
+            state.waker.take()
+        };
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Items discarded by the drop policy so far.
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn drain<T>(queue: &BoundedQueue<T>) -> Vec<T> {
+        std::iter::from_fn(|| queue.try_pop()).collect()
+    }
+
+    #[test]
+    fn drop_policies() {
+        let oldest = BoundedQueue::new(3, DropPolicy::DropOldest);
+        let newest = BoundedQueue::new(3, DropPolicy::DropNewest);
+        for i in 0..5 {
+            oldest.push(i).unwrap();
+            newest.push(i).unwrap();
+        }
+        assert_eq!((oldest.len(), oldest.dropped()), (3, 2));
+        assert_eq!(drain(&oldest), [2, 3, 4]);
+        assert_eq!((newest.len(), newest.dropped()), (3, 2));
+        assert_eq!(drain(&newest), [0, 1, 2]);
+        // a capacity of 0 still holds one item
+        let single = BoundedQueue::new(0, DropPolicy::DropOldest);
+        single.push(1).unwrap();
+        single.push(2).unwrap();
+        assert_eq!((single.capacity(), drain(&single)), (1, vec![2]));
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let queue = Arc::new(BoundedQueue::new(2, DropPolicy::Block));
+        let pushed = Arc::new(AtomicUsize::new(0));
+        let producer = {
+            let (queue, pushed) = (queue.clone(), pushed.clone());
+            std::thread::spawn(move || {
+                for i in 0..5 {
+                    queue.push(i).unwrap();
+                    pushed.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(pushed.load(Ordering::SeqCst), 2);
+        let popped: Vec<_> = (0..5)
+            .map(|_| queue.pop(Some(Duration::from_secs(5))).unwrap())
+            .collect();
+        producer.join().unwrap();
+        assert_eq!(popped, [0, 1, 2, 3, 4]);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn close_wakes_producers_and_consumers() {
+        let queue = Arc::new(BoundedQueue::new(1, DropPolicy::Block));
+        queue.push(1).unwrap();
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.push(2))
+        };
+        let consumer = {
+            let empty = Arc::new(BoundedQueue::<u32>::new(1, DropPolicy::Block));
+            let waiting = empty.clone();
+            (empty, std::thread::spawn(move || waiting.pop(None)))
+        };
+        std::thread::sleep(Duration::from_millis(30));
+        queue.close();
+        consumer.0.close();
+        // the blocked push gets its item back, the queued one can still be popped
+        assert_eq!(producer.join().unwrap(), Err(2));
+        assert_eq!(consumer.1.join().unwrap(), None);
+        assert!(queue.is_closed());
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(queue.pop(None), Some(1));
+        assert_eq!(queue.pop(None), None);
+    }
+
+    #[test]
+    fn pop_timeout() {
+        let queue = BoundedQueue::<u32>::new(1, DropPolicy::DropOldest);
+        let started = Instant::now();
+        assert_eq!(queue.pop(Some(Duration::from_millis(30))), None);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn producers_and_consumers() {
+        let queue = Arc::new(BoundedQueue::new(4, DropPolicy::Block));
+        let producers: Vec<_> = (0..4)
+            .map(|p| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0..250 {
+                        queue.push(p * 1000 + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    std::iter::from_fn(|| queue.pop(None)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        queue.close();
+        let mut items: Vec<u32> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        items.sort_unstable();
+        let expected: Vec<u32> = (0..4)
+            .flat_map(|p| (0..250).map(move |i| p * 1000 + i))
+            .collect();
+        assert_eq!(items, expected);
+    }
+}
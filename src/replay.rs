@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::annexb::ParameterSetCache;
+use crate::codec::{Codec, Packet, PacketWriter};
+use crate::segment::ContainerFormat;
+
+// bookkeeping cost charged per packet on top of its payload
+const PACKET_OVERHEAD: usize = std::mem::size_of::<Packet>();
+
+/// In-memory ring of encoded packets covering at least the last `window`, for
+/// "save the last 30 seconds" style instant replay.
+///
+/// The buffer always starts on a keyframe: packets are only ever dropped a whole GOP
+/// at a time, and only while the remaining GOPs still cover the window. `max_bytes`
+/// bounds the memory use and wins over the window when both cannot be met, although
+/// the GOP currently being written is never dropped.
+pub struct ReplayBuffer {
+    codec: Codec,
+    window: Duration,
+    max_bytes: usize,
+    packets: VecDeque<Packet>,
+    bytes: usize,
+    parameter_sets: ParameterSetCache,
+}
+
+impl ReplayBuffer {
+    pub fn new(codec: Codec, window: Duration, max_bytes: usize) -> Self {
+        Self {
+            codec,
+            window,
+            max_bytes,
+            packets: VecDeque::new(),
+            bytes: 0,
+            parameter_sets: ParameterSetCache::new(codec),
+        }
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        if packet.codec != self.codec {
+            log::error!(
+                "replay: expected {} packet, got {}",
+                self.codec,
+                packet.codec
+            );
+            return;
+        }
+        if self.packets.is_empty() && !packet.keyframe {
+            return;
+        }
+        if self.codec.is_annexb() {
+            self.parameter_sets.update(&packet.data);
+        }
+        self.bytes += packet.data.len() + PACKET_OVERHEAD;
+        self.packets.push_back(packet);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let Some(newest) = self.packets.back().map(|p| p.pts) else {
+            return;
+        };
+        while let Some(next_gop) = self.packets.iter().skip(1).position(|p| p.keyframe) {
+            let next_gop = next_gop + 1;
+            let covered = newest.saturating_sub(self.packets[next_gop].pts) >= self.window;
+            if !covered && self.bytes <= self.max_bytes {
+                break;
+            }
+            for packet in self.packets.drain(..next_gop) {
+                self.bytes -= packet.data.len() + PACKET_OVERHEAD;
+            }
+        }
+        if self.bytes > self.max_bytes {
+            log::debug!(
+                "replay: single GOP of {} bytes exceeds the {} byte limit",
+                self.bytes,
+                self.max_bytes
+            );
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Memory charged to the buffered packets.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub fn duration(&self) -> Duration {
+        match (self.packets.front(), self.packets.back()) {
+            (Some(first), Some(last)) => last.pts.saturating_sub(first.pts),
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.bytes = 0;
+    }
+
+    pub fn packets(&self) -> impl Iterator<Item = &Packet> {
+        self.packets.iter()
+    }
+
+    /// Writes the buffered packets to `writer`, returns the number written.
+    /// The first keyframe gets the cached parameter sets if it has none of its own.
+    pub fn save(&self, writer: &mut dyn PacketWriter) -> io::Result<usize> {
+        for (i, packet) in self.packets.iter().enumerate() {
+            if i == 0 && self.codec.is_annexb() {
+                let mut first = packet.clone();
+                first.data = self.parameter_sets.prepare_keyframe(&packet.data);
+                writer.write_packet(&first)?;
+            } else {
+                writer.write_packet(packet)?;
+            }
+        }
+        Ok(self.packets.len())
+    }
+
+    pub fn save_to_file(
+        &self,
+        path: impl AsRef<Path>,
+        format: ContainerFormat,
+        width: u32,
+        height: u32,
+    ) -> io::Result<usize> {
+        let file = BufWriter::new(File::create(path.as_ref())?);
+        let mut writer = format.create(file, self.codec, width, height)?;
+        let count = self.save(writer.as_mut())?;
+        writer.close()?;
+        log::debug!("replay: saved {} packets to {:?}", count, path.as_ref());
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annexb;
+
+    const SPS: [u8; 7] = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    // the i-th packet of a stream with a frame every 100 ms
+    fn h264(i: u64, keyframe: bool, len: usize) -> Packet {
+        let mut data = annexb::START_CODE.to_vec();
+        if keyframe {
+            if i == 0 {
+                for nal in [&SPS[..], &PPS] {
+                    data.extend_from_slice(nal);
+                    data.extend_from_slice(&annexb::START_CODE);
+                }
+            }
+            data.push(0x65);
+        } else {
+            data.push(0x41);
+        }
+        data.resize(data.len() + len, i as u8);
+        Packet::new(Codec::H264, data, Duration::from_millis(i * 100))
+    }
+
+    // GOPs of `gop` packets
+    fn stream(gops: u64, gop: u64, len: usize) -> impl Iterator<Item = Packet> {
+        (0..gops).flat_map(move |g| (0..gop).map(move |j| h264(g * gop + j, j == 0, len)))
+    }
+
+    struct Collect(Vec<Packet>);
+
+    impl PacketWriter for Collect {
+        fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+            self.0.push(packet.clone());
+            Ok(())
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trims_whole_gops_to_the_window() {
+        let mut buffer = ReplayBuffer::new(Codec::H264, Duration::from_secs(2), usize::MAX);
+        // nothing to start from
+        buffer.push(h264(1, false, 10));
+        assert!(buffer.is_empty());
+        for packet in stream(10, 10, 10) {
+            let pts = packet.pts;
+            buffer.push(packet);
+            assert!(buffer.packets().next().unwrap().keyframe);
+            if pts >= Duration::from_secs(2) {
+                // covers the window, and dropping one more GOP would not
+                assert!(buffer.duration() >= Duration::from_secs(2));
+                assert!(buffer.duration() < Duration::from_secs(3));
+            }
+        }
+        assert_eq!(
+            buffer.packets().next().unwrap().pts,
+            Duration::from_millis(7000)
+        );
+        assert_eq!(buffer.len(), 30);
+    }
+
+    #[test]
+    fn byte_limit() {
+        let packet_bytes = 1 + 100 + PACKET_OVERHEAD + 4;
+        let max_bytes = 25 * packet_bytes;
+        let mut buffer = ReplayBuffer::new(Codec::H264, Duration::from_secs(60), max_bytes);
+        for packet in stream(10, 10, 100) {
+            let pts = packet.pts;
+            buffer.push(packet);
+            let first = buffer.packets().next().unwrap();
+            assert!(first.keyframe);
+            assert!(
+                buffer.bytes() <= max_bytes,
+                "{} bytes at {:?}",
+                buffer.bytes(),
+                pts
+            );
+            let counted: usize = buffer
+                .packets()
+                .map(|p| p.data.len() + PACKET_OVERHEAD)
+                .sum();
+            assert_eq!(buffer.bytes(), counted);
+        }
+        // two whole GOPs and the one being written
+        assert_eq!(buffer.len(), 20);
+
+        // a GOP larger than the limit is kept alone
+        let mut buffer = ReplayBuffer::new(Codec::H264, Duration::from_secs(60), 5 * packet_bytes);
+        for packet in stream(3, 10, 100).take(28) {
+            buffer.push(packet);
+        }
+        assert_eq!(buffer.len(), 8);
+        assert_eq!(buffer.packets().next().unwrap().pts, Duration::from_secs(2));
+        buffer.clear();
+        assert_eq!((buffer.len(), buffer.bytes()), (0, 0));
+    }
+
+    #[test]
+    fn save_primes_the_first_keyframe() {
+        let mut buffer = ReplayBuffer::new(Codec::H264, Duration::from_secs(1), usize::MAX);
+        for packet in stream(4, 10, 10).take(35) {
+            buffer.push(packet);
+        }
+        let mut writer = Collect(Vec::new());
+        assert_eq!(buffer.save(&mut writer).unwrap(), 15);
+        let first = &writer.0[0];
+        assert_eq!(first.pts, Duration::from_secs(2));
+        let nals: Vec<_> = annexb::split(&first.data).map(|n| n[0]).collect();
+        assert_eq!(nals, [0x67, 0x68, 0x65]);
+        assert_eq!(writer.0[1].data, buffer.packets().nth(1).unwrap().data);
+    }
+}
@@ -0,0 +1,587 @@
+use flate2::{Compress, Compression, FlushCompress};
+
+use crate::frame::{Frame, Rect};
+use crate::net::CursorShape;
+
+pub const ENCODING_RAW: i32 = 0;
+pub const ENCODING_COPY_RECT: i32 = 1;
+pub const ENCODING_HEXTILE: i32 = 5;
+pub const ENCODING_TIGHT: i32 = 7;
+pub const ENCODING_ZRLE: i32 = 16;
+pub const ENCODING_DESKTOP_SIZE: i32 = -223;
+pub const ENCODING_POINTER_POS: i32 = -232;
+pub const ENCODING_CURSOR: i32 = -239;
+
+// Tight limits from the TightVNC implementation
+const TIGHT_MAX_WIDTH: u32 = 2048;
+const TIGHT_MAX_AREA: u32 = 16384;
+const TIGHT_MIN_TO_COMPRESS: usize = 12;
+
+/// An RFB pixel format, as sent in ServerInit and SetPixelFormat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RfbPixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub red_max: u16,
+    pub green_max: u16,
+    pub blue_max: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl RfbPixelFormat {
+    /// 32 bit little endian with the byte order of a BGRA frame, what the server offers.
+    pub const BGRX: Self = Self {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        red_max: 255,
+        green_max: 255,
+        blue_max: 255,
+        red_shift: 16,
+        green_shift: 8,
+        blue_shift: 0,
+    };
+
+    pub fn parse(b: &[u8; 16]) -> Self {
+        Self {
+            bits_per_pixel: b[0],
+            depth: b[1],
+            big_endian: b[2] != 0,
+            true_colour: b[3] != 0,
+            red_max: u16::from_be_bytes([b[4], b[5]]),
+            green_max: u16::from_be_bytes([b[6], b[7]]),
+            blue_max: u16::from_be_bytes([b[8], b[9]]),
+            red_shift: b[10],
+            green_shift: b[11],
+            blue_shift: b[12],
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut b = [0u8; 16];
+        b[0] = self.bits_per_pixel;
+        b[1] = self.depth;
+        b[2] = self.big_endian as u8;
+        b[3] = self.true_colour as u8;
+        b[4..6].copy_from_slice(&self.red_max.to_be_bytes());
+        b[6..8].copy_from_slice(&self.green_max.to_be_bytes());
+        b[8..10].copy_from_slice(&self.blue_max.to_be_bytes());
+        b[10] = self.red_shift;
+        b[11] = self.green_shift;
+        b[12] = self.blue_shift;
+        b
+    }
+
+    /// Colour maps are not supported, only true colour at 8, 16 or 32 bits per pixel.
+    pub fn is_supported(&self) -> bool {
+        let bits = self.bits_per_pixel as u32;
+        self.true_colour
+            && matches!(bits, 8 | 16 | 32)
+            && [
+                (self.red_max, self.red_shift),
+                (self.green_max, self.green_shift),
+                (self.blue_max, self.blue_shift),
+            ]
+            .iter()
+            .all(|&(max, shift)| max > 0 && (max as u64) << shift < 1u64 << bits)
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel as usize / 8
+    }
+
+    /// Pixel value of a BGRA pixel.
+    pub fn pixel(&self, bgra: &[u8]) -> u32 {
+        let scale = |c: u8, max: u16| (c as u32 * max as u32 + 127) / 255;
+        scale(bgra[2], self.red_max) << self.red_shift
+            | scale(bgra[1], self.green_max) << self.green_shift
+            | scale(bgra[0], self.blue_max) << self.blue_shift
+    }
+
+    fn put(&self, value: u32, out: &mut Vec<u8>) {
+        match (self.bits_per_pixel, self.big_endian) {
+            (8, _) => out.push(value as u8),
+            (16, false) => out.extend_from_slice(&(value as u16).to_le_bytes()),
+            (16, true) => out.extend_from_slice(&(value as u16).to_be_bytes()),
+            (_, false) => out.extend_from_slice(&value.to_le_bytes()),
+            (_, true) => out.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    fn colour_mask(&self) -> u64 {
+        (self.red_max as u64) << self.red_shift
+            | (self.green_max as u64) << self.green_shift
+            | (self.blue_max as u64) << self.blue_shift
+    }
+
+    // ZRLE's compressed pixel drops the unused byte of 32 bit formats with depth <= 24
+    fn cpixel_bytes(&self) -> Option<bool> {
+        if !(self.bits_per_pixel == 32 && self.depth <= 24 && self.true_colour) {
+            return None;
+        }
+        let mask = self.colour_mask();
+        if mask < 1 << 24 {
+            Some(false)
+        } else if mask & 0xff == 0 {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn cpixel_len(&self) -> usize {
+        match self.cpixel_bytes() {
+            Some(_) => 3,
+            None => self.bytes_per_pixel(),
+        }
+    }
+
+    fn put_cpixel(&self, value: u32, out: &mut Vec<u8>) {
+        match (self.cpixel_bytes(), self.big_endian) {
+            (None, _) => self.put(value, out),
+            (Some(false), false) => out.extend_from_slice(&value.to_le_bytes()[..3]),
+            (Some(false), true) => out.extend_from_slice(&value.to_be_bytes()[1..]),
+            (Some(true), false) => out.extend_from_slice(&value.to_le_bytes()[1..]),
+            (Some(true), true) => out.extend_from_slice(&value.to_be_bytes()[..3]),
+        }
+    }
+
+    // Tight sends 24 bit formats as R, G, B
+    fn tight_rgb(&self) -> bool {
+        self.bits_per_pixel == 32
+            && self.depth == 24
+            && self.red_max == 255
+            && self.green_max == 255
+            && self.blue_max == 255
+    }
+
+    fn put_tpixel(&self, value: u32, out: &mut Vec<u8>) {
+        if self.tight_rgb() {
+            out.extend_from_slice(&[
+                (value >> self.red_shift) as u8,
+                (value >> self.green_shift) as u8,
+                (value >> self.blue_shift) as u8,
+            ]);
+        } else {
+            self.put(value, out);
+        }
+    }
+}
+
+/// Pixel values of `rect` of `frame`, row-major. `rect` must lie inside the frame.
+pub fn translate(frame: &Frame, rect: Rect, format: &RfbPixelFormat) -> Vec<u32> {
+    let mut pixels = Vec::with_capacity(rect.area() as usize);
+    let bpp = frame.format.bytes_per_pixel();
+    let native = *format == RfbPixelFormat::BGRX;
+    for y in rect.top..rect.bottom {
+        let row = &frame.row(y as u32)[rect.left as usize * bpp..rect.right as usize * bpp];
+        for bgra in row.chunks_exact(bpp) {
+            pixels.push(if native {
+                u32::from_le_bytes([bgra[0], bgra[1], bgra[2], 0])
+            } else {
+                format.pixel(bgra)
+            });
+        }
+    }
+    pixels
+}
+
+pub fn put_rect_header(out: &mut Vec<u8>, rect: Rect, encoding: i32) {
+    for v in [rect.left, rect.top, rect.width(), rect.height()] {
+        out.extend_from_slice(&(v as u16).to_be_bytes());
+    }
+    out.extend_from_slice(&encoding.to_be_bytes());
+}
+
+// distinct values in `pixels`, `None` past `max`
+fn palette(pixels: &[u32], max: usize) -> Option<Vec<u32>> {
+    let mut palette: Vec<u32> = Vec::with_capacity(max);
+    for &p in pixels {
+        if !palette.contains(&p) {
+            if palette.len() == max {
+                return None;
+            }
+            palette.push(p);
+        }
+    }
+    Some(palette)
+}
+
+// deflates `data` on a stream that lives as long as the connection
+fn deflate(z: &mut Compress, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    let mut pos = 0;
+    loop {
+        if out.capacity() - out.len() < 64 {
+            out.reserve(out.capacity().max(1024));
+        }
+        let before = z.total_in();
+        // only fails on a corrupt stream, which deflating cannot produce
+        z.compress_vec(&data[pos..], &mut out, FlushCompress::Sync)
+            .expect("deflate failed");
+        pos += (z.total_in() - before) as usize;
+        // the flush is complete once it stops filling the buffer
+        if pos == data.len() && out.len() < out.capacity() {
+            return out;
+        }
+    }
+}
+
+/// Per-connection encoder state, the zlib streams of ZRLE and Tight.
+pub struct Encoder {
+    zrle: Compress,
+    tight: [Compress; 4],
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        let stream = || Compress::new(Compression::fast(), true);
+        Self {
+            zrle: stream(),
+            tight: [stream(), stream(), stream(), stream()],
+        }
+    }
+}
+
+impl Encoder {
+    /// Appends `rect` of `frame` in `encoding`, returns the number of rectangles written.
+    pub fn encode(
+        &mut self,
+        encoding: i32,
+        frame: &Frame,
+        rect: Rect,
+        format: &RfbPixelFormat,
+        out: &mut Vec<u8>,
+    ) -> usize {
+        if encoding == ENCODING_TIGHT {
+            return self.encode_tight(frame, rect, format, out);
+        }
+        let pixels = translate(frame, rect, format);
+        let (w, h) = (rect.width() as u32, rect.height() as u32);
+        match encoding {
+            ENCODING_HEXTILE => {
+                put_rect_header(out, rect, ENCODING_HEXTILE);
+                encode_hextile(&pixels, w, h, format, out);
+            }
+            ENCODING_ZRLE => {
+                put_rect_header(out, rect, ENCODING_ZRLE);
+                let data = zrle_tiles(&pixels, w, h, format);
+                let compressed = deflate(&mut self.zrle, &data);
+                out.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+                out.extend_from_slice(&compressed);
+            }
+            _ => {
+                put_rect_header(out, rect, ENCODING_RAW);
+                for p in pixels {
+                    format.put(p, out);
+                }
+            }
+        }
+        1
+    }
+
+    fn encode_tight(
+        &mut self,
+        frame: &Frame,
+        rect: Rect,
+        format: &RfbPixelFormat,
+        out: &mut Vec<u8>,
+    ) -> usize {
+        let width = (rect.width() as u32).min(TIGHT_MAX_WIDTH);
+        let rows = (TIGHT_MAX_AREA / width).max(1) as i32;
+        let mut count = 0;
+        for top in (rect.top..rect.bottom).step_by(rows as usize) {
+            for left in (rect.left..rect.right).step_by(width as usize) {
+                let chunk = Rect::new(
+                    left,
+                    top,
+                    (left + width as i32).min(rect.right),
+                    (top + rows).min(rect.bottom),
+                );
+                let pixels = translate(frame, chunk, format);
+                put_rect_header(out, chunk, ENCODING_TIGHT);
+                self.tight_chunk(&pixels, chunk.width() as usize, format, out);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn tight_chunk(
+        &mut self,
+        pixels: &[u32],
+        width: usize,
+        format: &RfbPixelFormat,
+        out: &mut Vec<u8>,
+    ) {
+        let mut data = Vec::new();
+        let stream = match palette(pixels, 16) {
+            Some(colours) if colours.len() == 1 => {
+                // fill
+                out.push(0x80);
+                format.put_tpixel(colours[0], out);
+                return;
+            }
+            Some(colours) => {
+                // stream 1 with the palette filter
+                out.extend_from_slice(&[0x50, 1, colours.len() as u8 - 1]);
+                for &c in &colours {
+                    format.put_tpixel(c, out);
+                }
+                let index = |p: &u32| colours.iter().position(|c| c == p).unwrap() as u8;
+                if colours.len() == 2 {
+                    for row in pixels.chunks(width) {
+                        for bits in row.chunks(8) {
+                            let byte = bits
+                                .iter()
+                                .enumerate()
+                                .fold(0u8, |b, (i, p)| b | index(p) << (7 - i));
+                            data.push(byte);
+                        }
+                    }
+                } else {
+                    data.extend(pixels.iter().map(index));
+                }
+                1
+            }
+            None => {
+                // stream 0, no filter
+                out.push(0x00);
+                for &p in pixels {
+                    format.put_tpixel(p, &mut data);
+                }
+                0
+            }
+        };
+        if data.len() < TIGHT_MIN_TO_COMPRESS {
+            out.extend_from_slice(&data);
+            return;
+        }
+        let compressed = deflate(&mut self.tight[stream], &data);
+        put_compact_len(out, compressed.len());
+        out.extend_from_slice(&compressed);
+    }
+}
+
+fn put_compact_len(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else if len < 0x4000 {
+        out.extend_from_slice(&[len as u8 | 0x80, (len >> 7) as u8]);
+    } else {
+        out.extend_from_slice(&[len as u8 | 0x80, (len >> 7) as u8 | 0x80, (len >> 14) as u8]);
+    }
+}
+
+fn tile_pixels(pixels: &[u32], width: u32, x: u32, y: u32, w: u32, h: u32) -> Vec<u32> {
+    let mut tile = Vec::with_capacity((w * h) as usize);
+    for row in y..y + h {
+        let start = (row * width + x) as usize;
+        tile.extend_from_slice(&pixels[start..start + w as usize]);
+    }
+    tile
+}
+
+fn encode_hextile(
+    pixels: &[u32],
+    width: u32,
+    height: u32,
+    format: &RfbPixelFormat,
+    out: &mut Vec<u8>,
+) {
+    const RAW: u8 = 1;
+    const BACKGROUND: u8 = 2;
+    const FOREGROUND: u8 = 4;
+    const ANY_SUBRECTS: u8 = 8;
+    let bpp = format.bytes_per_pixel();
+    // the background carries over to the next tile unless that one is raw
+    let mut last_bg = None;
+    for y in (0..height).step_by(16) {
+        for x in (0..width).step_by(16) {
+            let (w, h) = ((width - x).min(16), (height - y).min(16));
+            let tile = tile_pixels(pixels, width, x, y, w, h);
+            let colours = palette(&tile, 2);
+            let mut encoded = Vec::new();
+            let mut flags = 0;
+            match colours.as_deref() {
+                Some([colour]) => {
+                    if last_bg != Some(*colour) {
+                        flags |= BACKGROUND;
+                        format.put(*colour, &mut encoded);
+                    }
+                    last_bg = Some(*colour);
+                }
+                Some(&[a, b]) => {
+                    let count_a = tile.iter().filter(|p| **p == a).count();
+                    let (bg, fg) = if count_a * 2 >= tile.len() {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    if last_bg != Some(bg) {
+                        flags |= BACKGROUND;
+                        format.put(bg, &mut encoded);
+                    }
+                    flags |= FOREGROUND | ANY_SUBRECTS;
+                    format.put(fg, &mut encoded);
+                    let count_at = encoded.len();
+                    encoded.push(0);
+                    let mut subrects = 0usize;
+                    for (ry, row) in tile.chunks(w as usize).enumerate() {
+                        let mut rx = 0;
+                        while rx < row.len() {
+                            if row[rx] != fg {
+                                rx += 1;
+                                continue;
+                            }
+                            let start = rx;
+                            while rx < row.len() && row[rx] == fg {
+                                rx += 1;
+                            }
+                            encoded.push((start as u8) << 4 | ry as u8);
+                            encoded.push(((rx - start - 1) as u8) << 4);
+                            subrects += 1;
+                        }
+                    }
+                    if subrects > 255 || encoded.len() > tile.len() * bpp {
+                        flags = RAW;
+                    } else {
+                        encoded[count_at] = subrects as u8;
+                        last_bg = Some(bg);
+                    }
+                }
+                _ => flags = RAW,
+            }
+            out.push(flags);
+            if flags == RAW {
+                for &p in &tile {
+                    format.put(p, out);
+                }
+                last_bg = None;
+            } else {
+                out.extend_from_slice(&encoded);
+            }
+        }
+    }
+}
+
+// runs of equal pixels
+fn runs(pixels: &[u32]) -> Vec<(u32, usize)> {
+    let mut runs: Vec<(u32, usize)> = Vec::new();
+    for &p in pixels {
+        match runs.last_mut() {
+            Some((value, len)) if *value == p => *len += 1,
+            _ => runs.push((p, 1)),
+        }
+    }
+    runs
+}
+
+fn put_run_length(out: &mut Vec<u8>, len: usize) {
+    let mut rest = len - 1;
+    while rest >= 255 {
+        out.push(255);
+        rest -= 255;
+    }
+    out.push(rest as u8);
+}
+
+// uncompressed ZRLE data: 64x64 tiles, each solid, packed palette, plain RLE or raw
+fn zrle_tiles(pixels: &[u32], width: u32, height: u32, format: &RfbPixelFormat) -> Vec<u8> {
+    let cp = format.cpixel_len();
+    let mut data = Vec::new();
+    for y in (0..height).step_by(64) {
+        for x in (0..width).step_by(64) {
+            let (w, h) = ((width - x).min(64), (height - y).min(64));
+            let tile = tile_pixels(pixels, width, x, y, w, h);
+            let colours = palette(&tile, 16);
+            if let Some([colour]) = colours.as_deref() {
+                data.push(1);
+                format.put_cpixel(*colour, &mut data);
+                continue;
+            }
+            let raw_len = tile.len() * cp;
+            let tile_runs = runs(&tile);
+            let rle_len: usize = tile_runs.iter().map(|(_, n)| cp + (n - 1) / 255 + 1).sum();
+            let packed = colours.map(|colours| {
+                let bits = match colours.len() {
+                    2 => 1,
+                    3..=4 => 2,
+                    _ => 4,
+                };
+                let len = colours.len() * cp + (w as usize * bits).div_ceil(8) * h as usize;
+                (colours, bits, len)
+            });
+            match packed {
+                Some((colours, bits, len)) if len <= rle_len && len <= raw_len => {
+                    data.push(colours.len() as u8);
+                    for &c in &colours {
+                        format.put_cpixel(c, &mut data);
+                    }
+                    for row in tile.chunks(w as usize) {
+                        let mut byte = 0u8;
+                        let mut used = 0;
+                        for p in row {
+                            let index = colours.iter().position(|c| c == p).unwrap() as u8;
+                            byte |= index << (8 - bits - used);
+                            used += bits;
+                            if used == 8 {
+                                data.push(byte);
+                                byte = 0;
+                                used = 0;
+                            }
+                        }
+                        if used > 0 {
+                            data.push(byte);
+                        }
+                    }
+                }
+                _ if rle_len < raw_len => {
+                    data.push(128);
+                    for (value, len) in tile_runs {
+                        format.put_cpixel(value, &mut data);
+                        put_run_length(&mut data, len);
+                    }
+                }
+                _ => {
+                    data.push(0);
+                    for &p in &tile {
+                        format.put_cpixel(p, &mut data);
+                    }
+                }
+            }
+        }
+    }
+    data
+}
+
+/// Cursor pseudo-encoding: the shape in the client's format and a visibility bitmask.
+pub fn encode_cursor(shape: &CursorShape, format: &RfbPixelFormat, out: &mut Vec<u8>) {
+    let rect = Rect::new(
+        shape.hot_x as i32,
+        shape.hot_y as i32,
+        shape.hot_x as i32 + shape.width as i32,
+        shape.hot_y as i32 + shape.height as i32,
+    );
+    put_rect_header(out, rect, ENCODING_CURSOR);
+    if rect.is_empty() {
+        return;
+    }
+    for bgra in shape.data.chunks_exact(4) {
+        format.put(format.pixel(bgra), out);
+    }
+    for row in shape.data.chunks_exact(shape.width as usize * 4) {
+        for pixels in row.chunks(32) {
+            let byte = pixels
+                .chunks_exact(4)
+                .enumerate()
+                .fold(0u8, |b, (i, bgra)| b | ((bgra[3] >= 0x80) as u8) << (7 - i));
+            out.push(byte);
+        }
+    }
+}
@@ -0,0 +1,768 @@
+//! A view-only RFB (VNC) 3.8 server over the capture stream.
+//!
+//! Any VNC viewer can connect. Frames are sent with whichever of Tight, ZRLE, Hextile
+//! or Raw the client prefers, move rects go out as CopyRect, and the cursor and
+//! desktop size pseudo-encodings are supported. There is no authentication, so bind
+//! to localhost or tunnel the connection.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::backend::CaptureBackend;
+use crate::frame::Frame;
+use crate::net::{merge_cursor, CursorUpdate, Endpoint, Listener};
+
+mod encoding;
+mod session;
+
+pub use encoding::{
+    RfbPixelFormat, ENCODING_COPY_RECT, ENCODING_CURSOR, ENCODING_DESKTOP_SIZE, ENCODING_HEXTILE,
+    ENCODING_POINTER_POS, ENCODING_RAW, ENCODING_TIGHT, ENCODING_ZRLE,
+};
+
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone)]
+pub struct RfbConfig {
+    // desktop name shown by viewers
+    pub name: String,
+    // a client that does not read for this long is disconnected
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for RfbConfig {
+    fn default() -> Self {
+        Self {
+            name: "dxgi".to_string(),
+            write_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+struct Shared {
+    config: RfbConfig,
+    clients: Mutex<Vec<Arc<session::Client>>>,
+    cursor: Mutex<Option<CursorUpdate>>,
+    last_frame: Mutex<Option<Arc<Frame>>>,
+    shutdown: AtomicBool,
+}
+
+/// Serves the frames passed to `send_frame` to every connected VNC viewer.
+///
+/// Viewers pull updates, so a slow one simply gets fewer: changes accumulate per client
+/// until it asks for the next update. Dirty and move rects must be relative to the
+/// previous frame passed to `send_frame`, frames without any are sent whole. A client
+/// is only accepted once there is a frame to tell it the desktop size.
+pub struct RfbServer {
+    shared: Arc<Shared>,
+    endpoint: Endpoint,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl RfbServer {
+    pub fn bind(endpoint: &Endpoint, config: RfbConfig) -> io::Result<Self> {
+        let listener = Listener::bind(endpoint)?;
+        let endpoint = listener.local_endpoint()?;
+        let shared = Arc::new(Shared {
+            config,
+            clients: Mutex::new(Vec::new()),
+            cursor: Mutex::new(None),
+            last_frame: Mutex::new(None),
+            shutdown: AtomicBool::new(false),
+        });
+        let accept = {
+            let shared = shared.clone();
+            std::thread::Builder::new()
+                .name("rfb-accept".to_string())
+                .spawn(move || accept_loop(listener, shared))?
+        };
+        log::debug!("rfb: listening on {}", endpoint);
+        Ok(Self {
+            shared,
+            endpoint,
+            accept: Some(accept),
+        })
+    }
+
+    /// The bound endpoint, with the actual port when bound to port 0.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub fn send_frame(&self, frame: Frame) {
+        let frame = Arc::new(frame);
+        *self.shared.last_frame.lock() = Some(frame.clone());
+        let clients = self.shared.clients.lock().clone();
+        for client in clients {
+            client.push_frame(frame.clone());
+        }
+    }
+
+    /// A cursor update without a shape keeps the last shape sent.
+    pub fn send_cursor(&self, cursor: CursorUpdate) {
+        merge_cursor(&mut self.shared.cursor.lock(), cursor.clone());
+        for client in self.shared.clients.lock().iter() {
+            client.push_cursor(&cursor);
+        }
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.shared.clients.lock().len()
+    }
+
+    /// Captures from `backend` and serves every new frame until `stop` is set.
+    pub fn run<B: CaptureBackend + ?Sized>(
+        &self,
+        backend: &mut B,
+        stop: &AtomicBool,
+    ) -> anyhow::Result<()> {
+        while !stop.load(Ordering::SeqCst) {
+            if let Some(frame) = backend.capture(100, true)? {
+                self.send_frame(frame);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RfbServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+        for client in self.shared.clients.lock().iter() {
+            client.close();
+        }
+    }
+}
+
+fn accept_loop(listener: Listener, shared: Arc<Shared>) {
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok(Some((stream, peer))) => {
+                let shared = shared.clone();
+                let spawned = std::thread::Builder::new()
+                    .name("rfb-client".to_string())
+                    .spawn(move || {
+                        if let Err(e) = session::serve(stream, &peer, &shared) {
+                            log::debug!("rfb: client {} disconnected: {}", peer, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    log::error!("rfb: failed to spawn client thread: {}", e);
+                }
+            }
+            Ok(None) => std::thread::sleep(ACCEPT_POLL),
+            Err(e) => {
+                log::error!("rfb: accept failed: {}", e);
+                std::thread::sleep(ACCEPT_POLL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use flate2::{Decompress, FlushDecompress};
+
+    use super::*;
+    use crate::frame::{PixelFormat, Rect};
+    use crate::net::CursorShape;
+    use crate::synthetic::SyntheticBackend;
+
+    const RGB565: RfbPixelFormat = RfbPixelFormat {
+        bits_per_pixel: 16,
+        depth: 16,
+        big_endian: false,
+        true_colour: true,
+        red_max: 31,
+        green_max: 63,
+        blue_max: 31,
+        red_shift: 11,
+        green_shift: 5,
+        blue_shift: 0,
+    };
+
+    fn inflate(z: &mut Decompress, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 << 16);
+        let mut pos = 0;
+        loop {
+            if out.capacity() - out.len() < 1024 {
+                out.reserve(1 << 16);
+            }
+            let before = z.total_in();
+            z.decompress_vec(&data[pos..], &mut out, FlushDecompress::Sync)
+                .unwrap();
+            pos += (z.total_in() - before) as usize;
+            if pos == data.len() && out.len() < out.capacity() {
+                return out;
+            }
+        }
+    }
+
+    // a little endian viewer keeping a framebuffer of pixel values
+    struct Viewer {
+        stream: TcpStream,
+        format: RfbPixelFormat,
+        width: usize,
+        height: usize,
+        fb: Vec<u32>,
+        zrle: Decompress,
+        tight: Vec<Decompress>,
+        // rect of the last cursor shape, hot spot and size
+        cursor: Option<(usize, usize, usize, usize)>,
+        pointer: Option<(usize, usize)>,
+        encodings: Vec<i32>,
+    }
+
+    impl Viewer {
+        fn connect(server: &RfbServer, version: &[u8; 12]) -> Self {
+            let Endpoint::Tcp(addr) = server.endpoint() else {
+                unreachable!()
+            };
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut viewer = Self {
+                stream,
+                format: RfbPixelFormat::BGRX,
+                width: 0,
+                height: 0,
+                fb: Vec::new(),
+                zrle: Decompress::new(true),
+                tight: (0..4).map(|_| Decompress::new(true)).collect(),
+                cursor: None,
+                pointer: None,
+                encodings: Vec::new(),
+            };
+            assert_eq!(&viewer.bytes(12), b"RFB 003.008\n");
+            viewer.stream.write_all(version).unwrap();
+            if version == b"RFB 003.003\n" {
+                // the server picks None
+                assert_eq!(viewer.u32(), 1);
+            } else {
+                assert_eq!(viewer.bytes(1 + 1), [1, 1]);
+                viewer.stream.write_all(&[1]).unwrap();
+                if version[8..11] >= b"008"[..] {
+                    // SecurityResult OK, later minor versions are taken as 3.8
+                    assert_eq!(viewer.u32(), 0);
+                }
+            }
+            // ClientInit, shared
+            viewer.stream.write_all(&[1]).unwrap();
+            viewer.width = viewer.u16() as usize;
+            viewer.height = viewer.u16() as usize;
+            let format = viewer.bytes(16).try_into().unwrap();
+            assert_eq!(RfbPixelFormat::parse(&format), RfbPixelFormat::BGRX);
+            let len = viewer.u32() as usize;
+            assert_eq!(viewer.bytes(len), b"test");
+            viewer.fb = vec![0; viewer.width * viewer.height];
+            viewer
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            let mut b = vec![0; len];
+            self.stream.read_exact(&mut b).unwrap();
+            b
+        }
+
+        fn u8(&mut self) -> u8 {
+            self.bytes(1)[0]
+        }
+
+        fn u16(&mut self) -> u16 {
+            u16::from_be_bytes(self.bytes(2).try_into().unwrap())
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_be_bytes(self.bytes(4).try_into().unwrap())
+        }
+
+        fn set_pixel_format(&mut self, format: RfbPixelFormat) {
+            let mut message = vec![0, 0, 0, 0];
+            message.extend_from_slice(&format.to_bytes());
+            self.stream.write_all(&message).unwrap();
+            self.format = format;
+        }
+
+        fn set_encodings(&mut self, encodings: &[i32]) {
+            let mut message = vec![2, 0];
+            message.extend_from_slice(&(encodings.len() as u16).to_be_bytes());
+            for encoding in encodings {
+                message.extend_from_slice(&encoding.to_be_bytes());
+            }
+            self.stream.write_all(&message).unwrap();
+        }
+
+        fn request(&mut self, incremental: bool) {
+            let mut message = vec![3, incremental as u8];
+            for v in [0, 0, self.width as u16, self.height as u16] {
+                message.extend_from_slice(&v.to_be_bytes());
+            }
+            self.stream.write_all(&message).unwrap();
+        }
+
+        fn pixel(b: &[u8]) -> u32 {
+            let mut le = [0u8; 4];
+            le[..b.len()].copy_from_slice(b);
+            u32::from_le_bytes(le)
+        }
+
+        fn read_pixel(&mut self) -> u32 {
+            let b = self.bytes(self.format.bytes_per_pixel());
+            Self::pixel(&b)
+        }
+
+        // ZRLE's CPIXEL, and Tight's TPIXEL which is R, G, B for 24 bit colour
+        fn compact_len(&self) -> usize {
+            match self.format.bits_per_pixel {
+                32 => 3,
+                _ => self.format.bytes_per_pixel(),
+            }
+        }
+
+        fn tight_pixel(&self, b: &[u8]) -> u32 {
+            match self.format.bits_per_pixel {
+                32 => (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32,
+                _ => Self::pixel(b),
+            }
+        }
+
+        fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, pixel: u32) {
+            for row in y..y + h {
+                self.fb[row * self.width + x..][..w].fill(pixel);
+            }
+        }
+
+        fn put_tile(&mut self, x: usize, y: usize, w: usize, tile: &[u32]) {
+            for (j, row) in tile.chunks(w).enumerate() {
+                self.fb[(y + j) * self.width + x..][..w].copy_from_slice(row);
+            }
+        }
+
+        // reads one FramebufferUpdate, returns its rectangle count
+        fn update(&mut self) -> usize {
+            assert_eq!(self.u8(), 0);
+            self.u8();
+            let count = self.u16() as usize;
+            for _ in 0..count {
+                let (x, y) = (self.u16() as usize, self.u16() as usize);
+                let (w, h) = (self.u16() as usize, self.u16() as usize);
+                let encoding = self.u32() as i32;
+                self.encodings.push(encoding);
+                match encoding {
+                    ENCODING_RAW => {
+                        let tile: Vec<_> = (0..w * h).map(|_| self.read_pixel()).collect();
+                        self.put_tile(x, y, w, &tile);
+                    }
+                    ENCODING_COPY_RECT => {
+                        let (sx, sy) = (self.u16() as usize, self.u16() as usize);
+                        let old = self.fb.clone();
+                        for j in 0..h {
+                            let src = &old[(sy + j) * self.width + sx..][..w];
+                            self.fb[(y + j) * self.width + x..][..w].copy_from_slice(src);
+                        }
+                    }
+                    ENCODING_HEXTILE => self.hextile(x, y, w, h),
+                    ENCODING_ZRLE => self.zrle(x, y, w, h),
+                    ENCODING_TIGHT => self.tight(x, y, w, h),
+                    ENCODING_CURSOR => {
+                        self.bytes(self.format.bytes_per_pixel() * w * h + w.div_ceil(8) * h);
+                        self.cursor = Some((x, y, w, h));
+                    }
+                    ENCODING_POINTER_POS => self.pointer = Some((x, y)),
+                    ENCODING_DESKTOP_SIZE => {
+                        self.width = w;
+                        self.height = h;
+                        self.fb = vec![0; w * h];
+                    }
+                    encoding => panic!("unexpected encoding {}", encoding),
+                }
+            }
+            count
+        }
+
+        fn hextile(&mut self, x0: usize, y0: usize, w: usize, h: usize) {
+            let (mut bg, mut fg) = (0, 0);
+            for ty in (0..h).step_by(16) {
+                for tx in (0..w).step_by(16) {
+                    let (x, y) = (x0 + tx, y0 + ty);
+                    let (tw, th) = ((w - tx).min(16), (h - ty).min(16));
+                    let flags = self.u8();
+                    if flags & 1 != 0 {
+                        let tile: Vec<_> = (0..tw * th).map(|_| self.read_pixel()).collect();
+                        self.put_tile(x, y, tw, &tile);
+                        continue;
+                    }
+                    if flags & 2 != 0 {
+                        bg = self.read_pixel();
+                    }
+                    self.fill(x, y, tw, th, bg);
+                    if flags & 4 != 0 {
+                        fg = self.read_pixel();
+                    }
+                    if flags & 8 == 0 {
+                        continue;
+                    }
+                    for _ in 0..self.u8() {
+                        let colour = if flags & 16 != 0 {
+                            self.read_pixel()
+                        } else {
+                            fg
+                        };
+                        let (xy, wh) = (self.u8() as usize, self.u8() as usize);
+                        let (sw, sh) = ((wh >> 4) + 1, (wh & 15) + 1);
+                        self.fill(x + (xy >> 4), y + (xy & 15), sw, sh, colour);
+                    }
+                }
+            }
+        }
+
+        fn zrle(&mut self, x0: usize, y0: usize, w: usize, h: usize) {
+            let len = self.u32() as usize;
+            let compressed = self.bytes(len);
+            let data = inflate(&mut self.zrle, &compressed);
+            let cp = self.compact_len();
+            let mut pos = 0;
+            let mut take = |n: usize| {
+                pos += n;
+                &data[pos - n..pos]
+            };
+            for ty in (0..h).step_by(64) {
+                for tx in (0..w).step_by(64) {
+                    let (tw, th) = ((w - tx).min(64), (h - ty).min(64));
+                    let mut tile = Vec::with_capacity(tw * th);
+                    match take(1)[0] as usize {
+                        0 => (0..tw * th).for_each(|_| tile.push(Self::pixel(take(cp)))),
+                        1 => tile.resize(tw * th, Self::pixel(take(cp))),
+                        size @ 2..=16 => {
+                            let palette: Vec<_> =
+                                (0..size).map(|_| Self::pixel(take(cp))).collect();
+                            let bits = match size {
+                                2 => 1,
+                                3..=4 => 2,
+                                _ => 4,
+                            };
+                            for _ in 0..th {
+                                let row = take((tw * bits).div_ceil(8));
+                                for i in 0..tw {
+                                    let bit = i * bits;
+                                    let index =
+                                        row[bit / 8] >> (8 - bits - bit % 8) & ((1 << bits) - 1);
+                                    tile.push(palette[index as usize]);
+                                }
+                            }
+                        }
+                        128 => {
+                            while tile.len() < tw * th {
+                                let p = Self::pixel(take(cp));
+                                let mut len = 1;
+                                loop {
+                                    let b = take(1)[0];
+                                    len += b as usize;
+                                    if b != 255 {
+                                        break;
+                                    }
+                                }
+                                tile.resize(tile.len() + len, p);
+                            }
+                        }
+                        subencoding => panic!("unexpected ZRLE subencoding {}", subencoding),
+                    }
+                    assert_eq!(tile.len(), tw * th);
+                    self.put_tile(x0 + tx, y0 + ty, tw, &tile);
+                }
+            }
+            assert_eq!(pos, data.len());
+        }
+
+        fn tight(&mut self, x: usize, y: usize, w: usize, h: usize) {
+            let control = self.u8();
+            for (i, stream) in self.tight.iter_mut().enumerate() {
+                if control & (1 << i) != 0 {
+                    *stream = Decompress::new(true);
+                }
+            }
+            let tp = self.compact_len();
+            if control >> 4 == 8 {
+                let b = self.bytes(tp);
+                let p = self.tight_pixel(&b);
+                self.fill(x, y, w, h, p);
+                return;
+            }
+            assert!(control >> 4 < 8, "unexpected Tight control {:x}", control);
+            let stream = (control >> 4 & 3) as usize;
+            let filter = if control & 0x40 != 0 { self.u8() } else { 0 };
+            let mut palette = Vec::new();
+            let len = match filter {
+                0 => w * h * tp,
+                1 => {
+                    for _ in 0..self.u8() as usize + 1 {
+                        let b = self.bytes(tp);
+                        palette.push(self.tight_pixel(&b));
+                    }
+                    if palette.len() == 2 {
+                        w.div_ceil(8) * h
+                    } else {
+                        w * h
+                    }
+                }
+                filter => panic!("unexpected Tight filter {}", filter),
+            };
+            let data = if len < 12 {
+                self.bytes(len)
+            } else {
+                let mut compressed = 0;
+                for i in 0..3 {
+                    let b = self.u8() as usize;
+                    if i == 2 {
+                        compressed |= b << 14;
+                        break;
+                    }
+                    compressed |= (b & 0x7f) << (7 * i);
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let compressed = self.bytes(compressed);
+                inflate(&mut self.tight[stream], &compressed)
+            };
+            assert_eq!(data.len(), len);
+            let tile: Vec<_> = (0..w * h)
+                .map(|i| match palette.len() {
+                    0 => self.tight_pixel(&data[i * tp..][..tp]),
+                    2 => {
+                        let (row, col) = (i / w, i % w);
+                        let byte = data[row * w.div_ceil(8) + col / 8];
+                        palette[(byte >> (7 - col % 8) & 1) as usize]
+                    }
+                    _ => palette[data[i] as usize],
+                })
+                .collect();
+            self.put_tile(x, y, w, &tile);
+        }
+
+        fn assert_shows(&self, frame: &Frame) {
+            assert_eq!(
+                (self.width, self.height),
+                (frame.width as usize, frame.height as usize)
+            );
+            for y in 0..self.height {
+                let row = frame.row(y as u32);
+                for x in 0..self.width {
+                    let expected = self.format.pixel(&row[x * 4..x * 4 + 4]);
+                    assert_eq!(self.fb[y * self.width + x], expected, "pixel {},{}", x, y);
+                }
+            }
+        }
+    }
+
+    fn server() -> RfbServer {
+        let config = RfbConfig {
+            name: "test".to_string(),
+            ..Default::default()
+        };
+        RfbServer::bind(&"tcp://127.0.0.1:0".parse().unwrap(), config).unwrap()
+    }
+
+    fn wait_for_client(server: &RfbServer) {
+        while server.client_count() == 0 {
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    // a moving bar from the synthetic backend, then a change without dirty rects
+    fn stream(encoding: i32, format: Option<RfbPixelFormat>, version: &[u8; 12]) {
+        let server = server();
+        let mut backend = SyntheticBackend::new(300, 170, 500);
+        let mut frame = backend.capture(100, true).unwrap().unwrap();
+        server.send_frame(frame.clone());
+        let mut viewer = Viewer::connect(&server, version);
+        wait_for_client(&server);
+        if let Some(format) = format {
+            viewer.set_pixel_format(format);
+        }
+        viewer.set_encodings(&[encoding, ENCODING_COPY_RECT]);
+        viewer.request(false);
+        viewer.update();
+        viewer.assert_shows(&frame);
+        for _ in 0..40 {
+            frame = backend.capture(100, true).unwrap().unwrap();
+            server.send_frame(frame.clone());
+            viewer.request(true);
+            viewer.update();
+            viewer.assert_shows(&frame);
+        }
+        frame.fill_rect(Rect::new(10, 10, 200, 100), [1, 2, 3, 255]);
+        frame.fill_rect(Rect::new(50, 20, 60, 30), [9, 9, 9, 255]);
+        frame.dirty_rects.clear();
+        frame.move_rects.clear();
+        server.send_frame(frame.clone());
+        viewer.request(true);
+        viewer.update();
+        viewer.assert_shows(&frame);
+        assert!(viewer.encodings.contains(&encoding));
+        // the bar slides right most of the time
+        assert!(viewer.encodings.contains(&ENCODING_COPY_RECT));
+        let used: Vec<_> = viewer
+            .encodings
+            .iter()
+            .filter(|e| **e != ENCODING_COPY_RECT)
+            .collect();
+        assert!(used.iter().all(|e| **e == encoding), "{:?}", used);
+    }
+
+    #[test]
+    fn handshakes() {
+        let server = server();
+        let mut frame = Frame::new(64, 32, PixelFormat::Bgra8);
+        frame.fill_rect(Rect::new(8, 8, 24, 16), [1, 2, 3, 255]);
+        server.send_frame(frame.clone());
+        // without SetEncodings everything is sent raw
+        for version in [
+            b"RFB 003.008\n",
+            b"RFB 003.007\n",
+            b"RFB 003.003\n",
+            b"RFB 003.889\n",
+        ] {
+            let mut viewer = Viewer::connect(&server, version);
+            assert_eq!((viewer.width, viewer.height), (64, 32));
+            viewer.request(false);
+            viewer.update();
+            viewer.assert_shows(&frame);
+            assert!(viewer.encodings.iter().all(|e| *e == ENCODING_RAW));
+        }
+    }
+
+    #[test]
+    fn raw() {
+        stream(ENCODING_RAW, None, b"RFB 003.008\n");
+        stream(ENCODING_RAW, Some(RGB565), b"RFB 003.008\n");
+    }
+
+    #[test]
+    fn hextile() {
+        stream(ENCODING_HEXTILE, None, b"RFB 003.008\n");
+        stream(ENCODING_HEXTILE, Some(RGB565), b"RFB 003.008\n");
+    }
+
+    #[test]
+    fn zrle() {
+        stream(ENCODING_ZRLE, None, b"RFB 003.008\n");
+        stream(ENCODING_ZRLE, Some(RGB565), b"RFB 003.008\n");
+    }
+
+    #[test]
+    fn tight() {
+        stream(ENCODING_TIGHT, None, b"RFB 003.008\n");
+        stream(ENCODING_TIGHT, Some(RGB565), b"RFB 003.008\n");
+    }
+
+    #[test]
+    fn palette_content() {
+        // mostly flat content takes the palette paths of each encoding
+        let server = server();
+        let mut frame = Frame::new(200, 100, PixelFormat::Bgra8);
+        for k in 0..12u8 {
+            let (x, y) = (k as i32 * 15, k as i32 * 7);
+            frame.fill_rect(
+                Rect::new(x, y, x + 9, y + 13),
+                [k * 20, 255 - k * 20, 7 * k, 255],
+            );
+        }
+        for x in (0..200).step_by(3) {
+            frame.fill_rect(Rect::new(x, 90, x + 1, 91), [255; 4]);
+        }
+        server.send_frame(frame.clone());
+        for encoding in [ENCODING_TIGHT, ENCODING_ZRLE, ENCODING_HEXTILE] {
+            let mut viewer = Viewer::connect(&server, b"RFB 003.008\n");
+            viewer.set_encodings(&[encoding]);
+            viewer.request(false);
+            viewer.update();
+            viewer.assert_shows(&frame);
+        }
+    }
+
+    #[test]
+    fn cursor_and_desktop_size() {
+        let server = server();
+        server.send_frame(Frame::new(64, 32, PixelFormat::Bgra8));
+        let mut viewer = Viewer::connect(&server, b"RFB 003.008\n");
+        viewer.set_encodings(&[
+            ENCODING_RAW,
+            ENCODING_CURSOR,
+            ENCODING_POINTER_POS,
+            ENCODING_DESKTOP_SIZE,
+        ]);
+        viewer.request(false);
+        wait_for_client(&server);
+        viewer.update();
+        server.send_cursor(CursorUpdate {
+            x: 10,
+            y: 12,
+            visible: true,
+            shape: Some(CursorShape {
+                width: 9,
+                height: 3,
+                hot_x: 1,
+                hot_y: 2,
+                data: vec![0xff; 9 * 3 * 4],
+            }),
+        });
+        viewer.request(true);
+        viewer.update();
+        assert_eq!(viewer.cursor, Some((1, 2, 9, 3)));
+        assert_eq!(viewer.pointer, Some((10, 12)));
+
+        let mut bigger = Frame::new(80, 40, PixelFormat::Bgra8);
+        bigger.fill_rect(Rect::new(5, 5, 20, 20), [1, 2, 3, 255]);
+        server.send_frame(bigger.clone());
+        viewer.request(true);
+        // the new size alone, then the whole desktop
+        assert_eq!(viewer.update(), 1);
+        assert_eq!((viewer.width, viewer.height), (80, 40));
+        viewer.request(true);
+        viewer.update();
+        viewer.assert_shows(&bigger);
+
+        // a viewer that cannot resize is disconnected
+        let mut fixed = Viewer::connect(&server, b"RFB 003.008\n");
+        fixed.set_encodings(&[ENCODING_RAW]);
+        fixed.request(false);
+        fixed.update();
+        server.send_frame(Frame::new(30, 30, PixelFormat::Bgra8));
+        fixed.request(true);
+        assert_eq!(fixed.stream.read(&mut [0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn run_captures_from_the_backend() {
+        let server = server();
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut backend = SyntheticBackend::new(128, 64, 200);
+                server.run(&mut backend, &stop).unwrap();
+            });
+            let mut viewer = Viewer::connect(&server, b"RFB 003.008\n");
+            viewer.set_encodings(&[ENCODING_ZRLE, ENCODING_COPY_RECT]);
+            viewer.request(false);
+            for _ in 0..20 {
+                viewer.update();
+                viewer.request(true);
+            }
+            assert_eq!((viewer.width, viewer.height), (128, 64));
+            stop.store(true, Ordering::SeqCst);
+        });
+    }
+}
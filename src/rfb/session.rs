@@ -0,0 +1,418 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use super::encoding::*;
+use super::Shared;
+use crate::frame::{Frame, MoveRect, Rect};
+use crate::net::{merge_cursor, CursorUpdate, Stream};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// more pending rects than this are merged into their bounding box
+const MAX_DAMAGE: usize = 64;
+
+const SECURITY_NONE: u8 = 1;
+
+#[derive(Default)]
+struct State {
+    format: Option<RfbPixelFormat>,
+    encodings: Vec<i32>,
+    // an update request is outstanding
+    requested: bool,
+    frame: Option<Arc<Frame>>,
+    // framebuffer size the client knows about
+    size: (u32, u32),
+    resized: bool,
+    // copies to do on the client, in order, before the damage is repainted
+    moves: Vec<MoveRect>,
+    damage: Vec<Rect>,
+    cursor: Option<CursorUpdate>,
+    cursor_shape: bool,
+    cursor_position: bool,
+    closed: bool,
+}
+
+impl State {
+    fn supports(&self, encoding: i32) -> bool {
+        self.encodings.contains(&encoding)
+    }
+
+    fn has_update(&self) -> bool {
+        self.resized
+            || !self.moves.is_empty()
+            || !self.damage.is_empty()
+            || (self.cursor_shape && self.supports(ENCODING_CURSOR))
+            || (self.cursor_position && self.supports(ENCODING_POINTER_POS))
+    }
+
+    fn add_damage(&mut self, rect: Rect) {
+        self.damage.push(rect);
+        if self.damage.len() > MAX_DAMAGE {
+            let bounds = self.damage.iter().fold(Rect::default(), |a, r| a.union(r));
+            self.damage = vec![bounds];
+        }
+    }
+}
+
+pub(crate) struct Client {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+impl Client {
+    pub(crate) fn push_frame(&self, frame: Arc<Frame>) {
+        let mut state = self.state.lock();
+        let bounds = frame.bounds();
+        if (frame.width, frame.height) != state.size {
+            state.size = (frame.width, frame.height);
+            state.resized = true;
+            state.moves.clear();
+            state.damage = vec![bounds];
+        } else if frame.dirty_rects.is_empty() && frame.move_rects.is_empty() {
+            // nothing known about what changed
+            state.moves.clear();
+            state.damage = vec![bounds];
+        } else {
+            for m in &frame.move_rects {
+                let source = m.source();
+                // the client only has the right pixels at the source if they are not
+                // waiting to be repainted
+                let usable = source.intersect(&bounds) == source
+                    && m.destination.intersect(&bounds) == m.destination
+                    && !state.damage.iter().any(|d| d.intersects(&source));
+                if usable {
+                    state.moves.push(*m);
+                } else {
+                    state.add_damage(m.destination);
+                }
+            }
+            for rect in &frame.dirty_rects {
+                state.add_damage(*rect);
+            }
+        }
+        state.frame = Some(frame);
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    pub(crate) fn push_cursor(&self, cursor: &CursorUpdate) {
+        let mut state = self.state.lock();
+        state.cursor_shape |= cursor.shape.is_some();
+        state.cursor_position = true;
+        merge_cursor(&mut state.cursor, cursor.clone());
+        drop(state);
+        self.wake.notify_one();
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.lock().closed = true;
+        self.wake.notify_one();
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_be_bytes(b))
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_be_bytes(b))
+}
+
+fn skip<R: Read>(r: &mut R, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut r.take(len), &mut io::sink())?;
+    if skipped < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn protocol_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// version and security handshake, returns the negotiated minor version
+fn handshake<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u32> {
+    writer.write_all(b"RFB 003.008\n")?;
+    writer.flush()?;
+    let mut version = [0u8; 12];
+    reader.read_exact(&mut version)?;
+    let minor = match &version {
+        b"RFB 003.007\n" => 7,
+        v if v.starts_with(b"RFB 003.") && v[8..11] >= b"008"[..] => 8,
+        // anything else unknown is to be treated as 3.3
+        v if v.starts_with(b"RFB 003.") => 3,
+        _ => return Err(protocol_error("not an RFB client".to_string())),
+    };
+    if minor == 3 {
+        writer.write_all(&(SECURITY_NONE as u32).to_be_bytes())?;
+    } else {
+        writer.write_all(&[1, SECURITY_NONE])?;
+        writer.flush()?;
+        let chosen = read_u8(reader)?;
+        if chosen != SECURITY_NONE {
+            if minor == 8 {
+                let reason = b"unsupported security type";
+                writer.write_all(&1u32.to_be_bytes())?;
+                writer.write_all(&(reason.len() as u32).to_be_bytes())?;
+                writer.write_all(reason)?;
+                writer.flush()?;
+            }
+            return Err(protocol_error(format!(
+                "client chose security type {}",
+                chosen
+            )));
+        }
+        if minor == 8 {
+            writer.write_all(&0u32.to_be_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(minor)
+}
+
+pub(crate) fn serve(stream: Stream, peer: &str, shared: &Arc<Shared>) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(shared.config.write_timeout)?;
+    let mut writer = BufWriter::with_capacity(1 << 16, stream.try_clone()?);
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let minor = handshake(&mut reader, &mut writer)?;
+    // ClientInit, every session is shared
+    read_u8(&mut reader)?;
+
+    // ServerInit needs the framebuffer size, wait for the first frame
+    let frame = loop {
+        if let Some(frame) = shared.last_frame.lock().clone() {
+            break frame;
+        }
+        if shared.shutdown.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    let name = shared.config.name.as_bytes();
+    writer.write_all(&(frame.width as u16).to_be_bytes())?;
+    writer.write_all(&(frame.height as u16).to_be_bytes())?;
+    writer.write_all(&RfbPixelFormat::BGRX.to_bytes())?;
+    writer.write_all(&(name.len() as u32).to_be_bytes())?;
+    writer.write_all(name)?;
+    writer.flush()?;
+    stream.set_read_timeout(None)?;
+
+    let client = Arc::new(Client {
+        state: Mutex::new(State {
+            size: (frame.width, frame.height),
+            frame: Some(frame),
+            ..Default::default()
+        }),
+        wake: Condvar::new(),
+    });
+    {
+        shared.clients.lock().push(client.clone());
+        // a frame sent between reading `last_frame` and registering is picked up here
+        if let Some(frame) = shared.last_frame.lock().clone() {
+            client.push_frame(frame);
+        }
+        if let Some(cursor) = shared.cursor.lock().clone() {
+            client.push_cursor(&cursor);
+        }
+    }
+    if shared.shutdown.load(Ordering::SeqCst) {
+        client.close();
+    }
+    log::debug!("rfb: client {} connected with RFB 3.{}", peer, minor);
+
+    let reader_client = client.clone();
+    let reader_peer = peer.to_string();
+    let spawned = std::thread::Builder::new()
+        .name("rfb-reader".to_string())
+        .spawn(move || {
+            if let Err(e) = read_loop(&mut reader, &reader_client) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    log::debug!("rfb: client {}: {}", reader_peer, e);
+                }
+            }
+            reader_client.close();
+        });
+    let result = match spawned {
+        Ok(_) => write_loop(&mut writer, &client),
+        Err(e) => Err(e),
+    };
+    client.close();
+    stream.shutdown();
+    shared
+        .clients
+        .lock()
+        .retain(|other| !Arc::ptr_eq(other, &client));
+    log::debug!("rfb: client {} disconnected", peer);
+    result
+}
+
+fn read_loop<R: Read>(reader: &mut R, client: &Client) -> io::Result<()> {
+    loop {
+        match read_u8(reader)? {
+            // SetPixelFormat
+            0 => {
+                let mut b = [0u8; 19];
+                reader.read_exact(&mut b)?;
+                let format = RfbPixelFormat::parse(b[3..].try_into().unwrap());
+                if !format.is_supported() {
+                    return Err(protocol_error(format!(
+                        "unsupported pixel format {:?}",
+                        format
+                    )));
+                }
+                let mut state = client.state.lock();
+                state.format = Some(format);
+                // what the client has was sent in the old format
+                if let Some(frame) = state.frame.clone() {
+                    state.moves.clear();
+                    state.damage = vec![frame.bounds()];
+                }
+            }
+            // SetEncodings
+            2 => {
+                read_u8(reader)?;
+                let count = read_u16(reader)?;
+                let mut encodings = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    encodings.push(read_u32(reader)? as i32);
+                }
+                log::debug!("rfb: client encodings {:?}", encodings);
+                let mut state = client.state.lock();
+                state.encodings = encodings;
+                // a cursor shape is only sent to clients that asked for it
+                state.cursor_shape = state.cursor.as_ref().is_some_and(|c| c.shape.is_some());
+            }
+            // FramebufferUpdateRequest
+            3 => {
+                let incremental = read_u8(reader)? != 0;
+                let x = read_u16(reader)? as i32;
+                let y = read_u16(reader)? as i32;
+                let w = read_u16(reader)? as i32;
+                let h = read_u16(reader)? as i32;
+                let mut state = client.state.lock();
+                state.requested = true;
+                if !incremental {
+                    state.add_damage(Rect::new(x, y, x + w, y + h));
+                }
+                drop(state);
+                client.wake.notify_one();
+            }
+            // KeyEvent and PointerEvent, the server is view only
+            4 => skip(reader, 7)?,
+            5 => skip(reader, 5)?,
+            // ClientCutText
+            6 => {
+                skip(reader, 3)?;
+                let len = read_u32(reader)?;
+                skip(reader, len as u64)?;
+            }
+            kind => return Err(protocol_error(format!("unknown message type {}", kind))),
+        }
+    }
+}
+
+fn write_loop<W: Write>(writer: &mut W, client: &Client) -> io::Result<()> {
+    let mut encoder = Encoder::default();
+    loop {
+        let mut state = client.state.lock();
+        while !state.closed && (!state.requested || !state.has_update()) {
+            client.wake.wait(&mut state);
+        }
+        if state.closed {
+            return Ok(());
+        }
+        state.requested = false;
+        let frame = state.frame.clone().unwrap();
+        let format = state.format.unwrap_or(RfbPixelFormat::BGRX);
+        let mut body = Vec::new();
+        let mut count = 0;
+
+        if state.resized {
+            if !state.supports(ENCODING_DESKTOP_SIZE) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "desktop size changed and the client cannot resize",
+                ));
+            }
+            // alone in its update, the repaint follows on the next request
+            state.resized = false;
+            drop(state);
+            put_rect_header(&mut body, frame.bounds(), ENCODING_DESKTOP_SIZE);
+            write_update(writer, 1, &body)?;
+            continue;
+        }
+
+        if state.cursor_shape && state.supports(ENCODING_CURSOR) {
+            if let Some(shape) = state.cursor.as_ref().and_then(|c| c.shape.as_ref()) {
+                encode_cursor(shape, &format, &mut body);
+                count += 1;
+            }
+            state.cursor_shape = false;
+        }
+        if state.cursor_position && state.supports(ENCODING_POINTER_POS) {
+            if let Some(cursor) = &state.cursor {
+                let at = Rect::new(cursor.x, cursor.y, cursor.x, cursor.y);
+                put_rect_header(&mut body, at, ENCODING_POINTER_POS);
+                count += 1;
+            }
+            state.cursor_position = false;
+        }
+
+        let mut moves = std::mem::take(&mut state.moves);
+        let mut damage = std::mem::take(&mut state.damage);
+        if !state.supports(ENCODING_COPY_RECT) {
+            damage.extend(moves.drain(..).map(|m| m.destination));
+        }
+        let encoding = state
+            .encodings
+            .iter()
+            .copied()
+            .find(|e| {
+                matches!(
+                    *e,
+                    ENCODING_RAW | ENCODING_HEXTILE | ENCODING_ZRLE | ENCODING_TIGHT
+                )
+            })
+            .unwrap_or(ENCODING_RAW);
+        drop(state);
+
+        for m in &moves {
+            put_rect_header(&mut body, m.destination, ENCODING_COPY_RECT);
+            body.extend_from_slice(&(m.source_x as u16).to_be_bytes());
+            body.extend_from_slice(&(m.source_y as u16).to_be_bytes());
+            count += 1;
+        }
+        let bounds = frame.bounds();
+        for rect in damage {
+            let rect = rect.intersect(&bounds);
+            if !rect.is_empty() {
+                count += encoder.encode(encoding, &frame, rect, &format, &mut body);
+            }
+        }
+        write_update(writer, count, &body)?;
+    }
+}
+
+fn write_update<W: Write>(writer: &mut W, count: usize, body: &[u8]) -> io::Result<()> {
+    if count > u16::MAX as usize {
+        return Err(io::Error::other("too many rectangles in one update"));
+    }
+    writer.write_all(&[0, 0])?;
+    writer.write_all(&(count as u16).to_be_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()
+}
@@ -0,0 +1,682 @@
+//! RTP payload formats for H.264 (RFC 6184) and H.265 (RFC 7798).
+//!
+//! Only the non-interleaved packetization mode is produced and understood: single NAL
+//! unit packets, STAP-A / AP aggregation and FU-A / FU fragmentation.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use crate::annexb::{self, START_CODE};
+use crate::codec::{Codec, Packet};
+
+pub const CLOCK_RATE: u32 = 90_000;
+// RTP packet size, header included, that stays clear of the usual 1500 byte MTU
+pub const DEFAULT_MTU: usize = 1200;
+
+const HEADER_LEN: usize = 12;
+const VERSION: u8 = 2;
+
+const H264_STAP_A: u8 = 24;
+const H264_FU_A: u8 = 28;
+const H265_AP: u8 = 48;
+const H265_FU: u8 = 49;
+
+/// Media time in 90 kHz units, wrapping like RTP timestamps do.
+pub fn to_rtp_time(d: Duration) -> u32 {
+    (d.as_nanos() * CLOCK_RATE as u128 / 1_000_000_000) as u32
+}
+
+fn invalid_data<E>(msg: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtpPacket {
+    // set on the last packet of an access unit
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload: Vec<u8>,
+}
+
+impl RtpPacket {
+    /// Parses a datagram. CSRCs, header extensions and padding are skipped.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(invalid_data("rtp packet too short"));
+        }
+        if data[0] >> 6 != VERSION {
+            return Err(invalid_data(format!("rtp version {}", data[0] >> 6)));
+        }
+        let mut start = HEADER_LEN + (data[0] & 0x0f) as usize * 4;
+        if data[0] & 0x10 != 0 {
+            let words = data
+                .get(start + 2..start + 4)
+                .ok_or_else(|| invalid_data("truncated rtp header extension"))?;
+            start += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+        }
+        let mut end = data.len();
+        if data[0] & 0x20 != 0 {
+            end = end.saturating_sub(data[end - 1] as usize);
+        }
+        if start > end {
+            return Err(invalid_data("truncated rtp packet"));
+        }
+        Ok(Self {
+            marker: data[1] & 0x80 != 0,
+            payload_type: data[1] & 0x7f,
+            sequence: u16::from_be_bytes([data[2], data[3]]),
+            timestamp: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+            ssrc: u32::from_be_bytes(data[8..12].try_into().unwrap()),
+            payload: data[start..end].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        out.push(VERSION << 6);
+        out.push((self.marker as u8) << 7 | (self.payload_type & 0x7f));
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&self.timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(&self.payload);
+        out
+    }
+}
+
+/// Splits access units into RTP packets.
+///
+/// Small NAL units are aggregated and large ones fragmented to keep every packet within
+/// the MTU. Timestamps are the packet pts at 90 kHz plus a fixed offset.
+pub struct RtpPacketizer {
+    codec: Codec,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp_offset: u32,
+    mtu: usize,
+}
+
+impl RtpPacketizer {
+    pub fn new(codec: Codec, payload_type: u8, ssrc: u32) -> io::Result<Self> {
+        if !codec.is_annexb() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no rtp payload format for {}", codec),
+            ));
+        }
+        Ok(Self {
+            codec,
+            payload_type,
+            ssrc,
+            sequence: 0,
+            timestamp_offset: 0,
+            mtu: DEFAULT_MTU,
+        })
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn set_mtu(&mut self, mtu: usize) {
+        // room for the header and at least one fragment byte
+        self.mtu = mtu.max(HEADER_LEN + 4);
+    }
+
+    /// Sequence number of the next packet. RFC 3550 recommends a random start.
+    pub fn set_sequence(&mut self, sequence: u16) {
+        self.sequence = sequence;
+    }
+
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    /// Added to every timestamp. RFC 3550 recommends a random one.
+    pub fn set_timestamp_offset(&mut self, offset: u32) {
+        self.timestamp_offset = offset;
+    }
+
+    pub fn packetize(&mut self, packet: &Packet) -> io::Result<Vec<RtpPacket>> {
+        if packet.codec != self.codec {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} packet passed to {} packetizer",
+                    packet.codec, self.codec
+                ),
+            ));
+        }
+        let timestamp = to_rtp_time(packet.pts).wrapping_add(self.timestamp_offset);
+        let max = self.mtu - HEADER_LEN;
+        let header_len = self.nal_header_len();
+        let nals: Vec<&[u8]> = annexb::split(&packet.data)
+            .filter(|nal| nal.len() >= header_len)
+            .collect();
+        let mut payloads = Vec::new();
+        let mut i = 0;
+        while i < nals.len() {
+            if nals[i].len() > max {
+                self.fragment(nals[i], max, &mut payloads);
+                i += 1;
+                continue;
+            }
+            // aggregation header plus a 16 bit size per NAL unit
+            let mut len = header_len + 2 + nals[i].len();
+            let mut end = i + 1;
+            while end < nals.len() && len + 2 + nals[end].len() <= max {
+                len += 2 + nals[end].len();
+                end += 1;
+            }
+            if end - i > 1 {
+                payloads.push(self.aggregate(&nals[i..end]));
+            } else {
+                payloads.push(nals[i].to_vec());
+            }
+            i = end;
+        }
+        let count = payloads.len();
+        Ok(payloads
+            .into_iter()
+            .enumerate()
+            .map(|(i, payload)| {
+                let sequence = self.sequence;
+                self.sequence = self.sequence.wrapping_add(1);
+                RtpPacket {
+                    marker: i + 1 == count,
+                    payload_type: self.payload_type,
+                    sequence,
+                    timestamp,
+                    ssrc: self.ssrc,
+                    payload,
+                }
+            })
+            .collect())
+    }
+
+    fn nal_header_len(&self) -> usize {
+        match self.codec {
+            Codec::H265 => 2,
+            _ => 1,
+        }
+    }
+
+    fn aggregate(&self, nals: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self.codec {
+            Codec::H265 => {
+                // F is set if any unit has it, layer id and temporal id are the lowest
+                let forbidden = nals.iter().fold(0, |f, nal| f | nal[0] & 0x80);
+                let layer = nals
+                    .iter()
+                    .map(|nal| (nal[0] & 1) << 5 | nal[1] >> 3)
+                    .min()
+                    .unwrap();
+                let tid = nals.iter().map(|nal| nal[1] & 7).min().unwrap();
+                out.push(forbidden | H265_AP << 1 | layer >> 5);
+                out.push((layer & 0x1f) << 3 | tid);
+            }
+            _ => {
+                // F is set if any unit has it, NRI is the highest
+                let forbidden = nals.iter().fold(0, |f, nal| f | nal[0] & 0x80);
+                let nri = nals.iter().map(|nal| nal[0] & 0x60).max().unwrap();
+                out.push(forbidden | nri | H264_STAP_A);
+            }
+        }
+        for nal in nals {
+            out.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            out.extend_from_slice(nal);
+        }
+        out
+    }
+
+    fn fragment(&self, nal: &[u8], max: usize, payloads: &mut Vec<Vec<u8>>) {
+        let (header, body) = match self.codec {
+            Codec::H265 => (
+                vec![nal[0] & 0x81 | H265_FU << 1, nal[1], (nal[0] >> 1) & 0x3f],
+                &nal[2..],
+            ),
+            _ => (vec![nal[0] & 0xe0 | H264_FU_A, nal[0] & 0x1f], &nal[1..]),
+        };
+        let chunks = body.chunks(max - header.len());
+        let count = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let mut payload = Vec::with_capacity(header.len() + chunk.len());
+            payload.extend_from_slice(&header);
+            let fu_header = payload.last_mut().unwrap();
+            if i == 0 {
+                *fu_header |= 0x80;
+            }
+            if i + 1 == count {
+                *fu_header |= 0x40;
+            }
+            payload.extend_from_slice(chunk);
+            payloads.push(payload);
+        }
+    }
+}
+
+/// Reassembles access units from RTP packets.
+///
+/// An access unit is complete on the marker bit, or when a packet with a new timestamp
+/// arrives. Packets are expected in order: a gap in sequence numbers drops the NAL unit
+/// being reassembled, the rest of the access unit is kept. The pts of an access unit is
+/// its timestamp, extended past wraparound, at 90 kHz.
+pub struct RtpDepacketizer {
+    codec: Codec,
+    // Annex-B data of the access unit being assembled
+    data: Vec<u8>,
+    timestamp: Option<u32>,
+    // extended timestamp of `timestamp`
+    extended: i64,
+    fragment: Option<Vec<u8>>,
+    next_sequence: Option<u16>,
+    lost: u64,
+    ready: VecDeque<Packet>,
+}
+
+impl RtpDepacketizer {
+    pub fn new(codec: Codec) -> io::Result<Self> {
+        if !codec.is_annexb() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("no rtp payload format for {}", codec),
+            ));
+        }
+        Ok(Self {
+            codec,
+            data: Vec::new(),
+            timestamp: None,
+            extended: 0,
+            fragment: None,
+            next_sequence: None,
+            lost: 0,
+            ready: VecDeque::new(),
+        })
+    }
+
+    /// Packets missing from the sequence so far.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    pub fn push(&mut self, packet: &RtpPacket) -> io::Result<()> {
+        if let Some(expected) = self.next_sequence {
+            let gap = packet.sequence.wrapping_sub(expected);
+            // anything far behind is a late or duplicate packet
+            if gap >= 0x8000 {
+                return Ok(());
+            }
+            if gap > 0 {
+                self.lost += gap as u64;
+                self.fragment = None;
+            }
+        }
+        self.next_sequence = Some(packet.sequence.wrapping_add(1));
+
+        match self.timestamp {
+            Some(timestamp) if timestamp != packet.timestamp => {
+                self.finish();
+                self.extended += packet.timestamp.wrapping_sub(timestamp) as i32 as i64;
+            }
+            Some(_) => {}
+            None => self.extended = packet.timestamp as i64,
+        }
+        self.timestamp = Some(packet.timestamp);
+
+        let result = match self.codec {
+            Codec::H265 => self.push_h265(&packet.payload),
+            _ => self.push_h264(&packet.payload),
+        };
+        if packet.marker {
+            self.finish();
+        }
+        result
+    }
+
+    /// Next complete access unit.
+    pub fn pop(&mut self) -> Option<Packet> {
+        self.ready.pop_front()
+    }
+
+    /// Completes the access unit in progress, e.g. at the end of a stream without a
+    /// final marker.
+    pub fn flush(&mut self) -> Option<Packet> {
+        self.finish();
+        self.pop()
+    }
+
+    fn finish(&mut self) {
+        self.fragment = None;
+        if self.data.is_empty() {
+            return;
+        }
+        let nanos = self.extended.max(0) as u128 * 1_000_000_000 / CLOCK_RATE as u128;
+        let pts = Duration::from_nanos(nanos as u64);
+        let data = std::mem::take(&mut self.data);
+        self.ready.push_back(Packet::new(self.codec, data, pts));
+    }
+
+    fn add_nal(&mut self, nal: &[u8]) {
+        self.data.extend_from_slice(&START_CODE);
+        self.data.extend_from_slice(nal);
+    }
+
+    fn push_aggregated(&mut self, mut units: &[u8]) -> io::Result<()> {
+        while !units.is_empty() {
+            if units.len() < 2 {
+                return Err(invalid_data("truncated aggregation packet"));
+            }
+            let len = u16::from_be_bytes([units[0], units[1]]) as usize;
+            let nal = units
+                .get(2..2 + len)
+                .ok_or_else(|| invalid_data("truncated aggregation packet"))?;
+            self.add_nal(nal);
+            units = &units[2 + len..];
+        }
+        Ok(())
+    }
+
+    fn push_fragment(&mut self, start: bool, end: bool, header: &[u8], body: &[u8]) {
+        if start {
+            let mut nal = header.to_vec();
+            nal.extend_from_slice(body);
+            self.fragment = Some(nal);
+        } else if let Some(nal) = &mut self.fragment {
+            nal.extend_from_slice(body);
+        } else {
+            // the start was lost
+            return;
+        }
+        if end {
+            let nal = self.fragment.take().unwrap();
+            self.add_nal(&nal);
+        }
+    }
+
+    fn push_h264(&mut self, payload: &[u8]) -> io::Result<()> {
+        let Some(&indicator) = payload.first() else {
+            return Err(invalid_data("empty rtp payload"));
+        };
+        match indicator & 0x1f {
+            1..=23 => self.add_nal(payload),
+            H264_STAP_A => self.push_aggregated(&payload[1..])?,
+            H264_FU_A => {
+                let &fu = payload
+                    .get(1)
+                    .ok_or_else(|| invalid_data("truncated FU-A"))?;
+                let header = [indicator & 0xe0 | fu & 0x1f];
+                self.push_fragment(fu & 0x80 != 0, fu & 0x40 != 0, &header, &payload[2..]);
+            }
+            t => {
+                return Err(invalid_data(format!(
+                    "unsupported h264 rtp packet type {}",
+                    t
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn push_h265(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() < 2 {
+            return Err(invalid_data("truncated h265 rtp payload"));
+        }
+        match (payload[0] >> 1) & 0x3f {
+            H265_AP => self.push_aggregated(&payload[2..])?,
+            H265_FU => {
+                let &fu = payload
+                    .get(2)
+                    .ok_or_else(|| invalid_data("truncated h265 FU"))?;
+                let header = [payload[0] & 0x81 | (fu & 0x3f) << 1, payload[1]];
+                self.push_fragment(fu & 0x80 != 0, fu & 0x40 != 0, &header, &payload[3..]);
+            }
+            50 => return Err(invalid_data("unsupported h265 PACI packet")),
+            _ => self.add_nal(payload),
+        }
+        Ok(())
+    }
+}
+
+/// Sends access units to a connected UDP socket.
+pub struct RtpSender {
+    socket: UdpSocket,
+    packetizer: RtpPacketizer,
+}
+
+impl RtpSender {
+    pub fn new(socket: UdpSocket, packetizer: RtpPacketizer) -> Self {
+        Self { socket, packetizer }
+    }
+
+    pub fn packetizer_mut(&mut self) -> &mut RtpPacketizer {
+        &mut self.packetizer
+    }
+
+    /// Returns the number of RTP packets sent.
+    pub fn send(&mut self, packet: &Packet) -> io::Result<usize> {
+        let packets = self.packetizer.packetize(packet)?;
+        for rtp in &packets {
+            self.socket.send(&rtp.to_bytes())?;
+        }
+        Ok(packets.len())
+    }
+}
+
+/// Receives access units from a UDP socket.
+pub struct RtpReceiver {
+    socket: UdpSocket,
+    depacketizer: RtpDepacketizer,
+    buf: Vec<u8>,
+}
+
+impl RtpReceiver {
+    pub fn new(socket: UdpSocket, codec: Codec) -> io::Result<Self> {
+        Ok(Self {
+            socket,
+            depacketizer: RtpDepacketizer::new(codec)?,
+            buf: vec![0; 65536],
+        })
+    }
+
+    pub fn depacketizer(&self) -> &RtpDepacketizer {
+        &self.depacketizer
+    }
+
+    /// Waits for the next complete access unit. Malformed datagrams are skipped, the
+    /// socket's read timeout applies to each datagram.
+    pub fn recv(&mut self) -> io::Result<Packet> {
+        loop {
+            if let Some(packet) = self.depacketizer.pop() {
+                return Ok(packet);
+            }
+            let len = self.socket.recv(&mut self.buf)?;
+            let pushed =
+                RtpPacket::parse(&self.buf[..len]).and_then(|rtp| self.depacketizer.push(&rtp));
+            if let Err(e) = pushed {
+                log::debug!("rtp: dropped packet: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nal(codec: Codec, nal_type: u8, len: usize, seed: u8) -> Vec<u8> {
+        let mut nal = match codec {
+            Codec::H265 => vec![nal_type << 1, 1],
+            _ => vec![0x60 | nal_type],
+        };
+        nal.extend((0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed) | 1));
+        nal
+    }
+
+    // small parameter sets to aggregate, a slice larger than any MTU to fragment
+    fn access_unit(codec: Codec, keyframe: bool, seed: u8) -> Vec<u8> {
+        let nals = match (codec, keyframe) {
+            (Codec::H265, true) => vec![
+                nal(codec, 32, 20, seed),
+                nal(codec, 33, 30, seed),
+                nal(codec, 34, 5, seed),
+                nal(codec, 19, 4000, seed),
+            ],
+            (Codec::H265, false) => vec![nal(codec, 1, 1500, seed), nal(codec, 1, 200, seed)],
+            (_, true) => vec![
+                nal(codec, 9, 1, seed),
+                nal(codec, 7, 20, seed),
+                nal(codec, 8, 4, seed),
+                nal(codec, 5, 5000, seed),
+            ],
+            (_, false) => vec![
+                nal(codec, 1, 100, seed),
+                nal(codec, 1, 250, seed),
+                nal(codec, 1, 900, seed),
+            ],
+        };
+        nals.iter()
+            .flat_map(|nal| [&START_CODE[..], nal].concat())
+            .collect()
+    }
+
+    fn loopback() -> (UdpSocket, UdpSocket) {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        rx.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.connect(rx.local_addr().unwrap()).unwrap();
+        (tx, rx)
+    }
+
+    fn payload_type(codec: Codec, payload: &[u8]) -> u8 {
+        match codec {
+            Codec::H265 => (payload[0] >> 1) & 0x3f,
+            _ => payload[0] & 0x1f,
+        }
+    }
+
+    // every datagram goes through the socket, then is checked and depacketized
+    fn udp_round_trip(codec: Codec) {
+        let (tx, rx) = loopback();
+        let mut packetizer = RtpPacketizer::new(codec, 96, 0x1234).unwrap();
+        packetizer.set_mtu(300);
+        // the sequence number wraps within the first access units
+        packetizer.set_sequence(65530);
+        // and the timestamp a few seconds in
+        packetizer.set_timestamp_offset(u32::MAX - 90_000);
+        let mut depacketizer = RtpDepacketizer::new(codec).unwrap();
+        let mut first_pts = None;
+        let mut types = Vec::new();
+        let mut sequences = Vec::new();
+        let mut buf = [0u8; 2048];
+        for i in 0..50 {
+            let pts = Duration::from_millis(i * 40);
+            let sent = Packet::new(codec, access_unit(codec, i == 0 || i == 25, i as u8), pts);
+            let packets = packetizer.packetize(&sent).unwrap();
+            for rtp in &packets {
+                tx.send(&rtp.to_bytes()).unwrap();
+            }
+            for n in 0..packets.len() {
+                let len = rx.recv(&mut buf).unwrap();
+                assert!(len <= 300);
+                let rtp = RtpPacket::parse(&buf[..len]).unwrap();
+                assert_eq!(rtp, packets[n]);
+                // only the last packet of an access unit has the marker
+                assert_eq!(rtp.marker, n + 1 == packets.len());
+                types.push(payload_type(codec, &rtp.payload));
+                sequences.push(rtp.sequence);
+                depacketizer.push(&rtp).unwrap();
+                // nothing comes out before the marker
+                if !rtp.marker {
+                    assert!(depacketizer.pop().is_none());
+                }
+            }
+            let received = depacketizer.pop().unwrap();
+            assert!(depacketizer.pop().is_none());
+            assert_eq!(received.data, sent.data);
+            assert_eq!(received.keyframe, sent.keyframe);
+            // keeps counting up across the timestamp wrap
+            let first = *first_pts.get_or_insert(received.pts);
+            assert_eq!(received.pts - first, pts);
+        }
+        assert!(sequences.windows(2).all(|s| s[1] == s[0].wrapping_add(1)));
+        assert!(sequences.contains(&u16::MAX) && sequences.contains(&0));
+        let (single, aggregated, fragmented) = match codec {
+            Codec::H265 => (1, H265_AP, H265_FU),
+            _ => (1, H264_STAP_A, H264_FU_A),
+        };
+        for t in [single, aggregated, fragmented] {
+            assert!(types.contains(&t), "{} never sent packet type {}", codec, t);
+        }
+        assert_eq!(depacketizer.lost(), 0);
+    }
+
+    #[test]
+    fn h264_over_udp() {
+        udp_round_trip(Codec::H264);
+    }
+
+    #[test]
+    fn h265_over_udp() {
+        udp_round_trip(Codec::H265);
+    }
+
+    #[test]
+    fn sender_and_receiver() {
+        let (tx, rx) = loopback();
+        let mut sender = RtpSender::new(tx, RtpPacketizer::new(Codec::H265, 97, 7).unwrap());
+        sender.packetizer_mut().set_sequence(u16::MAX - 3);
+        let mut receiver = RtpReceiver::new(rx, Codec::H265).unwrap();
+        for i in 0..20 {
+            let pts = Duration::from_millis(i * 33);
+            let sent = Packet::new(Codec::H265, access_unit(Codec::H265, i == 0, i as u8), pts);
+            assert!(sender.send(&sent).unwrap() > 1);
+            let received = receiver.recv().unwrap();
+            assert_eq!(received.data, sent.data);
+            assert_eq!(to_rtp_time(received.pts), to_rtp_time(pts));
+        }
+        assert_eq!(receiver.depacketizer().lost(), 0);
+        assert!(RtpPacketizer::new(Codec::VP8, 96, 1).is_err());
+    }
+
+    #[test]
+    fn lost_fragment() {
+        let codec = Codec::H264;
+        let mut packetizer = RtpPacketizer::new(codec, 96, 1).unwrap();
+        packetizer.set_sequence(u16::MAX);
+        let mut depacketizer = RtpDepacketizer::new(codec).unwrap();
+        let idr = Packet::new(codec, access_unit(codec, true, 1), Duration::ZERO);
+        let mut packets = packetizer.packetize(&idr).unwrap();
+        assert!(packets.len() > 3);
+        // a middle fragment of the IDR slice, and the marker with the last one
+        packets.remove(2);
+        packets.last_mut().unwrap().marker = false;
+        for rtp in &packets {
+            depacketizer.push(rtp).unwrap();
+        }
+        assert!(depacketizer.pop().is_none());
+
+        // the next timestamp completes the access unit without the broken slice
+        let next = Packet::new(
+            codec,
+            access_unit(codec, false, 2),
+            Duration::from_millis(40),
+        );
+        for rtp in packetizer.packetize(&next).unwrap() {
+            depacketizer.push(&rtp).unwrap();
+        }
+        let broken = depacketizer.pop().unwrap();
+        assert!(!broken.keyframe);
+        assert!(broken.data.len() < 100);
+        assert_eq!(depacketizer.pop().unwrap().data, next.data);
+        assert_eq!(depacketizer.lost(), 1);
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::codec::{Codec, Packet, PacketWriter};
+use crate::ivf::{IvfHeader, IvfWriter};
+use crate::mkv::MkvWriter;
+use crate::mp4::Mp4Writer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerFormat {
+    Mp4,
+    Mkv,
+    Ivf,
+}
+
+impl ContainerFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContainerFormat::Mp4 => "mp4",
+            ContainerFormat::Mkv => "mkv",
+            ContainerFormat::Ivf => "ivf",
+        }
+    }
+
+    pub fn create<W: Write + Seek + 'static>(
+        &self,
+        writer: W,
+        codec: Codec,
+        width: u32,
+        height: u32,
+    ) -> io::Result<Box<dyn PacketWriter>> {
+        if *self == ContainerFormat::Mp4 && !codec.is_annexb() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is not supported in mp4", codec),
+            ));
+        }
+        Ok(match self {
+            ContainerFormat::Mp4 => Box::new(Mp4Writer::new(writer, codec, width, height)),
+            ContainerFormat::Mkv => Box::new(MkvWriter::new(writer, codec, width, height)),
+            ContainerFormat::Ivf => Box::new(IvfWriter::new(
+                writer,
+                IvfHeader::new(codec, width as u16, height as u16),
+            )?),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SegmentConfig {
+    pub directory: PathBuf,
+    // supports {monitor}, {index}, {timestamp} (UTC, YYYYMMDD-HHMMSS), {unix} and {ext}
+    pub file_template: String,
+    pub monitor: u32,
+    pub format: ContainerFormat,
+    pub codec: Codec,
+    pub width: u32,
+    pub height: u32,
+    pub max_duration: Option<Duration>,
+    // counts what the muxer still buffers, so MP4 fragments are not missed
+    pub max_bytes: Option<u64>,
+    // total size of closed segments to keep, oldest are deleted first
+    pub retention_bytes: Option<u64>,
+}
+
+impl SegmentConfig {
+    pub fn new(directory: impl Into<PathBuf>, codec: Codec, width: u32, height: u32) -> Self {
+        Self {
+            directory: directory.into(),
+            file_template: "capture-{monitor}-{timestamp}-{index}.{ext}".to_string(),
+            monitor: 0,
+            format: ContainerFormat::Mkv,
+            codec,
+            width,
+            height,
+            max_duration: Some(Duration::from_secs(10 * 60)),
+            max_bytes: None,
+            retention_bytes: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+// the size of the file written through it, rewriting earlier bytes does not count
+struct CountingWriter<W> {
+    inner: W,
+    position: u64,
+    count: Arc<AtomicU64>,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.position += n as u64;
+        self.count.fetch_max(self.position, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// for the IVF writer, which patches its header on close
+impl<W: Seek> Seek for CountingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+struct Segment {
+    writer: Box<dyn PacketWriter>,
+    path: PathBuf,
+    bytes: Arc<AtomicU64>,
+    first_pts: Duration,
+    last_pts: Duration,
+}
+
+/// Records packets into a series of files, rolling over to a new file on the first
+/// keyframe after `max_duration` or `max_bytes` is reached.
+///
+/// Only segments written by this recorder are considered by the retention policy.
+pub struct SegmentedRecorder {
+    config: SegmentConfig,
+    current: Option<Segment>,
+    index: u32,
+    closed: VecDeque<SegmentInfo>,
+}
+
+impl SegmentedRecorder {
+    pub fn new(config: SegmentConfig) -> io::Result<Self> {
+        std::fs::create_dir_all(&config.directory)?;
+        Ok(Self {
+            config,
+            current: None,
+            index: 0,
+            closed: VecDeque::new(),
+        })
+    }
+
+    pub fn config(&self) -> &SegmentConfig {
+        &self.config
+    }
+
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|s| s.path.as_path())
+    }
+
+    /// Closed segments still on disk, oldest first.
+    pub fn segments(&self) -> impl Iterator<Item = &SegmentInfo> {
+        self.closed.iter()
+    }
+
+    pub fn write(&mut self, packet: &Packet) -> io::Result<()> {
+        if packet.keyframe && self.should_roll_over(packet.pts) {
+            self.close_segment()?;
+        }
+        if self.current.is_none() {
+            if !packet.keyframe {
+                log::debug!("segment: waiting for keyframe");
+                return Ok(());
+            }
+            self.open_segment(packet.pts)?;
+        }
+        let segment = self.current.as_mut().unwrap();
+        segment.writer.write_packet(packet)?;
+        segment.last_pts = packet.pts;
+        Ok(())
+    }
+
+    /// Closes the current segment, the next keyframe starts a new one.
+    pub fn roll_over(&mut self) -> io::Result<()> {
+        self.close_segment()
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<SegmentInfo>> {
+        self.close_segment()?;
+        Ok(self.closed.drain(..).collect())
+    }
+
+    fn should_roll_over(&self, pts: Duration) -> bool {
+        let Some(segment) = self.current.as_ref() else {
+            return false;
+        };
+        let by_time = self
+            .config
+            .max_duration
+            .is_some_and(|max| pts.saturating_sub(segment.first_pts) >= max);
+        let by_size = self.config.max_bytes.is_some_and(|max| {
+            segment.bytes.load(Ordering::Relaxed) + segment.writer.buffered_bytes() >= max
+        });
+        by_time || by_size
+    }
+
+    fn open_segment(&mut self, pts: Duration) -> io::Result<()> {
+        let name = format_file_name(
+            &self.config.file_template,
+            self.config.monitor,
+            self.index,
+            SystemTime::now(),
+            self.config.format.extension(),
+        );
+        let (file, path) = create_new(&self.config.directory.join(name))?;
+        log::debug!("segment: opening {:?}", path);
+        let bytes = Arc::new(AtomicU64::new(0));
+        let file = CountingWriter {
+            inner: BufWriter::new(file),
+            position: 0,
+            count: bytes.clone(),
+        };
+        let writer = self.config.format.create(
+            file,
+            self.config.codec,
+            self.config.width,
+            self.config.height,
+        )?;
+        self.index += 1;
+        self.current = Some(Segment {
+            writer,
+            path,
+            bytes,
+            first_pts: pts,
+            last_pts: pts,
+        });
+        Ok(())
+    }
+
+    fn close_segment(&mut self) -> io::Result<()> {
+        let Some(mut segment) = self.current.take() else {
+            return Ok(());
+        };
+        segment.writer.close()?;
+        let info = SegmentInfo {
+            path: segment.path,
+            bytes: segment.bytes.load(Ordering::Relaxed),
+            duration: segment.last_pts.saturating_sub(segment.first_pts),
+        };
+        log::debug!("segment: closed {:?} ({} bytes)", info.path, info.bytes);
+        self.closed.push_back(info);
+        self.apply_retention();
+        Ok(())
+    }
+
+    fn apply_retention(&mut self) {
+        let Some(quota) = self.config.retention_bytes else {
+            return;
+        };
+        let mut total: u64 = self.closed.iter().map(|s| s.bytes).sum();
+        // the segment just closed is kept even if it alone exceeds the quota
+        while total > quota && self.closed.len() > 1 {
+            let Some(oldest) = self.closed.pop_front() else {
+                break;
+            };
+            total -= oldest.bytes;
+            match std::fs::remove_file(&oldest.path) {
+                Ok(_) => log::debug!("segment: removed {:?}", oldest.path),
+                Err(e) => log::error!("segment: failed to remove {:?}: {:?}", oldest.path, e),
+            }
+        }
+    }
+}
+
+impl Drop for SegmentedRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.close_segment() {
+            log::error!("segment: failed to close segment: {:?}", e);
+        }
+    }
+}
+
+// Creates `path`, or `name-1.ext`, `name-2.ext`... if it exists, so a template without
+// {index} never truncates an earlier segment.
+fn create_new(path: &Path) -> io::Result<(File, PathBuf)> {
+    let mut candidate = path.to_path_buf();
+    let mut n = 0;
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && n < 1000 => {
+                n += 1;
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let mut name = format!("{}-{}", stem, n);
+                if let Some(ext) = path.extension() {
+                    name = format!("{}.{}", name, ext.to_string_lossy());
+                }
+                candidate = path.with_file_name(name);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn format_file_name(
+    template: &str,
+    monitor: u32,
+    index: u32,
+    time: SystemTime,
+    ext: &str,
+) -> String {
+    let unix = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    template
+        .replace("{monitor}", &monitor.to_string())
+        .replace("{index}", &format!("{:04}", index))
+        .replace("{timestamp}", &format_utc(unix))
+        .replace("{unix}", &unix.to_string())
+        .replace("{ext}", ext)
+}
+
+// YYYYMMDD-HHMMSS for seconds since the unix epoch
+fn format_utc(unix: u64) -> String {
+    let (year, month, day, hour, minute, second) = civil_from_unix(unix);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, hour, minute, second
+    )
+}
+
+// (year, month, day, hour, minute, second) in UTC for seconds since the unix epoch
+pub(crate) fn civil_from_unix(unix: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (unix / 86_400) as i64;
+    let secs = (unix % 86_400) as u32;
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an empty directory of its own for each test
+    fn directory(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dxgi-segment-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn vp8(keyframe: bool, ms: u64, len: usize) -> Packet {
+        let mut data = vec![0x9d; len];
+        data[0] = if keyframe { 0x10 } else { 0x11 };
+        Packet::new(Codec::VP8, data, Duration::from_millis(ms))
+    }
+
+    fn config(dir: &Path) -> SegmentConfig {
+        let mut config = SegmentConfig::new(dir, Codec::VP8, 64, 48);
+        config.format = ContainerFormat::Ivf;
+        config
+    }
+
+    #[test]
+    fn rolls_over_on_keyframes() {
+        let dir = directory("duration");
+        let mut config = config(&dir);
+        config.max_duration = Some(Duration::from_secs(1));
+        let mut recorder = SegmentedRecorder::new(config).unwrap();
+        // dropped, segments start on a keyframe
+        recorder.write(&vp8(false, 0, 10)).unwrap();
+        assert!(recorder.current_path().is_none());
+        // keyframe every 400 ms, 100 ms apart
+        for i in 1..=30 {
+            recorder.write(&vp8(i % 4 == 1, i * 100, 10)).unwrap();
+        }
+        let segments = recorder.finish().unwrap();
+        let durations: Vec<_> = segments.iter().map(|s| s.duration.as_millis()).collect();
+        assert_eq!(durations, [1100, 1100, 500]);
+        for (i, segment) in segments.iter().enumerate() {
+            assert!(segment
+                .path
+                .to_string_lossy()
+                .ends_with(&format!("-{:04}.ivf", i)));
+            let data = std::fs::read(&segment.path).unwrap();
+            assert_eq!(data.len() as u64, segment.bytes);
+            let reader = crate::ivf::IvfReader::new(&data[..]).unwrap();
+            let frames = reader.header().frame_count as usize;
+            assert_eq!(frames, segment.duration.as_millis() as usize / 100 + 1);
+            let packets: Vec<_> = reader.map(Result::unwrap).collect();
+            assert_eq!(packets.len(), frames);
+            assert!(packets[0].keyframe);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rolls_over_by_size() {
+        let dir = directory("size");
+        let mut config = config(&dir);
+        config.max_duration = None;
+        config.max_bytes = Some(1000);
+        let mut recorder = SegmentedRecorder::new(config).unwrap();
+        for i in 0..20 {
+            recorder.write(&vp8(i % 2 == 0, i * 10, 300)).unwrap();
+        }
+        let segments = recorder.finish().unwrap();
+        // 32 byte header, then 312 bytes per frame, cut at the next keyframe past 1000
+        let sizes: Vec<_> = segments.iter().map(|s| s.bytes).collect();
+        assert_eq!(sizes, [32 + 4 * 312; 5]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mp4_counts_the_pending_fragment() {
+        let dir = directory("mp4");
+        let mut config = SegmentConfig::new(&dir, Codec::H264, 64, 48);
+        config.format = ContainerFormat::Mp4;
+        config.max_duration = None;
+        config.max_bytes = Some(1000);
+        let mut recorder = SegmentedRecorder::new(config).unwrap();
+        for i in 0..20 {
+            let mut data = vec![0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80];
+            data.extend_from_slice(&[0, 0, 0, 1, 0x68, 0xce, 0x3c, 0x80]);
+            data.extend_from_slice(&[0, 0, 0, 1, if i % 4 == 0 { 0x65 } else { 0x41 }]);
+            data.resize(data.len() + 300, 0x9d);
+            let packet = Packet::new(Codec::H264, data, Duration::from_millis(i * 10));
+            assert_eq!(packet.keyframe, i % 4 == 0);
+            recorder.write(&packet).unwrap();
+        }
+        let segments = recorder.finish().unwrap();
+        // the fragment is still buffered when the next keyframe arrives, one GOP per file
+        assert_eq!(segments.len(), 5);
+        for segment in &segments {
+            let data = std::fs::read(&segment.path).unwrap();
+            assert_eq!(data.len() as u64, segment.bytes);
+            let names: Vec<_> = crate::mp4::boxes(&data).map(|b| b.name).collect();
+            assert_eq!(names, [*b"ftyp", *b"moov", *b"moof", *b"mdat"]);
+            assert!(segment.bytes < 1000 + 4 * 320);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retention_keeps_the_newest() {
+        let dir = directory("retention");
+        let mut config = config(&dir);
+        config.max_duration = None;
+        config.retention_bytes = Some(1500);
+        let mut recorder = SegmentedRecorder::new(config).unwrap();
+        let mut closed = Vec::new();
+        for (i, len) in [500, 500, 500, 2000, 100].into_iter().enumerate() {
+            recorder.write(&vp8(true, i as u64 * 10, len)).unwrap();
+            recorder.roll_over().unwrap();
+            closed.push(recorder.segments().last().unwrap().path.clone());
+            let total: u64 = recorder.segments().map(|s| s.bytes).sum();
+            assert!(total <= 1500 || recorder.segments().count() == 1);
+        }
+        let kept: Vec<_> = recorder.segments().map(|s| s.path.clone()).collect();
+        // the 2 kB segment exceeded the quota alone and was kept until the next one
+        assert_eq!(kept, closed[4..]);
+        for path in &closed {
+            assert_eq!(path.exists(), kept.contains(path), "{:?}", path);
+        }
+        drop(recorder);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn template_without_index() {
+        let dir = directory("template");
+        let mut config = config(&dir);
+        config.file_template = "capture.{ext}".to_string();
+        let mut recorder = SegmentedRecorder::new(config).unwrap();
+        for i in 0..3 {
+            recorder
+                .write(&vp8(true, i * 10, 100 + i as usize))
+                .unwrap();
+            recorder.roll_over().unwrap();
+        }
+        let segments = recorder.finish().unwrap();
+        let names: Vec<_> = segments
+            .iter()
+            .map(|s| s.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["capture.ivf", "capture-1.ivf", "capture-2.ivf"]);
+        for (i, segment) in segments.iter().enumerate() {
+            let data = std::fs::read(&segment.path).unwrap();
+            assert_eq!(data.len(), 32 + 12 + 100 + i);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
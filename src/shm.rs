@@ -0,0 +1,901 @@
+//! Frame ring in named shared memory, for consumers on the same machine.
+//!
+//! One producer publishes frames into a fixed number of slots, round robin. Every slot
+//! is guarded by a seqlock: the producer makes its counter odd, writes, then makes it
+//! even again, and a reader only accepts what it copied if the counter was even and
+//! unchanged around the copy. Readers never block the producer; a reader that falls a
+//! whole ring behind skips ahead to the oldest frame still in it.
+//!
+//! Layout: a 4 KiB header, then `slots` x (512 byte slot header + slot size rounded up
+//! to 64 bytes). Pixels are stored as tightly packed rows.
+
+use std::io;
+use std::mem::size_of;
+use std::sync::atomic::{fence, AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::frame::{Frame, PixelFormat, Rect};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"DXGR");
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 4096;
+const SLOT_HEADER_SIZE: usize = 512;
+const MAX_RECTS: usize = 16;
+// how long a reader waits on a slot that stays mid-write, e.g. after the producer died
+const STUCK_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[repr(C)]
+struct RingHeader {
+    magic: u32,
+    version: u32,
+    slots: u32,
+    closed: AtomicU32,
+    slot_size: u64,
+    total_size: u64,
+    // sequence number of the newest complete frame, 0 before the first
+    write_seq: AtomicU64,
+}
+
+#[repr(C)]
+struct SlotHeader {
+    // seqlock, odd while the producer writes the slot
+    lock: AtomicU64,
+    seq: AtomicU64,
+    width: AtomicU32,
+    height: AtomicU32,
+    format: AtomicU32,
+    rect_count: AtomicU32,
+    present_time: AtomicU64,
+    len: AtomicU64,
+    rects: [[AtomicI32; 4]; MAX_RECTS],
+}
+
+const _: () = assert!(size_of::<RingHeader>() <= HEADER_SIZE);
+const _: () = assert!(size_of::<SlotHeader>() <= SLOT_HEADER_SIZE);
+
+fn format_code(format: PixelFormat) -> u32 {
+    match format {
+        PixelFormat::Bgra8 => 0,
+    }
+}
+
+fn parse_format(code: u32) -> Option<PixelFormat> {
+    match code {
+        0 => Some(PixelFormat::Bgra8),
+        _ => None,
+    }
+}
+
+fn slot_stride(slot_size: u64) -> usize {
+    SLOT_HEADER_SIZE + (slot_size as usize).div_ceil(64) * 64
+}
+
+/// Size of a ring with `slots` slots of `slot_size` pixel bytes.
+pub fn ring_size(slots: u32, slot_size: usize) -> usize {
+    HEADER_SIZE + slots as usize * slot_stride(slot_size as u64)
+}
+
+/// Describes a frame in shared memory, see `FrameRingReader::with_latest`.
+pub struct FrameView<'a> {
+    pub seq: u64,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    pub present_time: Duration,
+    pub dirty_rects: &'a [Rect],
+    // tightly packed rows, the producer may be writing them, so never handed out as `&[u8]`
+    data: *const u8,
+    len: usize,
+}
+
+impl FrameView<'_> {
+    /// Size of the pixels in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies `dst.len()` bytes of pixels starting at `offset` into `dst`. What is read
+    /// may be torn, `with_latest` only returns results of intact reads.
+    ///
+    /// # Panics
+    ///
+    /// If the range is outside the frame.
+    pub fn read(&self, offset: usize, dst: &mut [u8]) {
+        assert!(
+            offset
+                .checked_add(dst.len())
+                .is_some_and(|end| end <= self.len),
+            "{} bytes at {} outside a {} byte frame",
+            dst.len(),
+            offset,
+            self.len
+        );
+        unsafe { volatile_copy(self.data.add(offset), dst) };
+    }
+}
+
+// copies memory another process may be writing, in cache line sized loads
+unsafe fn volatile_copy(src: *const u8, dst: &mut [u8]) {
+    const CHUNK: usize = 64;
+    let tail = dst.len() / CHUNK * CHUNK;
+    let mut chunks = dst.chunks_exact_mut(CHUNK);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        let bytes = std::ptr::read_volatile(src.add(i * CHUNK) as *const [u8; CHUNK]);
+        chunk.copy_from_slice(&bytes);
+    }
+    for (i, b) in chunks.into_remainder().iter_mut().enumerate() {
+        *b = std::ptr::read_volatile(src.add(tail + i));
+    }
+}
+
+// outcome of `Ring::read_slot`
+enum SlotRead<R> {
+    Done(R),
+    // the slot moved on to another frame, a newer one is published
+    Overwritten,
+    // stays mid-write or holds nonsense, e.g. the producer died while writing it
+    Unreadable,
+}
+
+struct Ring {
+    mem: sys::Mapping,
+}
+
+impl Ring {
+    fn header(&self) -> &RingHeader {
+        unsafe { &*(self.mem.ptr() as *const RingHeader) }
+    }
+
+    fn slot(&self, index: usize) -> (&SlotHeader, *mut u8) {
+        let stride = slot_stride(self.header().slot_size);
+        unsafe {
+            let base = self.mem.ptr().add(HEADER_SIZE + index * stride);
+            (&*(base as *const SlotHeader), base.add(SLOT_HEADER_SIZE))
+        }
+    }
+
+    fn slot_for(&self, seq: u64) -> (&SlotHeader, *mut u8) {
+        self.slot(((seq - 1) % self.header().slots as u64) as usize)
+    }
+
+    // runs `f` on a stable copy of frame `seq`, `f` returns `None` for a header that
+    // makes no sense
+    fn read_slot<R>(
+        &self,
+        seq: u64,
+        mut f: impl FnMut(&SlotHeader, *const u8, usize) -> Option<R>,
+    ) -> SlotRead<R> {
+        let (slot, data) = self.slot_for(seq);
+        let slot_size = self.header().slot_size;
+        let mut stuck_since = None;
+        for spin in 0u64.. {
+            let before = slot.lock.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                if spin > 100 {
+                    if stuck_since.get_or_insert_with(Instant::now).elapsed() >= STUCK_TIMEOUT {
+                        break;
+                    }
+                    std::thread::yield_now();
+                } else {
+                    std::hint::spin_loop();
+                }
+                continue;
+            }
+            if slot.seq.load(Ordering::Relaxed) != seq {
+                return SlotRead::Overwritten;
+            }
+            let len = slot.len.load(Ordering::Relaxed);
+            // everything read here may be torn, only trusted once the lock checks out
+            let result = if len <= slot_size {
+                f(slot, data, len as usize)
+            } else {
+                None
+            };
+            fence(Ordering::Acquire);
+            if slot.lock.load(Ordering::Relaxed) == before {
+                return result.map_or(SlotRead::Unreadable, SlotRead::Done);
+            }
+        }
+        SlotRead::Unreadable
+    }
+}
+
+// header fields of a slot, read inside `Ring::read_slot`
+fn slot_info(
+    slot: &SlotHeader,
+    len: usize,
+) -> Option<(u32, u32, PixelFormat, Duration, Vec<Rect>)> {
+    let width = slot.width.load(Ordering::Relaxed);
+    let height = slot.height.load(Ordering::Relaxed);
+    let format = parse_format(slot.format.load(Ordering::Relaxed))?;
+    if width as usize * height as usize * format.bytes_per_pixel() != len {
+        return None;
+    }
+    let present_time = Duration::from_nanos(slot.present_time.load(Ordering::Relaxed));
+    let count = (slot.rect_count.load(Ordering::Relaxed) as usize).min(MAX_RECTS);
+    let rects = slot.rects[..count]
+        .iter()
+        .map(|r| {
+            Rect::new(
+                r[0].load(Ordering::Relaxed),
+                r[1].load(Ordering::Relaxed),
+                r[2].load(Ordering::Relaxed),
+                r[3].load(Ordering::Relaxed),
+            )
+        })
+        .collect();
+    Some((width, height, format, present_time, rects))
+}
+
+/// Producer side of a shared-memory frame ring.
+pub struct FrameRingWriter {
+    ring: Ring,
+    name: String,
+    seq: u64,
+}
+
+impl FrameRingWriter {
+    /// Creates the ring `name` with `slots` slots of up to `max_frame_bytes` pixels each,
+    /// replacing a ring of that name left behind by a previous run.
+    pub fn create(name: &str, slots: u32, max_frame_bytes: usize) -> io::Result<Self> {
+        if slots == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ring needs at least one slot",
+            ));
+        }
+        let total = ring_size(slots, max_frame_bytes);
+        let mem = sys::Mapping::create(name, total)?;
+        // freshly created memory is zeroed, so the slots start unlocked and empty
+        unsafe {
+            let header = mem.ptr() as *mut RingHeader;
+            (*header).magic = MAGIC;
+            (*header).version = VERSION;
+            (*header).slots = slots;
+            (*header).slot_size = max_frame_bytes as u64;
+            (*header).total_size = total as u64;
+        }
+        log::debug!(
+            "shm: created ring {} with {} slots of {} bytes",
+            name,
+            slots,
+            max_frame_bytes
+        );
+        Ok(Self {
+            ring: Ring { mem },
+            name: name.to_string(),
+            seq: 0,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn slots(&self) -> u32 {
+        self.ring.header().slots
+    }
+
+    pub fn max_frame_bytes(&self) -> usize {
+        self.ring.header().slot_size as usize
+    }
+
+    /// Copies `frame` into the next slot and returns its sequence number. Move rects are
+    /// stored as dirty rects, and more than 16 changed rects leave them unknown.
+    pub fn publish(&mut self, frame: &Frame) -> io::Result<u64> {
+        let row_len = frame.width as usize * frame.format.bytes_per_pixel();
+        let len = row_len * frame.height as usize;
+        if len > self.max_frame_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{}x{} frame does not fit a {} byte slot",
+                    frame.width,
+                    frame.height,
+                    self.max_frame_bytes()
+                ),
+            ));
+        }
+        let seq = self.seq + 1;
+        let (slot, data) = self.ring.slot_for(seq);
+        let lock = slot.lock.load(Ordering::Relaxed);
+        slot.lock.store(lock + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        slot.seq.store(seq, Ordering::Relaxed);
+        slot.width.store(frame.width, Ordering::Relaxed);
+        slot.height.store(frame.height, Ordering::Relaxed);
+        slot.format
+            .store(format_code(frame.format), Ordering::Relaxed);
+        slot.present_time
+            .store(frame.present_time.as_nanos() as u64, Ordering::Relaxed);
+        slot.len.store(len as u64, Ordering::Relaxed);
+        let mut rects = frame.changed_rects();
+        if rects.len() > MAX_RECTS {
+            rects.clear();
+        }
+        slot.rect_count.store(rects.len() as u32, Ordering::Relaxed);
+        for (dst, rect) in slot.rects.iter().zip(&rects) {
+            for (d, v) in dst
+                .iter()
+                .zip([rect.left, rect.top, rect.right, rect.bottom])
+            {
+                d.store(v, Ordering::Relaxed);
+            }
+        }
+        for y in 0..frame.height {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    frame.row(y).as_ptr(),
+                    data.add(y as usize * row_len),
+                    row_len,
+                );
+            }
+        }
+
+        slot.lock.store(lock + 2, Ordering::Release);
+        self.ring.header().write_seq.store(seq, Ordering::Release);
+        self.seq = seq;
+        Ok(seq)
+    }
+}
+
+impl Drop for FrameRingWriter {
+    fn drop(&mut self) {
+        self.ring.header().closed.store(1, Ordering::Release);
+    }
+}
+
+/// Consumer side of a shared-memory frame ring, any number may be open.
+pub struct FrameRingReader {
+    ring: Ring,
+    last: u64,
+}
+
+impl FrameRingReader {
+    pub fn open(name: &str) -> io::Result<Self> {
+        let mem = sys::Mapping::open(name)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if mem.len() < HEADER_SIZE {
+            return Err(invalid("shared memory too small for a frame ring"));
+        }
+        let ring = Ring { mem };
+        let header = ring.header();
+        if header.magic != MAGIC {
+            return Err(invalid("shared memory is not a frame ring"));
+        }
+        if header.version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported frame ring version {}", header.version),
+            ));
+        }
+        if header.slots == 0
+            || header.total_size as usize != ring_size(header.slots, header.slot_size as usize)
+            || ring.mem.len() < header.total_size as usize
+        {
+            return Err(invalid("frame ring header does not match its size"));
+        }
+        Ok(Self { ring, last: 0 })
+    }
+
+    /// Sequence number of the newest frame published, 0 before the first.
+    pub fn latest_seq(&self) -> u64 {
+        self.ring.header().write_seq.load(Ordering::Acquire)
+    }
+
+    /// Sequence number of the last frame this reader returned.
+    pub fn last_seq(&self) -> u64 {
+        self.last
+    }
+
+    /// Whether the producer has gone away.
+    pub fn is_closed(&self) -> bool {
+        self.ring.header().closed.load(Ordering::Acquire) != 0
+    }
+
+    /// Copies the newest frame into `frame`, reusing its buffer. `None` when there is
+    /// nothing newer than the last frame read, or the newest frame stays mid-write
+    /// because the producer died while writing it.
+    pub fn read_latest(&mut self, frame: &mut Frame) -> Option<u64> {
+        loop {
+            let seq = self.latest_seq();
+            if seq <= self.last {
+                return None;
+            }
+            match self.copy(seq, frame) {
+                SlotRead::Done(()) => {
+                    self.last = seq;
+                    return Some(seq);
+                }
+                // overwritten while copying, the producer has moved on
+                SlotRead::Overwritten => {}
+                SlotRead::Unreadable => return None,
+            }
+        }
+    }
+
+    /// Copies the frame after the last one read into `frame`, skipping ahead if the
+    /// producer has lapped this reader or left a slot mid-write. Returns the sequence
+    /// number and how many frames were skipped.
+    pub fn read_next(&mut self, frame: &mut Frame) -> Option<(u64, u64)> {
+        let slots = self.ring.header().slots as u64;
+        let mut next = self.last + 1;
+        loop {
+            let latest = self.latest_seq();
+            if latest < next {
+                return None;
+            }
+            let seq = next.max(latest.saturating_sub(slots - 1));
+            match self.copy(seq, frame) {
+                SlotRead::Done(()) => {
+                    let skipped = seq - self.last - 1;
+                    self.last = seq;
+                    return Some((seq, skipped));
+                }
+                SlotRead::Overwritten => {}
+                SlotRead::Unreadable if seq < latest => next = seq + 1,
+                SlotRead::Unreadable => return None,
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for a frame newer than the last one read and copies it.
+    /// Polls, so expect up to a millisecond of extra latency.
+    pub fn wait_latest(&mut self, frame: &mut Frame, timeout: Duration) -> Option<u64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(seq) = self.read_latest(frame) {
+                return Some(seq);
+            }
+            if self.is_closed() || Instant::now() >= deadline {
+                return None;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Runs `f` directly on the newest frame in shared memory, without copying all of it.
+    ///
+    /// The producer does not wait for readers, so the pixels can change while `f` runs
+    /// and `f` only reads the parts it needs through `FrameView::read`. If they did
+    /// change, the result is thrown away and `f` runs again on a newer frame; only
+    /// results computed from an intact frame are returned. Keep `f` short, a slow `f`
+    /// may never see an intact frame once the producer laps it. `None` like
+    /// `read_latest`.
+    pub fn with_latest<R>(&mut self, mut f: impl FnMut(&FrameView) -> R) -> Option<(u64, R)> {
+        loop {
+            let seq = self.latest_seq();
+            if seq <= self.last {
+                return None;
+            }
+            let result = self.ring.read_slot(seq, |slot, data, len| {
+                let (width, height, format, present_time, rects) = slot_info(slot, len)?;
+                let view = FrameView {
+                    seq,
+                    width,
+                    height,
+                    format,
+                    present_time,
+                    dirty_rects: &rects,
+                    data,
+                    len,
+                };
+                Some(f(&view))
+            });
+            match result {
+                SlotRead::Done(result) => {
+                    self.last = seq;
+                    return Some((seq, result));
+                }
+                SlotRead::Overwritten => {}
+                SlotRead::Unreadable => return None,
+            }
+        }
+    }
+
+    fn copy(&self, seq: u64, frame: &mut Frame) -> SlotRead<()> {
+        self.ring.read_slot(seq, |slot, data, len| {
+            let (width, height, format, present_time, rects) = slot_info(slot, len)?;
+            frame.width = width;
+            frame.height = height;
+            frame.format = format;
+            frame.stride = width as usize * format.bytes_per_pixel();
+            frame.present_time = present_time;
+            frame.dirty_rects = rects;
+            frame.move_rects.clear();
+            frame.data.resize(len, 0);
+            unsafe { volatile_copy(data, &mut frame.data) };
+            Some(())
+        })
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::CString;
+    use std::io;
+
+    pub struct Mapping {
+        ptr: *mut u8,
+        len: usize,
+        // set on the creating side, which unlinks the name on drop
+        name: Option<CString>,
+    }
+
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    fn shm_name(name: &str) -> io::Result<CString> {
+        CString::new(format!("/{}", name.trim_start_matches('/')))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad shared memory name"))
+    }
+
+    fn map(fd: libc::c_int, len: usize) -> io::Result<*mut u8> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    impl Mapping {
+        pub fn create(name: &str, len: usize) -> io::Result<Self> {
+            let cname = shm_name(name)?;
+            unsafe {
+                libc::shm_unlink(cname.as_ptr());
+                let fd = libc::shm_open(
+                    cname.as_ptr(),
+                    libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                    0o600,
+                );
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mapped = if libc::ftruncate(fd, len as libc::off_t) < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    map(fd, len)
+                };
+                libc::close(fd);
+                match mapped {
+                    Ok(ptr) => Ok(Self {
+                        ptr,
+                        len,
+                        name: Some(cname),
+                    }),
+                    Err(e) => {
+                        libc::shm_unlink(cname.as_ptr());
+                        Err(e)
+                    }
+                }
+            }
+        }
+
+        pub fn open(name: &str) -> io::Result<Self> {
+            let cname = shm_name(name)?;
+            unsafe {
+                let fd = libc::shm_open(cname.as_ptr(), libc::O_RDWR, 0);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut stat: libc::stat = std::mem::zeroed();
+                let mapped = if libc::fstat(fd, &mut stat) < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    map(fd, stat.st_size as usize).map(|ptr| (ptr, stat.st_size as usize))
+                };
+                libc::close(fd);
+                let (ptr, len) = mapped?;
+                Ok(Self {
+                    ptr,
+                    len,
+                    name: None,
+                })
+            }
+        }
+
+        pub fn ptr(&self) -> *mut u8 {
+            self.ptr
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+                // readers keep their mapping, the name just goes away
+                if let Some(name) = &self.name {
+                    libc::shm_unlink(name.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE, INVALID_HANDLE_VALUE,
+    };
+    use windows::Win32::System::Memory::{
+        CreateFileMappingW, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, VirtualQuery,
+        FILE_MAP_ALL_ACCESS, MEMORY_BASIC_INFORMATION, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+    };
+
+    pub struct Mapping {
+        handle: HANDLE,
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    fn wide_name(name: &str) -> Vec<u16> {
+        format!("Local\\{}", name)
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect()
+    }
+
+    fn map(handle: HANDLE, len: usize) -> io::Result<*mut u8> {
+        let view = unsafe { MapViewOfFile(handle, FILE_MAP_ALL_ACCESS, 0, 0, len) };
+        if view.Value.is_null() {
+            let e = io::Error::last_os_error();
+            unsafe {
+                let _ = CloseHandle(handle);
+            }
+            return Err(e);
+        }
+        Ok(view.Value as *mut u8)
+    }
+
+    impl Mapping {
+        pub fn create(name: &str, len: usize) -> io::Result<Self> {
+            let name = wide_name(name);
+            let handle = unsafe {
+                CreateFileMappingW(
+                    INVALID_HANDLE_VALUE,
+                    None,
+                    PAGE_READWRITE,
+                    (len as u64 >> 32) as u32,
+                    len as u32,
+                    PCWSTR(name.as_ptr()),
+                )
+            }
+            .map_err(io::Error::other)?;
+            // the mapping lives as long as any handle, a running producer or reader
+            // still holds the old one
+            if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+                unsafe {
+                    let _ = CloseHandle(handle);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "shared memory is still in use",
+                ));
+            }
+            let ptr = map(handle, len)?;
+            Ok(Self { handle, ptr, len })
+        }
+
+        pub fn open(name: &str) -> io::Result<Self> {
+            let name = wide_name(name);
+            let handle =
+                unsafe { OpenFileMappingW(FILE_MAP_ALL_ACCESS.0, false, PCWSTR(name.as_ptr())) }
+                    .map_err(io::Error::other)?;
+            let ptr = map(handle, 0)?;
+            let mut info = MEMORY_BASIC_INFORMATION::default();
+            unsafe {
+                VirtualQuery(
+                    Some(ptr as *const _),
+                    &mut info,
+                    std::mem::size_of::<MEMORY_BASIC_INFORMATION>(),
+                )
+            };
+            Ok(Self {
+                handle,
+                ptr,
+                len: info.RegionSize,
+            })
+        }
+
+        pub fn ptr(&self) -> *mut u8 {
+            self.ptr
+        }
+
+        pub fn len(&self) -> usize {
+            self.len
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                let _ = UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS {
+                    Value: self.ptr as *mut _,
+                });
+                let _ = CloseHandle(self.handle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READER_ENV: &str = "DXGI_SHM_TEST_RING";
+
+    // every byte of frame `seq` is `seq as u8`, a torn copy mixes two frames
+    fn frame(seq: u64) -> Frame {
+        let mut frame = Frame::new(640, 360, PixelFormat::Bgra8);
+        frame.data.fill(seq as u8);
+        frame.present_time = Duration::from_millis(seq);
+        frame
+    }
+
+    fn intact(seq: u64, data: &[u8]) -> bool {
+        data.iter().all(|b| *b == seq as u8)
+    }
+
+    fn view_intact(view: &FrameView) -> bool {
+        let mut data = vec![0; view.len()];
+        view.read(0, &mut data);
+        intact(view.seq, &data)
+    }
+
+    #[test]
+    fn read_next_and_view() {
+        let name = format!("dxgi-test-view-{}", std::process::id());
+        let mut writer = FrameRingWriter::create(&name, 4, 640 * 360 * 4).unwrap();
+        let mut reader = FrameRingReader::open(&name).unwrap();
+        let mut copy = Frame::new(1, 1, PixelFormat::Bgra8);
+        assert!(reader.read_next(&mut copy).is_none());
+        for seq in 1..=10 {
+            assert_eq!(writer.publish(&frame(seq)).unwrap(), seq);
+        }
+        // lapped, the oldest frame still in the ring comes next
+        assert_eq!(reader.read_next(&mut copy), Some((7, 6)));
+        assert!(intact(7, &copy.data));
+        assert_eq!(reader.read_next(&mut copy), Some((8, 0)));
+        let (seq, view) = reader
+            .with_latest(|view| (view.width, view.height, view_intact(view)))
+            .unwrap();
+        assert_eq!((seq, view), (10, (640, 360, true)));
+        assert!(reader.read_latest(&mut copy).is_none());
+
+        let big = Frame::new(1000, 1000, PixelFormat::Bgra8);
+        assert_eq!(
+            writer.publish(&big).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        drop(writer);
+        assert!(reader.is_closed());
+        assert!(FrameRingReader::open(&name).is_err());
+    }
+
+    #[test]
+    fn slots_left_mid_write() {
+        let name = format!("dxgi-test-locked-{}", std::process::id());
+        let mut writer = FrameRingWriter::create(&name, 2, 640 * 360 * 4).unwrap();
+        let mut reader = FrameRingReader::open(&name).unwrap();
+        let mut copy = Frame::new(1, 1, PixelFormat::Bgra8);
+        for seq in 1..=3 {
+            writer.publish(&frame(seq)).unwrap();
+        }
+        // as if the producer died while writing frame 4 over frame 2
+        writer
+            .ring
+            .slot_for(4)
+            .0
+            .lock
+            .fetch_add(1, Ordering::Relaxed);
+        assert_eq!(reader.read_next(&mut copy), Some((3, 2)));
+        assert!(intact(3, &copy.data));
+        assert!(reader.read_next(&mut copy).is_none());
+
+        // over the newest frame instead, nothing to read but no hang either
+        writer
+            .ring
+            .slot_for(4)
+            .0
+            .lock
+            .fetch_sub(1, Ordering::Relaxed);
+        writer
+            .ring
+            .slot_for(3)
+            .0
+            .lock
+            .fetch_add(1, Ordering::Relaxed);
+        let mut reader = FrameRingReader::open(&name).unwrap();
+        assert!(reader.read_latest(&mut copy).is_none());
+        assert!(reader.with_latest(|_| ()).is_none());
+        assert_eq!(reader.read_next(&mut copy), Some((2, 1)));
+        assert!(reader.read_next(&mut copy).is_none());
+        assert!(reader
+            .wait_latest(&mut copy, Duration::from_millis(20))
+            .is_none());
+        assert_eq!(reader.last_seq(), 2);
+    }
+
+    // only does something when spawned by `readers_in_other_processes`
+    #[test]
+    fn reader_process() {
+        let Ok(name) = std::env::var(READER_ENV) else {
+            return;
+        };
+        let mut reader = FrameRingReader::open(&name).unwrap();
+        let mut copy = Frame::new(1, 1, PixelFormat::Bgra8);
+        let (mut copies, mut views) = (0, 0);
+        loop {
+            let closed = reader.is_closed();
+            if let Some(seq) = reader.read_latest(&mut copy) {
+                assert!(intact(seq, &copy.data), "frame {} read torn", seq);
+                assert_eq!(copy.present_time, Duration::from_millis(seq));
+                copies += 1;
+            }
+            if let Some((seq, intact)) = reader.with_latest(view_intact) {
+                assert!(intact, "frame {} viewed torn", seq);
+                views += 1;
+            }
+            if closed && reader.last_seq() == reader.latest_seq() {
+                break;
+            }
+        }
+        assert!(copies > 0 && views > 0);
+        println!("read {} copies and {} views", copies, views);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn readers_in_other_processes() {
+        let name = format!("dxgi-test-procs-{}", std::process::id());
+        // few slots so the writer keeps overwriting what the readers are copying
+        let mut writer = FrameRingWriter::create(&name, 2, 640 * 360 * 4).unwrap();
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "shm::tests::reader_process", "--nocapture"])
+                    .env(READER_ENV, &name)
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        // the readers open the ring while this is running
+        let start = Instant::now();
+        let mut seq = 0;
+        while start.elapsed() < Duration::from_secs(1) || seq < 2000 {
+            seq = writer.publish(&frame(seq + 1)).unwrap();
+        }
+        drop(writer);
+        for reader in readers {
+            let output = reader.wait_with_output().unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            assert!(stdout.contains("read "), "{}", stdout);
+        }
+    }
+}
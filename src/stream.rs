@@ -0,0 +1,225 @@
+use crate::backend::CaptureBackend;
+use crate::frame::{Frame, PixelFormat};
+
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    // every following frame has the new width and height, until the next one
+    Reconfigured {
+        old: (u32, u32),
+        new: (u32, u32),
+        format: PixelFormat,
+    },
+    Frame(Frame),
+}
+
+/// Frames of a capture backend with explicit geometry changes.
+///
+/// A backend's `width()` and `height()` change under the caller when the mode of the
+/// desktop does, e.g. `CaptureDXGI` after it reacquired the duplication. The stream
+/// instead returns `Reconfigured` first and holds the frame back for the next call, so
+/// a consumer can recreate its encoder before it sees a frame of the new size.
+///
+/// ```
+/// use dxgi::frame::PixelFormat;
+/// use dxgi::stream::{FrameStream, StreamEvent};
+/// use dxgi::synthetic::SyntheticBackend;
+///
+/// let mut stream = FrameStream::new(SyntheticBackend::new(64, 48, 500));
+/// let next = |stream: &mut FrameStream<SyntheticBackend>| loop {
+///     if let Some(event) = stream.next_event(100, true).unwrap() {
+///         return event;
+///     }
+/// };
+/// let StreamEvent::Frame(frame) = next(&mut stream) else { panic!() };
+/// assert_eq!((frame.width, frame.height), (64, 48));
+///
+/// stream.backend_mut().set_size(32, 16);
+/// match next(&mut stream) {
+///     StreamEvent::Reconfigured { old, new, format } => {
+///         assert_eq!(old, (64, 48));
+///         assert_eq!(new, (32, 16));
+///         assert_eq!(format, PixelFormat::Bgra8);
+///     }
+///     other => panic!("expected Reconfigured, got {:?}", other),
+/// }
+/// assert_eq!(stream.geometry(), (32, 16));
+/// let StreamEvent::Frame(frame) = next(&mut stream) else { panic!() };
+/// assert_eq!((frame.width, frame.height), (32, 16));
+/// ```
+pub struct FrameStream<B: CaptureBackend> {
+    backend: B,
+    geometry: (u32, u32),
+    format: PixelFormat,
+    // the first frame in the new geometry, returned after `Reconfigured`
+    pending: Option<Frame>,
+}
+
+impl<B: CaptureBackend> FrameStream<B> {
+    /// Starts from the backend's current size, the first frame only reconfigures the
+    /// stream if it does not match.
+    pub fn new(backend: B) -> Self {
+        Self {
+            geometry: (backend.width(), backend.height()),
+            format: PixelFormat::Bgra8,
+            backend,
+            pending: None,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+
+    /// Width and height of the frames currently returned.
+    pub fn geometry(&self) -> (u32, u32) {
+        self.geometry
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Like `CaptureBackend::capture`.
+    pub fn next_event(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<StreamEvent>> {
+        if let Some(frame) = self.pending.take() {
+            return Ok(Some(StreamEvent::Frame(frame)));
+        }
+        let Some(frame) = self.backend.capture(timeout, skip)? else {
+            return Ok(None);
+        };
+        let geometry = (frame.width, frame.height);
+        if geometry == self.geometry && frame.format == self.format {
+            return Ok(Some(StreamEvent::Frame(frame)));
+        }
+        let old = std::mem::replace(&mut self.geometry, geometry);
+        self.format = frame.format;
+        self.pending = Some(frame);
+        Ok(Some(StreamEvent::Reconfigured {
+            old,
+            new: geometry,
+            format: self.format,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::synthetic::SyntheticBackend;
+
+    // hands out prepared captures, `None` for a timeout
+    struct Script {
+        size: (u32, u32),
+        captures: VecDeque<Option<(u32, u32)>>,
+    }
+
+    impl CaptureBackend for Script {
+        fn capture(&mut self, _timeout: u32, _skip: bool) -> anyhow::Result<Option<Frame>> {
+            let capture = self.captures.pop_front().expect("script ended");
+            Ok(capture.map(|(width, height)| {
+                // like CaptureDXGI, the size only changes with the frame
+                self.size = (width, height);
+                Frame::new(width, height, PixelFormat::Bgra8)
+            }))
+        }
+
+        fn width(&self) -> u32 {
+            self.size.0
+        }
+
+        fn height(&self) -> u32 {
+            self.size.1
+        }
+    }
+
+    fn describe(event: Option<StreamEvent>) -> String {
+        match event {
+            None => "timeout".to_string(),
+            Some(StreamEvent::Frame(frame)) => format!("frame {}x{}", frame.width, frame.height),
+            Some(StreamEvent::Reconfigured { old, new, .. }) => {
+                format!("{}x{} -> {}x{}", old.0, old.1, new.0, new.1)
+            }
+        }
+    }
+
+    #[test]
+    fn reconfigures_before_each_new_size() {
+        let captures = [
+            Some((640, 480)),
+            None,
+            Some((640, 480)),
+            Some((800, 600)),
+            None,
+            Some((800, 600)),
+            Some((640, 480)),
+        ];
+        let mut stream = FrameStream::new(Script {
+            size: (1024, 768),
+            captures: captures.into_iter().collect(),
+        });
+        assert_eq!(stream.geometry(), (1024, 768));
+        let events: Vec<_> = (0..10)
+            .map(|_| describe(stream.next_event(0, true).unwrap()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                // the backend's size before the first frame does not count
+                "1024x768 -> 640x480",
+                "frame 640x480",
+                "timeout",
+                "frame 640x480",
+                "640x480 -> 800x600",
+                // the held back frame comes before the next capture
+                "frame 800x600",
+                "timeout",
+                "frame 800x600",
+                "800x600 -> 640x480",
+                "frame 640x480",
+            ]
+        );
+        assert_eq!(stream.geometry(), (640, 480));
+        assert!(stream.into_inner().captures.is_empty());
+    }
+
+    #[test]
+    fn resized_synthetic_desktop() {
+        let mut stream = FrameStream::new(SyntheticBackend::new(64, 48, 1000));
+        assert_eq!(
+            describe(stream.next_event(100, true).unwrap()),
+            "frame 64x48"
+        );
+        stream.backend_mut().set_paused(true);
+        stream.backend_mut().set_size(32, 16);
+        // nothing presented yet, the old frame must not be repeated in the new size
+        assert_eq!(describe(stream.next_event(0, false).unwrap()), "timeout");
+        stream.backend_mut().set_paused(false);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            describe(stream.next_event(100, true).unwrap()),
+            "64x48 -> 32x16"
+        );
+        let Some(StreamEvent::Frame(frame)) = stream.next_event(100, true).unwrap() else {
+            panic!("expected the held back frame");
+        };
+        assert_eq!(
+            (frame.width, frame.height, frame.data.len()),
+            (32, 16, 32 * 16 * 4)
+        );
+        assert_eq!(
+            describe(stream.next_event(0, false).unwrap()),
+            "frame 32x16"
+        );
+    }
+}
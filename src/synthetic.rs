@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+
+use crate::backend::CaptureBackend;
+use crate::frame::{Frame, MoveRect, PixelFormat, Rect};
+
+const BACKGROUND: [u8; 4] = [0x30, 0x30, 0x30, 0xff];
+const BAR: [u8; 4] = [0x00, 0xc0, 0xff, 0xff];
+const BAR_WIDTH: i32 = 16;
+const BAR_STEP: i32 = 8;
+
+/// Portable capture backend producing a moving test pattern at a fixed rate.
+///
+/// Frames are "presented" every `interval` in real time, so timeouts and pacing
+/// behave like a desktop that redraws at that rate. Each frame moves a vertical bar,
+/// reported as a move rect plus the uncovered strip, or as two dirty rects when it
+/// wraps around.
+pub struct SyntheticBackend {
+    width: u32,
+    height: u32,
+    interval: Duration,
+    start: Instant,
+    presented: u64,
+    paused: bool,
+    last: Option<Frame>,
+    last_bar: Rect,
+}
+
+impl SyntheticBackend {
+    pub fn new(width: u32, height: u32, fps: u32) -> Self {
+        Self {
+            width,
+            height,
+            interval: Duration::from_secs(1) / fps.max(1),
+            start: Instant::now(),
+            presented: 0,
+            paused: false,
+            last: None,
+            last_bar: Rect::default(),
+        }
+    }
+
+    /// Number of frames presented so far.
+    pub fn frame_index(&self) -> u64 {
+        self.presented
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// While paused the "desktop" does not change and captures time out.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            // resume presenting from now rather than catching up
+            self.start = Instant::now() - self.due(self.presented);
+        }
+        self.paused = paused;
+    }
+
+    /// Changes the resolution of the "desktop", from the next frame on.
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        // a repeated frame must not have the old size either
+        self.last = None;
+    }
+
+    fn bar_rect(&self, index: u64) -> Rect {
+        let span = (self.width as i32 - BAR_WIDTH).max(1);
+        let left = (index as i64 * BAR_STEP as i64 % span as i64) as i32;
+        Rect::new(left, 0, left + BAR_WIDTH, self.height as i32)
+    }
+
+    fn render(&mut self, present_time: Duration) -> Frame {
+        let index = self.presented;
+        let bar = self.bar_rect(index);
+        let mut frame = match self.last.take() {
+            Some(last) if last.width == self.width && last.height == self.height => {
+                let mut frame = last;
+                let old = self.last_bar;
+                frame.fill_rect(old, BACKGROUND);
+                frame.fill_rect(bar, BAR);
+                if bar.left > old.left {
+                    // the bar slid right, it is moved and only the strip it left behind
+                    // is redrawn
+                    frame.move_rects = vec![MoveRect {
+                        source_x: old.left,
+                        source_y: old.top,
+                        destination: bar,
+                    }];
+                    frame.dirty_rects = vec![Rect::new(
+                        old.left,
+                        old.top,
+                        bar.left.min(old.right),
+                        old.bottom,
+                    )];
+                } else {
+                    frame.move_rects.clear();
+                    frame.dirty_rects = vec![old, bar];
+                }
+                frame
+            }
+            _ => {
+                let mut frame = Frame::new(self.width, self.height, PixelFormat::Bgra8);
+                let bounds = frame.bounds();
+                frame.fill_rect(bounds, BACKGROUND);
+                frame.fill_rect(bar, BAR);
+                frame.dirty_rects = vec![bounds];
+                frame
+            }
+        };
+        self.last_bar = bar;
+        frame.present_time = present_time;
+        self.presented += 1;
+        self.last = Some(frame.clone());
+        frame
+    }
+
+    // present time of frame `index`
+    fn due(&self, index: u64) -> Duration {
+        Duration::from_nanos((self.interval.as_nanos() * index as u128) as u64)
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+        let timeout = Duration::from_millis(timeout as u64);
+        let elapsed = self.start.elapsed();
+        if !self.paused {
+            // like desktop duplication, frames missed while nobody was capturing collapse
+            // into the newest one
+            let behind = (elapsed.as_nanos() / self.interval.as_nanos().max(1)) as u64;
+            if behind > self.presented {
+                self.presented = behind;
+            }
+            let due = self.due(self.presented);
+            if due <= elapsed {
+                return Ok(Some(self.render(due)));
+            }
+            if due - elapsed <= timeout {
+                std::thread::sleep(due - elapsed);
+                return Ok(Some(self.render(due)));
+            }
+        }
+        std::thread::sleep(timeout);
+        if skip {
+            return Ok(None);
+        }
+        Ok(self.last.clone().map(|mut frame| {
+            frame.dirty_rects.clear();
+            frame.move_rects.clear();
+            frame
+        }))
+    }
+
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+}
@@ -0,0 +1,505 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::backend::CaptureBackend;
+use crate::codec::{Codec, Packet, PacketWriter};
+use crate::frame::{Frame, Rect};
+use crate::jpeg;
+use crate::segment::{civil_from_unix, ContainerFormat};
+
+const TILE: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelapseTrigger {
+    // take a frame every interval, whether or not the desktop changed
+    Interval(Duration),
+    // take a frame once `threshold` (0.0 - 1.0) of the screen changed since the last one
+    Change {
+        threshold: f32,
+        min_interval: Duration,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelapseConfig {
+    pub trigger: TimelapseTrigger,
+    // rate the taken frames are played back at
+    pub playback_fps: u32,
+    pub timestamp_overlay: bool,
+    // capture timeout in milliseconds, as passed to `CaptureBackend::capture`
+    pub timeout: u32,
+}
+
+impl TimelapseConfig {
+    pub fn new(trigger: TimelapseTrigger) -> Self {
+        Self {
+            trigger,
+            playback_fps: 30,
+            timestamp_overlay: false,
+            timeout: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelapseFrame {
+    pub frame: Frame,
+    // position in the timelapse video, frames are spaced 1 / playback_fps apart
+    pub pts: Duration,
+    pub captured_at: SystemTime,
+}
+
+/// Timelapse on top of a capture backend.
+///
+/// `Interval` captures with `skip == false`, so an unchanged desktop still yields a frame.
+/// `Change` captures with `skip == true` and accumulates the changed area, from the
+/// frame's dirty rects or a tile diff when it has none, until it reaches the threshold.
+/// The returned frames are restamped for playback, `TimelapseRecorder` writes them into
+/// a video.
+pub struct Timelapse<B: CaptureBackend> {
+    backend: B,
+    config: TimelapseConfig,
+    previous: Option<Frame>,
+    changed: Vec<bool>,
+    last_taken: Option<Instant>,
+    taken: u64,
+}
+
+impl<B: CaptureBackend> Timelapse<B> {
+    pub fn new(backend: B, config: TimelapseConfig) -> Self {
+        Self {
+            backend,
+            config,
+            previous: None,
+            changed: Vec::new(),
+            last_taken: None,
+            taken: 0,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+
+    /// Number of frames taken so far.
+    pub fn taken(&self) -> u64 {
+        self.taken
+    }
+
+    /// Waits at most about `timeout` for the next timelapse frame.
+    pub fn poll(&mut self) -> anyhow::Result<Option<TimelapseFrame>> {
+        let timeout = self.config.timeout;
+        match self.config.trigger {
+            TimelapseTrigger::Interval(interval) => {
+                if let Some(remaining) = self.remaining(interval) {
+                    std::thread::sleep(remaining.min(Duration::from_millis(timeout as u64)));
+                    return Ok(None);
+                }
+                match self.backend.capture(timeout, false)? {
+                    Some(frame) => Ok(Some(self.take(frame))),
+                    None => Ok(None),
+                }
+            }
+            TimelapseTrigger::Change {
+                threshold,
+                min_interval,
+            } => {
+                let Some(frame) = self.backend.capture(timeout, true)? else {
+                    return Ok(None);
+                };
+                let fraction = self.accumulate(&frame);
+                self.previous = Some(frame);
+                if fraction < threshold || self.remaining(min_interval).is_some() {
+                    return Ok(None);
+                }
+                let frame = self.previous.clone().unwrap();
+                Ok(Some(self.take(frame)))
+            }
+        }
+    }
+
+    fn remaining(&self, interval: Duration) -> Option<Duration> {
+        let elapsed = self.last_taken?.elapsed();
+        interval.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+
+    // marks the tiles changed by `frame`, returns the changed fraction since the last take
+    fn accumulate(&mut self, frame: &Frame) -> f32 {
+        let tiles_x = frame.width.div_ceil(TILE) as usize;
+        let tiles_y = frame.height.div_ceil(TILE) as usize;
+        let same_geometry = self
+            .previous
+            .as_ref()
+            .is_some_and(|p| p.width == frame.width && p.height == frame.height);
+        if !same_geometry || self.changed.len() != tiles_x * tiles_y {
+            self.changed = vec![true; tiles_x * tiles_y];
+        } else if !frame.dirty_rects.is_empty() || !frame.move_rects.is_empty() {
+            for rect in &frame.changed_rects() {
+                let rect = rect.intersect(&frame.bounds());
+                if rect.is_empty() {
+                    continue;
+                }
+                for ty in rect.top as u32 / TILE..(rect.bottom as u32).div_ceil(TILE) {
+                    for tx in rect.left as u32 / TILE..(rect.right as u32).div_ceil(TILE) {
+                        self.changed[ty as usize * tiles_x + tx as usize] = true;
+                    }
+                }
+            }
+        } else if let Some(tiles) = frame.changed_tiles(self.previous.as_ref().unwrap(), TILE) {
+            for (changed, tile) in self.changed.iter_mut().zip(tiles) {
+                *changed |= tile;
+            }
+        }
+        let count = self.changed.iter().filter(|c| **c).count();
+        count as f32 / self.changed.len().max(1) as f32
+    }
+
+    fn take(&mut self, mut frame: Frame) -> TimelapseFrame {
+        let captured_at = SystemTime::now();
+        self.changed.iter_mut().for_each(|c| *c = false);
+        self.last_taken = Some(Instant::now());
+        let pts = Duration::from_secs(self.taken) / self.config.playback_fps.max(1);
+        self.taken += 1;
+        if self.config.timestamp_overlay {
+            draw_timestamp(&mut frame, captured_at);
+        }
+        TimelapseFrame {
+            frame,
+            pts,
+            captured_at,
+        }
+    }
+}
+
+/// Turns timelapse frames into packets for a `PacketWriter`.
+pub trait FrameEncoder {
+    fn codec(&self) -> Codec;
+
+    fn encode(&mut self, frame: &Frame, pts: Duration) -> anyhow::Result<Packet>;
+}
+
+/// Motion JPEG with the crate's JPEG encoder, so a timelapse needs no hardware encoder.
+/// Matroska and IVF can hold it, MP4 cannot.
+#[derive(Debug, Clone, Copy)]
+pub struct MjpegEncoder {
+    // 1 - 100
+    pub quality: u8,
+}
+
+impl Default for MjpegEncoder {
+    fn default() -> Self {
+        Self { quality: 85 }
+    }
+}
+
+impl FrameEncoder for MjpegEncoder {
+    fn codec(&self) -> Codec {
+        Codec::MJPEG
+    }
+
+    fn encode(&mut self, frame: &Frame, pts: Duration) -> anyhow::Result<Packet> {
+        Ok(Packet::new(
+            Codec::MJPEG,
+            jpeg::encode(frame, self.quality),
+            pts,
+        ))
+    }
+}
+
+/// Writes the frames a `Timelapse` takes into a video, at the playback timestamps.
+///
+/// ```
+/// use std::time::Duration;
+/// use dxgi::codec::PacketWriter;
+/// use dxgi::synthetic::SyntheticBackend;
+/// use dxgi::timelapse::*;
+///
+/// struct Count(u32);
+/// impl PacketWriter for Count {
+///     fn write_packet(&mut self, _: &dxgi::codec::Packet) -> std::io::Result<()> {
+///         self.0 += 1;
+///         Ok(())
+///     }
+///     fn close(&mut self) -> std::io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let mut config = TimelapseConfig::new(TimelapseTrigger::Interval(Duration::ZERO));
+/// config.playback_fps = 10;
+/// let timelapse = Timelapse::new(SyntheticBackend::new(64, 48, 100), config);
+/// let mut recorder =
+///     TimelapseRecorder::new(timelapse, MjpegEncoder::default(), Box::new(Count(0)));
+/// while recorder.timelapse().taken() < 3 {
+///     recorder.poll().unwrap();
+/// }
+/// assert_eq!(recorder.finish().unwrap(), 3);
+/// ```
+pub struct TimelapseRecorder<B: CaptureBackend, E: FrameEncoder = MjpegEncoder> {
+    timelapse: Timelapse<B>,
+    encoder: E,
+    writer: Box<dyn PacketWriter>,
+}
+
+impl<B: CaptureBackend, E: FrameEncoder> TimelapseRecorder<B, E> {
+    pub fn new(timelapse: Timelapse<B>, encoder: E, writer: Box<dyn PacketWriter>) -> Self {
+        Self {
+            timelapse,
+            encoder,
+            writer,
+        }
+    }
+
+    /// Records into a new file at `path`, sized like the backend's desktop.
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: ContainerFormat,
+        timelapse: Timelapse<B>,
+        encoder: E,
+    ) -> io::Result<Self> {
+        let backend = timelapse.backend();
+        let (width, height) = (backend.width(), backend.height());
+        let file = BufWriter::new(File::create(path)?);
+        let writer = format.create(file, encoder.codec(), width, height)?;
+        Ok(Self::new(timelapse, encoder, writer))
+    }
+
+    pub fn timelapse(&self) -> &Timelapse<B> {
+        &self.timelapse
+    }
+
+    pub fn timelapse_mut(&mut self) -> &mut Timelapse<B> {
+        &mut self.timelapse
+    }
+
+    /// Like `Timelapse::poll`, a frame taken is also encoded and written.
+    pub fn poll(&mut self) -> anyhow::Result<Option<TimelapseFrame>> {
+        let Some(taken) = self.timelapse.poll()? else {
+            return Ok(None);
+        };
+        let packet = self.encoder.encode(&taken.frame, taken.pts)?;
+        self.writer.write_packet(&packet)?;
+        Ok(Some(taken))
+    }
+
+    /// Closes the video, returns the number of frames in it.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.writer.close()?;
+        Ok(self.timelapse.taken())
+    }
+}
+
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 7;
+const SCALE: i32 = 2;
+const MARGIN: i32 = 4;
+
+// 5x7 bitmaps, one byte per row with the leftmost pixel in bit 4
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        _ => [0; 7],
+    }
+}
+
+/// Burns `text` into the top-left corner of a BGRA frame, white on black.
+/// Only digits, '-', ':' and spaces are drawn.
+pub fn draw_text(frame: &mut Frame, text: &str) {
+    let advance = (GLYPH_WIDTH + 1) * SCALE;
+    let width = text.chars().count() as i32 * advance + MARGIN * 2;
+    let height = GLYPH_HEIGHT * SCALE + MARGIN * 2;
+    frame.fill_rect(Rect::new(0, 0, width, height), [0, 0, 0, 0xff]);
+    for (i, c) in text.chars().enumerate() {
+        let x0 = MARGIN + i as i32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0x10 >> col) == 0 {
+                    continue;
+                }
+                let x = x0 + col * SCALE;
+                let y = MARGIN + row as i32 * SCALE;
+                frame.fill_rect(Rect::new(x, y, x + SCALE, y + SCALE), [0xff; 4]);
+            }
+        }
+    }
+}
+
+fn draw_timestamp(frame: &mut Frame, time: SystemTime) {
+    let unix = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day, hour, minute, second) = civil_from_unix(unix);
+    let text = format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year, month, day, hour, minute, second
+    );
+    draw_text(frame, &text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+    use crate::ivf::IvfReader;
+    use crate::synthetic::SyntheticBackend;
+
+    // every capture presents a frame with the given dirty rects
+    struct Scripted {
+        rects: Vec<Vec<Rect>>,
+    }
+
+    impl CaptureBackend for Scripted {
+        fn capture(&mut self, _: u32, _: bool) -> anyhow::Result<Option<Frame>> {
+            let mut frame = Frame::new(128, 128, PixelFormat::Bgra8);
+            frame.dirty_rects = self.rects.remove(0);
+            Ok(Some(frame))
+        }
+
+        fn width(&self) -> u32 {
+            128
+        }
+
+        fn height(&self) -> u32 {
+            128
+        }
+    }
+
+    #[test]
+    fn interval_sampling() {
+        let interval = Duration::from_millis(30);
+        let mut config = TimelapseConfig::new(TimelapseTrigger::Interval(interval));
+        config.timeout = 5;
+        config.playback_fps = 25;
+        // only presents every 100 ms, the interval still takes frames
+        let mut timelapse = Timelapse::new(SyntheticBackend::new(64, 48, 10), config);
+        let mut taken = Vec::new();
+        let start = Instant::now();
+        while taken.len() < 4 {
+            taken.extend(timelapse.poll().unwrap());
+        }
+        assert!(start.elapsed() >= interval * 3);
+        let pts: Vec<_> = taken.iter().map(|t| t.pts.as_millis()).collect();
+        assert_eq!(pts, [0, 40, 80, 120]);
+        for pair in taken.windows(2) {
+            let between = pair[1].captured_at.duration_since(pair[0].captured_at);
+            assert!(between.unwrap() >= interval - Duration::from_millis(1));
+        }
+        assert_eq!(timelapse.taken(), 4);
+    }
+
+    #[test]
+    fn change_threshold() {
+        // 16 tiles of 32x32, each capture changes two of them
+        let column = |x| vec![Rect::new(x * 32, 0, x * 32 + 32, 64)];
+        let rects = vec![
+            vec![],
+            column(0),
+            column(0),
+            column(1),
+            column(2),
+            column(3),
+        ];
+        let trigger = TimelapseTrigger::Change {
+            threshold: 0.375,
+            min_interval: Duration::ZERO,
+        };
+        let mut timelapse = Timelapse::new(Scripted { rects }, TimelapseConfig::new(trigger));
+        // the first frame is all new
+        assert!(timelapse.poll().unwrap().is_some());
+        // the same two tiles twice, then 4 and 6 of 16
+        let taken: Vec<_> = (0..5)
+            .map(|_| timelapse.poll().unwrap().is_some())
+            .collect();
+        assert_eq!(taken, [false, false, false, true, false]);
+        assert_eq!(timelapse.taken(), 2);
+    }
+
+    #[test]
+    fn timestamp_overlay() {
+        let mut config = TimelapseConfig::new(TimelapseTrigger::Interval(Duration::ZERO));
+        config.timestamp_overlay = true;
+        let mut backend = SyntheticBackend::new(320, 64, 100);
+        let plain = backend.capture(100, false).unwrap().unwrap();
+        let mut timelapse = Timelapse::new(backend, config);
+        let frame = loop {
+            if let Some(taken) = timelapse.poll().unwrap() {
+                break taken.frame;
+            }
+        };
+        // black box behind white digits in the corner, the rest untouched
+        assert_eq!(frame.row(0)[..4], [0, 0, 0, 0xff]);
+        assert!(frame
+            .row(4 + 2 * 2)
+            .chunks(4)
+            .take(50)
+            .any(|p| p == [0xff; 4]));
+        assert_eq!(frame.row(63)[1000..], plain.row(63)[1000..]);
+    }
+
+    #[test]
+    fn recorder_writes_a_video() {
+        let path = std::env::temp_dir().join(format!("dxgi-timelapse-{}.ivf", std::process::id()));
+        let mut config = TimelapseConfig::new(TimelapseTrigger::Interval(Duration::from_millis(5)));
+        config.playback_fps = 30;
+        let timelapse = Timelapse::new(SyntheticBackend::new(64, 48, 200), config);
+        let mut recorder = TimelapseRecorder::create(
+            &path,
+            ContainerFormat::Ivf,
+            timelapse,
+            MjpegEncoder::default(),
+        )
+        .unwrap();
+        while recorder.timelapse().taken() < 5 {
+            recorder.poll().unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), 5);
+
+        let reader = IvfReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.header().codec, Codec::MJPEG);
+        assert_eq!((reader.header().width, reader.header().height), (64, 48));
+        assert_eq!(reader.header().frame_count, 5);
+        let packets: Vec<_> = reader.map(Result::unwrap).collect();
+        // played back at 30 fps however far apart they were taken
+        let pts: Vec<_> = packets.iter().map(|p| p.pts.as_millis()).collect();
+        assert_eq!(pts, [0, 33, 66, 100, 133]);
+        assert!(packets
+            .iter()
+            .all(|p| p.keyframe && p.data.ends_with(&[0xff, 0xd9])));
+        std::fs::remove_file(&path).unwrap();
+
+        let timelapse = Timelapse::new(
+            SyntheticBackend::new(64, 48, 200),
+            TimelapseConfig::new(TimelapseTrigger::Interval(Duration::ZERO)),
+        );
+        let mp4 = TimelapseRecorder::create(
+            &path,
+            ContainerFormat::Mp4,
+            timelapse,
+            MjpegEncoder::default(),
+        );
+        assert_eq!(mp4.err().unwrap().kind(), io::ErrorKind::Unsupported);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,420 @@
+//! Display topology changes, computed by diffing successive output enumerations.
+//!
+//! DXGI only reports a changed desktop through `DXGI_ERROR_ACCESS_LOST` on the next
+//! capture. Polling `utils::get_outputs_desc` and diffing the snapshots tells which
+//! output changed and how.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::OutputDesc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopologyEvent {
+    // attached to the desktop, or moved to another adapter
+    Added(OutputDesc),
+    // unplugged or detached from the desktop
+    Removed(OutputDesc),
+    // position or size in the virtual desktop, or bits per color
+    ModeChanged { old: OutputDesc, new: OutputDesc },
+    RotationChanged { old: OutputDesc, new: OutputDesc },
+    HdrChanged { old: OutputDesc, new: OutputDesc },
+}
+
+impl TopologyEvent {
+    /// The output as it is now, or as it was before it was removed.
+    pub fn output(&self) -> &OutputDesc {
+        match self {
+            TopologyEvent::Added(output) | TopologyEvent::Removed(output) => output,
+            TopologyEvent::ModeChanged { new, .. }
+            | TopologyEvent::RotationChanged { new, .. }
+            | TopologyEvent::HdrChanged { new, .. } => new,
+        }
+    }
+}
+
+fn same_output(a: &OutputDesc, b: &OutputDesc) -> bool {
+    a.adapter_luid == b.adapter_luid && a.device_name == b.device_name
+}
+
+/// The events that turn `old` into `new`.
+///
+/// Only outputs attached to the desktop count. Outputs are matched by adapter and
+/// device name, so an output that moves to another adapter is removed and added again.
+/// Removals come first, then the other events in the order of `new`. A rotation
+/// usually swaps width and height too and then also reports a mode change.
+///
+/// ```
+/// use dxgi::frame::Rect;
+/// use dxgi::topology::{diff, TopologyEvent};
+/// use dxgi::{ColorSpace, Luid, OutputDesc, Rotation};
+///
+/// let output = |name: &str, rect| OutputDesc {
+///     adapter_luid: Luid(1),
+///     device_name: name.to_string(),
+///     desktop_coordinates: rect,
+///     attached_to_desktop: true,
+///     ..Default::default()
+/// };
+/// let left = output(r"\\.\DISPLAY1", Rect::new(0, 0, 1920, 1080));
+/// let right = output(r"\\.\DISPLAY2", Rect::new(1920, 0, 3840, 1080));
+/// let before = vec![left.clone(), right.clone()];
+/// assert!(diff(&before, &before).is_empty());
+///
+/// // unplugging and plugging in
+/// assert_eq!(diff(&before, &before[..1]), [TopologyEvent::Removed(right.clone())]);
+/// assert_eq!(diff(&before[..1], &before), [TopologyEvent::Added(right.clone())]);
+/// let mut detached = before.clone();
+/// detached[1].attached_to_desktop = false;
+/// assert_eq!(diff(&before, &detached), [TopologyEvent::Removed(right.clone())]);
+///
+/// // resolution change, rotation and HDR
+/// let mut after = before.clone();
+/// after[0].desktop_coordinates = Rect::new(0, 0, 2560, 1440);
+/// after[1].rotation = Rotation::Rotate90;
+/// after[1].color_space = ColorSpace::Hdr10;
+/// assert_eq!(
+///     diff(&before, &after),
+///     [
+///         TopologyEvent::ModeChanged { old: left.clone(), new: after[0].clone() },
+///         TopologyEvent::RotationChanged { old: right.clone(), new: after[1].clone() },
+///         TopologyEvent::HdrChanged { old: right.clone(), new: after[1].clone() },
+///     ]
+/// );
+///
+/// // the same monitor on another adapter
+/// let mut moved = before.clone();
+/// moved[0].adapter_luid = Luid(2);
+/// let events = diff(&before, &moved);
+/// assert_eq!(events[0], TopologyEvent::Removed(left.clone()));
+/// assert_eq!(events[1], TopologyEvent::Added(moved[0].clone()));
+/// ```
+pub fn diff(old: &[OutputDesc], new: &[OutputDesc]) -> Vec<TopologyEvent> {
+    let old: Vec<_> = old.iter().filter(|o| o.attached_to_desktop).collect();
+    let new: Vec<_> = new.iter().filter(|o| o.attached_to_desktop).collect();
+    let mut events = Vec::new();
+    for &output in &old {
+        if !new.iter().any(|n| same_output(output, n)) {
+            events.push(TopologyEvent::Removed(output.clone()));
+        }
+    }
+    for &output in &new {
+        let Some(before) = old.iter().copied().find(|o| same_output(o, output)) else {
+            events.push(TopologyEvent::Added(output.clone()));
+            continue;
+        };
+        let (old, new) = (before.clone(), output.clone());
+        if before.desktop_coordinates != output.desktop_coordinates
+            || before.bits_per_color != output.bits_per_color
+        {
+            events.push(TopologyEvent::ModeChanged {
+                old: old.clone(),
+                new: new.clone(),
+            });
+        }
+        if before.rotation != output.rotation {
+            events.push(TopologyEvent::RotationChanged {
+                old: old.clone(),
+                new: new.clone(),
+            });
+        }
+        if before.is_hdr() != output.is_hdr() {
+            events.push(TopologyEvent::HdrChanged { old, new });
+        }
+    }
+    events
+}
+
+/// Remembers the last snapshot and reports what changed since.
+#[derive(Debug, Clone, Default)]
+pub struct TopologyWatcher {
+    outputs: Vec<OutputDesc>,
+}
+
+impl TopologyWatcher {
+    pub fn new(outputs: Vec<OutputDesc>) -> Self {
+        Self { outputs }
+    }
+
+    /// The outputs of the last snapshot.
+    pub fn outputs(&self) -> &[OutputDesc] {
+        &self.outputs
+    }
+
+    pub fn update(&mut self, outputs: Vec<OutputDesc>) -> Vec<TopologyEvent> {
+        let events = diff(&self.outputs, &outputs);
+        self.outputs = outputs;
+        events
+    }
+
+    /// Watches the outputs of this machine, starting from the current ones.
+    #[cfg(windows)]
+    pub fn desktop() -> Option<Self> {
+        crate::utils::get_outputs_desc().map(Self::new)
+    }
+
+    /// Enumerates the outputs again. `None` if the enumeration failed, the last
+    /// snapshot is kept then.
+    #[cfg(windows)]
+    pub fn poll(&mut self) -> Option<Vec<TopologyEvent>> {
+        crate::utils::get_outputs_desc().map(|outputs| self.update(outputs))
+    }
+}
+
+/// Events from `watch`. Dropping it stops the polling thread and waits for the
+/// snapshot in progress.
+pub struct TopologyEvents {
+    receiver: Receiver<TopologyEvent>,
+    // never sent on, dropping it wakes the thread up to stop
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TopologyEvents {
+    /// Waits for the next event. `None` on timeout.
+    pub fn recv(&self, timeout: Option<Duration>) -> Option<TopologyEvent> {
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            None => self.receiver.recv().ok(),
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<TopologyEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Blocks for every event in turn.
+    pub fn iter(&self) -> mpsc::Iter<'_, TopologyEvent> {
+        self.receiver.iter()
+    }
+
+    /// Stops polling and waits for the thread.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TopologyEvents {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Calls `snapshot` every `interval` on a background thread and sends the events.
+///
+/// The first snapshot is the baseline and produces no events. Failed snapshots are
+/// skipped. The thread runs until the returned `TopologyEvents` is dropped.
+///
+/// ```
+/// use std::time::Duration;
+/// use dxgi::topology::{watch, TopologyEvent};
+/// use dxgi::OutputDesc;
+///
+/// let monitor = OutputDesc {
+///     device_name: r"\\.\DISPLAY1".to_string(),
+///     attached_to_desktop: true,
+///     ..Default::default()
+/// };
+/// let mut polls = 0;
+/// let events = watch(Duration::from_millis(1), move || {
+///     polls += 1;
+///     Some(if polls < 3 { vec![] } else { vec![monitor.clone()] })
+/// })
+/// .unwrap();
+/// match events.recv(None).unwrap() {
+///     TopologyEvent::Added(output) => assert_eq!(output.device_name, r"\\.\DISPLAY1"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+pub fn watch<F>(interval: Duration, mut snapshot: F) -> std::io::Result<TopologyEvents>
+where
+    F: FnMut() -> Option<Vec<OutputDesc>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = std::thread::Builder::new()
+        .name("topology-watch".to_string())
+        .spawn(move || {
+            let mut watcher = None;
+            loop {
+                if let Some(outputs) = snapshot() {
+                    match &mut watcher {
+                        None => watcher = Some(TopologyWatcher::new(outputs)),
+                        Some(watcher) => {
+                            for event in watcher.update(outputs) {
+                                if sender.send(event).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+                if stopped.recv_timeout(interval) != Err(RecvTimeoutError::Timeout) {
+                    return;
+                }
+            }
+        })?;
+    Ok(TopologyEvents {
+        receiver,
+        stop: Some(stop),
+        thread: Some(thread),
+    })
+}
+
+/// `watch` on the outputs of this machine.
+#[cfg(windows)]
+pub fn watch_desktop(interval: Duration) -> std::io::Result<TopologyEvents> {
+    watch(interval, crate::utils::get_outputs_desc)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::frame::Rect;
+    use crate::{ColorSpace, Luid, Rotation};
+
+    fn output(name: &str, rect: Rect) -> OutputDesc {
+        OutputDesc {
+            adapter_luid: Luid(1),
+            device_name: name.to_string(),
+            desktop_coordinates: rect,
+            attached_to_desktop: true,
+            bits_per_color: 8,
+            ..Default::default()
+        }
+    }
+
+    fn kinds(events: &[TopologyEvent]) -> Vec<&'static str> {
+        events
+            .iter()
+            .map(|event| match event {
+                TopologyEvent::Added(_) => "added",
+                TopologyEvent::Removed(_) => "removed",
+                TopologyEvent::ModeChanged { .. } => "mode",
+                TopologyEvent::RotationChanged { .. } => "rotation",
+                TopologyEvent::HdrChanged { .. } => "hdr",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn watcher_sequence() {
+        let laptop = output(r"\\.\DISPLAY1", Rect::new(0, 0, 1920, 1200));
+        let external = output(r"\\.\DISPLAY2", Rect::new(1920, 0, 4480, 1440));
+        let mut watcher = TopologyWatcher::new(vec![laptop.clone()]);
+        assert!(watcher.update(vec![laptop.clone()]).is_empty());
+
+        let events = watcher.update(vec![laptop.clone(), external.clone()]);
+        assert_eq!(events, [TopologyEvent::Added(external.clone())]);
+        assert_eq!(watcher.outputs().len(), 2);
+
+        // turning the external monitor to portrait swaps its size too
+        let mut portrait = external.clone();
+        portrait.rotation = Rotation::Rotate90;
+        portrait.desktop_coordinates = Rect::new(1920, 0, 3360, 2560);
+        let events = watcher.update(vec![laptop.clone(), portrait.clone()]);
+        assert_eq!(kinds(&events), ["mode", "rotation"]);
+        assert!(events.iter().all(|event| event.output() == &portrait));
+
+        // a deeper format is a mode change, a non-HDR color space is no HDR change
+        let mut deep = portrait.clone();
+        deep.bits_per_color = 10;
+        deep.color_space = ColorSpace::ScRgb;
+        let events = watcher.update(vec![laptop.clone(), deep.clone()]);
+        assert_eq!(kinds(&events), ["mode"]);
+        let mut hdr = deep.clone();
+        hdr.color_space = ColorSpace::Hdr10;
+        hdr.max_luminance = 600.0;
+        let events = watcher.update(vec![laptop.clone(), hdr.clone()]);
+        assert_eq!(
+            events,
+            [TopologyEvent::HdrChanged {
+                old: deep.clone(),
+                new: hdr.clone()
+            }]
+        );
+
+        // removals come first even when listed after the other changes
+        let mut moved = laptop.clone();
+        moved.desktop_coordinates = Rect::new(-1920, 0, 0, 1200);
+        let events = watcher.update(vec![moved.clone()]);
+        assert_eq!(kinds(&events), ["removed", "mode"]);
+        assert_eq!(events[0].output(), &hdr);
+        assert_eq!(events[1].output(), &moved);
+        assert_eq!(watcher.outputs(), [moved]);
+    }
+
+    #[test]
+    fn watch_skips_failed_snapshots_and_stops() {
+        let display = output(r"\\.\DISPLAY1", Rect::new(0, 0, 1920, 1080));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let events = watch(Duration::from_millis(1), move || {
+            let poll = counter.fetch_add(1, Ordering::SeqCst);
+            // fails every other time, which must not look like an unplug
+            match poll % 4 {
+                0 | 2 => None,
+                1 => Some(vec![]),
+                _ => Some(vec![display.clone()]),
+            }
+        })
+        .unwrap();
+        let received: Vec<_> = events.iter().take(3).collect();
+        assert_eq!(kinds(&received), ["added", "removed", "added"]);
+
+        // joins the thread, so there is no poll after this
+        events.stop();
+        let stopped = polls.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(polls.load(Ordering::SeqCst), stopped);
+    }
+
+    #[test]
+    fn dropping_the_events_stops_a_quiet_watch() {
+        let display = output(r"\\.\DISPLAY1", Rect::new(0, 0, 1920, 1080));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        // a long interval, the drop must not wait for it
+        let events = watch(Duration::from_secs(60), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(vec![display.clone()])
+        })
+        .unwrap();
+        while polls.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+        assert!(events.try_recv().is_none());
+        let start = std::time::Instant::now();
+        drop(events);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+
+        // and with a short one, a stable desktop never sends an event to fail on
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let display = output(r"\\.\DISPLAY1", Rect::new(0, 0, 1920, 1080));
+        let events = watch(Duration::from_millis(1), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Some(vec![display.clone()])
+        })
+        .unwrap();
+        while polls.load(Ordering::SeqCst) < 5 {
+            std::thread::yield_now();
+        }
+        drop(events);
+        let stopped = polls.load(Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(polls.load(Ordering::SeqCst), stopped);
+    }
+}
@@ -8,7 +8,8 @@ use windows::Win32::Graphics::Direct3D::{
     D3D_FEATURE_LEVEL_11_1,
 };
 
-use windows::Win32::Graphics::Dxgi::{IDXGIFactory4, DXGI_CREATE_FACTORY_FLAGS};
+use windows::Win32::Graphics::Dxgi::DXGI_CREATE_FACTORY_FLAGS;
+use windows::Win32::System::Performance::QueryPerformanceFrequency;
 use windows::Win32::System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED};
 
 use windows::Win32::System::Com::{CoInitializeEx, COINIT_MULTITHREADED};
@@ -29,9 +30,8 @@ use windows::{
                 D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_CREATE_DEVICE_FLAG, D3D11_SDK_VERSION,
             },
             Dxgi::{
-                CreateDXGIFactory2, IDXGIAdapter1, IDXGIDevice, IDXGIFactory7,
-                IDXGIOutput6, DXGI_ADAPTER_DESC1, DXGI_ADAPTER_FLAG, DXGI_ADAPTER_FLAG_NONE,
-                DXGI_ADAPTER_FLAG_SOFTWARE, DXGI_ERROR_UNSUPPORTED, DXGI_OUTDUPL_DESC,
+                CreateDXGIFactory2, IDXGIAdapter1, IDXGIDevice, IDXGIFactory1, IDXGIFactory7,
+                IDXGIOutput6, DXGI_ADAPTER_DESC1, DXGI_ERROR_UNSUPPORTED, DXGI_OUTDUPL_DESC,
                 DXGI_OUTPUT_DESC,
             },
         },
@@ -41,7 +41,8 @@ use windows::{
     },
 };
 
-use crate::{AdapterDesc, Luid};
+use crate::frame::Rect;
+use crate::{AdapterDesc, ColorSpace, Luid, OutputDesc, RawAdapterDesc, Rotation};
 
 use crate::OutputDuplication;
 
@@ -97,6 +98,17 @@ fn _co_init() {
     }
 }
 
+pub fn qpc_to_duration(ticks: i64) -> std::time::Duration {
+    static FREQUENCY: std::sync::OnceLock<i64> = std::sync::OnceLock::new();
+    let frequency = *FREQUENCY.get_or_init(|| {
+        let mut frequency = 0;
+        unsafe { QueryPerformanceFrequency(&mut frequency).ok() };
+        frequency.max(1)
+    });
+    let ticks = ticks.max(0) as u128;
+    std::time::Duration::from_nanos((ticks * 1_000_000_000 / frequency as u128) as u64)
+}
+
 fn find_terminal_idx(content: &[u16]) -> usize {
     for (i, val) in content.iter().enumerate() {
         if *val == 0 {
@@ -150,395 +162,260 @@ pub(crate) fn get_d3d_interface_from_object<S: Interface, R: Interface>(object:
     Ok(object)
 }
 
-pub fn get_hardware_adapters_desc() -> Option<Vec<AdapterDesc>> {
-    let factory = unsafe {
-        match CreateDXGIFactory2::<IDXGIFactory7>(DXGI_CREATE_FACTORY_FLAGS(0u32)) {
-            Ok(factory) => factory,
-            Err(e) => {
-                log::debug!("factory2 init fail: {:?}", e);
-                return None;
-            }
+fn create_factory() -> Option<IDXGIFactory7> {
+    match unsafe { CreateDXGIFactory2::<IDXGIFactory7>(DXGI_CREATE_FACTORY_FLAGS(0u32)) } {
+        Ok(factory) => Some(factory),
+        Err(e) => {
+            log::debug!("factory2 init fail: {:?}", e);
+            None
         }
-    };
+    }
+}
 
-    // --- Enumerate adapters
-    let mut adapters = Vec::new();
-    unsafe {
-        let mut i = 0;
-        while let Ok(adapter) = factory.EnumAdapters1(i) {
-            adapters.push(adapter);
-            i += 1;
+fn raw_adapter_desc(adapter: &IDXGIAdapter1) -> Option<RawAdapterDesc> {
+    let desc: DXGI_ADAPTER_DESC1 = match unsafe { adapter.GetDesc1() } {
+        Ok(desc) => desc,
+        Err(e) => {
+            log::debug!("adapters1 GetDesc1 fail: {:?}", e);
+            return None;
         }
     };
+    Some(RawAdapterDesc {
+        description: convert_u16_to_string(desc.Description.as_ref()),
+        vendor_id: desc.VendorId,
+        device_id: desc.DeviceId,
+        dedicated_video_memory: desc.DedicatedVideoMemory as u64,
+        dedicated_system_memory: desc.DedicatedSystemMemory as u64,
+        shared_system_memory: desc.SharedSystemMemory as u64,
+        luid: desc.AdapterLuid.into(),
+        flags: desc.Flags,
+    })
+}
 
-    let mut adapters_desc = Vec::new();
+/// Every adapter, software ones included, with its description in enumeration order.
+/// An adapter that cannot be described is left out.
+fn enumerate_adapters() -> Option<Vec<(IDXGIAdapter1, AdapterDesc)>> {
+    let factory = create_factory()?;
+    let mut adapters = Vec::new();
     let mut i = 0;
-    for adapter in adapters {
-        unsafe {
-            match adapter.GetDesc1() {
-                Ok(adapter_desc) => {
-                    let adapter_flag = DXGI_ADAPTER_FLAG(adapter_desc.Flags as _);
-
-                    let adapter_name = convert_u16_to_string(adapter_desc.Description.as_ref());
-                    // let _adapter_dedicated_video_memory = adapter_desc.DedicatedVideoMemory;
-                    let luid = adapter_desc.AdapterLuid;
-                    // let high_part = adapter_desc.AdapterLuid.HighPart;
-                    // let low_part = adapter_desc.AdapterLuid.LowPart;
-                    let vendor_id = adapter_desc.VendorId;
-                    let device_id = adapter_desc.DeviceId;
-
-                    let is_integrated =
-                        vendor_id == 0x8086 && (device_id == 0x163c || device_id == 0x1616);
-                    let is_discrete = !is_integrated;
-                    let is_default = false;
-                    let mut is_software = false;
-
-                    // desc.VendorId == 0x10DE  NVIDIA
-                    // desc.VendorId == 0x1002 || 0x1022  AMD
-                    // desc.VendorId == 0x8086) 0x163C, 0x8087 Intel
-                    // Skip the software adaptor.
-                    if (vendor_id == 0x1414) && (device_id == 0x8c) {
-                        // log::debug!("is Microsoft Basic Render Driver");
-                        is_software = true;
-                    }
-                    if (adapter_flag & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE {
-                        // log::debug!("Skip Software Adapter");
-                        is_software = true;
-                    }
-
-                    let is_hardware = !is_software;
-
-                    // Print some info about the adapter.
-                    // log::debug!(
-                    //     "===> DXGI Adapter: [{}], LUID=[{:?}] -> VendorId[{:x}] DeviceId[{:x}] with {} memory",
-                    //     adapter_name,
-                    //     luid,
-                    //     vendor_id,
-                    //     device_id,
-                    //     adapter_dedicated_video_memory
-                    // );
-
-                    let adapter_desc = AdapterDesc {
-                        index: i,
-                        description: adapter_name.to_string(),
-                        luid: luid.into(),
-                        device_id,
-                        vendor_id,
-                        is_default,
-                        is_software,
-                        is_hardware,
-                        is_discrete,
-                        is_integrated,
-                    };
-
-                    // log::debug!(
-                    //     "===> DXGI {} with {} memory",
-                    //     adapter_desc,
-                    //     adapter_dedicated_video_memory
-                    // );
-
-                    adapters_desc.push(adapter_desc);
-                }
-                Err(e) => {
-                    log::debug!("adapters1 GetDesc1 fail: {:?}", e);
-                    return None;
-                }
-            }
-        };
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(i) } {
+        let index = i;
         i += 1;
+        let Some(raw) = raw_adapter_desc(&adapter) else {
+            continue;
+        };
+        let adapter_desc = AdapterDesc::from_raw(&raw, index, index == 0);
+        log::debug!(
+            "===> DXGI{} with {} memory",
+            adapter_desc,
+            adapter_desc.dedicated_video_memory
+        );
+        adapters.push((adapter, adapter_desc));
     }
-    if !adapters_desc.is_empty() {
-        return Some(adapters_desc);
+    Some(adapters)
+}
+
+/// Every adapter, software ones such as WARP included.
+pub fn get_adapters_desc() -> Option<Vec<AdapterDesc>> {
+    let adapters = enumerate_adapters()?;
+    Some(adapters.into_iter().map(|(_, desc)| desc).collect())
+}
+
+/// The hardware adapters, `None` when there are none.
+pub fn get_hardware_adapters_desc() -> Option<Vec<AdapterDesc>> {
+    let adapters: Vec<_> = get_adapters_desc()?
+        .into_iter()
+        .filter(|desc| !desc.is_software)
+        .collect();
+    if adapters.is_empty() {
+        return None;
     }
-    None
+    Some(adapters)
 }
 
+/// Describes `adapter`, finding its index through the factory that enumerated it.
 pub fn get_hardware_adapter_desc(adapter: &IDXGIAdapter1) -> Option<AdapterDesc> {
-    unsafe {
-        match adapter.GetDesc1() {
-            Ok(adapter_desc) => {
-                let adapter_flag = DXGI_ADAPTER_FLAG(adapter_desc.Flags as _);
-
-                let adapter_name = convert_u16_to_string(adapter_desc.Description.as_ref());
-                // let _adapter_dedicated_video_memory = adapter_desc.DedicatedVideoMemory;
-                let luid = adapter_desc.AdapterLuid;
-                // let high_part = adapter_desc.AdapterLuid.HighPart;
-                // let low_part = adapter_desc.AdapterLuid.LowPart;
-                let vendor_id = adapter_desc.VendorId;
-                let device_id = adapter_desc.DeviceId;
-
-                let is_integrated =
-                    vendor_id == 0x8086 && (device_id == 0x163c || device_id == 0x1616);
-                let is_discrete = !is_integrated;
-                let is_default = false;
-                let mut is_software = false;
-
-                // desc.VendorId == 0x10DE  NVIDIA
-                // desc.VendorId == 0x1002 || 0x1022  AMD
-                // desc.VendorId == 0x8086) 0x163C, 0x8087 Intel
-                // Skip the software adaptor.
-                if (vendor_id == 0x1414) && (device_id == 0x8c) {
-                    // log::debug!("is Microsoft Basic Render Driver");
-                    is_software = true;
-                }
-                if (adapter_flag & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE {
-                    // log::debug!("Skip Software Adapter");
-                    is_software = true;
-                }
-
-                let is_hardware = !is_software;
-
-                let adapter_desc = AdapterDesc {
-                    index: 0,
-                    description: adapter_name.to_string(),
-                    luid: luid.into(),
-                    device_id,
-                    vendor_id,
-                    is_default,
-                    is_software,
-                    is_hardware,
-                    is_discrete,
-                    is_integrated,
-                };
-
-                Some(adapter_desc)
-            }
-            Err(e) => {
-                log::debug!("adapters1 GetDesc1 fail: {:?}", e);
-                return None;
-            }
+    let raw = raw_adapter_desc(adapter)?;
+    let factory = match unsafe { adapter.GetParent::<IDXGIFactory1>() } {
+        Ok(factory) => factory,
+        Err(e) => {
+            log::debug!("adapter GetParent fail: {:?}", e);
+            return None;
         }
+    };
+    let mut i = 0;
+    while let Ok(other) = unsafe { factory.EnumAdapters1(i) } {
+        if raw_adapter_desc(&other).is_some_and(|other| other.luid == raw.luid) {
+            return Some(AdapterDesc::from_raw(&raw, i, i == 0));
+        }
+        i += 1;
     }
+    log::debug!("adapter {} is not enumerated by its factory", raw.luid);
+    None
 }
 
-pub(crate) fn init_adaptor() -> Option<(IDXGIAdapter1, ID3D11Device, ID3D11DeviceContext)> {
-    let factory = unsafe {
-        match CreateDXGIFactory2::<IDXGIFactory7>(DXGI_CREATE_FACTORY_FLAGS(0u32)) {
-            Ok(factory) => factory,
-            Err(e) => {
-                log::debug!("factory2 init fail: {:?}", e);
-                return None;
-            }
+/// Describes every output of `adapter`, attached to the desktop or not.
+pub fn enumerate_outputs(adapter: &IDXGIAdapter1) -> Option<Vec<OutputDesc>> {
+    let adapter_luid = raw_adapter_desc(adapter)?.luid;
+    let mut outputs = Vec::new();
+    let mut i = 0;
+    while let Ok(output) = unsafe { adapter.EnumOutputs(i) } {
+        match output.cast::<IDXGIOutput6>() {
+            Ok(output) => outputs.extend(get_output_desc(adapter_luid, i, &output)),
+            Err(e) => log::debug!("output[{}] IDXGIOutput6 cast fail: {:?}", i, e),
         }
-    };
+        i += 1;
+    }
+    Some(outputs)
+}
 
-    // --- Enumerate adapters
-    let mut adapters = Vec::new();
-    unsafe {
-        let mut i = 0;
-        while let Ok(adapter) = factory.EnumAdapters1(i) {
-            adapters.push(adapter);
-            i += 1;
-        }
-    };
+/// The outputs of all adapters, software ones included.
+pub fn get_outputs_desc() -> Option<Vec<OutputDesc>> {
+    let factory = create_factory()?;
 
-    for adapter in adapters {
-        unsafe {
-            match adapter.GetDesc1() {
-                Ok(adapter_desc) => {
-                    let adapter_flag = DXGI_ADAPTER_FLAG(adapter_desc.Flags as _);
-
-                    let adapter_name = convert_u16_to_string(adapter_desc.Description.as_ref());
-                    let adapter_dedicated_video_memory = adapter_desc.DedicatedVideoMemory;
-                    let high_part = adapter_desc.AdapterLuid.HighPart;
-                    let low_part = adapter_desc.AdapterLuid.LowPart;
-                    let vendor_id = adapter_desc.VendorId;
-                    let device_id = adapter_desc.DeviceId;
-
-                    // desc.VendorId == 0x10DE  NVIDIA
-                    // desc.VendorId == 0x1002 || 0x1022  AMD
-                    // desc.VendorId == 0x8086) 0x163C, 0x8087 Intel
-                    // Skip the software adaptor.
-                    if (vendor_id == 0x1414) && (device_id == 0x8c) {
-                        log::debug!("Skip Microsoft Basic Render Driver");
-                        continue;
-                    }
-                    if (adapter_flag & DXGI_ADAPTER_FLAG_SOFTWARE) != DXGI_ADAPTER_FLAG_NONE {
-                        log::debug!("Skip Software Adapter");
-                        continue;
-                    }
-
-                    // Print some info about the adapter.
-                    log::debug!(
-                        "===> DXGI Adapter: [{}], LUID=[{:08x}-{:08x}] -> VendorId[{:x}] DeviceId[{:x}] with {} memory",
-                        adapter_name,
-                        high_part,
-                        low_part,
-                        vendor_id,
-                        device_id,
-                        adapter_dedicated_video_memory
-                    );
-                }
-                Err(e) => {
-                    log::debug!("adapters1 GetDesc1 fail: {:?}", e);
-                    return None;
-                }
-            }
+    let mut outputs = Vec::new();
+    let mut i = 0;
+    while let Ok(adapter) = unsafe { factory.EnumAdapters1(i) } {
+        // like `enumerate_adapters`, one adapter failing GetDesc1 does not hide the rest
+        let Some(adapter_outputs) = enumerate_outputs(&adapter) else {
+            log::debug!("adapter[{}] outputs skipped", i);
+            i += 1;
+            continue;
         };
+        outputs.extend(adapter_outputs);
+        i += 1;
+    }
+    Some(outputs)
+}
 
-        let mut level_used = D3D_FEATURE_LEVEL_11_0;
-        let feature_levels = [D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0];
-        let device_types = D3D_DRIVER_TYPE_UNKNOWN;
-
-        let mut device_flags = D3D11_CREATE_DEVICE_FLAG::default();
-        device_flags |= D3D11_CREATE_DEVICE_BGRA_SUPPORT;
-
-        let mut d3d11_device: Option<ID3D11Device> = None;
-        let mut d3d11_device_ctx: Option<ID3D11DeviceContext> = None;
+pub(crate) fn get_output_desc(
+    adapter_luid: Luid,
+    index: u32,
+    output: &IDXGIOutput6,
+) -> Option<OutputDesc> {
+    match unsafe { output.GetDesc1() } {
+        Ok(desc) => {
+            let rect = desc.DesktopCoordinates;
+            Some(OutputDesc {
+                adapter_luid,
+                index,
+                device_name: convert_u16_to_string(desc.DeviceName.as_ref()),
+                desktop_coordinates: Rect::new(rect.left, rect.top, rect.right, rect.bottom),
+                attached_to_desktop: desc.AttachedToDesktop == TRUE,
+                rotation: Rotation::from_raw(desc.Rotation.0),
+                monitor: desc.Monitor.0 as isize,
+                bits_per_color: desc.BitsPerColor,
+                color_space: ColorSpace::from_raw(desc.ColorSpace.0),
+                min_luminance: desc.MinLuminance,
+                max_luminance: desc.MaxLuminance,
+                max_full_frame_luminance: desc.MaxFullFrameLuminance,
+            })
+        }
+        Err(e) => {
+            log::error!("Failed to get output[{}] desc: {:?}", index, e);
+            None
+        }
+    }
+}
 
-        match unsafe {
-            D3D11CreateDevice(
-                &adapter,
-                device_types,
-                None,
-                device_flags,
-                Some(&feature_levels),
-                D3D11_SDK_VERSION,
-                Some(&mut d3d11_device),
-                Some(&mut level_used),
-                Some(&mut d3d11_device_ctx),
-            )
-        } {
-            Ok(_) => {
-                log::debug!("D3D11 device ok for {:?} ", device_types);
-                log::debug!(
-                    "D3D11 {:?} {:?} {:?}",
-                    d3d11_device,
-                    level_used,
-                    d3d11_device_ctx
-                );
-                let d3d11_device = d3d11_device.unwrap();
-                let d3d11_device_ctx = d3d11_device_ctx.unwrap();
-                return Some((adapter, d3d11_device, d3d11_device_ctx));
-            }
-            Err(err) => {
-                log::debug!("D3D11 device fail: {:?}", err);
-            }
+fn create_device(adapter: &IDXGIAdapter1) -> Option<(ID3D11Device, ID3D11DeviceContext)> {
+    let mut level_used = D3D_FEATURE_LEVEL_11_0;
+    let feature_levels = [D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0];
+    let device_types = D3D_DRIVER_TYPE_UNKNOWN;
+
+    let mut device_flags = D3D11_CREATE_DEVICE_FLAG::default();
+    device_flags |= D3D11_CREATE_DEVICE_BGRA_SUPPORT;
+
+    let mut d3d11_device: Option<ID3D11Device> = None;
+    let mut d3d11_device_ctx: Option<ID3D11DeviceContext> = None;
+
+    match unsafe {
+        D3D11CreateDevice(
+            adapter,
+            device_types,
+            None,
+            device_flags,
+            Some(&feature_levels),
+            D3D11_SDK_VERSION,
+            Some(&mut d3d11_device),
+            Some(&mut level_used),
+            Some(&mut d3d11_device_ctx),
+        )
+    } {
+        Ok(_) => {
+            log::debug!("D3D11 device ok for {:?} ", device_types);
+            log::debug!(
+                "D3D11 {:?} {:?} {:?}",
+                d3d11_device,
+                level_used,
+                d3d11_device_ctx
+            );
+            Some((d3d11_device?, d3d11_device_ctx?))
+        }
+        Err(err) => {
+            log::debug!("D3D11 device fail: {:?}", err);
+            None
         }
     }
-    None
 }
 
 pub(crate) fn init_adaptor_by_luid(
     luid: LUID,
-) -> Option<(IDXGIAdapter1, ID3D11Device, ID3D11DeviceContext)> {
-    let factory = unsafe {
-        match CreateDXGIFactory2::<IDXGIFactory7>(DXGI_CREATE_FACTORY_FLAGS(0u32)) {
-            Ok(factory) => factory,
-            Err(e) => {
-                log::debug!("factory2 init fail: {:?}", e);
-                return None;
-            }
-        }
-    };
-
+) -> Option<(
+    IDXGIAdapter1,
+    AdapterDesc,
+    ID3D11Device,
+    ID3D11DeviceContext,
+)> {
     log::debug!("init_adaptor_by_luid {:?}", luid);
 
-    unsafe {
-        match factory.EnumAdapterByLuid::<IDXGIFactory4>(luid) {
-            Ok(adapter) => {
-                let adapter1 = adapter.cast::<IDXGIAdapter1>().unwrap();
-                let mut level_used = D3D_FEATURE_LEVEL_11_0;
-                let feature_levels = [D3D_FEATURE_LEVEL_11_1, D3D_FEATURE_LEVEL_11_0];
-                let device_types = D3D_DRIVER_TYPE_UNKNOWN;
-
-                let mut device_flags = D3D11_CREATE_DEVICE_FLAG::default();
-                device_flags |= D3D11_CREATE_DEVICE_BGRA_SUPPORT;
-
-                let mut d3d11_device: Option<ID3D11Device> = None;
-                let mut d3d11_device_ctx: Option<ID3D11DeviceContext> = None;
-
-                match D3D11CreateDevice(
-                    &adapter1,
-                    device_types,
-                    None,
-                    device_flags,
-                    Some(&feature_levels),
-                    D3D11_SDK_VERSION,
-                    Some(&mut d3d11_device),
-                    Some(&mut level_used),
-                    Some(&mut d3d11_device_ctx),
-                ) {
-                    Ok(_) => {
-                        log::debug!("D3D11 device ok for {:?} ", device_types);
-                        log::debug!(
-                            "D3D11 {:?} {:?} {:?}",
-                            d3d11_device,
-                            level_used,
-                            d3d11_device_ctx
-                        );
-                        let d3d11_device = d3d11_device.unwrap();
-                        let d3d11_device_ctx = d3d11_device_ctx.unwrap();
-                        return Some((adapter1, d3d11_device, d3d11_device_ctx));
-                    }
-                    Err(err) => {
-                        log::debug!("D3D11 device fail: {:?}", err);
-                    }
-                }
-            }
-            Err(e) => {
-                log::debug!("adapters1 GetDesc1 fail: {:?}", e);
-                return None;
-            }
-        }
-    }
-    None
+    let luid = Luid::from(luid);
+    let Some((adapter, adapter_desc)) = enumerate_adapters()?
+        .into_iter()
+        .find(|(_, desc)| desc.luid == luid)
+    else {
+        log::debug!("no adapter with luid {}", luid);
+        return None;
+    };
+    let (d3d11_device, d3d11_device_ctx) = create_device(&adapter)?;
+    Some((adapter, adapter_desc, d3d11_device, d3d11_device_ctx))
 }
 
 pub(crate) fn acquire_duplication(
     adapter: &IDXGIAdapter1,
+    adapter_desc: AdapterDesc,
     d3d11_device: &ID3D11Device,
     capture_monitor_index: u32,
 ) -> Option<OutputDuplication> {
-    let index = capture_monitor_index + 1;
-    for output_index in 0..index {
-        match unsafe { adapter.EnumOutputs(output_index) } {
-            Ok(output) => {
-                let output = match output.cast::<IDXGIOutput6>() {
-                    Ok(output1) => output1,
-                    Err(e) => {
-                        log::error!("Failed to IDXGIOutput1 cast: {:?}", e);
-                        return None;
-                    }
-                };
-                match unsafe { output.GetDesc() } {
-                    Ok(output_desc) => {
-                        let _name = convert_u16_to_string(output_desc.DeviceName.as_ref());
-                        let _monitor = output_desc.Monitor;
-                        let _attached_to_desktop = output_desc.AttachedToDesktop == TRUE;
-
-                        let width = output_desc.DesktopCoordinates.right
-                            - output_desc.DesktopCoordinates.left;
-                        let height = output_desc.DesktopCoordinates.bottom
-                            - output_desc.DesktopCoordinates.top;
-                        match unsafe { output.DuplicateOutput(d3d11_device) } {
-                            Ok(duplicator) => {
-                                // let duplicator_desc =  unsafe { duplicator.GetDesc() };
-                                let adapter_desc = get_hardware_adapter_desc(adapter)?;
-                                return Some(OutputDuplication {
-                                    duplication: duplicator,
-                                    output_dimensions: (width as _, height as _),
-                                    adapter_desc,
-                                });
-                            }
-                            Err(e) => {
-                                log::error!(
-                                    "Failed to duplicate output[{}]: {:?}",
-                                    output_index,
-                                    e
-                                );
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to get output[{}] desc: {:?}", output_index, e);
-                    }
-                }
-            }
-            Err(err) => {
-                log::error!("Failed to get output[{}]: {:?}", output_index, err);
-            }
+    let output_index = capture_monitor_index;
+    let output = match unsafe { adapter.EnumOutputs(output_index) } {
+        Ok(output) => output,
+        Err(err) => {
+            log::error!("Failed to get output[{}]: {:?}", output_index, err);
+            return None;
+        }
+    };
+    let output = match output.cast::<IDXGIOutput6>() {
+        Ok(output1) => output1,
+        Err(e) => {
+            log::error!("Failed to IDXGIOutput1 cast: {:?}", e);
+            return None;
+        }
+    };
+    let output_desc = get_output_desc(adapter_desc.luid, output_index, &output)?;
+    match unsafe { output.DuplicateOutput(d3d11_device) } {
+        Ok(duplicator) => {
+            // let duplicator_desc =  unsafe { duplicator.GetDesc() };
+            Some(OutputDuplication {
+                duplication: duplicator,
+                output_dimensions: (output_desc.width(), output_desc.height()),
+                adapter_desc,
+                output_desc,
+            })
+        }
+        Err(e) => {
+            log::error!("Failed to duplicate output[{}]: {:?}", output_index, e);
+            None
         }
     }
-    None
 }
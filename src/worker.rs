@@ -0,0 +1,359 @@
+//! Capturing on a dedicated thread.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::backend::CaptureBackend;
+use crate::frame::Frame;
+use crate::queue::{BoundedQueue, DropPolicy};
+
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    // frames queued before the drop policy applies
+    pub capacity: usize,
+    pub drop_policy: DropPolicy,
+    // capture timeout in milliseconds, also how long pause and stop may take
+    pub timeout: u32,
+    // as passed to `CaptureBackend::capture`, only queue frames the desktop presented
+    pub skip: bool,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 2,
+            drop_policy: DropPolicy::DropOldest,
+            timeout: 100,
+            skip: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WorkerStats {
+    pub captured: u64,
+    // captures that returned no frame
+    pub timeouts: u64,
+    // frames discarded by the drop policy
+    pub dropped: u64,
+    // why the worker ended on its own, if it did
+    pub error: Option<String>,
+}
+
+struct Control {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    stopped: AtomicBool,
+    stats: Mutex<WorkerStats>,
+}
+
+/// Owns a capture backend on its own thread and queues the frames it captures.
+///
+/// The backend is created on the worker thread, so backends that cannot be sent
+/// between threads, like `CaptureDXGI`, work too. A capture error ends the worker, the
+/// queue is closed then and the error is reported in the stats.
+///
+/// ```
+/// use std::time::Duration;
+/// use dxgi::queue::DropPolicy;
+/// use dxgi::synthetic::SyntheticBackend;
+/// use dxgi::worker::{CaptureWorker, WorkerConfig};
+///
+/// let config = WorkerConfig {
+///     capacity: 2,
+///     drop_policy: DropPolicy::DropOldest,
+///     timeout: 10,
+///     ..Default::default()
+/// };
+/// let worker = CaptureWorker::start(config, || Ok(SyntheticBackend::new(64, 48, 200)))
+///     .unwrap();
+/// // nobody reads for a while, only the newest two frames are kept
+/// std::thread::sleep(Duration::from_millis(100));
+/// assert!(worker.stats().dropped > 0);
+/// let frame = worker.recv(Some(Duration::from_secs(1))).unwrap();
+/// assert_eq!((frame.width, frame.height), (64, 48));
+///
+/// worker.pause();
+/// std::thread::sleep(Duration::from_millis(30));
+/// while worker.try_recv().is_some() {}
+/// let captured = worker.stats().captured;
+/// std::thread::sleep(Duration::from_millis(50));
+/// assert_eq!(worker.stats().captured, captured);
+/// assert!(worker.try_recv().is_none());
+///
+/// worker.resume();
+/// assert!(worker.recv(Some(Duration::from_secs(1))).is_some());
+/// let stats = worker.stop();
+/// assert!(stats.captured > captured);
+/// assert!(stats.error.is_none());
+/// ```
+pub struct CaptureWorker {
+    queue: Arc<BoundedQueue<Frame>>,
+    control: Arc<Control>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CaptureWorker {
+    /// Starts capturing from the backend `open` creates on the worker thread.
+    pub fn start<B, F>(config: WorkerConfig, open: F) -> std::io::Result<Self>
+    where
+        B: CaptureBackend,
+        F: FnOnce() -> anyhow::Result<B> + Send + 'static,
+    {
+        let queue = Arc::new(BoundedQueue::new(config.capacity, config.drop_policy));
+        let control = Arc::new(Control {
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            stats: Mutex::new(WorkerStats::default()),
+        });
+        let thread = {
+            let queue = queue.clone();
+            let control = control.clone();
+            std::thread::Builder::new()
+                .name("capture-worker".to_string())
+                .spawn(move || {
+                    if let Err(e) = run(open, &config, &queue, &control) {
+                        log::error!("capture worker: {:#}", e);
+                        control.stats.lock().error = Some(format!("{:#}", e));
+                    }
+                    control.stats.lock().dropped = queue.dropped();
+                    queue.close();
+                })?
+        };
+        Ok(Self {
+            queue,
+            control,
+            thread: Some(thread),
+        })
+    }
+
+    /// Waits for the next frame. `None` on timeout, or once the worker has ended and
+    /// the queue is drained.
+    pub fn recv(&self, timeout: Option<Duration>) -> Option<Frame> {
+        self.queue.pop(timeout)
+    }
+
+    pub fn try_recv(&self) -> Option<Frame> {
+        self.queue.try_pop()
+    }
+
+    /// The frame queue, to hand the consuming side to another thread.
+    pub fn queue(&self) -> &Arc<BoundedQueue<Frame>> {
+        &self.queue
+    }
+
+    /// Stops capturing after the capture in progress. Queued frames stay queued.
+    pub fn pause(&self) {
+        *self.control.paused.lock() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.control.paused.lock() = false;
+        self.control.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.control.paused.lock()
+    }
+
+    /// False once the worker ended, on its own or by `stop`.
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    pub fn stats(&self) -> WorkerStats {
+        let mut stats = self.control.stats.lock().clone();
+        stats.dropped = self.queue.dropped();
+        stats
+    }
+
+    /// Ends the worker, waits for its thread and returns the final stats.
+    pub fn stop(mut self) -> WorkerStats {
+        self.shutdown();
+        self.stats()
+    }
+
+    pub(crate) fn shutdown(&mut self) {
+        self.signal_stop();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                self.control.stats.lock().error = Some("capture worker panicked".to_string());
+            }
+        }
+    }
+
+    /// Like `shutdown`, but leaves the thread to end on its own after the capture in
+    /// progress rather than waiting for it.
+    #[cfg(feature = "async")]
+    pub(crate) fn detach(&mut self) {
+        self.signal_stop();
+        self.thread.take();
+    }
+
+    fn signal_stop(&self) {
+        self.control.stopped.store(true, Ordering::SeqCst);
+        self.resume();
+        // also wakes a worker blocked on a full queue
+        self.queue.close();
+    }
+}
+
+impl Drop for CaptureWorker {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run<B, F>(
+    open: F,
+    config: &WorkerConfig,
+    queue: &BoundedQueue<Frame>,
+    control: &Control,
+) -> anyhow::Result<()>
+where
+    B: CaptureBackend,
+    F: FnOnce() -> anyhow::Result<B>,
+{
+    let mut backend = open()?;
+    loop {
+        {
+            let mut paused = control.paused.lock();
+            while *paused && !control.stopped.load(Ordering::SeqCst) {
+                control.resumed.wait(&mut paused);
+            }
+        }
+        if control.stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        match backend.capture(config.timeout, config.skip)? {
+            Some(frame) => {
+                control.stats.lock().captured += 1;
+                if queue.push(frame).is_err() {
+                    // closed by `stop`
+                    return Ok(());
+                }
+            }
+            None => control.stats.lock().timeouts += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::synthetic::SyntheticBackend;
+
+    // the synthetic desktop, failing after `frames` captures
+    struct Failing {
+        inner: SyntheticBackend,
+        frames: u32,
+    }
+
+    impl CaptureBackend for Failing {
+        fn capture(&mut self, timeout: u32, skip: bool) -> anyhow::Result<Option<Frame>> {
+            if self.frames == 0 {
+                anyhow::bail!("device removed");
+            }
+            self.frames -= 1;
+            self.inner.capture(timeout, skip)
+        }
+
+        fn width(&self) -> u32 {
+            self.inner.width()
+        }
+
+        fn height(&self) -> u32 {
+            self.inner.height()
+        }
+    }
+
+    fn recv_all(worker: &CaptureWorker) -> Vec<Frame> {
+        std::iter::from_fn(|| worker.recv(Some(Duration::from_secs(5)))).collect()
+    }
+
+    #[test]
+    fn open_failure() {
+        let worker = CaptureWorker::start(WorkerConfig::default(), || {
+            anyhow::Result::<SyntheticBackend>::Err(anyhow::anyhow!("no output"))
+        })
+        .unwrap();
+        assert!(worker.recv(Some(Duration::from_secs(5))).is_none());
+        assert!(worker.queue().is_closed());
+        let stats = worker.stop();
+        assert_eq!(stats.error.as_deref(), Some("no output"));
+        assert_eq!(stats.captured, 0);
+    }
+
+    #[test]
+    fn capture_error_ends_the_worker() {
+        let config = WorkerConfig {
+            capacity: 8,
+            drop_policy: DropPolicy::Block,
+            ..Default::default()
+        };
+        let worker = CaptureWorker::start(config, || {
+            Ok(Failing {
+                inner: SyntheticBackend::new(32, 32, 500),
+                frames: 3,
+            })
+        })
+        .unwrap();
+        // the frames captured before the error are still delivered
+        assert_eq!(recv_all(&worker).len(), 3);
+        let started = Instant::now();
+        while worker.is_running() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!worker.is_running());
+        let stats = worker.stop();
+        assert_eq!(stats.captured, 3);
+        assert_eq!(stats.error.as_deref(), Some("device removed"));
+    }
+
+    #[test]
+    fn stop_while_blocked_on_a_full_queue() {
+        let config = WorkerConfig {
+            capacity: 1,
+            drop_policy: DropPolicy::Block,
+            timeout: 10,
+            ..Default::default()
+        };
+        let worker =
+            CaptureWorker::start(config, || Ok(SyntheticBackend::new(32, 32, 500))).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        // one frame queued, one held by the blocked push
+        assert_eq!(worker.stats().captured, 2);
+        assert_eq!(worker.queue().len(), 1);
+        let started = Instant::now();
+        let stats = worker.stop();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!((stats.dropped, stats.error), (0, None));
+    }
+
+    #[test]
+    fn timeouts_are_counted() {
+        let config = WorkerConfig {
+            timeout: 5,
+            skip: true,
+            ..Default::default()
+        };
+        let worker = CaptureWorker::start(config, || {
+            let mut backend = SyntheticBackend::new(32, 32, 30);
+            backend.set_paused(true);
+            Ok(backend)
+        })
+        .unwrap();
+        assert!(worker.recv(Some(Duration::from_millis(50))).is_none());
+        assert!(worker.is_running());
+        let stats = worker.stop();
+        assert_eq!(stats.captured, 0);
+        assert!(stats.timeouts >= 3, "{} timeouts", stats.timeouts);
+    }
+}
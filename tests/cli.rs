@@ -0,0 +1,152 @@
+//! Runs the `dxgi` tool against the synthetic backend and checks its exit codes.
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use dxgi::codec::Codec;
+use dxgi::ivf::IvfReader;
+
+fn dxgi(args: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dxgi"))
+        .args(args.split_whitespace())
+        .output()
+        .unwrap()
+}
+
+fn code(args: &str) -> i32 {
+    dxgi(args).status.code().unwrap()
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dxgi-cli-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn usage_errors() {
+    let output = dxgi("");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("usage: dxgi <command>"));
+    assert_eq!(code("nope"), 2);
+    assert_eq!(code("snapshot --synthetic --quality 0"), 2);
+    assert_eq!(code("snapshot --synthetic --bogus"), 2);
+    assert_eq!(code("snapshot --synthetic -o frame.gif"), 2);
+    assert_eq!(code("record --synthetic"), 2);
+    assert_eq!(
+        code("record --synthetic -o out.mkv --interval 1s --change 0.5"),
+        2
+    );
+    assert_eq!(code("record --synthetic -o out.mp4"), 2);
+    assert_eq!(code("replay"), 2);
+    assert_eq!(code("stream --protocol ftp"), 2);
+    assert_eq!(code("help"), 0);
+    assert_eq!(code("record --help"), 0);
+}
+
+#[test]
+fn failures() {
+    #[cfg(not(windows))]
+    assert_eq!(code("adapters"), 3);
+    // no frame within the time given
+    assert_eq!(code("bench --synthetic --duration 0"), 4);
+    let missing = temp("missing.ivf");
+    assert_eq!(code(&format!("replay {}", missing.display())), 1);
+}
+
+#[test]
+fn snapshot() {
+    let path = temp("snapshot.ppm");
+    let output = dxgi(&format!(
+        "snapshot --synthetic --size 64x48 -o {}",
+        path.display()
+    ));
+    assert_eq!(output.status.code(), Some(0));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("width: 64\nheight: 48\n"), "{}", report);
+    let data = std::fs::read(&path).unwrap();
+    assert!(data.starts_with(b"P6\n64 48\n255\n"));
+    assert_eq!(data.len(), 13 + 64 * 48 * 3);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn record() {
+    let path = temp("record.ivf");
+    let args = format!(
+        "record --synthetic --size 64x48 --fps 100 --interval 0 --frames 4 --playback-fps 10 -o {}",
+        path.display()
+    );
+    assert_eq!(code(&args), 0);
+    let reader = IvfReader::new(File::open(&path).unwrap()).unwrap();
+    let header = *reader.header();
+    assert_eq!(header.codec, Codec::MJPEG);
+    assert_eq!(
+        (header.width, header.height, header.frame_count),
+        (64, 48, 4)
+    );
+    let pts: Vec<_> = reader.map(|packet| packet.unwrap().pts).collect();
+    assert_eq!(
+        pts,
+        (0..4)
+            .map(|i| Duration::from_millis(i * 100))
+            .collect::<Vec<_>>()
+    );
+
+    // every MJPEG frame is a keyframe, the last 200 ms are the frames at 100 to 300 ms
+    let out = temp("replay.mkv");
+    let output = dxgi(&format!(
+        "replay {} -o {} --last 200ms",
+        path.display(),
+        out.display()
+    ));
+    assert_eq!(output.status.code(), Some(0));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("codec: mjpeg\n"), "{}", report);
+    assert!(report.contains("packets: 4\n"), "{}", report);
+    assert!(report.contains("packets: 3\n"), "{}", report);
+    let data = std::fs::read(&out).unwrap();
+    assert!(data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]));
+    std::fs::remove_file(&out).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bench() {
+    let output = dxgi("bench --synthetic --size 64x48 --fps 100 --frames 3");
+    assert_eq!(output.status.code(), Some(0));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("frames: 3\n"), "{}", report);
+}
+
+#[test]
+fn stream() {
+    let output = dxgi("stream --synthetic --size 64x48 --listen 127.0.0.1:0 --duration 200ms");
+    assert_eq!(output.status.code(), Some(0));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("protocol: net\n"), "{}", report);
+    assert!(report.contains("endpoint: "), "{}", report);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json() {
+    let output = dxgi("outputs --synthetic --size 64x48 --json");
+    assert_eq!(output.status.code(), Some(0));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["outputs"][0]["name"], "synthetic");
+    assert_eq!(report["outputs"][0]["width"], 64);
+
+    // failures are reported as JSON too
+    let output = dxgi("snapshot --synthetic --quality 0 --json");
+    assert_eq!(output.status.code(), Some(2));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["code"], 2);
+    assert_eq!(report["error"], "--quality takes 1 to 100");
+}
+
+#[cfg(not(feature = "serde"))]
+#[test]
+fn json() {
+    assert_eq!(code("outputs --synthetic --json"), 3);
+}